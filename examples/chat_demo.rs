@@ -0,0 +1,212 @@
+//! Two clients chatting through a chat server across the mini deployment of
+//! `demo_net`: registration, client list, messages and an unknown recipient.
+//!
+//! ```text
+//! cargo run --example chat_demo --features demo
+//! ```
+
+mod demo_net;
+
+use common::chat_rooms::RoomRegistry;
+use common::client_registry::ClientRegistry;
+use common::codec::CodecFlags;
+use common::testing::MockNetwork;
+use common::types::{ChatRequest, ChatResponse, Command, NodeCommand, ServerType};
+use common::{FragmentAssembler, Processor, RoutingHandler};
+use crossbeam_channel::{Receiver, never, unbounded};
+use demo_net::{ALICE, BOB, ClientHandle, SERVER, discover_servers, topology};
+use std::time::Duration;
+use wg_internal::network::NodeId;
+use wg_internal::packet::{NodeType, Packet};
+
+/// Chat server registering the clients and relaying their messages.
+struct ChatServer {
+    router: RoutingHandler,
+    assembler: FragmentAssembler,
+    controller_recv: Receiver<Box<dyn Command>>,
+    packet_recv: Receiver<Packet>,
+    clients: ClientRegistry,
+    rooms: RoomRegistry,
+}
+
+impl ChatServer {
+    fn send(&mut self, to: NodeId, response: &ChatResponse) {
+        if let Ok(data) = serde_json::to_vec(response) {
+            let _ = self.router.send_message(&data, Some(to), None);
+        }
+    }
+
+    fn respond(&mut self, from: NodeId, request: ChatRequest) {
+        let handled = match self.clients.handle(from, &request) {
+            Ok(Some(responses)) => Some(responses),
+            Ok(None) => self.rooms.handle(from, &request),
+            // the registry isn't persisted, registrations cannot fail
+            Err(_) => None,
+        };
+        if let Some(responses) = handled {
+            for (to, response) in responses {
+                self.send(to, &response);
+            }
+            return;
+        }
+        let response = match request {
+            ChatRequest::ServerTypeQuery => ChatResponse::ServerType {
+                server_type: ServerType::ChatServer,
+            },
+            ChatRequest::MessageFor { client_id, message } => {
+                if self.clients.is_registered(client_id) {
+                    let relayed = ChatResponse::MessageFrom {
+                        client_id: from,
+                        message,
+                    };
+                    self.send(client_id, &relayed);
+                    return;
+                }
+                ChatResponse::ErrorWrongClientId {
+                    wrong_id: client_id,
+                }
+            }
+            _ => return,
+        };
+        self.send(from, &response);
+    }
+}
+
+impl Processor for ChatServer {
+    fn controller_recv(&self) -> &Receiver<Box<dyn Command>> {
+        &self.controller_recv
+    }
+
+    fn packet_recv(&self) -> &Receiver<Packet> {
+        &self.packet_recv
+    }
+
+    fn assembler(&mut self) -> &mut FragmentAssembler {
+        &mut self.assembler
+    }
+
+    fn routing_handler(&mut self) -> &mut RoutingHandler {
+        &mut self.router
+    }
+
+    fn handle_msg(&mut self, msg: Vec<u8>, from: NodeId, _session_id: u64) {
+        if let Ok(request) = serde_json::from_slice(&msg) {
+            self.respond(from, request);
+        }
+    }
+
+    fn handle_command(&mut self, cmd: Box<dyn Command>) -> bool {
+        matches!(
+            cmd.into_any().downcast::<NodeCommand>().as_deref(),
+            Ok(NodeCommand::Shutdown)
+        )
+    }
+}
+
+fn main() {
+    let mut network = MockNetwork::from_config(&topology());
+    let (controller_send, _events) = unbounded();
+    let (_server_commands, controller_recv) = unbounded();
+    let server = ChatServer {
+        router: RoutingHandler::new(
+            SERVER,
+            NodeType::Server,
+            network.neighbors(SERVER),
+            controller_send,
+        ),
+        assembler: FragmentAssembler::default(),
+        controller_recv,
+        packet_recv: never(),
+        clients: ClientRegistry::new(SERVER, CodecFlags::all(), Duration::from_mins(5)),
+        rooms: RoomRegistry::new(),
+    };
+    let _ = network.host(SERVER, Box::new(server));
+    let alice = ClientHandle::host(&mut network, ALICE);
+    let bob = ClientHandle::host(&mut network, BOB);
+    discover_servers(&mut network);
+
+    let chat = alice.discover(&mut network, &ServerType::ChatServer);
+    assert_eq!(bob.discover(&mut network, &ServerType::ChatServer), chat);
+    println!("chat server found at {chat}");
+
+    for (client, id) in [(&alice, ALICE), (&bob, BOB)] {
+        client.send(
+            &mut network,
+            chat,
+            &ChatRequest::RegistrationToChat {
+                client_id: id,
+                codecs: CodecFlags::all(),
+            },
+        );
+        let (_, response) = client.receive::<ChatResponse>(&mut network);
+        assert!(matches!(
+            response,
+            ChatResponse::RegistrationAccepted { .. }
+        ));
+        println!("{id} registered: {response:?}");
+    }
+    bob.send(&mut network, chat, &ChatRequest::ClientListQuery);
+    let (_, ChatResponse::ClientList { list_of_client_ids }) = bob.receive(&mut network) else {
+        panic!("expected the client list");
+    };
+    assert_eq!(list_of_client_ids, vec![ALICE, BOB]);
+    println!("registered clients: {list_of_client_ids:?}");
+
+    alice.send(
+        &mut network,
+        chat,
+        &ChatRequest::MessageFor {
+            client_id: BOB,
+            message: "hi bob".to_string(),
+        },
+    );
+    let (_, ChatResponse::MessageFrom { client_id, message }) = bob.receive(&mut network) else {
+        panic!("expected a message");
+    };
+    assert_eq!((client_id, message.as_str()), (ALICE, "hi bob"));
+    println!("bob got {message:?} from {client_id}");
+
+    bob.send(
+        &mut network,
+        chat,
+        &ChatRequest::MessageFor {
+            client_id: 99,
+            message: "anyone there?".to_string(),
+        },
+    );
+    assert!(matches!(
+        bob.receive(&mut network),
+        (_, ChatResponse::ErrorWrongClientId { wrong_id: 99 })
+    ));
+    println!("messages to 99 are refused");
+
+    alice.send(
+        &mut network,
+        chat,
+        &ChatRequest::CreateRoom {
+            name: "general".to_string(),
+        },
+    );
+    let (_, ChatResponse::RoomCreated { room_id, .. }) = alice.receive(&mut network) else {
+        panic!("expected the room");
+    };
+    bob.send(&mut network, chat, &ChatRequest::JoinRoom { room_id });
+    for client in [&alice, &bob] {
+        assert!(matches!(
+            client.receive(&mut network),
+            (_, ChatResponse::RoomJoined { client_id: BOB, .. })
+        ));
+    }
+    bob.send(
+        &mut network,
+        chat,
+        &ChatRequest::MessageToRoom {
+            room_id,
+            message: "hi all".to_string(),
+        },
+    );
+    let (_, ChatResponse::MessageFromRoom { message, .. }) = alice.receive(&mut network) else {
+        panic!("expected a room message");
+    };
+    println!("alice got {message:?} in room {room_id}");
+}
@@ -0,0 +1,205 @@
+//! Mini deployment shared by the demos: the bootstrap topology simulated by a
+//! `MockNetwork`, whose drones relay the packets, and clients hosted in it that the
+//! demos drive through [`ClientHandle`].
+//!
+//! ```text
+//! alice(1) - drone(10) - drone(11) - server(20)
+//!                          |
+//!                        bob(2)
+//! ```
+
+// each demo uses its own part of the deployment
+#![allow(dead_code)]
+
+use common::WebBrowser;
+use common::discovery::{DEFAULT_DISCOVERY_QUIET_PERIOD, ServiceDiscovery};
+use common::testing::MockNetwork;
+use common::types::{Command, NodeCommand, ServerType};
+use common::{FragmentAssembler, Processor, RoutingHandler};
+use crossbeam_channel::{Receiver, Sender, bounded, never, unbounded};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use wg_internal::config::{Client, Config, Drone, Server};
+use wg_internal::network::NodeId;
+use wg_internal::packet::{NodeType, Packet};
+
+pub const ALICE: NodeId = 1;
+pub const BOB: NodeId = 2;
+pub const SERVER: NodeId = 20;
+
+pub fn topology() -> Config {
+    Config {
+        drone: vec![
+            Drone {
+                id: 10,
+                connected_node_ids: vec![ALICE, 11],
+                pdr: 0.0,
+            },
+            Drone {
+                id: 11,
+                connected_node_ids: vec![10, BOB, SERVER],
+                pdr: 0.0,
+            },
+        ],
+        client: vec![
+            Client {
+                id: ALICE,
+                connected_drone_ids: vec![10],
+            },
+            Client {
+                id: BOB,
+                connected_drone_ids: vec![11],
+            },
+        ],
+        server: vec![Server {
+            id: SERVER,
+            connected_drone_ids: vec![11],
+        }],
+    }
+}
+
+/// Floods from every node, then lets the clients query the servers found once the
+/// floods settled.
+pub fn discover_servers(network: &mut MockNetwork) {
+    network.flood().expect("flood not started");
+    network
+        .advance(DEFAULT_DISCOVERY_QUIET_PERIOD)
+        .expect("servers not queried");
+    let _ = network.run_until(|_| false);
+}
+
+type Call = Box<dyn FnOnce(&mut ClientNode) + Send>;
+
+/// Client whose messages are handed to the demo, which drives it through
+/// [`ClientHandle::call`]. Servers are discovered by its [`ServiceDiscovery`] and web
+/// responses are handled by its [`WebBrowser`].
+pub struct ClientNode {
+    pub router: RoutingHandler,
+    pub discovery: ServiceDiscovery,
+    pub browser: WebBrowser,
+    assembler: FragmentAssembler,
+    controller_recv: Receiver<Box<dyn Command>>,
+    packet_recv: Receiver<Packet>,
+    messages: Sender<(NodeId, Vec<u8>)>,
+}
+
+impl Processor for ClientNode {
+    fn controller_recv(&self) -> &Receiver<Box<dyn Command>> {
+        &self.controller_recv
+    }
+
+    fn packet_recv(&self) -> &Receiver<Packet> {
+        &self.packet_recv
+    }
+
+    fn assembler(&mut self) -> &mut FragmentAssembler {
+        &mut self.assembler
+    }
+
+    fn routing_handler(&mut self) -> &mut RoutingHandler {
+        &mut self.router
+    }
+
+    fn service_discovery(&mut self) -> Option<&mut ServiceDiscovery> {
+        Some(&mut self.discovery)
+    }
+
+    fn handle_msg(&mut self, msg: Vec<u8>, from: NodeId, _session_id: u64) {
+        let handled = self.browser.handle_response(&mut self.router, from, &msg);
+        if !matches!(handled, Ok(true)) {
+            let _ = self.messages.send((from, msg));
+        }
+    }
+
+    fn handle_command(&mut self, cmd: Box<dyn Command>) -> bool {
+        let cmd = match cmd.into_any().downcast::<Call>() {
+            Ok(call) => {
+                (*call)(self);
+                return false;
+            }
+            Err(cmd) => cmd,
+        };
+        matches!(
+            cmd.downcast::<NodeCommand>().as_deref(),
+            Ok(NodeCommand::Shutdown)
+        )
+    }
+}
+
+/// Demo side of a [`ClientNode`] hosted in a [`MockNetwork`].
+pub struct ClientHandle {
+    commands: Sender<Box<dyn Command>>,
+    messages: Receiver<(NodeId, Vec<u8>)>,
+}
+
+impl ClientHandle {
+    /// Hosts a [`ClientNode`] at the client `id` of `network`.
+    pub fn host(network: &mut MockNetwork, id: NodeId) -> Self {
+        // the demo follows the clients through their messages, not their events
+        let (controller_send, _) = unbounded();
+        let (commands, controller_recv) = unbounded();
+        let (messages_send, messages) = unbounded();
+        let neighbors = network.neighbors(id);
+        let node = ClientNode {
+            router: RoutingHandler::new(id, NodeType::Client, neighbors, controller_send),
+            discovery: ServiceDiscovery::default(),
+            browser: WebBrowser::new(),
+            assembler: FragmentAssembler::default(),
+            controller_recv,
+            packet_recv: never(),
+            messages: messages_send,
+        };
+        assert!(network.host(id, Box::new(node)), "{id} is not a client");
+        Self { commands, messages }
+    }
+
+    /// Runs `f` on the client during the next round of `network` and returns its result.
+    pub fn call<T: Send + 'static>(
+        &self,
+        network: &mut MockNetwork,
+        f: impl FnOnce(&mut ClientNode) -> T + Send + 'static,
+    ) -> T {
+        let (result_send, result_recv) = bounded(1);
+        let call: Call = Box::new(move |node| {
+            let _ = result_send.send(f(node));
+        });
+        self.commands
+            .send(Box::new(call))
+            .expect("client not hosted");
+        let _ = network.step();
+        result_recv.try_recv().expect("client not hosted")
+    }
+
+    pub fn send(&self, network: &mut MockNetwork, to: NodeId, request: &impl Serialize) {
+        let data = serde_json::to_vec(request).expect("request not serializable");
+        self.call(network, move |node| {
+            node.router.send_message(&data, Some(to), None)
+        })
+        .expect("request not sent");
+    }
+
+    /// Runs `network` until the client receives a message decoding as a `T`, skipping
+    /// the others.
+    pub fn receive<T: DeserializeOwned>(&self, network: &mut MockNetwork) -> (NodeId, T) {
+        loop {
+            let _ = network.run_until(|_| !self.messages.is_empty());
+            let (from, msg) = self.messages.try_recv().expect("no answer received");
+            if let Ok(response) = serde_json::from_slice(&msg) {
+                return (from, response);
+            }
+        }
+    }
+
+    /// Returns the first server of `server_type` discovered, see [`discover_servers`].
+    pub fn discover(&self, network: &mut MockNetwork, server_type: &ServerType) -> NodeId {
+        let wanted = server_type.clone();
+        let found = self.call(network, move |node| {
+            node.discovery
+                .discovery()
+                .servers_of_type(&wanted)
+                .first()
+                .copied()
+        });
+        found.unwrap_or_else(|| panic!("no {server_type:?} found"))
+    }
+}
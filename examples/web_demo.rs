@@ -0,0 +1,116 @@
+//! A reader and a publisher sharing files through a web server across the mini
+//! deployment of `demo_net`: discovery, browsing, upload and chunked download.
+//!
+//! ```text
+//! cargo run --example web_demo --features demo
+//! ```
+
+mod demo_net;
+
+use common::publish::publish;
+use common::testing::{LoopbackNode, MockNetwork};
+use common::types::{
+    File, MediaFile, MediaReference, ServerType, TextFile, WebEvent, WebRequest, WebResponse,
+};
+use crossbeam_channel::{never, unbounded};
+use demo_net::{ALICE, BOB, ClientHandle, SERVER, discover_servers, topology};
+use uuid::Uuid;
+use wg_internal::network::NodeId;
+
+/// Fetches the text file `file_id` of `server` with the browser of `client`, which
+/// downloads every media it references.
+fn fetch(network: &mut MockNetwork, client: &ClientHandle, server: NodeId, file_id: Uuid) -> File {
+    client
+        .call(network, move |node| {
+            node.browser.request_file(&mut node.router, server, file_id)
+        })
+        .expect("request not sent");
+    let _ = network.run_until(|_| false);
+    for event in client.call(network, |node| node.browser.take_events()) {
+        match event {
+            WebEvent::File { file, .. } => println!(
+                "{:?} shown with {} media to download",
+                file.text_file.title,
+                file.placeholders().len()
+            ),
+            WebEvent::MediaArrived { media_id, .. } => println!("media {media_id} arrived"),
+            other => println!("{other:?}"),
+        }
+    }
+    client
+        .call(network, move |node| node.browser.file(file_id).cloned())
+        .expect("file not received")
+}
+
+/// Lists the text files of `server`.
+fn list(network: &mut MockNetwork, client: &ClientHandle, server: NodeId) -> Vec<Uuid> {
+    client.send(network, server, &WebRequest::TextFilesListQuery);
+    let (_, WebResponse::TextFilesList { files }) = client.receive(network) else {
+        panic!("expected a files list");
+    };
+    files
+        .iter()
+        .map(|id| id.parse().expect("malformed file id"))
+        .collect()
+}
+
+fn main() {
+    let mut network = MockNetwork::from_config(&topology());
+    let (controller_send, _events) = unbounded();
+    let (_server_commands, controller_recv) = unbounded();
+    let server = LoopbackNode::new(
+        SERVER,
+        controller_send,
+        controller_recv,
+        never(),
+        network.neighbors(SERVER),
+    );
+    let _ = network.host(SERVER, Box::new(server));
+    let alice = ClientHandle::host(&mut network, ALICE);
+    let bob = ClientHandle::host(&mut network, BOB);
+    discover_servers(&mut network);
+
+    let web = alice.discover(&mut network, &ServerType::TextServer);
+    println!("text server found at {web}");
+    let files = list(&mut network, &alice, web);
+    assert_eq!(files.len(), 1);
+    let file = fetch(&mut network, &alice, web, files[0]);
+    assert!(file.is_complete());
+    println!(
+        "alice read {:?} with {} media",
+        file.text_file.title,
+        file.media_files.len()
+    );
+
+    // bob publishes a file whose media is several chunks long
+    let picture = MediaFile::from_u8("picture.bin".to_string(), &[7; 3000]);
+    let text = TextFile::new(
+        "Holidays".to_string(),
+        "See the picture".to_string(),
+        vec![MediaReference {
+            location: BOB,
+            id: picture.id,
+        }],
+    );
+    let upload = File::new(text, vec![picture.clone()]);
+    assert_eq!(bob.discover(&mut network, &ServerType::TextServer), web);
+    let published = bob
+        .call(&mut network, move |node| {
+            publish(&mut node.router, &upload, web, &[web])
+        })
+        .expect("upload not sent");
+    for _ in 0..2 {
+        assert!(matches!(
+            bob.receive(&mut network),
+            (_, WebResponse::UploadAccepted { .. })
+        ));
+    }
+    println!("bob published {}", published.text.id);
+
+    let files = list(&mut network, &alice, web);
+    assert_eq!(files.len(), 2);
+    let new_file = fetch(&mut network, &alice, web, published.text.id);
+    assert!(new_file.is_complete());
+    assert_eq!(new_file.text_file.title, "Holidays");
+    assert_eq!(new_file.media_files[0].get_content(), picture.get_content());
+}
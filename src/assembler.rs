@@ -1,42 +1,689 @@
+use std::collections::btree_map::Entry;
 use std::collections::hash_map::Entry::Vacant;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use crate::clock::Clock;
+use crate::config::{CommonConfig, ReassemblyLimits};
+use crate::routing_handler::is_reserved_control_fragment;
+use crate::selfcheck::Diagnostic;
 use wg_internal::{network::NodeId, packet::Fragment};
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct AssemblerStats {
+    pub messages_delivered: u64,
+    // whole messages received again after having been delivered
+    pub duplicate_messages: u64,
+    pub duplicate_fragments: u64,
+    // incomplete messages dropped by `evict_stale` or the reassembly caps
+    pub sessions_evicted: u64,
+}
+
+/// Hook told about every new fragment of the messages being assembled, e.g. to draw
+/// the progress bar of a large download.
+pub trait ProgressObserver: Send + Debug {
+    /// Called once fragment `received` out of `total` of `session_id` from `sender`
+    /// arrived, the last call of a message having `received == total`.
+    fn on_progress(&mut self, session_id: u64, sender: NodeId, received: u64, total: u64);
+}
+
+/// Observer set with [`FragmentAssembler::set_progress_observer`], shared so that the
+/// node and its UI can both keep a handle on it.
+pub type SharedProgressObserver = Arc<Mutex<dyn ProgressObserver>>;
 
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct FragmentAssembler {
-    pub fragments: HashMap<(u64, NodeId), (u64, Vec<Fragment>)>, // session_id -> data buffer
+    // (session_id, sender) -> (announced total, fragments received by index)
+    pub fragments: HashMap<(u64, NodeId), (u64, BTreeMap<u64, Fragment>)>,
+    // recently delivered (session_id, sender) pairs -> (completion time, duplicate seen)
+    completed: HashMap<(u64, NodeId), (Instant, bool)>,
+    completed_order: VecDeque<(u64, NodeId)>,
+    dedup_window: Duration,
+    dedup_capacity: usize,
+    stats: AssemblerStats,
+    // maximum time a message waits for lower sessions of the same sender, `None` when disabled
+    ordered_hold: Option<Duration>,
+    // messages waiting for their turn: (sender, session_id) -> (completion time, data)
+    held: BTreeMap<(NodeId, u64), (Instant, Vec<u8>)>,
+    // completed messages are held until the batch of packets being handled is over
+    deferred: bool,
+    // session following the latest one released for each sender
+    next_session: HashMap<NodeId, u64>,
+    limits: ReassemblyLimits,
+    // timeouts of the messages not waiting for the one of `limits`
+    session_timeouts: HashMap<(u64, NodeId), Duration>,
+    // time of the latest fragment received for each message being assembled
+    last_fragment_at: HashMap<(u64, NodeId), Instant>,
+    // messages evicted since the latest `evict_stale`
+    evicted: Vec<(u64, NodeId)>,
+    progress_observer: Option<SharedProgressObserver>,
+    clock: Clock,
+}
+
+impl Default for FragmentAssembler {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_DEDUP_WINDOW, Self::DEFAULT_DEDUP_CAPACITY)
+    }
 }
 
 impl FragmentAssembler {
+    pub const DEFAULT_DEDUP_WINDOW: Duration = Duration::from_secs(30);
+    pub const DEFAULT_DEDUP_CAPACITY: usize = 1024;
+
+    /// Creates an assembler remembering up to `dedup_capacity` delivered sessions
+    /// for `dedup_window`, so that a sender retransmitting a whole session
+    /// doesn't get its message delivered twice.
+    #[must_use]
+    pub fn new(dedup_window: Duration, dedup_capacity: usize) -> Self {
+        Self {
+            fragments: HashMap::new(),
+            completed: HashMap::new(),
+            completed_order: VecDeque::new(),
+            dedup_window,
+            dedup_capacity,
+            stats: AssemblerStats::default(),
+            ordered_hold: None,
+            held: BTreeMap::new(),
+            deferred: false,
+            next_session: HashMap::new(),
+            limits: ReassemblyLimits::default(),
+            session_timeouts: HashMap::new(),
+            last_fragment_at: HashMap::new(),
+            evicted: Vec::new(),
+            progress_observer: None,
+            clock: Clock::default(),
+        }
+    }
+
+    /// Creates an assembler with the deduplication bounds and reassembly limits of `config`.
+    #[must_use]
+    pub fn from_config(config: &CommonConfig) -> Self {
+        let mut assembler = Self::new(config.dedup_window(), config.dedup_capacity);
+        assembler.set_reassembly_limits(config.reassembly);
+        assembler
+    }
+
+    /// Bounds the incomplete messages kept, so that the fragments of a sender dying
+    /// mid-message don't stay forever. The caps apply as fragments arrive, the timeout
+    /// on [`Self::evict_stale`].
+    pub fn set_reassembly_limits(&mut self, limits: ReassemblyLimits) {
+        self.limits = limits;
+        self.enforce_caps();
+    }
+
+    /// Gives the message `session_id` of `sender` its own reassembly timeout, e.g. a
+    /// longer one for a large download, in place of the one of the reassembly limits.
+    /// `None` goes back to the latter. The timeout is forgotten once the message is
+    /// delivered or evicted.
+    pub fn set_session_timeout(
+        &mut self,
+        session_id: u64,
+        sender: NodeId,
+        timeout: Option<Duration>,
+    ) {
+        match timeout {
+            Some(timeout) => {
+                let _ = self.session_timeouts.insert((session_id, sender), timeout);
+            }
+            None => {
+                let _ = self.session_timeouts.remove(&(session_id, sender));
+            }
+        }
+    }
+
+    /// Drops the incomplete messages idle for longer than their reassembly timeout, and
+    /// returns every message evicted since the previous call as `(session_id, sender)`,
+    /// so that the node can Nack them.
+    pub fn evict_stale(&mut self) -> Vec<(u64, NodeId)> {
+        let now = self.clock.now();
+        let mut stale = self
+            .fragments
+            .keys()
+            .filter(|id| {
+                let timeout = self.session_timeouts.get(id).copied();
+                timeout.or(self.limits.timeout()).is_some_and(|timeout| {
+                    self.last_fragment_at
+                        .get(id)
+                        .is_none_or(|at| now.duration_since(*at) >= timeout)
+                })
+            })
+            .copied()
+            .collect::<Vec<_>>();
+        stale.sort_unstable();
+        for id in stale {
+            self.evict(id);
+        }
+        self.enforce_caps();
+        std::mem::take(&mut self.evicted)
+    }
+
+    /// Evicts the least recently active messages until the caps are met.
+    fn enforce_caps(&mut self) {
+        let ReassemblyLimits {
+            max_sessions,
+            max_bytes,
+            ..
+        } = self.limits;
+        if max_sessions.is_none() && max_bytes.is_none() {
+            return;
+        }
+        let mut by_age = self
+            .fragments
+            .keys()
+            .map(|id| (self.last_fragment_at.get(id).copied(), *id))
+            .collect::<Vec<_>>();
+        by_age.sort_unstable();
+        let mut sessions = self.fragments.len();
+        let mut bytes = self.fragments.keys().map(|id| self.stored_bytes(id)).sum::<usize>();
+        for (_, id) in by_age {
+            let over_sessions = max_sessions.is_some_and(|max| sessions > max);
+            let over_bytes = max_bytes.is_some_and(|max| bytes > max);
+            if !over_sessions && !over_bytes {
+                break;
+            }
+            sessions -= 1;
+            bytes -= self.stored_bytes(&id);
+            self.evict(id);
+        }
+    }
+
+    fn stored_bytes(&self, communication_id: &(u64, NodeId)) -> usize {
+        self.fragments
+            .get(communication_id)
+            .map_or(0, |(_, fragments)| fragments.values().map(|f| f.data.len()).sum())
+    }
+
+    fn evict(&mut self, communication_id: (u64, NodeId)) {
+        let _ = self.fragments.remove(&communication_id);
+        let _ = self.last_fragment_at.remove(&communication_id);
+        let _ = self.session_timeouts.remove(&communication_id);
+        self.evicted.push(communication_id);
+        self.stats.sessions_evicted += 1;
+    }
+
+    /// Enables ordered delivery: a completed message is held back while a session
+    /// with a lower id from the same sender is still being assembled, or hasn't arrived
+    /// after the latest message released, for at most `hold`. Once the hold expires
+    /// the missing sessions are skipped. Held messages are returned by
+    /// [`Self::take_ready`] instead of [`Self::add_fragment`].
+    ///
+    /// Ordering relies on the sender allocating consecutive session ids to the messages
+    /// it sends to this node (see `RoutingHandler::set_sequential_session_ids`).
+    /// Passing `None` disables it; messages still held are released by the next `take_ready`.
+    pub fn set_ordered_delivery(&mut self, hold: Option<Duration>) {
+        self.ordered_hold = hold;
+    }
+
+    /// Defers the delivery of the completed messages while `defer` holds: they are
+    /// held instead of being returned by [`Self::add_fragment`], and [`Self::take_ready`]
+    /// returns nothing until the delivery is resumed. The processors defer it while
+    /// they handle a batch of packets, so that the messages completing together are
+    /// released by `take_ready` in a fair order.
+    pub fn defer_delivery(&mut self, defer: bool) {
+        self.deferred = defer;
+    }
+
+    /// Returns the held messages whose turn has come, as `(session_id, sender, data)`.
+    /// They are taken in rounds of one message per sender, by ascending sender id, and
+    /// by session for each sender, so that a sender completing many messages at once
+    /// doesn't delay the others and the order is reproducible.
+    pub fn take_ready(&mut self) -> Vec<(u64, NodeId, Vec<u8>)> {
+        if self.deferred {
+            return Vec::new();
+        }
+        let mut by_sender = BTreeMap::<NodeId, VecDeque<(u64, Vec<u8>)>>::new();
+        let mut blocked_sender = None;
+        let now = self.clock.now();
+        let keys = self.held.keys().copied().collect::<Vec<_>>();
+        for (sender, session_id) in keys {
+            if blocked_sender == Some(sender) {
+                continue;
+            }
+            let waited_enough = match (self.ordered_hold, self.held.get(&(sender, session_id))) {
+                (Some(hold), Some((at, _))) => now.duration_since(*at) >= hold,
+                _ => true,
+            };
+            let next = self.next_session.get(&sender).copied();
+            let gap = next.is_some_and(|next| session_id > next);
+            let lower_in_progress = self
+                .fragments
+                .keys()
+                .any(|(sid, from)| *from == sender && *sid < session_id);
+            if (gap || lower_in_progress) && !waited_enough {
+                blocked_sender = Some(sender);
+                continue;
+            }
+            if let Some((_, data)) = self.held.remove(&(sender, session_id)) {
+                let next = next.map_or(session_id + 1, |next| next.max(session_id + 1));
+                let _ = self.next_session.insert(sender, next);
+                by_sender.entry(sender).or_default().push_back((session_id, data));
+            }
+        }
+
+        let mut ready = Vec::new();
+        while !by_sender.is_empty() {
+            by_sender.retain(|sender, messages| {
+                if let Some((session_id, data)) = messages.pop_front() {
+                    ready.push((session_id, *sender, data));
+                }
+                !messages.is_empty()
+            });
+        }
+        ready
+    }
+
+    /// Returns how many fragments of `session_id` from `sender` were received out of
+    /// the announced total, `None` if the message isn't being assembled (not started,
+    /// delivered or evicted).
+    #[must_use]
+    pub fn progress(&self, session_id: u64, sender: NodeId) -> Option<(u64, u64)> {
+        let (total, fragments) = self.fragments.get(&(session_id, sender))?;
+        Some((fragments.range(..*total).count() as u64, *total))
+    }
+
+    /// Makes the timeouts of the assembler follow `clock`, e.g. the simulated clock of
+    /// a simulation.
+    pub fn set_clock(&mut self, clock: Clock) {
+        self.clock = clock;
+    }
+
+    /// Tells `observer` about every new fragment of a message, `None` to stop.
+    pub fn set_progress_observer(&mut self, observer: Option<SharedProgressObserver>) {
+        self.progress_observer = observer;
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> AssemblerStats {
+        self.stats
+    }
+
+    /// Checks that every fragment being assembled fits in its announced total.
+    #[must_use]
+    pub fn selfcheck(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        for ((session_id, sender), (total, fragments)) in &self.fragments {
+            for f in fragments.values().filter(|f| f.fragment_index >= *total) {
+                diagnostics.push(Diagnostic::FragmentOutOfRange {
+                    session_id: *session_id,
+                    sender: *sender,
+                    fragment_index: f.fragment_index,
+                    total: *total,
+                });
+            }
+        }
+        diagnostics
+    }
+
+    /// Stores `fragment` of `session_id` from `sender`, in any order, and returns the
+    /// message once every index below the announced total was received.
+    /// Each fragment contributes its own `length`, so senders may use any fragment size.
+    /// Duplicate fragments and fragments of an already delivered message are counted and ignored.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(
+            level = "trace",
+            skip_all,
+            fields(
+                session_id = session_id,
+                fragment_index = fragment.fragment_index,
+                from = sender
+            )
+        )
+    )]
     pub fn add_fragment(&mut self, fragment: Fragment, session_id: u64, sender: NodeId) -> Option<Vec<u8>> {
+        if is_reserved_control_fragment(&fragment) {
+            return None; // not part of a message, see `Processor::handle_control_fragment`
+        }
         let communication_id = ( session_id, sender );
-        if let Some((_, fragments)) = self.fragments.get_mut(&communication_id) {
-            if fragments.iter().any(|f| f.fragment_index == fragment.fragment_index) {
+        self.forget_expired_completions();
+        if let Some((_, duplicate_seen)) = self.completed.get_mut(&communication_id) {
+            self.stats.duplicate_fragments += 1;
+            if !*duplicate_seen {
+                *duplicate_seen = true;
+                self.stats.duplicate_messages += 1;
+            }
+            return None; // message already delivered
+        }
+
+        // the total announced by the first fragment received holds for the session
+        let (total, fragments) = self
+            .fragments
+            .entry(communication_id)
+            .or_insert_with(|| (fragment.total_n_fragments, BTreeMap::new()));
+        let fragment_index = fragment.fragment_index;
+        match fragments.entry(fragment_index) {
+            Entry::Occupied(_) => {
+                self.stats.duplicate_fragments += 1;
                 return None; // duplicate fragment
             }
-            fragments.push(fragment);
-        } else {
-            self.fragments.insert(communication_id, (fragment.total_n_fragments, vec![fragment]));
+            Entry::Vacant(entry) => {
+                let _ = entry.insert(fragment);
+            }
         }
+        let _ = self.last_fragment_at.insert(communication_id, self.clock.now());
 
-        let (total, fragments) = self.fragments.get_mut(&communication_id)?;
-        // check if all fragments has been received
-        if *total == fragments.len() as u64 {
-            let fragments = self.fragments.get_mut(&communication_id)?;
-            fragments.1.sort_by(|t, n| t.fragment_index.cmp(&n.fragment_index));
+        // fragments past the total are kept for `selfcheck` but never assembled
+        let total = *total;
+        let received = fragments.range(..total).count() as u64;
+        if let Some(observer) = &self.progress_observer
+            && let Ok(mut observer) = observer.lock()
+            && fragment_index < total
+        {
+            observer.on_progress(session_id, sender, received, total);
+        }
+        if total > 0 && received == total {
+            // the length of each fragment frames its data, the rest is padding
             let mut data = vec![];
-            for f in &fragments.1 {
-                data.extend_from_slice(&f.data);
-            }
-            if let Some(pos) = data.iter().position(|&b| b == 0) {
-                data.truncate(pos);
+            for (_, f) in fragments.range(..total) {
+                let length = usize::from(f.length).min(f.data.len());
+                data.extend_from_slice(&f.data[..length]);
             }
 
             let _ = self.fragments.remove(&communication_id);
+            let _ = self.last_fragment_at.remove(&communication_id);
+            let _ = self.session_timeouts.remove(&communication_id);
+            self.remember_completion(communication_id);
+            self.stats.messages_delivered += 1;
+            #[cfg(feature = "telemetry")]
+            tracing::debug!(bytes = data.len(), "message reassembled");
+            if self.ordered_hold.is_some() || self.deferred {
+                let _ = self.held.insert((sender, session_id), (self.clock.now(), data));
+                return None;
+            }
             return Some(data);
         }
+        self.enforce_caps();
         None
     }
+
+    fn remember_completion(&mut self, communication_id: (u64, NodeId)) {
+        if self.dedup_capacity == 0 {
+            return;
+        }
+        if self.completed_order.len() == self.dedup_capacity {
+            if let Some(oldest) = self.completed_order.pop_front() {
+                let _ = self.completed.remove(&oldest);
+            }
+        }
+        if let Vacant(entry) = self.completed.entry(communication_id) {
+            let _ = entry.insert((self.clock.now(), false));
+            self.completed_order.push_back(communication_id);
+        }
+    }
+
+    fn forget_expired_completions(&mut self) {
+        let now = self.clock.now();
+        while let Some(oldest) = self.completed_order.front() {
+            match self.completed.get(oldest) {
+                Some((at, _)) if now.duration_since(*at) <= self.dedup_window => break,
+                _ => {
+                    let _ = self.completed.remove(oldest);
+                    let _ = self.completed_order.pop_front();
+                }
+            }
+        }
+    }
 }
 
+#[cfg(test)]
+mod assembler_tests {
+    use super::*;
+
+    fn fragment(index: u64, total: u64, byte: u8) -> Fragment {
+        Fragment::new(index, total, [byte; 128])
+    }
+
+    #[test]
+    /// Tests that a retransmitted session is not delivered twice
+    fn test_duplicate_session_suppressed() {
+        let mut assembler = FragmentAssembler::default();
+        assert!(assembler.add_fragment(fragment(0, 1, 7), 10, 2).is_some());
+        assert!(assembler.add_fragment(fragment(0, 1, 7), 10, 2).is_none());
+        assert!(assembler.add_fragment(fragment(0, 1, 7), 10, 3).is_some());
+
+        let stats = assembler.stats();
+        assert_eq!(stats.messages_delivered, 2);
+        assert_eq!(stats.duplicate_messages, 1);
+        assert_eq!(stats.duplicate_fragments, 1);
+    }
+
+    #[test]
+    /// Tests that fragments shorter than the wire size are joined by their lengths
+    fn test_small_fragments() {
+        let mut assembler = FragmentAssembler::default();
+        let mut first = fragment(0, 2, 1);
+        first.length = 40;
+        let mut last = fragment(1, 2, 2);
+        last.length = 3;
+        assert!(assembler.add_fragment(last, 5, 2).is_none());
+        let data = assembler.add_fragment(first, 5, 2).unwrap();
+        assert_eq!(data.len(), 43);
+        assert_eq!(&data[38..], &[1, 1, 2, 2, 2]);
+    }
+
+    #[test]
+    /// Tests that fragments arriving out of order are assembled by index
+    fn test_out_of_order_fragments() {
+        let mut assembler = FragmentAssembler::default();
+        assert!(assembler.add_fragment(fragment(2, 3, 3), 5, 2).is_none());
+        assert!(assembler.add_fragment(fragment(0, 3, 1), 5, 2).is_none());
+        let data = assembler.add_fragment(fragment(1, 3, 2), 5, 2).unwrap();
+        assert_eq!(data.len(), 3 * 128);
+        assert!(data[..128].iter().all(|&b| b == 1));
+        assert!(data[128..256].iter().all(|&b| b == 2));
+        assert!(data[256..].iter().all(|&b| b == 3));
+        assert!(assembler.fragments.is_empty());
+    }
+
+    #[test]
+    /// Tests that binary data containing zeros is trimmed by the fragment lengths only
+    fn test_length_framing() {
+        let mut assembler = FragmentAssembler::default();
+        let mut last = fragment(1, 2, 0);
+        last.data[..3].copy_from_slice(&[0, 5, 0]);
+        last.length = 3;
+        assert!(assembler.add_fragment(fragment(0, 2, 0), 5, 2).is_none());
+        let data = assembler.add_fragment(last, 5, 2).unwrap();
+        assert_eq!(data.len(), 128 + 3);
+        assert_eq!(data[128..], [0, 5, 0]);
+    }
+
+    #[test]
+    /// Tests that duplicate and out-of-range fragments don't complete a message
+    fn test_duplicate_fragments() {
+        let mut assembler = FragmentAssembler::default();
+        assert!(assembler.add_fragment(fragment(0, 2, 1), 5, 2).is_none());
+        assert!(assembler.add_fragment(fragment(0, 2, 9), 5, 2).is_none());
+        assert!(assembler.add_fragment(fragment(3, 2, 1), 5, 2).is_none());
+        assert_eq!(assembler.stats().duplicate_fragments, 1);
+
+        let data = assembler.add_fragment(fragment(1, 2, 2), 5, 2).unwrap();
+        assert!(data[..128].iter().all(|&b| b == 1));
+        assert!(assembler.add_fragment(fragment(1, 2, 2), 5, 2).is_none());
+        assert_eq!(assembler.stats().duplicate_fragments, 2);
+        assert_eq!(assembler.stats().messages_delivered, 1);
+    }
+
+    #[test]
+    /// Tests that a message is held back until lower sessions of its sender complete
+    fn test_ordered_delivery() {
+        let mut assembler = FragmentAssembler::default();
+        assembler.set_ordered_delivery(Some(Duration::from_mins(1)));
+
+        assert!(assembler.add_fragment(fragment(0, 2, 1), 5, 2).is_none());
+        assert!(assembler.add_fragment(fragment(0, 1, 2), 6, 2).is_none());
+        assert!(assembler.add_fragment(fragment(0, 1, 3), 9, 3).is_none());
+        let ready = assembler.take_ready();
+        assert_eq!(ready.len(), 1);
+        assert_eq!((ready[0].0, ready[0].1), (9, 3));
+
+        assert!(assembler.add_fragment(fragment(1, 2, 1), 5, 2).is_none());
+        let ready = assembler
+            .take_ready()
+            .into_iter()
+            .map(|(sid, from, _)| (sid, from))
+            .collect::<Vec<_>>();
+        assert_eq!(ready, vec![(5, 2), (6, 2)]);
+    }
+
+    #[test]
+    /// Tests that messages completing together are delivered round-robin by sender
+    fn test_fair_delivery() {
+        let mut assembler = FragmentAssembler::default();
+        assembler.set_ordered_delivery(Some(Duration::ZERO));
+        for (session_id, sender) in [(7, 2), (10, 3), (5, 2), (6, 2), (9, 3)] {
+            assert!(assembler.add_fragment(fragment(0, 1, 1), session_id, sender).is_none());
+        }
+        let ready = assembler
+            .take_ready()
+            .into_iter()
+            .map(|(sid, from, _)| (sid, from))
+            .collect::<Vec<_>>();
+        assert_eq!(ready, vec![(5, 2), (9, 3), (6, 2), (10, 3), (7, 2)]);
+    }
+
+    #[test]
+    /// Tests that held messages are released once the hold time expires
+    fn test_ordered_delivery_timeout() {
+        let mut assembler = FragmentAssembler::default();
+        assembler.set_ordered_delivery(Some(Duration::ZERO));
+        assert!(assembler.add_fragment(fragment(0, 2, 1), 5, 2).is_none());
+        assert!(assembler.add_fragment(fragment(0, 1, 2), 6, 2).is_none());
+        assert_eq!(assembler.take_ready().len(), 1);
+    }
+
+    #[test]
+    /// Tests that a session missing from the sequence of a sender is skipped once the
+    /// hold time expires
+    fn test_ordered_delivery_gap() {
+        let clock = Clock::simulated();
+        let mut assembler = FragmentAssembler::default();
+        assembler.set_clock(clock.clone());
+        assembler.set_ordered_delivery(Some(Duration::from_secs(1)));
+        assert!(assembler.add_fragment(fragment(0, 1, 1), 5, 2).is_none());
+        assert_eq!(assembler.take_ready().len(), 1);
+
+        // session 6 never arrives
+        assert!(assembler.add_fragment(fragment(0, 1, 1), 8, 2).is_none());
+        assert!(assembler.add_fragment(fragment(0, 1, 1), 7, 2).is_none());
+        assert!(assembler.take_ready().is_empty());
+        clock.advance(Duration::from_secs(1));
+        let ready = assembler
+            .take_ready()
+            .into_iter()
+            .map(|(sid, from, _)| (sid, from))
+            .collect::<Vec<_>>();
+        assert_eq!(ready, vec![(7, 2), (8, 2)]);
+
+        assert!(assembler.add_fragment(fragment(0, 1, 1), 9, 2).is_none());
+        assert_eq!(assembler.take_ready().len(), 1);
+    }
+
+    #[test]
+    /// Tests that idle and excess incomplete messages are evicted and reported
+    fn test_reassembly_limits() {
+        let mut assembler = FragmentAssembler::default();
+        assert!(assembler.add_fragment(fragment(0, 2, 1), 5, 2).is_none());
+        assert!(assembler.add_fragment(fragment(0, 2, 1), 6, 2).is_none());
+        assembler.set_reassembly_limits(ReassemblyLimits {
+            max_sessions: Some(1),
+            ..ReassemblyLimits::default()
+        });
+        assert!(assembler.add_fragment(fragment(0, 2, 1), 7, 3).is_none());
+        assert_eq!(assembler.evict_stale(), vec![(5, 2), (6, 2)]);
+        assert!(assembler.evict_stale().is_empty());
+
+        assembler.set_reassembly_limits(ReassemblyLimits {
+            max_bytes: Some(2 * 128),
+            ..ReassemblyLimits::default()
+        });
+        assert!(assembler.add_fragment(fragment(0, 3, 1), 8, 3).is_none());
+        assert!(assembler.add_fragment(fragment(1, 3, 1), 8, 3).is_none());
+        assert_eq!(assembler.evict_stale(), vec![(7, 3)]);
+
+        assembler.set_reassembly_limits(ReassemblyLimits {
+            timeout_ms: Some(0),
+            ..ReassemblyLimits::default()
+        });
+        assert_eq!(assembler.evict_stale(), vec![(8, 3)]);
+        assert!(assembler.fragments.is_empty());
+        assert_eq!(assembler.stats().sessions_evicted, 4);
+        // a message evicted can still be completed by a full retransmission
+        assert!(assembler.add_fragment(fragment(0, 1, 1), 8, 3).is_some());
+
+        // a message with its own timeout doesn't wait for the one of the limits
+        assembler.set_reassembly_limits(ReassemblyLimits::default());
+        assert!(assembler.add_fragment(fragment(0, 2, 1), 9, 3).is_none());
+        assert!(assembler.add_fragment(fragment(0, 2, 1), 10, 3).is_none());
+        assembler.set_session_timeout(9, 3, Some(Duration::ZERO));
+        assert_eq!(assembler.evict_stale(), vec![(9, 3)]);
+        assembler.set_reassembly_limits(ReassemblyLimits {
+            timeout_ms: Some(0),
+            ..ReassemblyLimits::default()
+        });
+        assembler.set_session_timeout(10, 3, Some(Duration::from_hours(1)));
+        assert!(assembler.evict_stale().is_empty());
+        assembler.set_session_timeout(10, 3, None);
+        assert_eq!(assembler.evict_stale(), vec![(10, 3)]);
+    }
+
+    #[derive(Debug, Default)]
+    struct Recorder(Vec<(u64, u64)>);
+
+    impl ProgressObserver for Recorder {
+        fn on_progress(&mut self, _session_id: u64, _sender: NodeId, received: u64, total: u64) {
+            self.0.push((received, total));
+        }
+    }
+
+    #[test]
+    /// Tests that the progress of a message is reported fragment by fragment
+    fn test_progress() {
+        let mut assembler = FragmentAssembler::default();
+        let recorder = Arc::new(Mutex::new(Recorder::default()));
+        assembler.set_progress_observer(Some(recorder.clone()));
+        assert_eq!(assembler.progress(5, 2), None);
+        assert!(assembler.add_fragment(fragment(2, 3, 1), 5, 2).is_none());
+        assert!(assembler.add_fragment(fragment(2, 3, 1), 5, 2).is_none());
+        assert!(assembler.add_fragment(fragment(7, 3, 1), 5, 2).is_none());
+        assert_eq!(assembler.progress(5, 2), Some((1, 3)));
+        assert!(assembler.add_fragment(fragment(0, 3, 1), 5, 2).is_none());
+        assert!(assembler.add_fragment(fragment(1, 3, 1), 5, 2).is_some());
+        assert_eq!(assembler.progress(5, 2), None);
+        assert_eq!(recorder.lock().unwrap().0, vec![(1, 3), (2, 3), (3, 3)]);
+    }
+
+    #[test]
+    /// Tests that fragments past the announced total are reported
+    fn test_selfcheck() {
+        let mut assembler = FragmentAssembler::default();
+        assert!(assembler.add_fragment(fragment(0, 3, 1), 5, 2).is_none());
+        assert!(assembler.selfcheck().is_empty());
+
+        assert!(assembler.add_fragment(fragment(4, 3, 1), 5, 2).is_none());
+        assert_eq!(
+            assembler.selfcheck(),
+            vec![Diagnostic::FragmentOutOfRange {
+                session_id: 5,
+                sender: 2,
+                fragment_index: 4,
+                total: 3
+            }]
+        );
+    }
+
+    #[test]
+    /// Tests that completed sessions are forgotten after the window or past the capacity
+    fn test_dedup_bounds() {
+        let mut assembler = FragmentAssembler::new(Duration::ZERO, 8);
+        assert!(assembler.add_fragment(fragment(0, 1, 7), 10, 2).is_some());
+        std::thread::sleep(Duration::from_millis(2));
+        assert!(assembler.add_fragment(fragment(0, 1, 7), 10, 2).is_some());
+
+        let mut assembler = FragmentAssembler::new(Duration::from_mins(1), 1);
+        assert!(assembler.add_fragment(fragment(0, 1, 7), 10, 2).is_some());
+        assert!(assembler.add_fragment(fragment(0, 1, 7), 11, 2).is_some());
+        assert!(assembler.add_fragment(fragment(0, 1, 7), 10, 2).is_some());
+    }
+}
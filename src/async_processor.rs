@@ -0,0 +1,301 @@
+use crate::{
+    FragmentAssembler, RoutingHandler,
+    discovery::ServiceDiscovery,
+    metrics::Metrics,
+    negotiation::VersionNegotiator,
+    network::NetworkError,
+    node_state::NodeState,
+    packet_processor::{
+        NodeCore, SELFCHECK_INTERVAL, TICK_INTERVAL, combined_metrics, combined_stats,
+        deliver_ready, discover_servers, dispatch_packet, graceful_shutdown,
+    },
+    selfcheck::check_node,
+    transform::{PacketDirection, SharedMiddleware, run_middlewares},
+    types::{Command, NodeCommand, NodeStats, TerminationReason},
+};
+use std::future::Future;
+use tokio::sync::mpsc::Receiver;
+use tokio::time::{MissedTickBehavior, interval};
+use wg_internal::{network::NodeId, packet::Packet};
+
+/// Counterpart of [`Processor`](crate::Processor) fed by tokio channels, so that nodes
+/// can run as tasks of an async application instead of owning an OS thread each.
+///
+/// Packets are handled exactly as by `Processor::handle_packet`; the routing handler
+/// still talks to neighbors and controller through its crossbeam senders, which never block.
+pub trait AsyncProcessor: Send {
+    /// Returns the command and packet receivers of the node, borrowed together so that
+    /// [`run`](Self::run) can wait on both.
+    fn receivers(&mut self) -> (&mut Receiver<Box<dyn Command>>, &mut Receiver<Packet>);
+    fn assembler(&mut self) -> &mut FragmentAssembler;
+    fn routing_handler(&mut self) -> &mut RoutingHandler;
+    /// Persistent state of the node, see `Processor::node_state`.
+    fn node_state(&self) -> Option<&NodeState> {
+        None
+    }
+    /// Protocol version handshake of the node, see `Processor::version_negotiator`.
+    fn version_negotiator(&mut self) -> Option<&mut VersionNegotiator> {
+        None
+    }
+    /// Server discovery of the node, see `Processor::service_discovery`.
+    fn service_discovery(&mut self) -> Option<&mut ServiceDiscovery> {
+        None
+    }
+
+    /// Middleware chain of the node, see `Processor::middlewares`.
+    fn middlewares(&mut self) -> &mut Vec<SharedMiddleware> {
+        self.routing_handler().middlewares()
+    }
+
+    fn handle_msg(&mut self, msg: Vec<u8>, from: NodeId, session_id: u64);
+    /// See `Processor::handle_control_fragment`.
+    fn handle_control_fragment(&mut self, _payload: Vec<u8>, _from: NodeId, _session_id: u64) {}
+    fn handle_command(&mut self, cmd: Box<dyn Command>) -> bool;
+
+    /// Handles a packet in a standard way
+    /// # Errors
+    /// returns an Errors if handling fails
+    fn handle_packet(&mut self, pkt: Packet) -> Result<(), NetworkError> {
+        dispatch_packet(&mut AsyncNode(self), pkt)
+    }
+
+    /// Hands the messages released by ordered delivery over to `handle_msg`.
+    fn deliver_ready_messages(&mut self) {
+        deliver_ready(&mut AsyncNode(self));
+    }
+
+    /// Periodic housekeeping, called by [`run`](Self::run) every `TICK_INTERVAL`.
+    fn tick(&mut self) {
+        let _ = self.routing_handler().tick();
+        discover_servers(&mut AsyncNode(self));
+        let _ = self.assembler().evict_stale();
+        self.deliver_ready_messages();
+    }
+
+    /// Returns the statistics of the node, combining routing and assembler counters.
+    fn node_stats(&mut self) -> NodeStats {
+        combined_stats(&mut AsyncNode(self))
+    }
+
+    /// See `Processor::metrics`.
+    fn metrics(&mut self) -> Metrics {
+        combined_metrics(&mut AsyncNode(self))
+    }
+
+    /// See `Processor::answer_query`.
+    fn answer_query(&mut self, cmd: &dyn Command) -> bool {
+        match cmd.as_any().downcast_ref::<NodeCommand>() {
+            Some(NodeCommand::QueryTopology(reply)) => {
+                let _ = reply.send(self.routing_handler().network_view().clone());
+            }
+            Some(NodeCommand::QueryStats(reply)) => {
+                let _ = reply.send(self.node_stats());
+            }
+            Some(NodeCommand::QueryMetrics(reply)) => {
+                let _ = reply.send(self.metrics());
+            }
+            Some(NodeCommand::QueryBlacklist(reply)) => {
+                let _ = reply.send(self.routing_handler().blacklist());
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Runs the node until it is shut down, fails or one of its channels closes, then
+    /// notifies the controller with a `NodeEvent::Terminated`. Unlike `Processor::run`,
+    /// panics are not caught: they surface through the `JoinHandle` of the task.
+    fn run(&mut self) -> impl Future<Output = ()> + Send
+    where
+        Self: Sized,
+    {
+        async move {
+            let reason = self.event_loop().await;
+            if let Some(state) = self.node_state().cloned() {
+                let _ = self.routing_handler().save_buffer(&state);
+            }
+            let stats = self.node_stats();
+            self.routing_handler().notify_terminated(reason, stats);
+        }
+    }
+
+    /// Processes commands and packets until the node has to stop, returning why.
+    fn event_loop(&mut self) -> impl Future<Output = TerminationReason> + Send
+    where
+        Self: Sized,
+    {
+        async move {
+            let _ = check_node(&mut AsyncNode(self));
+            let _ = self.routing_handler().start_flood(None);
+            if let Some(state) = self.node_state().cloned() {
+                let _ = self.routing_handler().load_buffer(&state);
+            }
+            let mut ticks = interval(TICK_INTERVAL);
+            ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            let mut selfchecks = interval(SELFCHECK_INTERVAL);
+            selfchecks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // both intervals fire immediately, the startup work above covers that
+            ticks.tick().await;
+            selfchecks.tick().await;
+            loop {
+                let (commands, packets) = self.receivers();
+                tokio::select! {
+                    biased;
+
+                    cmd = commands.recv() => {
+                        let Some(cmd) = cmd else {
+                            return TerminationReason::Error("command channel closed".to_string());
+                        };
+                        if let Some(grace) = graceful_shutdown(cmd.as_ref()) {
+                            self.routing_handler().begin_shutdown(grace);
+                        } else if !self.answer_query(cmd.as_ref()) && self.handle_command(cmd) {
+                            return TerminationReason::Shutdown;
+                        }
+                    }
+
+                    pkt = packets.recv() => {
+                        let Some(pkt) = pkt else {
+                            return TerminationReason::Error("packet channel closed".to_string());
+                        };
+                        let pkt =
+                            run_middlewares(self.middlewares(), pkt, PacketDirection::Received);
+                        if let Some(Err(e)) = pkt.map(|pkt| self.handle_packet(pkt)) {
+                            return TerminationReason::Error(e.to_string());
+                        }
+                    }
+
+                    _ = ticks.tick() => self.tick(),
+
+                    _ = selfchecks.tick() => {
+                        let _ = check_node(&mut AsyncNode(self));
+                    }
+                }
+                if self.routing_handler().poll_shutdown() {
+                    return TerminationReason::Shutdown;
+                }
+            }
+        }
+    }
+}
+
+/// [`NodeCore`] view of an [`AsyncProcessor`].
+struct AsyncNode<'a, P: ?Sized>(&'a mut P);
+
+impl<P: AsyncProcessor + ?Sized> NodeCore for AsyncNode<'_, P> {
+    fn assembler(&mut self) -> &mut FragmentAssembler {
+        self.0.assembler()
+    }
+    fn routing_handler(&mut self) -> &mut RoutingHandler {
+        self.0.routing_handler()
+    }
+    fn version_negotiator(&mut self) -> Option<&mut VersionNegotiator> {
+        self.0.version_negotiator()
+    }
+    fn service_discovery(&mut self) -> Option<&mut ServiceDiscovery> {
+        self.0.service_discovery()
+    }
+    fn handle_msg(&mut self, msg: Vec<u8>, from: NodeId, session_id: u64) {
+        self.0.handle_msg(msg, from, session_id);
+    }
+    fn handle_control_fragment(&mut self, payload: Vec<u8>, from: NodeId, session_id: u64) {
+        self.0.handle_control_fragment(payload, from, session_id);
+    }
+    fn deliver_ready_messages(&mut self) {
+        self.0.deliver_ready_messages();
+    }
+}
+
+#[cfg(test)]
+mod async_processor_tests {
+    use super::*;
+    use crate::types::{Event, NodeEvent};
+    use crossbeam_channel::unbounded;
+    use std::collections::HashMap;
+    use tokio::sync::mpsc::{Sender, channel};
+    use wg_internal::network::SourceRoutingHeader;
+    use wg_internal::packet::{Fragment, NodeType};
+
+    struct TestNode {
+        controller_recv: Receiver<Box<dyn Command>>,
+        packet_recv: Receiver<Packet>,
+        assembler: FragmentAssembler,
+        router: RoutingHandler,
+        messages: Vec<Vec<u8>>,
+    }
+
+    impl AsyncProcessor for TestNode {
+        fn receivers(&mut self) -> (&mut Receiver<Box<dyn Command>>, &mut Receiver<Packet>) {
+            (&mut self.controller_recv, &mut self.packet_recv)
+        }
+        fn assembler(&mut self) -> &mut FragmentAssembler {
+            &mut self.assembler
+        }
+        fn routing_handler(&mut self) -> &mut RoutingHandler {
+            &mut self.router
+        }
+        fn handle_msg(&mut self, msg: Vec<u8>, _from: NodeId, _session_id: u64) {
+            self.messages.push(msg);
+        }
+        fn handle_command(&mut self, _cmd: Box<dyn Command>) -> bool {
+            true
+        }
+    }
+
+    #[tokio::test]
+    /// Tests that the async loop handles packets until shut down, then reports it
+    async fn test_run() {
+        let (controller_send, controller_recv) = unbounded::<Box<dyn Event>>();
+        let (cmd_send, cmd_recv): (Sender<Box<dyn Command>>, _) = channel(8);
+        let (packet_send, packet_recv) = channel(8);
+        let mut router = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_send, neighbor_recv) = unbounded();
+        router.add_neighbor(2, neighbor_send);
+        let mut node = TestNode {
+            controller_recv: cmd_recv,
+            packet_recv,
+            assembler: FragmentAssembler::default(),
+            router,
+            messages: Vec::new(),
+        };
+
+        let mut data = [0; 128];
+        data[..2].copy_from_slice(b"hi");
+        let fragment = Fragment {
+            fragment_index: 0,
+            total_n_fragments: 1,
+            length: 2,
+            data,
+        };
+        let header = SourceRoutingHeader::new(vec![2, 1], 1);
+        packet_send
+            .send(Packet::new_fragment(header, 9, fragment))
+            .await
+            .unwrap();
+        let task = tokio::spawn(async move {
+            node.run().await;
+            node
+        });
+        // the flood request sent at startup, then the Ack of the fragment
+        while neighbor_recv.len() < 2 {
+            tokio::task::yield_now().await;
+        }
+        cmd_send.send(Box::new(())).await.unwrap();
+        let node = task.await.unwrap();
+
+        assert_eq!(node.messages, vec![b"hi".to_vec()]);
+        let event = controller_recv
+            .try_iter()
+            .last()
+            .unwrap()
+            .into_any()
+            .downcast::<NodeEvent>()
+            .unwrap();
+        assert!(matches!(
+            *event,
+            NodeEvent::Terminated {
+                reason: TerminationReason::Shutdown,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,159 @@
+//! Throughput harness measuring the transport of this crate on an in-process network,
+//! so that routing and assembler changes can be compared with local runs.
+//!
+//! The network is stepped in rounds, the simulated clock of the harness: every round
+//! each packet in flight moves one hop. Drones are simulated after the protocol
+//! specification, dropping fragments with the given loss rate and answering with a
+//! `Nack`, so the retransmissions of the routing handler are exercised.
+
+use crate::network::{NetworkError, TopologyError};
+use crate::simulation::MockNetwork;
+use std::time::{Duration, Instant};
+use wg_internal::config::Config;
+
+pub use crate::simulation::MAX_ROUNDS;
+
+/// Messages sent by [`run_throughput_test`].
+pub const THROUGHPUT_MESSAGES: usize = 100;
+
+/// Outcome of a [`run_throughput_test`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThroughputReport {
+    pub messages_sent: usize,
+    pub messages_delivered: usize,
+    /// Fragments the messages are split into
+    pub fragments_needed: u64,
+    /// Fragments sent by the source, retransmissions included
+    pub fragments_sent: u64,
+    /// Rounds of the simulated clock until the last message was delivered
+    pub rounds: u64,
+    /// Wall time spent sending and delivering the messages
+    pub elapsed: Duration,
+}
+
+impl ThroughputReport {
+    #[must_use]
+    pub fn messages_per_sec(&self) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        let messages = self.messages_delivered as f64;
+        messages / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    /// Returns the fragments retransmitted per fragment needed.
+    #[must_use]
+    pub fn retransmit_overhead(&self) -> f64 {
+        if self.fragments_needed == 0 {
+            return 0.0;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let overhead = self.fragments_sent.saturating_sub(self.fragments_needed) as f64
+            / self.fragments_needed as f64;
+        overhead
+    }
+}
+
+/// Sends [`THROUGHPUT_MESSAGES`] messages of `payload_size` bytes from the first client
+/// of `topology` to its first server, every drone losing fragments with `loss_rate`
+/// instead of its configured PDR. Floods run before the measure starts.
+/// # Errors
+/// `TopologyError` if `topology` has no client or no server,
+/// or any error returned while sending the messages.
+pub fn run_throughput_test(
+    topology: &Config,
+    payload_size: usize,
+    loss_rate: f32,
+) -> Result<ThroughputReport, NetworkError> {
+    let (Some(source), Some(destination)) = (topology.client.first(), topology.server.first())
+    else {
+        return Err(TopologyError::Invalid.into());
+    };
+    let (source, destination) = (source.id, destination.id);
+    let mut network = MockNetwork::new(topology, loss_rate);
+
+    for endpoint in network.endpoints.values_mut() {
+        endpoint.router.start_flood(None)?;
+    }
+    let _ = network.run_until(|_| false);
+
+    let payload = vec![0xA5; payload_size];
+    let start = Instant::now();
+    for _ in 0..THROUGHPUT_MESSAGES {
+        if let Some(endpoint) = network.endpoints.get_mut(&source) {
+            endpoint
+                .router
+                .send_message(&payload, Some(destination), None)?;
+        }
+    }
+    let rounds = network.run_until(|network| {
+        network
+            .endpoints
+            .get(&destination)
+            .is_some_and(|endpoint| endpoint.received.len() >= THROUGHPUT_MESSAGES)
+    });
+
+    Ok(ThroughputReport {
+        messages_sent: THROUGHPUT_MESSAGES,
+        messages_delivered: network.received(destination).len(),
+        fragments_needed: (THROUGHPUT_MESSAGES * payload_size.div_ceil(128)) as u64,
+        fragments_sent: network
+            .fragments_from
+            .get(&source)
+            .copied()
+            .unwrap_or_default(),
+        rounds,
+        elapsed: start.elapsed(),
+    })
+}
+
+#[cfg(test)]
+mod bench_tests {
+    use super::*;
+    use wg_internal::config::{Client, Drone, Server};
+
+    fn line() -> Config {
+        Config {
+            drone: vec![
+                Drone {
+                    id: 2,
+                    connected_node_ids: vec![1, 3],
+                    pdr: 0.0,
+                },
+                Drone {
+                    id: 3,
+                    connected_node_ids: vec![2, 4],
+                    pdr: 0.0,
+                },
+            ],
+            client: vec![Client {
+                id: 1,
+                connected_drone_ids: vec![2],
+            }],
+            server: vec![Server {
+                id: 4,
+                connected_drone_ids: vec![3],
+            }],
+        }
+    }
+
+    #[test]
+    /// Tests that every message is delivered, with retransmissions only on a lossy network
+    fn test_throughput() {
+        let report = run_throughput_test(&line(), 300, 0.0).unwrap();
+        assert_eq!(report.messages_delivered, THROUGHPUT_MESSAGES);
+        assert_eq!(report.fragments_needed, 3 * THROUGHPUT_MESSAGES as u64);
+        assert_eq!(report.fragments_sent, report.fragments_needed);
+        assert!(report.retransmit_overhead().abs() < f64::EPSILON);
+
+        let lossy = run_throughput_test(&line(), 300, 0.3).unwrap();
+        assert_eq!(lossy.messages_delivered, THROUGHPUT_MESSAGES);
+        assert!(lossy.retransmit_overhead() > 0.0);
+        assert!(lossy.rounds > report.rounds);
+
+        let mut no_server = line();
+        no_server.server.clear();
+        assert!(matches!(
+            run_throughput_test(&no_server, 300, 0.0),
+            Err(NetworkError::Topology(TopologyError::Invalid))
+        ));
+    }
+}
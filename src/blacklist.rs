@@ -0,0 +1,172 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use wg_internal::network::NodeId;
+
+/// Drones blacklisted automatically by a `RoutingHandler`, see
+/// `RoutingHandler::set_auto_blacklist`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoBlacklist {
+    /// `Dropped` Nacks a drone may send within `window` before being blacklisted
+    pub max_drops: u32,
+    pub window: Duration,
+    /// How long the drone stays blacklisted
+    pub duration: Duration,
+}
+
+impl Default for AutoBlacklist {
+    fn default() -> Self {
+        Self {
+            max_drops: 5,
+            window: Duration::from_secs(10),
+            duration: Duration::from_mins(1),
+        }
+    }
+}
+
+/// Nodes that routes must not go through until their entry expires.
+///
+/// Entries are added by hand with [`insert`](Self::insert) or, with an
+/// [`AutoBlacklist`], for drones answering too many fragments with a `Dropped` Nack
+/// (see [`record_drop`](Self::record_drop)).
+#[derive(Debug, Clone, Default)]
+pub struct Blacklist {
+    // blacklisted node -> end of its blacklisting
+    until: HashMap<NodeId, Instant>,
+    auto: Option<AutoBlacklist>,
+    // recent `Dropped` Nacks by drone, oldest first
+    drops: HashMap<NodeId, VecDeque<Instant>>,
+}
+
+impl Blacklist {
+    /// Blacklists `node` for `duration` from `now`, extending a longer blacklisting
+    /// only. Returns whether it wasn't blacklisted.
+    pub fn insert(&mut self, node: NodeId, duration: Duration, now: Instant) -> bool {
+        let until = now + duration;
+        match self.until.get_mut(&node) {
+            Some(current) if *current > now => {
+                *current = (*current).max(until);
+                false
+            }
+            _ => {
+                let _ = self.until.insert(node, until);
+                true
+            }
+        }
+    }
+
+    /// Lifts the blacklisting of `node`, returns whether it was blacklisted.
+    pub fn remove(&mut self, node: NodeId) -> bool {
+        let _ = self.drops.remove(&node);
+        self.until.remove(&node).is_some()
+    }
+
+    #[must_use]
+    pub fn contains(&self, node: NodeId, now: Instant) -> bool {
+        self.until.get(&node).is_some_and(|until| *until > now)
+    }
+
+    /// Returns the nodes blacklisted at `now` with the time left, sorted by id.
+    #[must_use]
+    pub fn entries(&self, now: Instant) -> Vec<(NodeId, Duration)> {
+        let mut entries = self
+            .until
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(node, until)| (*node, until.duration_since(now)))
+            .collect::<Vec<_>>();
+        entries.sort_unstable();
+        entries
+    }
+
+    /// Forgets the entries expired at `now` and returns their nodes, sorted by id.
+    pub fn purge_expired(&mut self, now: Instant) -> Vec<NodeId> {
+        let mut expired = Vec::new();
+        self.until.retain(|node, until| {
+            let keep = *until > now;
+            if !keep {
+                expired.push(*node);
+            }
+            keep
+        });
+        if let Some(auto) = self.auto {
+            self.drops.retain(|_, drops| {
+                drops.retain(|at| now.duration_since(*at) < auto.window);
+                !drops.is_empty()
+            });
+        }
+        expired.sort_unstable();
+        expired
+    }
+
+    /// Blacklists the drones sending too many `Dropped` Nacks, `None` to stop.
+    pub fn set_auto(&mut self, auto: Option<AutoBlacklist>) {
+        self.auto = auto;
+        self.drops.clear();
+    }
+
+    #[must_use]
+    pub fn auto(&self) -> Option<AutoBlacklist> {
+        self.auto
+    }
+
+    /// Counts a `Dropped` Nack of `drone` at `now`, blacklisting it once it sent more than
+    /// allowed within the window. Returns whether it was just blacklisted.
+    pub fn record_drop(&mut self, drone: NodeId, now: Instant) -> bool {
+        let Some(auto) = self.auto else {
+            return false;
+        };
+        if self.contains(drone, now) {
+            return false;
+        }
+        let drops = self.drops.entry(drone).or_default();
+        drops.push_back(now);
+        while drops
+            .front()
+            .is_some_and(|at| now.duration_since(*at) >= auto.window)
+        {
+            let _ = drops.pop_front();
+        }
+        if drops.len() <= auto.max_drops as usize {
+            return false;
+        }
+        let _ = self.drops.remove(&drone);
+        self.insert(drone, auto.duration, now)
+    }
+}
+
+#[cfg(test)]
+mod blacklist_tests {
+    use super::*;
+
+    #[test]
+    /// Tests that entries expire and that drones dropping too much are blacklisted
+    fn test_blacklist() {
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut blacklist = Blacklist::default();
+        assert!(blacklist.insert(4, Duration::from_secs(10), start));
+        assert!(!blacklist.insert(4, Duration::from_secs(5), start));
+        assert!(blacklist.contains(4, at(9)));
+        assert_eq!(blacklist.entries(at(4)), vec![(4, Duration::from_secs(6))]);
+        assert!(blacklist.purge_expired(at(9)).is_empty());
+        assert_eq!(blacklist.purge_expired(at(10)), vec![4]);
+        assert!(!blacklist.remove(4));
+
+        assert!(!blacklist.record_drop(7, start));
+        blacklist.set_auto(Some(AutoBlacklist {
+            max_drops: 2,
+            window: Duration::from_secs(5),
+            duration: Duration::from_secs(30),
+        }));
+        assert!(!blacklist.record_drop(7, at(0)));
+        assert!(!blacklist.record_drop(7, at(1)));
+        // the first drops left the window
+        assert!(!blacklist.record_drop(7, at(6)));
+        assert!(!blacklist.record_drop(7, at(6)));
+        assert!(blacklist.record_drop(7, at(7)));
+        assert!(!blacklist.record_drop(7, at(8)));
+        assert_eq!(blacklist.entries(at(7)), vec![(7, Duration::from_secs(30))]);
+        assert!(blacklist.remove(7));
+        assert!(!blacklist.contains(7, at(8)));
+    }
+}
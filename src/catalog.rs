@@ -0,0 +1,185 @@
+use crate::types::TextFile;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fmt::Write;
+use uuid::Uuid;
+
+/// Hash of a text file, as listed in a `WebResponse::CatalogDigest`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FileDigest {
+    pub file_id: Uuid,
+    /// Hex encoded SHA-256 of the serialized text file
+    pub digest: String,
+}
+
+impl FileDigest {
+    /// Hashes `file`. Media files are immutable and referenced by id, so a changed
+    /// media shows as a changed reference of the text file.
+    #[must_use]
+    pub fn of(file: &TextFile) -> Self {
+        let bytes = serde_json::to_vec(file).unwrap_or_default();
+        Self {
+            file_id: file.id,
+            digest: sha256_hex(&bytes),
+        }
+    }
+}
+
+/// Hex encoded SHA-256 of `data`.
+pub(crate) fn sha256_hex(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+/// Listing entry of a text file, as sent in a `WebResponse::FilesListResponse`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct FileMetadata {
+    pub file_id: Uuid,
+    pub title: String,
+    /// Bytes of the text content
+    pub size: u64,
+    pub media_refs: usize,
+    /// Same digest as the [`FileDigest`] of the file, to spot stale cached copies
+    pub digest: String,
+}
+
+impl FileMetadata {
+    #[must_use]
+    pub fn of(file: &TextFile) -> Self {
+        Self {
+            file_id: file.id,
+            title: file.title.clone(),
+            size: file.content.len() as u64,
+            media_refs: file.media_refs.len(),
+            digest: FileDigest::of(file).digest,
+        }
+    }
+}
+
+/// Entries a `WebRequest::FilesListQuery` page holds at most, larger pages are truncated.
+pub const MAX_PAGE_SIZE: u32 = 100;
+
+/// Server side: page `page` (from 0) of `page_size` entries of the listing of `files`
+/// sorted by title, and the number of files, to answer a `WebRequest::FilesListQuery`.
+#[must_use]
+pub fn files_page<'a>(
+    files: impl IntoIterator<Item = &'a TextFile>,
+    page: u32,
+    page_size: u32,
+) -> (Vec<FileMetadata>, u64) {
+    let mut files = files.into_iter().collect::<Vec<_>>();
+    files.sort_by(|a, b| a.title.cmp(&b.title).then(a.id.cmp(&b.id)));
+    let page_size = page_size.min(MAX_PAGE_SIZE) as usize;
+    let entries = files
+        .iter()
+        .skip((page as usize).saturating_mul(page_size))
+        .take(page_size)
+        .map(|file| FileMetadata::of(file))
+        .collect();
+    (entries, files.len() as u64)
+}
+
+/// Server side: the digests of `files`, in ascending id order, to answer a
+/// `WebRequest::CatalogDigestQuery`.
+#[must_use]
+pub fn catalog_digest<'a>(files: impl IntoIterator<Item = &'a TextFile>) -> Vec<FileDigest> {
+    let mut digests = files.into_iter().map(FileDigest::of).collect::<Vec<_>>();
+    digests.sort_by_key(|d| d.file_id);
+    digests
+}
+
+/// Client side: what to fetch and forget to bring a cache in line with a catalog digest.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CatalogDiff {
+    /// Files not cached or whose cached copy differs from the server one
+    pub stale: Vec<Uuid>,
+    /// Cached files the server doesn't list anymore
+    pub removed: Vec<Uuid>,
+}
+
+impl CatalogDiff {
+    /// Compares the `cached` files with the `remote` digests received from the server.
+    #[must_use]
+    pub fn between<'a>(
+        cached: impl IntoIterator<Item = &'a TextFile>,
+        remote: &[FileDigest],
+    ) -> Self {
+        let mut local = cached
+            .into_iter()
+            .map(|file| {
+                let digest = FileDigest::of(file);
+                (digest.file_id, digest.digest)
+            })
+            .collect::<HashMap<_, _>>();
+
+        let mut stale = remote
+            .iter()
+            .filter(|d| {
+                local
+                    .remove(&d.file_id)
+                    .is_none_or(|digest| digest != d.digest)
+            })
+            .map(|d| d.file_id)
+            .collect::<Vec<_>>();
+        let mut removed = local.into_keys().collect::<Vec<_>>();
+        stale.sort_unstable();
+        removed.sort_unstable();
+        Self { stale, removed }
+    }
+
+    #[must_use]
+    pub fn is_up_to_date(&self) -> bool {
+        self.stale.is_empty() && self.removed.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod catalog_tests {
+    use super::*;
+
+    #[test]
+    /// Tests that only changed, new and removed files are reported
+    fn test_catalog_diff() {
+        let kept = TextFile::new("kept".to_string(), "same".to_string(), vec![]);
+        let mut edited = TextFile::new("edited".to_string(), "old".to_string(), vec![]);
+        let dropped = TextFile::new("dropped".to_string(), "gone".to_string(), vec![]);
+        let added = TextFile::new("added".to_string(), "new".to_string(), vec![]);
+        let cached = vec![kept.clone(), edited.clone(), dropped.clone()];
+
+        edited.content = "new".to_string();
+        let remote = catalog_digest([&kept, &edited, &added]);
+        assert_eq!(remote.len(), 3);
+        assert_eq!(remote[0].digest.len(), 64);
+
+        let diff = CatalogDiff::between(&cached, &remote);
+        let mut stale = vec![edited.id, added.id];
+        stale.sort_unstable();
+        assert_eq!(diff.stale, stale);
+        assert_eq!(diff.removed, vec![dropped.id]);
+        assert!(CatalogDiff::between([&kept], &catalog_digest([&kept])).is_up_to_date());
+    }
+
+    #[test]
+    /// Tests that listings are paged by title and carry the digest of each file
+    fn test_files_page() {
+        let files = ["c", "a", "b"]
+            .map(|title| TextFile::new(title.to_string(), "text".to_string(), vec![]));
+        let (first, total) = files_page(&files, 0, 2);
+        assert_eq!(total, 3);
+        let titles = first.iter().map(|e| e.title.as_str()).collect::<Vec<_>>();
+        assert_eq!(titles, vec!["a", "b"]);
+        assert_eq!(first[0], FileMetadata::of(&files[1]));
+        assert_eq!(first[0].digest, FileDigest::of(&files[1]).digest);
+        assert_eq!(first[0].size, 4);
+
+        let (last, _) = files_page(&files, 1, 2);
+        assert_eq!(last[0].file_id, files[0].id);
+        assert!(files_page(&files, 9, 2).0.is_empty());
+        assert_eq!(files_page(&files, 0, u32::MAX).0.len(), 3);
+    }
+}
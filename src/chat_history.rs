@@ -0,0 +1,276 @@
+use crate::node_state::NodeState;
+use crate::ring_log::RingLog;
+use crate::types::{ChatCommand, ChatEvent, Message};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use wg_internal::network::NodeId;
+
+/// Messages a [`ChatHistory`] keeps in memory per peer by default.
+pub const DEFAULT_HISTORY_WINDOW: usize = 100;
+
+/// Message exchanged with a peer, as logged by a [`ChatHistory`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct HistoryEntry {
+    // milliseconds since the unix epoch
+    pub timestamp: u64,
+    pub message: Message,
+}
+
+/// Conversations of a chat client, one per peer.
+///
+/// Every message sent or received is appended to the log of the peer, a
+/// `peer_{id}.jsonl` file holding one [`HistoryEntry`] per line, and to a window of
+/// the latest messages kept in memory. [`Self::answer`] serves
+/// `ChatCommand::GetChatsHistory` from the windows, while [`Self::search`] and
+/// [`Self::load`] read the whole logs. A history without a directory keeps the windows
+/// only.
+#[derive(Debug, Clone)]
+pub struct ChatHistory {
+    dir: Option<PathBuf>,
+    window: usize,
+    recent: BTreeMap<NodeId, RingLog<HistoryEntry>>,
+}
+
+impl Default for ChatHistory {
+    fn default() -> Self {
+        Self::in_memory(DEFAULT_HISTORY_WINDOW)
+    }
+}
+
+impl ChatHistory {
+    /// Opens the history kept in `dir`, creating it on first use, with the latest
+    /// `window` messages of every peer in memory.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created or a log is corrupted.
+    pub fn open(dir: impl AsRef<Path>, window: usize) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut history = Self {
+            dir: Some(dir.clone()),
+            ..Self::in_memory(window)
+        };
+        for entry in fs::read_dir(&dir)? {
+            let name = entry?.file_name();
+            let Some(peer) = name
+                .to_str()
+                .and_then(|name| name.strip_prefix("peer_")?.strip_suffix(".jsonl"))
+                .and_then(|id| id.parse::<NodeId>().ok())
+            else {
+                continue;
+            };
+            let mut recent = RingLog::new(window);
+            for entry in history.load(peer)? {
+                let _ = recent.push(entry);
+            }
+            let _ = history.recent.insert(peer, recent);
+        }
+        Ok(history)
+    }
+
+    /// Opens the history kept in the chat directory of a node.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the history cannot be opened, see [`Self::open`].
+    pub fn for_node(state: &NodeState, window: usize) -> io::Result<Self> {
+        Self::open(state.chat_dir(), window)
+    }
+
+    /// Creates a history that isn't persisted, keeping the latest `window` messages of
+    /// every peer.
+    #[must_use]
+    pub fn in_memory(window: usize) -> Self {
+        Self {
+            dir: None,
+            window,
+            recent: BTreeMap::new(),
+        }
+    }
+
+    fn log_path(&self, peer: NodeId) -> Option<PathBuf> {
+        self.dir
+            .as_ref()
+            .map(|dir| dir.join(format!("peer_{peer}.jsonl")))
+    }
+
+    /// Appends `message`, sent to or received from `peer`, to its conversation.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log cannot be written.
+    pub fn append(&mut self, peer: NodeId, message: Message) -> io::Result<()> {
+        let entry = HistoryEntry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| u64::try_from(d.as_millis()).unwrap_or(u64::MAX)),
+            message,
+        };
+        if let Some(path) = self.log_path(peer) {
+            let mut line = serde_json::to_vec(&entry).map_err(io::Error::other)?;
+            line.push(b'\n');
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?
+                .write_all(&line)?;
+        }
+        let window = self.window;
+        let recent = self
+            .recent
+            .entry(peer)
+            .or_insert_with(|| RingLog::new(window));
+        let _ = recent.push(entry);
+        Ok(())
+    }
+
+    /// Lists the peers with a conversation, in ascending order.
+    #[must_use]
+    pub fn peers(&self) -> Vec<NodeId> {
+        self.recent.keys().copied().collect()
+    }
+
+    /// Returns the latest messages exchanged with `peer` kept in memory, oldest first.
+    #[must_use]
+    pub fn recent(&self, peer: NodeId) -> Vec<&HistoryEntry> {
+        self.recent
+            .get(&peer)
+            .map(|recent| recent.iter().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns the whole conversation with `peer`, oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the log cannot be read or is corrupted.
+    pub fn load(&self, peer: NodeId) -> io::Result<Vec<HistoryEntry>> {
+        let Some(path) = self.log_path(peer) else {
+            return Ok(self.recent(peer).into_iter().cloned().collect());
+        };
+        let data = match fs::read_to_string(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        data.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                serde_json::from_str(line)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+            })
+            .collect()
+    }
+
+    /// Returns the messages containing `text`, ignoring case, exchanged with `peer` or
+    /// with every peer if `None`, by peer and oldest first.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a log cannot be read or is corrupted.
+    pub fn search(
+        &self,
+        text: &str,
+        peer: Option<NodeId>,
+    ) -> io::Result<Vec<(NodeId, HistoryEntry)>> {
+        let text = text.to_lowercase();
+        let peers = peer.map_or_else(|| self.peers(), |peer| vec![peer]);
+        let mut found = Vec::new();
+        for peer in peers {
+            found.extend(
+                self.load(peer)?
+                    .into_iter()
+                    .filter(|entry| entry.message.text.to_lowercase().contains(&text))
+                    .map(|entry| (peer, entry)),
+            );
+        }
+        Ok(found)
+    }
+
+    /// Returns the messages kept in memory by peer, as carried by
+    /// `ChatEvent::ChatHistory`.
+    #[must_use]
+    pub fn snapshot(&self) -> HashMap<NodeId, Vec<Message>> {
+        self.recent
+            .iter()
+            .map(|(peer, recent)| {
+                let messages = recent.iter().map(|entry| entry.message.clone()).collect();
+                (*peer, messages)
+            })
+            .collect()
+    }
+
+    /// Answers `ChatCommand::GetChatsHistory` with the `ChatEvent::ChatHistory` of the
+    /// client `notification_from`, `None` for other commands.
+    #[must_use]
+    pub fn answer(&self, command: &ChatCommand, notification_from: NodeId) -> Option<ChatEvent> {
+        matches!(command, ChatCommand::GetChatsHistory).then(|| ChatEvent::ChatHistory {
+            notification_from,
+            history: self.snapshot(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod chat_history_tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    /// Tests that conversations survive a reopen, are searchable and bounded in memory
+    fn test_chat_history() {
+        let dir = tempdir().unwrap();
+        let mut history = ChatHistory::open(dir.path(), 2).unwrap();
+        for text in ["Hello Bob", "how are you?", "fine, and HELLO again"] {
+            history
+                .append(5, Message::new(1, 5, text.to_string()))
+                .unwrap();
+        }
+        history
+            .append(7, Message::new(7, 1, "hello from 7".to_string()))
+            .unwrap();
+        assert_eq!(history.peers(), vec![5, 7]);
+        let recent = history.recent(5);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].message.text, "how are you?");
+
+        let history = ChatHistory::open(dir.path(), 2).unwrap();
+        assert_eq!(history.load(5).unwrap().len(), 3);
+        assert_eq!(history.recent(5).len(), 2);
+        let found = history.search("hello", None).unwrap();
+        let texts = found
+            .iter()
+            .map(|(peer, entry)| (*peer, entry.message.text.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            texts,
+            vec![
+                (5, "Hello Bob"),
+                (5, "fine, and HELLO again"),
+                (7, "hello from 7")
+            ]
+        );
+        assert_eq!(history.search("hello", Some(7)).unwrap().len(), 1);
+
+        let Some(ChatEvent::ChatHistory {
+            notification_from,
+            history: snapshot,
+        }) = history.answer(&ChatCommand::GetChatsHistory, 1)
+        else {
+            panic!("expected the chat history");
+        };
+        assert_eq!(notification_from, 1);
+        assert_eq!(snapshot[&5].len(), 2);
+        assert_eq!(snapshot[&7][0].from, 7);
+        assert!(
+            history
+                .answer(&ChatCommand::GetRegisteredClients, 1)
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,247 @@
+use crate::types::{ChatRequest, ChatResponse, RoomId, RoomInfo};
+use std::collections::{BTreeMap, BTreeSet};
+use wg_internal::network::NodeId;
+
+#[derive(Debug, Clone)]
+struct Room {
+    name: String,
+    members: BTreeSet<NodeId>,
+}
+
+/// Chat rooms of a chat server and their members.
+///
+/// [`Self::handle`] answers the room requests of `ChatRequest` with the responses to
+/// send, including the fan out of room messages and membership changes, so that a
+/// server only embeds the registry and sends what it returns. Rooms are removed once
+/// their last member leaves.
+#[derive(Debug, Clone, Default)]
+pub struct RoomRegistry {
+    rooms: BTreeMap<RoomId, Room>,
+    last_id: RoomId,
+}
+
+impl RoomRegistry {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a room called `name` with `creator` as its only member.
+    pub fn create(&mut self, name: String, creator: NodeId) -> RoomId {
+        self.last_id += 1;
+        let room = Room {
+            name,
+            members: BTreeSet::from([creator]),
+        };
+        let _ = self.rooms.insert(self.last_id, room);
+        self.last_id
+    }
+
+    /// Adds `client` to `room_id`, returns whether the room exists.
+    pub fn join(&mut self, room_id: RoomId, client: NodeId) -> bool {
+        self.rooms
+            .get_mut(&room_id)
+            .map(|room| room.members.insert(client))
+            .is_some()
+    }
+
+    /// Removes `client` from `room_id`, returns whether it was a member.
+    pub fn leave(&mut self, room_id: RoomId, client: NodeId) -> bool {
+        let Some(room) = self.rooms.get_mut(&room_id) else {
+            return false;
+        };
+        let left = room.members.remove(&client);
+        if room.members.is_empty() {
+            let _ = self.rooms.remove(&room_id);
+        }
+        left
+    }
+
+    /// Removes `client` from every room, e.g. once it is unregistered. Returns the rooms
+    /// it left.
+    pub fn leave_all(&mut self, client: NodeId) -> Vec<RoomId> {
+        let rooms = self
+            .rooms
+            .iter()
+            .filter(|(_, room)| room.members.contains(&client))
+            .map(|(room_id, _)| *room_id)
+            .collect::<Vec<_>>();
+        for room_id in &rooms {
+            let _ = self.leave(*room_id, client);
+        }
+        rooms
+    }
+
+    /// Returns the members of `room_id` in ascending order, `None` if it doesn't exist.
+    #[must_use]
+    pub fn members(&self, room_id: RoomId) -> Option<Vec<NodeId>> {
+        self.rooms
+            .get(&room_id)
+            .map(|room| room.members.iter().copied().collect())
+    }
+
+    /// Lists the rooms by id.
+    #[must_use]
+    pub fn rooms(&self) -> Vec<RoomInfo> {
+        self.rooms
+            .iter()
+            .map(|(room_id, room)| RoomInfo {
+                room_id: *room_id,
+                name: room.name.clone(),
+                members: room.members.iter().copied().collect(),
+            })
+            .collect()
+    }
+
+    /// Handles the room request `request` of `from`, returning the responses to send by
+    /// recipient, `None` if it is not a room request.
+    #[must_use]
+    pub fn handle(
+        &mut self,
+        from: NodeId,
+        request: &ChatRequest,
+    ) -> Option<Vec<(NodeId, ChatResponse)>> {
+        let responses = match request {
+            ChatRequest::CreateRoom { name } => {
+                let room_id = self.create(name.clone(), from);
+                vec![(
+                    from,
+                    ChatResponse::RoomCreated {
+                        room_id,
+                        name: name.clone(),
+                    },
+                )]
+            }
+            ChatRequest::JoinRoom { room_id } => {
+                if self.join(*room_id, from) {
+                    self.broadcast(
+                        *room_id,
+                        None,
+                        &ChatResponse::RoomJoined {
+                            room_id: *room_id,
+                            client_id: from,
+                        },
+                    )
+                } else {
+                    vec![(from, ChatResponse::ErrorWrongRoomId { room_id: *room_id })]
+                }
+            }
+            ChatRequest::LeaveRoom { room_id } => {
+                if self.leave(*room_id, from) {
+                    let left = ChatResponse::RoomLeft {
+                        room_id: *room_id,
+                        client_id: from,
+                    };
+                    let mut responses = self.broadcast(*room_id, None, &left);
+                    responses.push((from, left));
+                    responses
+                } else {
+                    vec![(from, ChatResponse::ErrorWrongRoomId { room_id: *room_id })]
+                }
+            }
+            ChatRequest::RoomListQuery => vec![(
+                from,
+                ChatResponse::RoomList {
+                    rooms: self.rooms(),
+                },
+            )],
+            ChatRequest::MessageToRoom { room_id, message } => {
+                if self
+                    .rooms
+                    .get(room_id)
+                    .is_some_and(|room| room.members.contains(&from))
+                {
+                    let relayed = ChatResponse::MessageFromRoom {
+                        room_id: *room_id,
+                        client_id: from,
+                        message: message.clone(),
+                    };
+                    self.broadcast(*room_id, Some(from), &relayed)
+                } else {
+                    vec![(from, ChatResponse::ErrorWrongRoomId { room_id: *room_id })]
+                }
+            }
+            _ => return None,
+        };
+        Some(responses)
+    }
+
+    /// Addresses `response` to every member of `room_id` but `except`.
+    fn broadcast(
+        &self,
+        room_id: RoomId,
+        except: Option<NodeId>,
+        response: &ChatResponse,
+    ) -> Vec<(NodeId, ChatResponse)> {
+        self.rooms.get(&room_id).map_or_else(Vec::new, |room| {
+            room.members
+                .iter()
+                .filter(|member| Some(**member) != except)
+                .map(|member| (*member, response.clone()))
+                .collect()
+        })
+    }
+}
+
+#[cfg(test)]
+mod chat_rooms_tests {
+    use super::*;
+
+    #[test]
+    /// Tests room creation, membership changes and the fan out of room messages
+    fn test_room_registry() {
+        let mut rooms = RoomRegistry::new();
+        let create = ChatRequest::CreateRoom {
+            name: "general".to_string(),
+        };
+        let responses = rooms.handle(3, &create).unwrap();
+        let [(3, ChatResponse::RoomCreated { room_id, .. })] = responses.as_slice() else {
+            panic!("unexpected {responses:?}");
+        };
+        let room_id = *room_id;
+
+        let joined = rooms.handle(4, &ChatRequest::JoinRoom { room_id }).unwrap();
+        let recipients = joined.iter().map(|(to, _)| *to).collect::<Vec<_>>();
+        assert_eq!(recipients, vec![3, 4]);
+        assert_eq!(rooms.members(room_id), Some(vec![3, 4]));
+
+        let message = ChatRequest::MessageToRoom {
+            room_id,
+            message: "hi".to_string(),
+        };
+        assert_eq!(
+            rooms.handle(4, &message),
+            Some(vec![(
+                3,
+                ChatResponse::MessageFromRoom {
+                    room_id,
+                    client_id: 4,
+                    message: "hi".to_string()
+                }
+            )])
+        );
+        // outsiders and unknown rooms are refused
+        let refused = Some(vec![(5, ChatResponse::ErrorWrongRoomId { room_id })]);
+        assert_eq!(rooms.handle(5, &message), refused);
+        assert_eq!(
+            rooms.handle(5, &ChatRequest::JoinRoom { room_id: 99 }),
+            Some(vec![(5, ChatResponse::ErrorWrongRoomId { room_id: 99 })])
+        );
+        assert_eq!(rooms.handle(5, &ChatRequest::ClientListQuery), None);
+
+        let left = rooms
+            .handle(3, &ChatRequest::LeaveRoom { room_id })
+            .unwrap();
+        assert_eq!(
+            left.iter().map(|(to, _)| *to).collect::<Vec<_>>(),
+            vec![4, 3]
+        );
+        assert_eq!(rooms.rooms()[0].members, vec![4]);
+        // the room goes away with its last member
+        assert_eq!(rooms.leave_all(4), vec![room_id]);
+        assert_eq!(
+            rooms.handle(4, &ChatRequest::RoomListQuery),
+            Some(vec![(4, ChatResponse::RoomList { rooms: vec![] })])
+        );
+    }
+}
@@ -0,0 +1,336 @@
+use crate::codec::{Codec, CodecFlags, PeerCodecs};
+use crate::keepalive::KeepAliveTracker;
+use crate::node_state::NodeState;
+use crate::types::{ChatEvent, ChatRequest, ChatResponse};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use wg_internal::network::NodeId;
+
+/// Clients registered to a chat server.
+///
+/// [`Self::handle`] answers `RegistrationToChat`, `ClientListQuery` and `KeepAlive`
+/// with the responses to send, negotiating the codec of every client. Registering
+/// twice only refreshes the client, and clients silent past the timeout are
+/// deregistered by [`Self::prune_expired`]. Changes of the registered clients are
+/// queued as `ChatEvent`s for the controller, see [`Self::take_events`]. A registry
+/// opened from a file keeps the ids registered across restarts.
+#[derive(Debug, Clone)]
+pub struct ClientRegistry {
+    server_id: NodeId,
+    clients: BTreeSet<NodeId>,
+    codecs: PeerCodecs,
+    liveness: KeepAliveTracker,
+    path: Option<PathBuf>,
+    events: Vec<ChatEvent>,
+}
+
+impl ClientRegistry {
+    const CLIENTS_FILE: &'static str = "clients.json";
+
+    /// Creates a registry that isn't persisted, for the server `server_id` speaking the
+    /// codecs `supported`, deregistering clients silent for longer than `timeout`.
+    #[must_use]
+    pub fn new(server_id: NodeId, supported: CodecFlags, timeout: Duration) -> Self {
+        Self {
+            server_id,
+            clients: BTreeSet::new(),
+            codecs: PeerCodecs::new(supported),
+            liveness: KeepAliveTracker::new(timeout),
+            path: None,
+            events: Vec::new(),
+        }
+    }
+
+    /// Opens the registry persisted in the file `path`, creating it on first use. The
+    /// clients registered before are kept, speaking JSON until they register again,
+    /// and get a whole timeout to show up.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is corrupted.
+    pub fn open(
+        path: impl AsRef<Path>,
+        server_id: NodeId,
+        supported: CodecFlags,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let clients: BTreeSet<NodeId> = match fs::read(&path) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeSet::new(),
+            Err(e) => return Err(e),
+        };
+        let mut registry = Self::new(server_id, supported, timeout);
+        for client in &clients {
+            registry.liveness.touch(*client);
+        }
+        registry.clients = clients;
+        registry.path = Some(path);
+        Ok(registry)
+    }
+
+    /// Opens the registry kept in the chat directory of a node.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the registry cannot be opened, see [`Self::open`].
+    pub fn for_node(
+        state: &NodeState,
+        supported: CodecFlags,
+        timeout: Duration,
+    ) -> io::Result<Self> {
+        fs::create_dir_all(state.chat_dir())?;
+        Self::open(
+            state.chat_dir().join(Self::CLIENTS_FILE),
+            state.id(),
+            supported,
+            timeout,
+        )
+    }
+
+    /// Returns the registered clients in ascending order.
+    #[must_use]
+    pub fn clients(&self) -> Vec<NodeId> {
+        self.clients.iter().copied().collect()
+    }
+
+    #[must_use]
+    pub fn is_registered(&self, client: NodeId) -> bool {
+        self.clients.contains(&client)
+    }
+
+    /// Returns the codec negotiated with `client`, JSON if it didn't advertise any.
+    #[must_use]
+    pub fn codec_for(&self, client: NodeId) -> Codec {
+        self.codecs.codec_for(client)
+    }
+
+    /// Registers `client`, which speaks the codecs `offered`, and returns the response
+    /// to send it. Registering again renegotiates the codec and refreshes the client.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the registry cannot be persisted.
+    pub fn register(&mut self, client: NodeId, offered: CodecFlags) -> io::Result<ChatResponse> {
+        let codec = self.codecs.negotiate(client, offered);
+        self.liveness.touch(client);
+        if self.clients.insert(client) {
+            self.events.push(ChatEvent::ClientRegistered {
+                client,
+                server: self.server_id,
+            });
+            self.changed()?;
+        }
+        // clients unaware of negotiation expect the legacy response
+        Ok(if offered == CodecFlags::default() {
+            ChatResponse::RegistrationSuccess
+        } else {
+            ChatResponse::RegistrationAccepted { codec }
+        })
+    }
+
+    /// Deregisters `client`, e.g. once it disconnects. Returns whether it was registered.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the registry cannot be persisted.
+    pub fn deregister(&mut self, client: NodeId) -> io::Result<bool> {
+        let _ = self.liveness.forget(client);
+        self.codecs.remove(client);
+        if !self.clients.remove(&client) {
+            return Ok(false);
+        }
+        self.changed()?;
+        Ok(true)
+    }
+
+    /// Deregisters the clients silent for longer than the timeout and returns them.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the registry cannot be persisted.
+    pub fn prune_expired(&mut self) -> io::Result<Vec<NodeId>> {
+        let mut expired = Vec::new();
+        for client in self.liveness.prune_expired() {
+            self.codecs.remove(client);
+            if self.clients.remove(&client) {
+                expired.push(client);
+            }
+        }
+        if !expired.is_empty() {
+            self.changed()?;
+        }
+        Ok(expired)
+    }
+
+    /// Handles the registration request `request` of `from`, returning the responses to
+    /// send by recipient, `None` if it is not a registration request. Any request of a
+    /// registered client counts as activity.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the registry cannot be persisted.
+    pub fn handle(
+        &mut self,
+        from: NodeId,
+        request: &ChatRequest,
+    ) -> io::Result<Option<Vec<(NodeId, ChatResponse)>>> {
+        if self.is_registered(from) {
+            self.liveness.touch(from);
+        }
+        let response = match request {
+            // a client may only register itself
+            ChatRequest::RegistrationToChat { client_id, .. } if *client_id != from => {
+                ChatResponse::ErrorWrongClientId {
+                    wrong_id: *client_id,
+                }
+            }
+            ChatRequest::RegistrationToChat { codecs, .. } => self.register(from, *codecs)?,
+            ChatRequest::ClientListQuery => {
+                self.events.push(ChatEvent::ClientListQueried {
+                    notification_from: self.server_id,
+                    from,
+                });
+                ChatResponse::ClientList {
+                    list_of_client_ids: self.clients(),
+                }
+            }
+            ChatRequest::KeepAlive { client_id }
+                if *client_id == from && self.is_registered(from) =>
+            {
+                ChatResponse::KeepAliveAck
+            }
+            // keep-alives of clients deregistered meanwhile, which must register again
+            ChatRequest::KeepAlive { client_id } => ChatResponse::ErrorWrongClientId {
+                wrong_id: *client_id,
+            },
+            _ => return Ok(None),
+        };
+        Ok(Some(vec![(from, response)]))
+    }
+
+    /// Returns the events queued since the previous call, oldest first.
+    pub fn take_events(&mut self) -> Vec<ChatEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Queues the new list of registered clients and persists it.
+    fn changed(&mut self) -> io::Result<()> {
+        self.events.push(ChatEvent::RegisteredClients {
+            notification_from: self.server_id,
+            list: self.clients(),
+        });
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let data = serde_json::to_vec(&self.clients).map_err(io::Error::other)?;
+        fs::write(path, data)
+    }
+}
+
+#[cfg(test)]
+mod client_registry_tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn registration(client_id: NodeId, codecs: CodecFlags) -> ChatRequest {
+        ChatRequest::RegistrationToChat { client_id, codecs }
+    }
+
+    #[test]
+    /// Tests registrations, duplicates, list queries and the events they queue
+    fn test_client_registry() {
+        let mut registry = ClientRegistry::new(9, CodecFlags::all(), Duration::from_mins(1));
+        assert_eq!(
+            registry
+                .handle(3, &registration(3, CodecFlags::all()))
+                .unwrap(),
+            Some(vec![(
+                3,
+                ChatResponse::RegistrationAccepted {
+                    codec: Codec::MessagePack
+                }
+            )])
+        );
+        assert_eq!(
+            registry
+                .handle(4, &registration(4, CodecFlags::default()))
+                .unwrap(),
+            Some(vec![(4, ChatResponse::RegistrationSuccess)])
+        );
+        assert_eq!(registry.codec_for(3), Codec::MessagePack);
+        let events = registry.take_events();
+        assert_eq!(events.len(), 4);
+        assert_eq!(
+            events[3],
+            ChatEvent::RegisteredClients {
+                notification_from: 9,
+                list: vec![3, 4]
+            }
+        );
+
+        // registering again only answers, and nobody registers someone else
+        let _ = registry
+            .handle(3, &registration(3, CodecFlags::all()))
+            .unwrap();
+        assert!(registry.take_events().is_empty());
+        assert_eq!(
+            registry
+                .handle(5, &registration(3, CodecFlags::all()))
+                .unwrap(),
+            Some(vec![(5, ChatResponse::ErrorWrongClientId { wrong_id: 3 })])
+        );
+        assert_eq!(
+            registry.handle(5, &ChatRequest::ClientListQuery).unwrap(),
+            Some(vec![(
+                5,
+                ChatResponse::ClientList {
+                    list_of_client_ids: vec![3, 4]
+                }
+            )])
+        );
+        assert_eq!(
+            registry.handle(5, &ChatRequest::RoomListQuery).unwrap(),
+            None
+        );
+
+        assert!(registry.deregister(4).unwrap());
+        assert!(!registry.deregister(4).unwrap());
+        let keep_alive = ChatRequest::KeepAlive { client_id: 4 };
+        assert_eq!(
+            registry.handle(4, &keep_alive).unwrap(),
+            Some(vec![(4, ChatResponse::ErrorWrongClientId { wrong_id: 4 })])
+        );
+    }
+
+    #[test]
+    /// Tests that silent clients expire and that the registered ids survive a restart
+    fn test_client_registry_persistence() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("clients.json");
+        let mut registry =
+            ClientRegistry::open(&path, 9, CodecFlags::all(), Duration::ZERO).unwrap();
+        let _ = registry.register(3, CodecFlags::all()).unwrap();
+        let _ = registry.register(4, CodecFlags::all()).unwrap();
+
+        let mut registry =
+            ClientRegistry::open(&path, 9, CodecFlags::all(), Duration::ZERO).unwrap();
+        assert_eq!(registry.clients(), vec![3, 4]);
+        assert_eq!(registry.codec_for(3), Codec::Json);
+        std::thread::sleep(Duration::from_millis(2));
+        assert_eq!(registry.prune_expired().unwrap(), vec![3, 4]);
+        assert_eq!(
+            registry.take_events(),
+            vec![ChatEvent::RegisteredClients {
+                notification_from: 9,
+                list: vec![]
+            }]
+        );
+        let registry = ClientRegistry::open(&path, 9, CodecFlags::all(), Duration::ZERO).unwrap();
+        assert!(registry.clients().is_empty());
+    }
+}
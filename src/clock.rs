@@ -0,0 +1,66 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Source of the current time of a node: the system clock, or a simulated clock that
+/// only moves when [`advance`](Self::advance)d, so that the timers of a simulation
+/// fire the same way on every run.
+///
+/// Clones of a simulated clock share its time, one clone being handed to every node
+/// of the simulation.
+#[derive(Debug, Clone, Default)]
+pub struct Clock {
+    simulated: Option<Arc<SimulatedTime>>,
+}
+
+#[derive(Debug)]
+struct SimulatedTime {
+    start: Instant,
+    // nanoseconds elapsed since `start`
+    elapsed: AtomicU64,
+}
+
+impl Clock {
+    #[must_use]
+    pub fn system() -> Self {
+        Self::default()
+    }
+
+    /// Creates a simulated clock, standing still at the current time until advanced.
+    #[must_use]
+    pub fn simulated() -> Self {
+        Self {
+            simulated: Some(Arc::new(SimulatedTime {
+                start: Instant::now(),
+                elapsed: AtomicU64::new(0),
+            })),
+        }
+    }
+
+    #[must_use]
+    pub fn is_simulated(&self) -> bool {
+        self.simulated.is_some()
+    }
+
+    #[must_use]
+    pub fn now(&self) -> Instant {
+        match &self.simulated {
+            Some(time) => time.start + Duration::from_nanos(time.elapsed.load(Ordering::Relaxed)),
+            None => Instant::now(),
+        }
+    }
+
+    /// Moves a simulated clock, and all its clones, `by` forward. The system clock
+    /// cannot be moved, it is left as is.
+    pub fn advance(&self, by: Duration) {
+        let Some(time) = &self.simulated else {
+            return;
+        };
+        let by = u64::try_from(by.as_nanos()).unwrap_or(u64::MAX);
+        let _ = time
+            .elapsed
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |elapsed| {
+                Some(elapsed.saturating_add(by))
+            });
+    }
+}
@@ -0,0 +1,318 @@
+use crate::config::CommonConfig;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use wg_internal::network::NodeId;
+
+/// Serialization format of the payloads exchanged with a peer, implemented by
+/// [`JsonCodec`], [`BincodeCodec`] and [`MessagePackCodec`], and by [`Codec`] for the
+/// format negotiated with a peer. See `types::encode_request` and its siblings for the
+/// request/response envelopes.
+pub trait WireCodec {
+    /// Returns the codec as advertised in [`CodecFlags`].
+    fn codec(&self) -> Codec;
+
+    /// Returns whether the encoding carries enough type information to decode the
+    /// internally tagged envelopes (`ChatRequest`, `WebResponse`, ...).
+    fn self_describing(&self) -> bool;
+
+    /// Serializes `value`.
+    /// # Errors
+    /// Returns an error if `value` cannot be serialized.
+    fn encode<T: Serialize>(&self, value: &T) -> anyhow::Result<Vec<u8>>;
+
+    /// Deserializes `bytes`.
+    /// # Errors
+    /// Returns an error if `bytes` are not a valid encoding of `T`.
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T>;
+}
+
+/// JSON, the format every peer speaks.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+impl WireCodec for JsonCodec {
+    fn codec(&self) -> Codec {
+        Codec::Json
+    }
+
+    fn self_describing(&self) -> bool {
+        true
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Bincode, the most compact format. It is not self-describing, so it cannot decode
+/// internally tagged enums.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+impl WireCodec for BincodeCodec {
+    fn codec(&self) -> Codec {
+        Codec::Bincode
+    }
+
+    fn self_describing(&self) -> bool {
+        false
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// `MessagePack` with named fields: self-describing like JSON, but with bytes and
+/// numbers in binary, so that e.g. the file data of a `WebResponse` shrinks about
+/// threefold.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePackCodec;
+
+impl WireCodec for MessagePackCodec {
+    fn codec(&self) -> Codec {
+        Codec::MessagePack
+    }
+
+    fn self_describing(&self) -> bool {
+        true
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> anyhow::Result<Vec<u8>> {
+        Ok(rmp_serde::to_vec_named(value)?)
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T> {
+        Ok(rmp_serde::from_slice(bytes)?)
+    }
+}
+
+/// Serialization format used for the payloads exchanged with a peer.
+///
+/// The request/response envelopes (`ChatRequest`, `WebResponse`, ...) are
+/// internally tagged, so they travel as JSON unless `MessagePack` was negotiated;
+/// the negotiated codec also applies to the bulk data they carry, e.g. the
+/// serialized [`File`](crate::types::File) inside `WebResponse::TextFile`.
+/// Bincode is not self-describing, so it cannot decode internally tagged enums.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Codec {
+    #[default]
+    Json,
+    Bincode,
+    MessagePack,
+}
+
+impl Codec {
+    /// Every codec, most preferred first: `MessagePack`, which carries the envelopes as
+    /// well, then Bincode, the most compact for the bulk data only, then JSON.
+    pub const PREFERENCE: [Codec; 3] = [Codec::MessagePack, Codec::Bincode, Codec::Json];
+
+    #[must_use]
+    pub fn flag(self) -> u8 {
+        match self {
+            Self::Json => 0b001,
+            Self::Bincode => 0b010,
+            Self::MessagePack => 0b100,
+        }
+    }
+
+    /// Serializes `value` with this codec.
+    /// # Errors
+    /// Returns an error if `value` cannot be serialized.
+    pub fn encode<T: Serialize>(self, value: &T) -> anyhow::Result<Vec<u8>> {
+        WireCodec::encode(&self, value)
+    }
+
+    /// Deserializes `bytes` with this codec.
+    /// # Errors
+    /// Returns an error if `bytes` are not a valid encoding of `T`.
+    pub fn decode<T: DeserializeOwned>(self, bytes: &[u8]) -> anyhow::Result<T> {
+        WireCodec::decode(&self, bytes)
+    }
+}
+
+impl WireCodec for Codec {
+    fn codec(&self) -> Codec {
+        *self
+    }
+
+    fn self_describing(&self) -> bool {
+        match self {
+            Self::Json => JsonCodec.self_describing(),
+            Self::Bincode => BincodeCodec.self_describing(),
+            Self::MessagePack => MessagePackCodec.self_describing(),
+        }
+    }
+
+    fn encode<T: Serialize>(&self, value: &T) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Json => JsonCodec.encode(value),
+            Self::Bincode => BincodeCodec.encode(value),
+            Self::MessagePack => MessagePackCodec.encode(value),
+        }
+    }
+
+    fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> anyhow::Result<T> {
+        match self {
+            Self::Json => JsonCodec.decode(bytes),
+            Self::Bincode => BincodeCodec.decode(bytes),
+            Self::MessagePack => MessagePackCodec.decode(bytes),
+        }
+    }
+}
+
+/// Set of codecs a node is able to speak, advertised at registration.
+///
+/// An empty set (e.g. a peer not aware of negotiation) is treated as JSON only.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(transparent)]
+pub struct CodecFlags(u8);
+
+impl CodecFlags {
+    #[must_use]
+    pub fn all() -> Self {
+        Self(Codec::PREFERENCE.iter().fold(0, |acc, c| acc | c.flag()))
+    }
+
+    #[must_use]
+    pub fn with(self, codec: Codec) -> Self {
+        Self(self.0 | codec.flag())
+    }
+
+    #[must_use]
+    pub fn supports(self, codec: Codec) -> bool {
+        codec == Codec::Json || self.0 & codec.flag() != 0
+    }
+
+    /// Picks the most preferred codec supported by both sides, see [`Codec::PREFERENCE`].
+    #[must_use]
+    pub fn negotiate(self, other: CodecFlags) -> Codec {
+        Codec::PREFERENCE
+            .into_iter()
+            .find(|c| self.supports(*c) && other.supports(*c))
+            .unwrap_or_default()
+    }
+}
+
+impl From<Codec> for CodecFlags {
+    fn from(codec: Codec) -> Self {
+        Self(codec.flag())
+    }
+}
+
+/// Per-peer codec table, filled in as registrations are negotiated.
+/// Peers without an entry are spoken to in JSON.
+#[derive(Debug, Clone, Default)]
+pub struct PeerCodecs {
+    supported: CodecFlags,
+    peers: HashMap<NodeId, Codec>,
+}
+
+impl PeerCodecs {
+    #[must_use]
+    pub fn new(supported: CodecFlags) -> Self {
+        Self {
+            supported,
+            peers: HashMap::new(),
+        }
+    }
+
+    /// Creates a table offering the `codecs` of `config`.
+    #[must_use]
+    pub fn from_config(config: &CommonConfig) -> Self {
+        Self::new(config.codecs)
+    }
+
+    #[must_use]
+    pub fn supported(&self) -> CodecFlags {
+        self.supported
+    }
+
+    /// Negotiates and stores the codec to use with `peer` given the codecs it offered.
+    pub fn negotiate(&mut self, peer: NodeId, offered: CodecFlags) -> Codec {
+        let codec = self.supported.negotiate(offered);
+        let _ = self.peers.insert(peer, codec);
+        codec
+    }
+
+    /// Stores the codec chosen by `peer` (e.g. the one in `ChatResponse::RegistrationAccepted`).
+    pub fn set(&mut self, peer: NodeId, codec: Codec) {
+        let _ = self.peers.insert(peer, codec);
+    }
+
+    pub fn remove(&mut self, peer: NodeId) {
+        let _ = self.peers.remove(&peer);
+    }
+
+    #[must_use]
+    pub fn codec_for(&self, peer: NodeId) -> Codec {
+        self.peers.get(&peer).copied().unwrap_or_default()
+    }
+
+    /// Serializes `value` with the codec negotiated with `peer`.
+    /// # Errors
+    /// Returns an error if `value` cannot be serialized.
+    pub fn encode_for<T: Serialize>(&self, peer: NodeId, value: &T) -> anyhow::Result<Vec<u8>> {
+        self.codec_for(peer).encode(value)
+    }
+
+    /// Deserializes `bytes` received from `peer` with the negotiated codec.
+    /// # Errors
+    /// Returns an error if `bytes` are not a valid encoding of `T`.
+    pub fn decode_from<T: DeserializeOwned>(&self, peer: NodeId, bytes: &[u8]) -> anyhow::Result<T> {
+        self.codec_for(peer).decode(bytes)
+    }
+}
+
+#[cfg(test)]
+mod codec_tests {
+    use super::*;
+    use crate::types::{ChatRequest, Message};
+
+    #[test]
+    /// Tests negotiation between nodes with different capabilities
+    fn test_negotiate() {
+        assert_eq!(CodecFlags::all().negotiate(CodecFlags::all()), Codec::MessagePack);
+        assert_eq!(CodecFlags::all().negotiate(CodecFlags::default()), Codec::Json);
+        assert_eq!(
+            CodecFlags::from(Codec::Json).negotiate(CodecFlags::all()),
+            Codec::Json
+        );
+
+        let mut codecs = PeerCodecs::new(CodecFlags::all());
+        assert_eq!(codecs.codec_for(4), Codec::Json);
+        assert_eq!(codecs.negotiate(4, CodecFlags::all()), Codec::MessagePack);
+        assert_eq!(codecs.codec_for(4), Codec::MessagePack);
+        let compact = CodecFlags::from(Codec::Json).with(Codec::Bincode);
+        assert_eq!(compact.negotiate(CodecFlags::all()), Codec::Bincode);
+    }
+
+    #[test]
+    /// Tests that payloads round-trip with every codec
+    fn test_round_trip() {
+        let msg = Message::new(1, 2, "hello".to_string());
+        for codec in Codec::PREFERENCE {
+            let bytes = codec.encode(&msg).unwrap();
+            assert_eq!(codec.decode::<Message>(&bytes).unwrap(), msg);
+        }
+    }
+
+    #[test]
+    /// Tests that registrations from peers unaware of negotiation still parse
+    fn test_legacy_registration() {
+        let req: ChatRequest =
+            serde_json::from_str(r#"{"request_type":"registration_to_chat","client_id":3}"#).unwrap();
+        assert!(matches!(
+            req,
+            ChatRequest::RegistrationToChat { client_id: 3, codecs } if codecs == CodecFlags::default()
+        ));
+    }
+}
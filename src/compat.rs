@@ -0,0 +1,299 @@
+//! Version-independent form of the `wg_internal` packets, for groups pinning a
+//! different release of it than this crate.
+//!
+//! Nodes built on this crate should name the protocol types through [`wg`], so that they
+//! always match the release this crate was built against. Packets crossing to code built
+//! against another release travel as [`WirePacket`], which only relies on the fields
+//! every release shares.
+
+use crate::network::RoutingError;
+use serde::{Deserialize, Serialize};
+use wg_internal::network::{NodeId, SourceRoutingHeader};
+use wg_internal::packet::{
+    FloodRequest, FloodResponse, Fragment, Nack, NackType, NodeType, Packet, PacketType,
+};
+
+/// The `wg_internal` release this crate is built against.
+pub use wg_internal as wg;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireNodeType {
+    Client,
+    Drone,
+    Server,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WireNackType {
+    ErrorInRouting(NodeId),
+    DestinationIsDrone,
+    Dropped,
+    UnexpectedRecipient(NodeId),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum WirePacketType {
+    MsgFragment {
+        fragment_index: u64,
+        total_n_fragments: u64,
+        // the framed data only, without the padding
+        data: Vec<u8>,
+    },
+    Ack {
+        fragment_index: u64,
+    },
+    Nack {
+        fragment_index: u64,
+        nack_type: WireNackType,
+    },
+    FloodRequest {
+        flood_id: u64,
+        initiator_id: NodeId,
+        path_trace: Vec<(NodeId, WireNodeType)>,
+    },
+    FloodResponse {
+        flood_id: u64,
+        path_trace: Vec<(NodeId, WireNodeType)>,
+    },
+}
+
+/// A [`Packet`] in a form any `wg_internal` release can be converted from and to.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct WirePacket {
+    pub hop_index: usize,
+    pub hops: Vec<NodeId>,
+    pub session_id: u64,
+    pub pack_type: WirePacketType,
+}
+
+impl From<NodeType> for WireNodeType {
+    fn from(node_type: NodeType) -> Self {
+        match node_type {
+            NodeType::Client => Self::Client,
+            NodeType::Drone => Self::Drone,
+            NodeType::Server => Self::Server,
+        }
+    }
+}
+
+impl From<WireNodeType> for NodeType {
+    fn from(node_type: WireNodeType) -> Self {
+        match node_type {
+            WireNodeType::Client => Self::Client,
+            WireNodeType::Drone => Self::Drone,
+            WireNodeType::Server => Self::Server,
+        }
+    }
+}
+
+impl From<NackType> for WireNackType {
+    fn from(nack_type: NackType) -> Self {
+        match nack_type {
+            NackType::ErrorInRouting(id) => Self::ErrorInRouting(id),
+            NackType::DestinationIsDrone => Self::DestinationIsDrone,
+            NackType::Dropped => Self::Dropped,
+            NackType::UnexpectedRecipient(id) => Self::UnexpectedRecipient(id),
+        }
+    }
+}
+
+impl From<WireNackType> for NackType {
+    fn from(nack_type: WireNackType) -> Self {
+        match nack_type {
+            WireNackType::ErrorInRouting(id) => Self::ErrorInRouting(id),
+            WireNackType::DestinationIsDrone => Self::DestinationIsDrone,
+            WireNackType::Dropped => Self::Dropped,
+            WireNackType::UnexpectedRecipient(id) => Self::UnexpectedRecipient(id),
+        }
+    }
+}
+
+fn wire_trace(path_trace: &[(NodeId, NodeType)]) -> Vec<(NodeId, WireNodeType)> {
+    path_trace
+        .iter()
+        .map(|(id, t)| (*id, (*t).into()))
+        .collect()
+}
+
+fn node_trace(path_trace: Vec<(NodeId, WireNodeType)>) -> Vec<(NodeId, NodeType)> {
+    path_trace
+        .into_iter()
+        .map(|(id, t)| (id, t.into()))
+        .collect()
+}
+
+impl From<&Packet> for WirePacket {
+    fn from(packet: &Packet) -> Self {
+        let pack_type = match &packet.pack_type {
+            PacketType::MsgFragment(fragment) => {
+                let length = usize::from(fragment.length).min(fragment.data.len());
+                WirePacketType::MsgFragment {
+                    fragment_index: fragment.fragment_index,
+                    total_n_fragments: fragment.total_n_fragments,
+                    data: fragment.data[..length].to_vec(),
+                }
+            }
+            PacketType::Ack(ack) => WirePacketType::Ack {
+                fragment_index: ack.fragment_index,
+            },
+            PacketType::Nack(nack) => WirePacketType::Nack {
+                fragment_index: nack.fragment_index,
+                nack_type: nack.nack_type.into(),
+            },
+            PacketType::FloodRequest(request) => WirePacketType::FloodRequest {
+                flood_id: request.flood_id,
+                initiator_id: request.initiator_id,
+                path_trace: wire_trace(&request.path_trace),
+            },
+            PacketType::FloodResponse(response) => WirePacketType::FloodResponse {
+                flood_id: response.flood_id,
+                path_trace: wire_trace(&response.path_trace),
+            },
+        };
+        Self {
+            hop_index: packet.routing_header.hop_index,
+            hops: packet.routing_header.hops.clone(),
+            session_id: packet.session_id,
+            pack_type,
+        }
+    }
+}
+
+impl TryFrom<WirePacket> for Packet {
+    type Error = RoutingError;
+
+    /// # Errors
+    /// `PayloadTooLarge` if the data of a fragment doesn't fit in one fragment.
+    fn try_from(packet: WirePacket) -> Result<Self, Self::Error> {
+        let routing_header = SourceRoutingHeader::new(packet.hops, packet.hop_index);
+        let session_id = packet.session_id;
+        Ok(match packet.pack_type {
+            WirePacketType::MsgFragment {
+                fragment_index,
+                total_n_fragments,
+                data: payload,
+            } => {
+                let mut data = [0; 128];
+                if payload.len() > data.len() {
+                    return Err(RoutingError::PayloadTooLarge { len: payload.len() });
+                }
+                data[..payload.len()].copy_from_slice(&payload);
+                let fragment = Fragment {
+                    fragment_index,
+                    total_n_fragments,
+                    #[allow(clippy::cast_possible_truncation)]
+                    length: payload.len() as u8,
+                    data,
+                };
+                Packet::new_fragment(routing_header, session_id, fragment)
+            }
+            WirePacketType::Ack { fragment_index } => {
+                Packet::new_ack(routing_header, session_id, fragment_index)
+            }
+            WirePacketType::Nack {
+                fragment_index,
+                nack_type,
+            } => Packet::new_nack(
+                routing_header,
+                session_id,
+                Nack {
+                    fragment_index,
+                    nack_type: nack_type.into(),
+                },
+            ),
+            WirePacketType::FloodRequest {
+                flood_id,
+                initiator_id,
+                path_trace,
+            } => Packet::new_flood_request(
+                routing_header,
+                session_id,
+                FloodRequest {
+                    flood_id,
+                    initiator_id,
+                    path_trace: node_trace(path_trace),
+                },
+            ),
+            WirePacketType::FloodResponse {
+                flood_id,
+                path_trace,
+            } => Packet::new_flood_response(
+                routing_header,
+                session_id,
+                FloodResponse {
+                    flood_id,
+                    path_trace: node_trace(path_trace),
+                },
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod compat_tests {
+    use super::*;
+
+    #[test]
+    /// Tests that every kind of packet survives the wire form
+    fn test_round_trip() {
+        let route = SourceRoutingHeader::new(vec![1, 2, 3], 1);
+        let mut data = [0; 128];
+        data[..3].copy_from_slice(&[0, 7, 0]);
+        let fragment = Fragment {
+            fragment_index: 1,
+            total_n_fragments: 2,
+            length: 3,
+            data,
+        };
+        let trace = vec![(1, NodeType::Client), (2, NodeType::Drone)];
+        let packets = vec![
+            Packet::new_fragment(route.clone(), 4, fragment),
+            Packet::new_ack(route.clone(), 4, 1),
+            Packet::new_nack(
+                route.clone(),
+                4,
+                Nack {
+                    fragment_index: 1,
+                    nack_type: NackType::ErrorInRouting(2),
+                },
+            ),
+            Packet::new_flood_request(
+                route.clone(),
+                4,
+                FloodRequest {
+                    flood_id: 9,
+                    initiator_id: 1,
+                    path_trace: trace.clone(),
+                },
+            ),
+            Packet::new_flood_response(
+                route,
+                4,
+                FloodResponse {
+                    flood_id: 9,
+                    path_trace: trace,
+                },
+            ),
+        ];
+        for packet in packets {
+            let wire = serde_json::to_vec(&WirePacket::from(&packet)).unwrap();
+            let wire = serde_json::from_slice::<WirePacket>(&wire).unwrap();
+            assert_eq!(Packet::try_from(wire).unwrap(), packet);
+        }
+
+        let oversized = WirePacket {
+            hop_index: 1,
+            hops: vec![1, 2],
+            session_id: 4,
+            pack_type: WirePacketType::MsgFragment {
+                fragment_index: 0,
+                total_n_fragments: 1,
+                data: vec![1; 129],
+            },
+        };
+        assert!(matches!(
+            Packet::try_from(oversized),
+            Err(RoutingError::PayloadTooLarge { len: 129 })
+        ));
+    }
+}
@@ -0,0 +1,220 @@
+//! Transparent compression of message payloads, so that large responses (e.g. JSON
+//! `WebResponse`s carrying files) take fewer fragments.
+//!
+//! With compression enabled on a `RoutingHandler`, every message sent starts with a
+//! one-byte header telling whether the rest is compressed, and every message received
+//! has it stripped before `Processor::handle_msg`. Both ends must enable it.
+
+use flate2::Compression as Level;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use std::cell::Cell;
+use std::io::{Read, Write};
+
+/// Header of a payload sent as is.
+pub const UNCOMPRESSED: u8 = 0;
+/// Header of a payload compressed with [`CompressionAlgorithm::Deflate`].
+pub const DEFLATE: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CompressionAlgorithm {
+    /// Raw deflate (RFC 1951) at the default level
+    #[default]
+    Deflate,
+}
+
+impl CompressionAlgorithm {
+    #[must_use]
+    pub fn header(self) -> u8 {
+        match self {
+            Self::Deflate => DEFLATE,
+        }
+    }
+}
+
+/// Compression applied to the messages sent, set with `RoutingHandler::set_compression`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionConfig {
+    pub algorithm: CompressionAlgorithm,
+    /// Payloads shorter than this are sent uncompressed
+    pub threshold: usize,
+    /// Bytes a received payload may inflate to, larger ones are rejected as corrupted
+    pub max_inflated: usize,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            algorithm: CompressionAlgorithm::default(),
+            threshold: 256,
+            max_inflated: 16 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Messages sent compressed
+    pub compressed: u64,
+    /// Messages sent uncompressed, below the threshold or not shrinking
+    pub uncompressed: u64,
+    /// Bytes of the compressed messages before compression
+    pub bytes_in: u64,
+    /// Bytes of the compressed messages after compression
+    pub bytes_out: u64,
+    /// Compressed messages received and inflated
+    pub decompressed: u64,
+    /// Messages received with an unknown header, corrupted data or inflating past
+    /// `max_inflated`
+    pub errors: u64,
+}
+
+impl CompressionStats {
+    /// Returns the size of the compressed messages over their original size, 1.0 when
+    /// nothing was compressed.
+    #[must_use]
+    pub fn ratio(&self) -> f64 {
+        if self.bytes_in == 0 {
+            return 1.0;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let ratio = self.bytes_out as f64 / self.bytes_in as f64;
+        ratio
+    }
+}
+
+/// Compression state of a `RoutingHandler`: its configuration and statistics.
+#[derive(Debug, Clone, Default)]
+pub struct Compressor {
+    config: CompressionConfig,
+    stats: Cell<CompressionStats>,
+}
+
+impl Compressor {
+    #[must_use]
+    pub fn new(config: CompressionConfig) -> Self {
+        Self {
+            config,
+            stats: Cell::default(),
+        }
+    }
+
+    #[must_use]
+    pub fn config(&self) -> CompressionConfig {
+        self.config
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> CompressionStats {
+        self.stats.get()
+    }
+
+    /// Returns `payload` behind its header, compressed if it reaches the threshold and
+    /// compression makes it smaller.
+    #[must_use]
+    pub fn compress(&self, payload: &[u8]) -> Vec<u8> {
+        let mut stats = self.stats.get();
+        let compressed = (payload.len() >= self.config.threshold)
+            .then(|| deflate(payload))
+            .flatten()
+            .filter(|compressed| compressed.len() < payload.len());
+        let framed = if let Some(compressed) = compressed {
+            stats.compressed += 1;
+            stats.bytes_in += payload.len() as u64;
+            stats.bytes_out += compressed.len() as u64;
+            [&[self.config.algorithm.header()], compressed.as_slice()].concat()
+        } else {
+            stats.uncompressed += 1;
+            [&[UNCOMPRESSED], payload].concat()
+        };
+        self.stats.set(stats);
+        framed
+    }
+
+    /// Strips the header of `payload` and inflates it if needed. Payloads with an unknown
+    /// header, failing to inflate or inflating past `max_inflated` are returned as
+    /// received, for the application to reject.
+    #[must_use]
+    pub fn decompress(&self, payload: Vec<u8>) -> Vec<u8> {
+        let mut stats = self.stats.get();
+        let result = match payload.split_first() {
+            Some((&UNCOMPRESSED, rest)) => Some(rest.to_vec()),
+            Some((&DEFLATE, rest)) => {
+                inflate(rest, self.config.max_inflated).inspect(|_| stats.decompressed += 1)
+            }
+            _ => None,
+        };
+        let result = result.unwrap_or_else(|| {
+            stats.errors += 1;
+            payload
+        });
+        self.stats.set(stats);
+        result
+    }
+}
+
+fn deflate(payload: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Level::default());
+    encoder.write_all(payload).ok()?;
+    encoder.finish().ok()
+}
+
+/// Inflates `data`, giving up once it exceeds `limit` bytes so that a small payload
+/// cannot expand into an arbitrarily large allocation.
+fn inflate(data: &[u8], limit: usize) -> Option<Vec<u8>> {
+    let mut payload = Vec::new();
+    DeflateDecoder::new(data)
+        .take(u64::try_from(limit).ok()?.saturating_add(1))
+        .read_to_end(&mut payload)
+        .ok()?;
+    (payload.len() <= limit).then_some(payload)
+}
+
+#[cfg(test)]
+mod compression_tests {
+    use super::*;
+
+    #[test]
+    /// Tests that large payloads shrink, small ones only get a header, and both round-trip
+    fn test_compression() {
+        let compressor = Compressor::new(CompressionConfig::default());
+        let large = br#"{"response_type":"FilesList","files":["a.txt","b.txt"]}"#.repeat(20);
+        let framed = compressor.compress(&large);
+        assert_eq!(framed[0], DEFLATE);
+        assert!(framed.len() < large.len() / 4);
+        let small = b"hello".to_vec();
+        assert_eq!(compressor.compress(&small), b"\0hello");
+
+        assert_eq!(compressor.decompress(framed), large);
+        assert_eq!(compressor.decompress(b"\0hello".to_vec()), small);
+        // corrupted data is handed over as received
+        assert_eq!(
+            compressor.decompress(vec![DEFLATE, 0xff]),
+            vec![DEFLATE, 0xff]
+        );
+
+        let stats = compressor.stats();
+        assert_eq!((stats.compressed, stats.uncompressed), (1, 1));
+        assert_eq!((stats.decompressed, stats.errors), (1, 1));
+        assert!(stats.ratio() < 0.25);
+    }
+
+    #[test]
+    /// Tests that payloads inflating past `max_inflated` are refused and counted as errors
+    fn test_inflate_limit() {
+        let sender = Compressor::new(CompressionConfig::default());
+        let bomb = sender.compress(&vec![0; 100_000]);
+        assert!(bomb.len() < 1_000);
+
+        let receiver = Compressor::new(CompressionConfig {
+            max_inflated: 10_000,
+            ..CompressionConfig::default()
+        });
+        assert_eq!(receiver.decompress(bomb.clone()), bomb);
+        let exact = sender.compress(&vec![0; 10_000]);
+        assert_eq!(receiver.decompress(exact), vec![0; 10_000]);
+
+        let stats = receiver.stats();
+        assert_eq!((stats.decompressed, stats.errors), (1, 1));
+    }
+}
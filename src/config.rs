@@ -0,0 +1,295 @@
+use crate::codec::CodecFlags;
+use anyhow::{Context, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Size of the data carried by a wire fragment, the upper bound of `fragment_size`.
+pub const MAX_FRAGMENT_SIZE: usize = 128;
+
+/// Verbosity of the `tracing` events kept by the subscriber of the application.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug,
+    Trace,
+}
+
+#[cfg(feature = "telemetry")]
+impl From<LogLevel> for tracing::level_filters::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => Self::ERROR,
+            LogLevel::Warn => Self::WARN,
+            LogLevel::Info => Self::INFO,
+            LogLevel::Debug => Self::DEBUG,
+            LogLevel::Trace => Self::TRACE,
+        }
+    }
+}
+
+/// How lost fragments are retransmitted.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Retransmissions of a fragment after `Nack`s before its session is given up,
+    /// `None` to retry forever
+    pub max_retries: Option<u32>,
+}
+
+/// Bounds on the incomplete messages kept by the assembler, enforced by
+/// `FragmentAssembler::evict_stale`. Unset bounds don't apply.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct ReassemblyLimits {
+    /// How long an incomplete message is kept without receiving any fragment
+    pub timeout_ms: Option<u64>,
+    /// Incomplete messages kept at once
+    pub max_sessions: Option<usize>,
+    /// Bytes of fragments kept across all incomplete messages
+    pub max_bytes: Option<usize>,
+}
+
+impl ReassemblyLimits {
+    #[must_use]
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+}
+
+/// Crate-wide tunables, so that deployments can change them without recompiling.
+///
+/// Every layer only overrides the keys it sets: start from [`CommonConfig::default`]
+/// and apply TOML files with [`CommonConfig::with_toml_file`] and the environment with
+/// [`CommonConfig::with_env`], or just call [`CommonConfig::load`].
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct CommonConfig {
+    /// Bytes of message data put in each fragment, at most [`MAX_FRAGMENT_SIZE`]
+    pub fragment_size: usize,
+    pub retry: RetryPolicy,
+    /// Root directory of the per-node persistent state (see `NodeState::from_config`)
+    pub cache_root: PathBuf,
+    /// Codecs offered when negotiating with peers (see `PeerCodecs::from_config`)
+    pub codecs: CodecFlags,
+    /// Interval between periodic floods refreshing the network view, `None` to only
+    /// flood on startup and on routing errors
+    pub flood_interval_ms: Option<u64>,
+    /// How long the assembler remembers delivered sessions to suppress duplicates
+    pub dedup_window_ms: u64,
+    /// How many delivered sessions the assembler remembers at most
+    pub dedup_capacity: usize,
+    pub reassembly: ReassemblyLimits,
+    /// Level of the telemetry subscriber (see [`CommonConfig::log_filter`])
+    pub log_level: LogLevel,
+}
+
+impl Default for CommonConfig {
+    fn default() -> Self {
+        Self {
+            fragment_size: MAX_FRAGMENT_SIZE,
+            retry: RetryPolicy::default(),
+            cache_root: std::env::temp_dir().join("common"),
+            codecs: CodecFlags::all(),
+            flood_interval_ms: None,
+            dedup_window_ms: 30_000,
+            dedup_capacity: 1024,
+            reassembly: ReassemblyLimits::default(),
+            log_level: LogLevel::default(),
+        }
+    }
+}
+
+impl CommonConfig {
+    /// Prefix of the environment variables read by [`Self::with_env`].
+    pub const ENV_PREFIX: &'static str = "COMMON_";
+    /// Environment variable naming a TOML file loaded by [`Self::load`].
+    pub const ENV_FILE: &'static str = "COMMON_CONFIG";
+
+    /// Builds the configuration from the defaults, the TOML file named by
+    /// `COMMON_CONFIG` if set, then the `COMMON_*` environment variables.
+    /// # Errors
+    /// Returns an error if the file cannot be read or a layer holds invalid values.
+    pub fn load() -> anyhow::Result<Self> {
+        let mut config = Self::default();
+        if let Ok(path) = std::env::var(Self::ENV_FILE) {
+            config = config.with_toml_file(path)?;
+        }
+        config.with_env()
+    }
+
+    /// Overrides the keys set in the TOML file at `path`.
+    /// # Errors
+    /// Returns an error if the file cannot be read or holds invalid values.
+    pub fn with_toml_file(self, path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let text =
+            fs::read_to_string(path).with_context(|| format!("reading {}", path.display()))?;
+        self.with_toml(&text)
+            .with_context(|| format!("parsing {}", path.display()))
+    }
+
+    /// Overrides the keys set in the TOML document `text`.
+    /// # Errors
+    /// Returns an error if `text` is not valid TOML or holds invalid values.
+    pub fn with_toml(self, text: &str) -> anyhow::Result<Self> {
+        let layer: toml::Table = text.parse()?;
+        self.with_layer(layer)
+    }
+
+    /// Overrides the keys set by the `COMMON_*` environment variables, nested keys being
+    /// separated by a double underscore (e.g. `COMMON_RETRY__MAX_RETRIES=5`).
+    /// # Errors
+    /// Returns an error if a variable holds an invalid value.
+    pub fn with_env(self) -> anyhow::Result<Self> {
+        self.with_vars(std::env::vars())
+    }
+
+    /// Same as [`Self::with_env`], reading the variables from `vars`.
+    /// # Errors
+    /// Returns an error if a variable holds an invalid value.
+    pub fn with_vars(
+        self,
+        vars: impl IntoIterator<Item = (String, String)>,
+    ) -> anyhow::Result<Self> {
+        let mut layer = toml::Table::new();
+        for (name, value) in vars {
+            let Some(key) = name.strip_prefix(Self::ENV_PREFIX) else {
+                continue;
+            };
+            if name == Self::ENV_FILE {
+                continue;
+            }
+            let path = key.to_lowercase();
+            let mut keys = path.split("__").collect::<Vec<_>>();
+            let Some(last) = keys.pop() else {
+                continue;
+            };
+            let mut table = &mut layer;
+            for k in keys {
+                table = table
+                    .entry(k)
+                    .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                    .as_table_mut()
+                    .ok_or_else(|| anyhow!("{name} conflicts with another variable"))?;
+            }
+            let _ = table.insert(last.to_string(), parse_env_value(&value));
+        }
+        self.with_layer(layer)
+    }
+
+    fn with_layer(self, layer: toml::Table) -> anyhow::Result<Self> {
+        let mut merged = toml::Table::try_from(&self)?;
+        merge_tables(&mut merged, layer);
+        let config: Self = merged.try_into()?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.fragment_size == 0 || self.fragment_size > MAX_FRAGMENT_SIZE {
+            return Err(anyhow!(
+                "fragment_size must be between 1 and {MAX_FRAGMENT_SIZE}, got {}",
+                self.fragment_size
+            ));
+        }
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn flood_interval(&self) -> Option<Duration> {
+        self.flood_interval_ms.map(Duration::from_millis)
+    }
+
+    #[must_use]
+    pub fn dedup_window(&self) -> Duration {
+        Duration::from_millis(self.dedup_window_ms)
+    }
+
+    /// Maximum level to install the `tracing` subscriber of the application with, e.g.
+    /// `tracing_subscriber::fmt().with_max_level(config.log_filter())`.
+    #[cfg(feature = "telemetry")]
+    #[must_use]
+    pub fn log_filter(&self) -> tracing::level_filters::LevelFilter {
+        self.log_level.into()
+    }
+}
+
+/// Parses an environment value as a TOML value, falling back to a plain string.
+fn parse_env_value(value: &str) -> toml::Value {
+    format!("v = {value}")
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut t| t.remove("v"))
+        .unwrap_or_else(|| toml::Value::String(value.to_string()))
+}
+
+fn merge_tables(base: &mut toml::Table, layer: toml::Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(layer)) => {
+                merge_tables(base, layer);
+            }
+            (_, value) => {
+                let _ = base.insert(key, value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+
+    #[test]
+    /// Tests that each layer only overrides the keys it sets
+    fn test_layers() {
+        let config = CommonConfig::default()
+            .with_toml(
+                "fragment_size = 64\nlog_level = \"debug\"\nflood_interval_ms = 500\n[retry]\nmax_retries = 3\n",
+            )
+            .unwrap()
+            .with_vars([
+                ("COMMON_RETRY__MAX_RETRIES".to_string(), "5".to_string()),
+                ("COMMON_CACHE_ROOT".to_string(), "/tmp/nodes".to_string()),
+                ("OTHER_DEDUP_CAPACITY".to_string(), "1".to_string()),
+                ("OTHER_FRAGMENT_SIZE".to_string(), "1".to_string()),
+            ])
+            .unwrap();
+
+        assert_eq!(config.fragment_size, 64);
+        assert_eq!(config.log_level, LogLevel::Debug);
+        assert_eq!(config.flood_interval(), Some(Duration::from_millis(500)));
+        assert_eq!(config.retry.max_retries, Some(5));
+        assert_eq!(config.cache_root, PathBuf::from("/tmp/nodes"));
+        assert_eq!(
+            config.dedup_capacity,
+            CommonConfig::default().dedup_capacity
+        );
+    }
+
+    #[test]
+    /// Tests that out of range values are refused
+    fn test_invalid_values() {
+        assert!(
+            CommonConfig::default()
+                .with_toml("fragment_size = 200")
+                .is_err()
+        );
+        assert!(
+            CommonConfig::default()
+                .with_toml("flood_interval_ms = -1")
+                .is_err()
+        );
+        assert!(
+            CommonConfig::default()
+                .with_vars([("COMMON_DEDUP_CAPACITY".to_string(), "many".to_string())])
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,715 @@
+//! Canonical wire examples of every request and response, to verify interoperability
+//! between teams without running a whole simulation.
+
+use crate::catalog::{FileDigest, FileMetadata};
+use crate::codec::{Codec, CodecFlags};
+use crate::types::{
+    ChatRequest, ChatResponse, MediaFile, MediaReference, ProtocolVersion, RoomInfo,
+    ServerType, TextFile, WebRequest, WebResponse,
+};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::fmt::Display;
+use uuid::Uuid;
+
+/// Protocol message family a test vector belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageKind {
+    WebRequest,
+    WebResponse,
+    ChatRequest,
+    ChatResponse,
+    ProtocolVersion,
+}
+
+impl MessageKind {
+    /// Decodes `bytes` as this kind of message and encodes it back.
+    fn round_trip(self, bytes: &[u8]) -> Result<Value, serde_json::Error> {
+        fn round_trip<T: Serialize + DeserializeOwned>(
+            bytes: &[u8],
+        ) -> Result<Value, serde_json::Error> {
+            serde_json::to_value(serde_json::from_slice::<T>(bytes)?)
+        }
+        match self {
+            Self::WebRequest => round_trip::<WebRequest>(bytes),
+            Self::WebResponse => round_trip::<WebResponse>(bytes),
+            Self::ChatRequest => round_trip::<ChatRequest>(bytes),
+            Self::ChatResponse => round_trip::<ChatResponse>(bytes),
+            Self::ProtocolVersion => round_trip::<ProtocolVersion>(bytes),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestVector {
+    pub name: &'static str,
+    pub kind: MessageKind,
+    pub json: &'static str,
+}
+
+const fn vector(name: &'static str, kind: MessageKind, json: &'static str) -> TestVector {
+    TestVector { name, kind, json }
+}
+
+/// Canonical JSON encoding of one message per variant.
+pub const VECTORS: &[TestVector] = &[
+    vector(
+        "web_server_type_query",
+        MessageKind::WebRequest,
+        r#"{"request_type":"server_type?"}"#,
+    ),
+    vector(
+        "web_files_list_query",
+        MessageKind::WebRequest,
+        r#"{"request_type":"files_list?"}"#,
+    ),
+    vector(
+        "web_file_query",
+        MessageKind::WebRequest,
+        r#"{"request_type":"file?","file_id":"00000000-0000-0000-0000-000000000001"}"#,
+    ),
+    vector(
+        "web_media_query",
+        MessageKind::WebRequest,
+        r#"{"request_type":"media?","media_id":"00000000-0000-0000-0000-000000000002"}"#,
+    ),
+    vector(
+        "web_catalog_digest_query",
+        MessageKind::WebRequest,
+        r#"{"request_type":"catalog_digest?"}"#,
+    ),
+    vector(
+        "web_upload_text",
+        MessageKind::WebRequest,
+        r#"{"request_type":"upload_text","file":{"id":"00000000-0000-0000-0000-000000000001","title":"title","content":"text","media_refs":[{"location":5,"id":"00000000-0000-0000-0000-000000000002"}]}}"#,
+    ),
+    vector(
+        "web_upload_media",
+        MessageKind::WebRequest,
+        r#"{"request_type":"upload_media","file":{"id":"00000000-0000-0000-0000-000000000002","title":"image","content":[[0,1,2]]}}"#,
+    ),
+    vector(
+        "web_file_chunk_request",
+        MessageKind::WebRequest,
+        r#"{"request_type":"file_chunk?","file_id":"00000000-0000-0000-0000-000000000001","offset":1024}"#,
+    ),
+    vector(
+        "web_files_page_query",
+        MessageKind::WebRequest,
+        r#"{"request_type":"files_page?","page":1,"page_size":20}"#,
+    ),
+    vector(
+        "web_server_type",
+        MessageKind::WebResponse,
+        r#"{"response_type":"server_type!","server_type":"TextServer"}"#,
+    ),
+    vector(
+        "web_files_list",
+        MessageKind::WebResponse,
+        r#"{"response_type":"files_list!","files":["00000000-0000-0000-0000-000000000001"]}"#,
+    ),
+    vector(
+        "web_text_file",
+        MessageKind::WebResponse,
+        r#"{"response_type":"file!","file_data":[123,125]}"#,
+    ),
+    vector(
+        "web_media_file",
+        MessageKind::WebResponse,
+        r#"{"response_type":"media!","media_data":[0,1,2]}"#,
+    ),
+    vector(
+        "web_file_not_found",
+        MessageKind::WebResponse,
+        r#"{"response_type":"error_requested_not_found!","file_id":"00000000-0000-0000-0000-000000000001"}"#,
+    ),
+    vector(
+        "web_bad_uuid",
+        MessageKind::WebResponse,
+        r#"{"response_type":"error_uuid_parsing!","uuid":"not-a-uuid"}"#,
+    ),
+    vector(
+        "web_upload_accepted",
+        MessageKind::WebResponse,
+        r#"{"response_type":"upload_accepted!","file_id":"00000000-0000-0000-0000-000000000001"}"#,
+    ),
+    vector(
+        "web_catalog_digest",
+        MessageKind::WebResponse,
+        r#"{"response_type":"catalog_digest!","files":[{"file_id":"00000000-0000-0000-0000-000000000001","digest":"00ff"}]}"#,
+    ),
+    vector(
+        "web_file_chunk_response",
+        MessageKind::WebResponse,
+        r#"{"response_type":"file_chunk!","file_id":"00000000-0000-0000-0000-000000000001","chunk_index":1,"total_chunks":2,"data":[0,1,2]}"#,
+    ),
+    vector(
+        "web_files_page",
+        MessageKind::WebResponse,
+        r#"{"response_type":"files_page!","entries":[{"file_id":"00000000-0000-0000-0000-000000000001","title":"title","size":4,"media_refs":1,"digest":"00ff"}],"total":21}"#,
+    ),
+    vector(
+        "chat_server_type_query",
+        MessageKind::ChatRequest,
+        r#"{"request_type":"server_type?"}"#,
+    ),
+    vector(
+        "chat_registration",
+        MessageKind::ChatRequest,
+        r#"{"request_type":"registration_to_chat","client_id":3,"codecs":7}"#,
+    ),
+    vector(
+        "chat_client_list_query",
+        MessageKind::ChatRequest,
+        r#"{"request_type":"client_list?"}"#,
+    ),
+    vector(
+        "chat_message_for",
+        MessageKind::ChatRequest,
+        r#"{"request_type":"message_for?","client_id":4,"message":"hello"}"#,
+    ),
+    vector(
+        "chat_keep_alive",
+        MessageKind::ChatRequest,
+        r#"{"request_type":"keep_alive?","client_id":3}"#,
+    ),
+    vector(
+        "chat_create_room",
+        MessageKind::ChatRequest,
+        r#"{"request_type":"create_room","name":"general"}"#,
+    ),
+    vector(
+        "chat_join_room",
+        MessageKind::ChatRequest,
+        r#"{"request_type":"join_room","room_id":1}"#,
+    ),
+    vector(
+        "chat_leave_room",
+        MessageKind::ChatRequest,
+        r#"{"request_type":"leave_room","room_id":1}"#,
+    ),
+    vector(
+        "chat_room_list_query",
+        MessageKind::ChatRequest,
+        r#"{"request_type":"room_list?"}"#,
+    ),
+    vector(
+        "chat_message_to_room",
+        MessageKind::ChatRequest,
+        r#"{"request_type":"message_to_room?","room_id":1,"message":"hello"}"#,
+    ),
+    vector(
+        "chat_server_type",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"server_type!","server_type":"ChatServer"}"#,
+    ),
+    vector(
+        "chat_client_list",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"client_list!","list_of_client_ids":[3,4]}"#,
+    ),
+    vector(
+        "chat_message_from",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"message_from!","client_id":3,"message":"hello"}"#,
+    ),
+    vector(
+        "chat_wrong_client_id",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"error_wrong_client_id!","wrong_id":9}"#,
+    ),
+    vector(
+        "chat_registration_success",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"registration_success"}"#,
+    ),
+    vector(
+        "chat_registration_accepted",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"registration_accepted","codec":"Bincode"}"#,
+    ),
+    vector(
+        "chat_keep_alive_ack",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"keep_alive!"}"#,
+    ),
+    vector(
+        "chat_room_created",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"room_created!","room_id":1,"name":"general"}"#,
+    ),
+    vector(
+        "chat_room_joined",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"room_joined!","room_id":1,"client_id":4}"#,
+    ),
+    vector(
+        "chat_room_left",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"room_left!","room_id":1,"client_id":4}"#,
+    ),
+    vector(
+        "chat_room_list",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"room_list!","rooms":[{"room_id":1,"name":"general","members":[3,4]}]}"#,
+    ),
+    vector(
+        "chat_message_from_room",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"message_from_room!","room_id":1,"client_id":3,"message":"hello"}"#,
+    ),
+    vector(
+        "chat_wrong_room_id",
+        MessageKind::ChatResponse,
+        r#"{"response_type":"error_wrong_room_id!","room_id":9}"#,
+    ),
+    vector(
+        "protocol_version_hello",
+        MessageKind::ProtocolVersion,
+        r#"{"version_type":"version?","supported":[1,2]}"#,
+    ),
+    vector(
+        "protocol_version_agreed",
+        MessageKind::ProtocolVersion,
+        r#"{"version_type":"version!","version":2}"#,
+    ),
+];
+
+/// Returns the vector called `name`.
+#[must_use]
+pub fn find(name: &str) -> Option<&'static TestVector> {
+    VECTORS.iter().find(|v| v.name == name)
+}
+
+/// Serializes the messages described by [`VECTORS`] with the current types,
+/// as `(name, json)` pairs in the same order.
+///
+/// # Panics
+///
+/// Panics if a message cannot be serialized, which is a bug in the protocol types.
+#[must_use]
+pub fn generate() -> Vec<(&'static str, String)> {
+    let mut messages = web_requests();
+    messages.extend(web_responses());
+    messages.extend(chat_requests());
+    messages.extend(chat_responses());
+    messages.extend([
+        (
+            "protocol_version_hello",
+            json(&ProtocolVersion::Hello {
+                supported: vec![1, 2],
+            }),
+        ),
+        (
+            "protocol_version_agreed",
+            json(&ProtocolVersion::Agreed { version: 2 }),
+        ),
+    ]);
+    messages
+}
+
+// ids of the file and media the vectors refer to
+const FILE_ID: Uuid = Uuid::from_u128(1);
+const MEDIA_ID: Uuid = Uuid::from_u128(2);
+
+fn json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("protocol messages always serialize")
+}
+
+/// Web requests of [`generate`].
+fn web_requests() -> Vec<(&'static str, String)> {
+    vec![
+        ("web_server_type_query", json(&WebRequest::ServerTypeQuery)),
+        (
+            "web_files_list_query",
+            json(&WebRequest::TextFilesListQuery),
+        ),
+        (
+            "web_file_query",
+            json(&WebRequest::FileQuery {
+                file_id: FILE_ID.to_string(),
+            }),
+        ),
+        (
+            "web_media_query",
+            json(&WebRequest::MediaQuery {
+                media_id: MEDIA_ID.to_string(),
+            }),
+        ),
+        (
+            "web_catalog_digest_query",
+            json(&WebRequest::CatalogDigestQuery),
+        ),
+        (
+            "web_upload_text",
+            json(&WebRequest::UploadTextFile {
+                file: TextFile {
+                    id: FILE_ID,
+                    title: "title".to_string(),
+                    content: "text".to_string(),
+                    media_refs: vec![MediaReference {
+                        location: 5,
+                        id: MEDIA_ID,
+                    }],
+                },
+            }),
+        ),
+        (
+            "web_upload_media",
+            json(&WebRequest::UploadMediaFile {
+                file: MediaFile {
+                    id: MEDIA_ID,
+                    title: "image".to_string(),
+                    content: vec![vec![0, 1, 2]],
+                    hash: String::new(),
+                },
+            }),
+        ),
+        (
+            "web_file_chunk_request",
+            json(&WebRequest::FileChunkRequest {
+                file_id: FILE_ID.to_string(),
+                offset: 1024,
+            }),
+        ),
+        (
+            "web_files_page_query",
+            json(&WebRequest::FilesListQuery {
+                page: 1,
+                page_size: 20,
+            }),
+        ),
+    ]
+}
+
+/// Web responses of [`generate`].
+fn web_responses() -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "web_server_type",
+            json(&WebResponse::ServerType {
+                server_type: ServerType::TextServer,
+            }),
+        ),
+        (
+            "web_files_list",
+            json(&WebResponse::TextFilesList {
+                files: vec![FILE_ID.to_string()],
+            }),
+        ),
+        (
+            "web_text_file",
+            json(&WebResponse::TextFile {
+                file_data: b"{}".to_vec(),
+            }),
+        ),
+        (
+            "web_media_file",
+            json(&WebResponse::MediaFile {
+                media_data: vec![0, 1, 2],
+            }),
+        ),
+        (
+            "web_file_not_found",
+            json(&WebResponse::ErrorFileNotFound(FILE_ID)),
+        ),
+        (
+            "web_bad_uuid",
+            json(&WebResponse::BadUuid("not-a-uuid".to_string())),
+        ),
+        (
+            "web_upload_accepted",
+            json(&WebResponse::UploadAccepted { file_id: FILE_ID }),
+        ),
+        (
+            "web_catalog_digest",
+            json(&WebResponse::CatalogDigest {
+                files: vec![FileDigest {
+                    file_id: FILE_ID,
+                    digest: "00ff".to_string(),
+                }],
+            }),
+        ),
+        (
+            "web_file_chunk_response",
+            json(&WebResponse::FileChunkResponse {
+                file_id: FILE_ID,
+                chunk_index: 1,
+                total_chunks: 2,
+                data: vec![0, 1, 2],
+            }),
+        ),
+        (
+            "web_files_page",
+            json(&WebResponse::FilesListResponse {
+                entries: vec![FileMetadata {
+                    file_id: FILE_ID,
+                    title: "title".to_string(),
+                    size: 4,
+                    media_refs: 1,
+                    digest: "00ff".to_string(),
+                }],
+                total: 21,
+            }),
+        ),
+    ]
+}
+
+/// Chat requests of [`generate`].
+fn chat_requests() -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "chat_server_type_query",
+            json(&ChatRequest::ServerTypeQuery),
+        ),
+        (
+            "chat_registration",
+            json(&ChatRequest::RegistrationToChat {
+                client_id: 3,
+                codecs: CodecFlags::all(),
+            }),
+        ),
+        (
+            "chat_client_list_query",
+            json(&ChatRequest::ClientListQuery),
+        ),
+        (
+            "chat_message_for",
+            json(&ChatRequest::MessageFor {
+                client_id: 4,
+                message: "hello".to_string(),
+            }),
+        ),
+        (
+            "chat_keep_alive",
+            json(&ChatRequest::KeepAlive { client_id: 3 }),
+        ),
+        (
+            "chat_create_room",
+            json(&ChatRequest::CreateRoom {
+                name: "general".to_string(),
+            }),
+        ),
+        ("chat_join_room", json(&ChatRequest::JoinRoom { room_id: 1 })),
+        ("chat_leave_room", json(&ChatRequest::LeaveRoom { room_id: 1 })),
+        ("chat_room_list_query", json(&ChatRequest::RoomListQuery)),
+        (
+            "chat_message_to_room",
+            json(&ChatRequest::MessageToRoom {
+                room_id: 1,
+                message: "hello".to_string(),
+            }),
+        ),
+    ]
+}
+
+/// Chat responses of [`generate`].
+fn chat_responses() -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "chat_server_type",
+            json(&ChatResponse::ServerType {
+                server_type: ServerType::ChatServer,
+            }),
+        ),
+        (
+            "chat_client_list",
+            json(&ChatResponse::ClientList {
+                list_of_client_ids: vec![3, 4],
+            }),
+        ),
+        (
+            "chat_message_from",
+            json(&ChatResponse::MessageFrom {
+                client_id: 3,
+                message: "hello".to_string(),
+            }),
+        ),
+        (
+            "chat_wrong_client_id",
+            json(&ChatResponse::ErrorWrongClientId { wrong_id: 9 }),
+        ),
+        (
+            "chat_registration_success",
+            json(&ChatResponse::RegistrationSuccess),
+        ),
+        (
+            "chat_registration_accepted",
+            json(&ChatResponse::RegistrationAccepted {
+                codec: Codec::Bincode,
+            }),
+        ),
+        ("chat_keep_alive_ack", json(&ChatResponse::KeepAliveAck)),
+        (
+            "chat_room_created",
+            json(&ChatResponse::RoomCreated {
+                room_id: 1,
+                name: "general".to_string(),
+            }),
+        ),
+        (
+            "chat_room_joined",
+            json(&ChatResponse::RoomJoined {
+                room_id: 1,
+                client_id: 4,
+            }),
+        ),
+        (
+            "chat_room_left",
+            json(&ChatResponse::RoomLeft {
+                room_id: 1,
+                client_id: 4,
+            }),
+        ),
+        (
+            "chat_room_list",
+            json(&ChatResponse::RoomList {
+                rooms: vec![RoomInfo {
+                    room_id: 1,
+                    name: "general".to_string(),
+                    members: vec![3, 4],
+                }],
+            }),
+        ),
+        (
+            "chat_message_from_room",
+            json(&ChatResponse::MessageFromRoom {
+                room_id: 1,
+                client_id: 3,
+                message: "hello".to_string(),
+            }),
+        ),
+        (
+            "chat_wrong_room_id",
+            json(&ChatResponse::ErrorWrongRoomId { room_id: 9 }),
+        ),
+    ]
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConformanceError {
+    UnknownVector(String),
+    /// The bytes are not a valid message of the expected kind
+    Malformed(String),
+    /// The message parses, but carries fields the protocol doesn't define
+    NotCanonical {
+        expected: String,
+        actual: String,
+    },
+    /// The message differs from the test vector
+    Mismatch {
+        expected: String,
+        actual: String,
+    },
+}
+
+impl Display for ConformanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownVector(name) => write!(f, "Unknown test vector {name}"),
+            Self::Malformed(e) => write!(f, "Malformed message: {e}"),
+            Self::NotCanonical { expected, actual } => {
+                write!(
+                    f,
+                    "Non canonical message: expected {expected}, got {actual}"
+                )
+            }
+            Self::Mismatch { expected, actual } => {
+                write!(f, "Message mismatch: expected {expected}, got {actual}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConformanceError {}
+
+/// Checks that `bytes` are a well-formed `kind` message without unknown fields.
+/// Key order and whitespace don't matter.
+///
+/// # Errors
+///
+/// `Malformed` if the bytes don't decode, `NotCanonical` if they carry more than
+/// the protocol defines.
+pub fn check(kind: MessageKind, bytes: &[u8]) -> Result<(), ConformanceError> {
+    let actual: Value =
+        serde_json::from_slice(bytes).map_err(|e| ConformanceError::Malformed(e.to_string()))?;
+    let canonical = kind
+        .round_trip(bytes)
+        .map_err(|e| ConformanceError::Malformed(e.to_string()))?;
+    if canonical != actual {
+        return Err(ConformanceError::NotCanonical {
+            expected: canonical.to_string(),
+            actual: actual.to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Checks that the bytes a peer produced for the message described by the vector
+/// `name` match it.
+///
+/// # Errors
+///
+/// `UnknownVector` if there is no such vector, any error of [`check`], or `Mismatch`
+/// if the message differs from the vector.
+pub fn check_vector(name: &str, bytes: &[u8]) -> Result<(), ConformanceError> {
+    let vector = find(name).ok_or_else(|| ConformanceError::UnknownVector(name.to_string()))?;
+    check(vector.kind, bytes)?;
+    let expected: Value = serde_json::from_str(vector.json)
+        .map_err(|e| ConformanceError::Malformed(e.to_string()))?;
+    let actual: Value =
+        serde_json::from_slice(bytes).map_err(|e| ConformanceError::Malformed(e.to_string()))?;
+    if expected != actual {
+        return Err(ConformanceError::Mismatch {
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod conformance_tests {
+    use super::*;
+
+    #[test]
+    /// Tests that the constant vectors match what the current types produce
+    fn test_vectors_up_to_date() {
+        let generated = generate();
+        assert_eq!(generated.len(), VECTORS.len());
+        for ((name, json), vector) in generated.iter().zip(VECTORS) {
+            assert_eq!(*name, vector.name);
+            assert_eq!(json, vector.json, "vector {name}");
+            check(vector.kind, json.as_bytes()).unwrap();
+        }
+    }
+
+    #[test]
+    /// Tests that the checker accepts reordered keys and refuses unknown or wrong fields
+    fn test_check_vector() {
+        check_vector(
+            "chat_message_for",
+            br#"{"message":"hello", "client_id":4, "request_type":"message_for?"}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            check_vector(
+                "chat_keep_alive",
+                br#"{"request_type":"keep_alive?","client_id":3,"extra":1}"#
+            ),
+            Err(ConformanceError::NotCanonical { .. })
+        ));
+        assert!(matches!(
+            check_vector(
+                "chat_keep_alive",
+                br#"{"request_type":"keep_alive?","client_id":4}"#
+            ),
+            Err(ConformanceError::Mismatch { .. })
+        ));
+        assert!(matches!(
+            check(MessageKind::WebRequest, br#"{"request_type":"unknown"}"#),
+            Err(ConformanceError::Malformed(_))
+        ));
+    }
+}
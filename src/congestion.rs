@@ -0,0 +1,313 @@
+use crate::ring_log::RingLog;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use wg_internal::network::NodeId;
+use wg_internal::packet::Packet;
+
+/// Phase of the congestion window kept for a destination.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CongestionPhase {
+    /// The window grows by one fragment per Ack, doubling every round trip
+    #[default]
+    SlowStart,
+    /// The window grows by one fragment per window of Acks
+    CongestionAvoidance,
+    /// A fragment was dropped, the window was halved and waits for the next Ack
+    Recovery,
+}
+
+/// Congestion state towards a destination, as listed by `RoutingHandler::congestion_state`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CongestionState {
+    pub destination: NodeId,
+    /// Fragments the transport allows in flight
+    pub window: u32,
+    /// Window past which slow start turns into congestion avoidance
+    pub threshold: u32,
+    /// Fragments sent and not acknowledged yet
+    pub in_flight: usize,
+    pub phase: CongestionPhase,
+    /// Smoothed round trip time measured on Acks, `None` before the first sample
+    pub srtt: Option<Duration>,
+    /// Spacing of the paced transmissions, zero while nothing was measured
+    pub pacing_interval: Duration,
+}
+
+/// Additive-increase/multiplicative-decrease window of a destination, driven by the
+/// Acks received and the fragments reported dropped.
+#[derive(Debug, Clone)]
+pub(crate) struct CongestionWindow {
+    window: u32,
+    threshold: u32,
+    phase: CongestionPhase,
+    // Acks counted towards the next increase in congestion avoidance
+    acked: u32,
+}
+
+impl Default for CongestionWindow {
+    fn default() -> Self {
+        Self {
+            window: Self::INITIAL_WINDOW,
+            threshold: Self::INITIAL_THRESHOLD,
+            phase: CongestionPhase::SlowStart,
+            acked: 0,
+        }
+    }
+}
+
+impl CongestionWindow {
+    const INITIAL_WINDOW: u32 = 4;
+    const INITIAL_THRESHOLD: u32 = 64;
+    const MIN_WINDOW: u32 = 2;
+
+    pub(crate) fn on_ack(&mut self) {
+        match self.phase {
+            CongestionPhase::SlowStart => {
+                self.window += 1;
+                if self.window >= self.threshold {
+                    self.phase = CongestionPhase::CongestionAvoidance;
+                }
+            }
+            CongestionPhase::CongestionAvoidance => {
+                self.acked += 1;
+                if self.acked >= self.window {
+                    self.acked = 0;
+                    self.window += 1;
+                }
+            }
+            CongestionPhase::Recovery => {
+                self.phase = CongestionPhase::CongestionAvoidance;
+            }
+        }
+    }
+
+    pub(crate) fn on_loss(&mut self) {
+        // a burst of drops from the same window only halves it once
+        if self.phase == CongestionPhase::Recovery {
+            return;
+        }
+        self.threshold = (self.window / 2).max(Self::MIN_WINDOW);
+        self.window = self.threshold;
+        self.acked = 0;
+        self.phase = CongestionPhase::Recovery;
+    }
+
+    pub(crate) fn state(&self, destination: NodeId, in_flight: usize) -> CongestionState {
+        CongestionState {
+            destination,
+            window: self.window,
+            threshold: self.threshold,
+            in_flight,
+            phase: self.phase,
+            srtt: None,
+            pacing_interval: Duration::ZERO,
+        }
+    }
+
+    pub(crate) fn window(&self) -> u32 {
+        self.window
+    }
+}
+
+/// Class of service of a session, ordering the fragments queued towards a destination,
+/// see `RoutingHandler::set_session_class`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum QosClass {
+    /// Small latency-sensitive messages, such as chat, served first
+    Interactive,
+    /// File and media transfers
+    #[default]
+    Bulk,
+}
+
+/// Spaces the transmissions towards a destination so that a window of fragments
+/// spreads over a round trip instead of leaving in a burst, never faster than the
+/// bottleneck suggested by the spacing of the Acks.
+#[derive(Debug, Clone)]
+pub(crate) struct Pacer {
+    srtt: Option<Duration>,
+    // send time of the fragments awaiting an Ack, `None` once retransmitted as their
+    // Ack could answer either transmission
+    sent_at: HashMap<(u64, u64), Option<Instant>>,
+    last_ack: Option<Instant>,
+    // latest gaps between Acks, the smallest approximates the bottleneck
+    ack_gaps: RingLog<Duration>,
+    next_send: Option<Instant>,
+    // ordered by class, first come first served within a class
+    queue: VecDeque<(QosClass, Packet)>,
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self {
+            srtt: None,
+            sent_at: HashMap::new(),
+            last_ack: None,
+            ack_gaps: RingLog::new(Self::ACK_GAPS),
+            next_send: None,
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+impl Pacer {
+    const ACK_GAPS: usize = 16;
+
+    pub(crate) fn on_sent(&mut self, session_id: u64, fragment_index: u64, at: Instant) {
+        let _ = self
+            .sent_at
+            .entry((session_id, fragment_index))
+            .and_modify(|sent| *sent = None)
+            .or_insert(Some(at));
+    }
+
+    pub(crate) fn on_ack(&mut self, session_id: u64, fragment_index: u64, at: Instant) {
+        if let Some(Some(sent)) = self.sent_at.remove(&(session_id, fragment_index)) {
+            let sample = at.saturating_duration_since(sent);
+            self.srtt = Some(match self.srtt {
+                Some(srtt) => srtt * 7 / 8 + sample / 8,
+                None => sample,
+            });
+        }
+        if let Some(last) = self.last_ack.replace(at) {
+            let _ = self.ack_gaps.push(at.saturating_duration_since(last));
+        }
+    }
+
+    pub(crate) fn srtt(&self) -> Option<Duration> {
+        self.srtt
+    }
+
+    /// Returns the spacing of the transmissions for `window` fragments per round trip.
+    pub(crate) fn interval(&self, window: u32) -> Duration {
+        let per_window = self
+            .srtt
+            .map_or(Duration::ZERO, |srtt| srtt / window.max(1));
+        let bottleneck = self.ack_gaps.iter().min().copied().unwrap_or_default();
+        per_window.max(bottleneck)
+    }
+
+    /// Queues `packet` behind the packets of its class, ahead of the less urgent ones.
+    pub(crate) fn enqueue(&mut self, packet: Packet, class: QosClass) {
+        let at = self.queue.partition_point(|(queued, _)| *queued <= class);
+        self.queue.insert(at, (class, packet));
+    }
+
+    /// Puts back at the front a packet taken by [`Self::ready`] that couldn't be sent.
+    pub(crate) fn requeue(&mut self, class: QosClass, packet: Packet) {
+        self.queue.push_front((class, packet));
+    }
+
+    /// Takes the next packet if its turn came at `now`, `paced` false taking it anyway.
+    pub(crate) fn ready(
+        &mut self,
+        now: Instant,
+        window: u32,
+        paced: bool,
+    ) -> Option<(QosClass, Packet)> {
+        if paced && self.next_send.is_some_and(|next| now < next) {
+            return None;
+        }
+        let queued = self.queue.pop_front()?;
+        self.next_send = Some(now + self.interval(window));
+        Some(queued)
+    }
+
+    pub(crate) fn queued(&self, session_id: u64) -> usize {
+        self.queue
+            .iter()
+            .filter(|(_, packet)| packet.session_id == session_id)
+            .count()
+    }
+
+    pub(crate) fn sessions(&self) -> impl Iterator<Item = u64> + '_ {
+        self.queue.iter().map(|(_, packet)| packet.session_id)
+    }
+
+    pub(crate) fn forget_session(&mut self, session_id: u64) {
+        self.queue.retain(|(_, packet)| packet.session_id != session_id);
+        self.sent_at.retain(|(sid, _), _| *sid != session_id);
+    }
+}
+
+#[cfg(test)]
+mod congestion_tests {
+    use super::*;
+    use wg_internal::network::SourceRoutingHeader;
+
+    #[test]
+    /// Tests that the window grows on Acks and is halved once per burst of drops
+    fn test_window() {
+        let mut window = CongestionWindow::default();
+        for _ in 0..60 {
+            window.on_ack();
+        }
+        let state = window.state(3, 0);
+        assert_eq!(state.window, 64);
+        assert_eq!(state.phase, CongestionPhase::CongestionAvoidance);
+
+        for _ in 0..64 {
+            window.on_ack();
+        }
+        assert_eq!(window.state(3, 0).window, 65);
+
+        window.on_loss();
+        window.on_loss();
+        let state = window.state(3, 5);
+        assert_eq!((state.window, state.threshold), (32, 32));
+        assert_eq!(state.phase, CongestionPhase::Recovery);
+        assert_eq!(state.in_flight, 5);
+
+        window.on_ack();
+        assert_eq!(
+            window.state(3, 0).phase,
+            CongestionPhase::CongestionAvoidance
+        );
+    }
+
+    #[test]
+    /// Tests that the pacing interval follows the RTT per window and the bottleneck
+    fn test_pacer() {
+        let start = Instant::now();
+        let ms = Duration::from_millis;
+        let mut pacer = Pacer::default();
+        assert_eq!(pacer.interval(4), Duration::ZERO);
+
+        pacer.on_sent(1, 0, start);
+        pacer.on_sent(1, 1, start);
+        pacer.on_sent(1, 1, start + ms(5));
+        pacer.on_ack(1, 0, start + ms(80));
+        pacer.on_ack(1, 1, start + ms(85));
+        // the retransmitted fragment gives no sample
+        assert_eq!(pacer.srtt(), Some(ms(80)));
+        assert_eq!(pacer.interval(4), ms(20));
+        assert_eq!(pacer.interval(64), ms(5));
+
+        let packet = |index| Packet::new_ack(SourceRoutingHeader::empty_route(), 1, index);
+        for index in 0..3 {
+            pacer.enqueue(packet(index), QosClass::Bulk);
+        }
+        assert!(pacer.ready(start, 4, true).is_some());
+        assert!(pacer.ready(start + ms(10), 4, true).is_none());
+        assert!(pacer.ready(start + ms(10), 4, false).is_some());
+        assert!(pacer.ready(start + ms(30), 4, true).is_some());
+        assert!(pacer.ready(start + ms(60), 4, true).is_none());
+
+        // interactive packets overtake the bulk ones, in their own order
+        pacer.enqueue(packet(3), QosClass::Bulk);
+        pacer.enqueue(packet(4), QosClass::Interactive);
+        pacer.enqueue(packet(5), QosClass::Interactive);
+        let order = (0..3)
+            .filter_map(|_| pacer.ready(start, 4, false))
+            .map(|(class, packet)| (class, packet.get_fragment_index()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            vec![
+                (QosClass::Interactive, 4),
+                (QosClass::Interactive, 5),
+                (QosClass::Bulk, 3)
+            ]
+        );
+    }
+}
@@ -0,0 +1,293 @@
+use crate::RoutingHandler;
+use crate::network::{ChannelError, NetworkError};
+use crate::types::{ServerType, WebRequest, WebResponse};
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use wg_internal::network::NodeId;
+
+/// How long [`ServiceDiscovery`] waits without flood responses before querying servers.
+pub const DEFAULT_DISCOVERY_QUIET_PERIOD: Duration = Duration::from_millis(200);
+
+/// Client-side bookkeeping of the servers found by floods and of their type.
+///
+/// Call [`poll`](Self::poll) after floods (e.g. from `Processor::tick`) to query the
+/// type of every newly discovered server, and hand the messages received to
+/// [`handle_response`](Self::handle_response) to record the answers.
+#[derive(Debug, Clone, Default)]
+pub struct Discovery {
+    // servers queried that didn't answer yet
+    pending: HashSet<NodeId>,
+    types: HashMap<NodeId, ServerType>,
+}
+
+impl Discovery {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sends a `ServerTypeQuery` to every server of the network view of `router` neither
+    /// queried nor known yet, and forgets the servers no longer in the view.
+    /// Returns the servers queried.
+    /// # Errors
+    /// Returns an error if a query cannot be sent.
+    pub fn poll(&mut self, router: &mut RoutingHandler) -> Result<Vec<NodeId>, NetworkError> {
+        let servers = router
+            .get_servers()
+            .unwrap_or_default()
+            .into_iter()
+            .collect::<HashSet<_>>();
+        self.pending.retain(|id| servers.contains(id));
+        self.types.retain(|id, _| servers.contains(id));
+
+        let mut queried = servers
+            .into_iter()
+            .filter(|id| !self.pending.contains(id) && !self.types.contains_key(id))
+            .collect::<Vec<_>>();
+        queried.sort_unstable();
+        if queried.is_empty() {
+            return Ok(queried);
+        }
+
+        // chat and web servers share the wire form of the query
+        let query = serde_json::to_vec(&WebRequest::ServerTypeQuery)
+            .map_err(|e| ChannelError::Encoding(e.to_string()))?;
+        for &server in &queried {
+            router.send_message(&query, Some(server), None)?;
+            let _ = self.pending.insert(server);
+        }
+        Ok(queried)
+    }
+
+    /// Records the type announced by `from` if `msg` is a `server_type!` response,
+    /// returning it. Other messages are ignored.
+    pub fn handle_response(&mut self, from: NodeId, msg: &[u8]) -> Option<ServerType> {
+        // chat servers answer with the same wire form as web servers
+        let Ok(WebResponse::ServerType { server_type }) = serde_json::from_slice(msg) else {
+            return None;
+        };
+        let _ = self.pending.remove(&from);
+        let _ = self.types.insert(from, server_type.clone());
+        Some(server_type)
+    }
+
+    #[must_use]
+    pub fn server_type(&self, server: NodeId) -> Option<ServerType> {
+        self.types.get(&server).cloned()
+    }
+
+    /// Returns the servers known to be of type `server_type`, in ascending order.
+    #[must_use]
+    pub fn servers_of_type(&self, server_type: &ServerType) -> Vec<NodeId> {
+        let mut servers = self
+            .types
+            .iter()
+            .filter(|(_, t)| *t == server_type)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        servers.sort_unstable();
+        servers
+    }
+
+    /// Returns the servers queried that didn't answer yet, in ascending order.
+    #[must_use]
+    pub fn pending(&self) -> Vec<NodeId> {
+        let mut pending = self.pending.iter().copied().collect::<Vec<_>>();
+        pending.sort_unstable();
+        pending
+    }
+
+    /// Forgets `server`, so that it is queried again by the next [`poll`](Self::poll).
+    pub fn forget(&mut self, server: NodeId) {
+        let _ = self.pending.remove(&server);
+        let _ = self.types.remove(&server);
+    }
+
+    /// Returns the servers whose type is known.
+    #[must_use]
+    pub fn discovered_servers(&self) -> HashMap<NodeId, ServerType> {
+        self.types.clone()
+    }
+}
+
+/// [`Discovery`] driven by the floods of the node, run by `Processor::tick` and
+/// `Processor::handle_packet` when `Processor::service_discovery` provides one.
+///
+/// Once the latest flood settled, i.e. no response arrived for the quiet period, the
+/// servers of the network view of unknown type are queried, including the ones that
+/// didn't answer the previous round. `server_type!` responses are consumed instead of
+/// reaching `handle_msg`, and the controller is sent a `NodeEvent::ServersDiscovered`
+/// whenever a round ends with the known servers changed.
+#[derive(Debug, Clone)]
+pub struct ServiceDiscovery {
+    discovery: Discovery,
+    quiet_period: Duration,
+    // latest flood whose servers were queried
+    polled_flood: u64,
+    // servers as last announced to the controller
+    announced: HashMap<NodeId, ServerType>,
+}
+
+impl Default for ServiceDiscovery {
+    fn default() -> Self {
+        Self::new(DEFAULT_DISCOVERY_QUIET_PERIOD)
+    }
+}
+
+impl ServiceDiscovery {
+    #[must_use]
+    pub fn new(quiet_period: Duration) -> Self {
+        Self {
+            discovery: Discovery::new(),
+            quiet_period,
+            polled_flood: 0,
+            announced: HashMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn discovery(&self) -> &Discovery {
+        &self.discovery
+    }
+
+    /// Returns the servers whose type is known.
+    #[must_use]
+    pub fn discovered_servers(&self) -> HashMap<NodeId, ServerType> {
+        self.discovery.discovered_servers()
+    }
+
+    /// Queries the servers found by the latest flood of `router` once it settled, at most
+    /// once per flood. Returns the servers queried.
+    /// # Errors
+    /// Returns an error if a query cannot be sent.
+    pub fn poll(&mut self, router: &mut RoutingHandler) -> Result<Vec<NodeId>, NetworkError> {
+        let flood = router.floods_started();
+        let now = router.now();
+        let settled = router
+            .last_flood_activity()
+            .is_some_and(|activity| now.duration_since(activity) >= self.quiet_period);
+        if flood == self.polled_flood || !settled {
+            return Ok(Vec::new());
+        }
+        self.polled_flood = flood;
+        self.discovery.pending.clear();
+        let queried = self.discovery.poll(router)?;
+        self.announce(router);
+        Ok(queried)
+    }
+
+    /// Records `msg` if it is the `server_type!` response of `from`, returning whether it
+    /// was.
+    pub fn handle_response(&mut self, router: &RoutingHandler, from: NodeId, msg: &[u8]) -> bool {
+        if self.discovery.handle_response(from, msg).is_none() {
+            return false;
+        }
+        self.announce(router);
+        true
+    }
+
+    /// Notifies the controller of the known servers once no query is pending, if they
+    /// changed since the last notification.
+    fn announce(&mut self, router: &RoutingHandler) {
+        if !self.discovery.pending.is_empty() || self.discovery.types == self.announced {
+            return;
+        }
+        self.announced.clone_from(&self.discovery.types);
+        router.notify_servers_discovered(self.announced.clone());
+    }
+}
+
+#[cfg(test)]
+mod discovery_tests {
+    use super::*;
+    use crate::types::NodeEvent;
+    use crossbeam_channel::unbounded;
+    use wg_internal::packet::{FloodResponse, NodeType, PacketType};
+
+    #[test]
+    /// Tests that discovered servers are queried once and cached by type
+    fn test_discovery() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut router = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        router.add_neighbor(2, neighbor_sender);
+        router.start_flood(None).unwrap();
+        let _ = neighbor_receiver.try_iter().count();
+        router
+            .handle_flood_response(&FloodResponse {
+                flood_id: 1,
+                path_trace: vec![(1, NodeType::Client), (2, NodeType::Server)],
+            })
+            .unwrap();
+
+        let mut discovery = Discovery::new();
+        assert_eq!(discovery.poll(&mut router).unwrap(), vec![2]);
+        let query = neighbor_receiver.try_recv().unwrap();
+        assert!(matches!(query.pack_type, PacketType::MsgFragment(_)));
+        assert_eq!(discovery.pending(), vec![2]);
+        assert!(discovery.poll(&mut router).unwrap().is_empty());
+
+        assert_eq!(
+            discovery.handle_response(2, b"{\"response_type\":\"client_list!\"}"),
+            None
+        );
+        let response = serde_json::to_vec(&WebResponse::ServerType {
+            server_type: ServerType::MediaServer,
+        })
+        .unwrap();
+        assert_eq!(
+            discovery.handle_response(2, &response),
+            Some(ServerType::MediaServer)
+        );
+        assert_eq!(discovery.servers_of_type(&ServerType::MediaServer), vec![2]);
+        assert!(discovery.servers_of_type(&ServerType::TextServer).is_empty());
+        assert!(discovery.pending().is_empty());
+        assert!(discovery.poll(&mut router).unwrap().is_empty());
+    }
+
+    #[test]
+    /// Tests that servers are queried once a flood settles and announced once they answer
+    fn test_service_discovery() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut router = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        router.add_neighbor(2, neighbor_sender);
+        let mut discovery = ServiceDiscovery::new(Duration::ZERO);
+        // nothing to query before the first flood
+        assert!(discovery.poll(&mut router).unwrap().is_empty());
+
+        router.start_flood(None).unwrap();
+        router
+            .handle_flood_response(&FloodResponse {
+                flood_id: 1,
+                path_trace: vec![(1, NodeType::Client), (2, NodeType::Server)],
+            })
+            .unwrap();
+        let _ = neighbor_receiver.try_iter().count();
+        assert_eq!(discovery.poll(&mut router).unwrap(), vec![2]);
+        assert!(discovery.poll(&mut router).unwrap().is_empty());
+        assert_eq!(neighbor_receiver.try_iter().count(), 1);
+
+        assert!(!discovery.handle_response(&router, 2, b"{\"response_type\":\"client_list!\"}"));
+        let response = serde_json::to_vec(&WebResponse::ServerType {
+            server_type: ServerType::TextServer,
+        })
+        .unwrap();
+        assert!(discovery.handle_response(&router, 2, &response));
+        let expected = HashMap::from([(2, ServerType::TextServer)]);
+        assert_eq!(discovery.discovered_servers(), expected);
+
+        // known servers aren't queried nor announced again after the next flood
+        router.start_flood(None).unwrap();
+        assert!(discovery.poll(&mut router).unwrap().is_empty());
+        let announced = controller_recv
+            .try_iter()
+            .filter_map(|e| e.into_any().downcast::<NodeEvent>().ok())
+            .filter_map(|e| match *e {
+                NodeEvent::ServersDiscovered { servers, .. } => Some(servers),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(announced, vec![expected]);
+    }
+}
@@ -0,0 +1,468 @@
+use crate::config::CommonConfig;
+use crate::node_state::NodeState;
+use crate::storage::{FsStorage, StorageBackend};
+use crate::types::{File, MediaFile, TextFile};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::path::Path;
+use uuid::Uuid;
+use wg_internal::network::NodeId;
+
+/// Kind of a file kept by a [`FileCache`].
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CachedKind {
+    Text,
+    Media,
+}
+
+/// Index entry of a cached file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    pub id: Uuid,
+    pub kind: CachedKind,
+    pub title: String,
+    /// Bytes taken on disk
+    pub size: u64,
+    // value of the cache clock when the file was last inserted or read
+    last_used: u64,
+}
+
+impl CacheEntry {
+    /// Name of the file holding the entry: the id, with the extension of the media title
+    /// so that cached media open with the right application.
+    fn file_name(&self) -> String {
+        let extension = match self.kind {
+            CachedKind::Text => Some("json"),
+            CachedKind::Media => Path::new(&self.title)
+                .extension()
+                .and_then(|ext| ext.to_str())
+                .filter(|ext| ext.chars().all(|c| c.is_ascii_alphanumeric())),
+        };
+        match extension {
+            Some(extension) => format!("{}.{extension}", self.id),
+            None => self.id.to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct CacheIndex {
+    entries: HashMap<Uuid, CacheEntry>,
+    clock: u64,
+}
+
+/// Cached text and media files, indexed by id and bounded in size.
+///
+/// Text files are stored as JSON and media files as their original bytes, next to an
+/// `index.json` manifest listing them. Once the files exceed the size limit, the least
+/// recently inserted or read ones are evicted. Servers keep their files in it as well
+/// as browsing clients. The files live in a directory by default, or in any other
+/// [`StorageBackend`] given to [`Self::with_storage`].
+#[derive(Debug)]
+pub struct FileCache<S: StorageBackend = FsStorage> {
+    storage: S,
+    max_bytes: u64,
+    index: CacheIndex,
+}
+
+impl FileCache {
+    /// Opens the cache in `dir`, creating it on first use, holding at most `max_bytes`.
+    /// Indexed files missing from the directory are forgotten.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created or the index is corrupted.
+    pub fn open(dir: impl AsRef<Path>, max_bytes: u64) -> io::Result<Self> {
+        Self::with_storage(FsStorage::open(dir)?, max_bytes)
+    }
+
+    /// Opens the cache of node `id` in the state directory it has under the `cache_root`
+    /// of `config`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state directory or the cache cannot be opened.
+    pub fn from_config(config: &CommonConfig, id: NodeId, max_bytes: u64) -> io::Result<Self> {
+        Self::for_node(&NodeState::from_config(config, id)?, max_bytes)
+    }
+
+    /// Opens the cache kept in the state directory of a node.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache cannot be opened, see [`Self::open`].
+    pub fn for_node(state: &NodeState, max_bytes: u64) -> io::Result<Self> {
+        Self::open(state.cache_dir(), max_bytes)
+    }
+
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        self.storage.dir()
+    }
+}
+
+impl<S: StorageBackend> FileCache<S> {
+    const INDEX_FILE: &'static str = "index.json";
+
+    /// Opens the cache kept in `storage`, holding at most `max_bytes`. Indexed files
+    /// missing from the storage are forgotten.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the storage cannot be read or the index is corrupted.
+    pub fn with_storage(storage: S, max_bytes: u64) -> io::Result<Self> {
+        let mut index: CacheIndex = match storage.get(Self::INDEX_FILE)? {
+            Some(data) => serde_json::from_slice(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            None => CacheIndex::default(),
+        };
+        let stored = storage.list()?.into_iter().collect::<HashSet<_>>();
+        index
+            .entries
+            .retain(|_, entry| stored.contains(&entry.file_name()));
+        let mut cache = Self {
+            storage,
+            max_bytes,
+            index,
+        };
+        let _ = cache.evict(&[])?;
+        Ok(cache)
+    }
+
+    #[must_use]
+    pub fn storage(&self) -> &S {
+        &self.storage
+    }
+
+    #[must_use]
+    pub fn max_bytes(&self) -> u64 {
+        self.max_bytes
+    }
+
+    /// Changes the size limit, evicting files as needed. Returns the ids evicted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an evicted file cannot be removed or the index cannot be written.
+    pub fn set_max_bytes(&mut self, max_bytes: u64) -> io::Result<Vec<Uuid>> {
+        self.max_bytes = max_bytes;
+        self.evict(&[])
+    }
+
+    /// Returns the bytes taken by the cached files.
+    #[must_use]
+    pub fn size(&self) -> u64 {
+        self.index.entries.values().map(|entry| entry.size).sum()
+    }
+
+    #[must_use]
+    pub fn contains(&self, id: Uuid) -> bool {
+        self.index.entries.contains_key(&id)
+    }
+
+    /// Lists the cached files by id.
+    #[must_use]
+    pub fn list(&self) -> Vec<&CacheEntry> {
+        let mut entries = self.index.entries.values().collect::<Vec<_>>();
+        entries.sort_by_key(|entry| entry.id);
+        entries
+    }
+
+    /// Caches `file`, replacing any previous version. Returns the ids evicted to make room.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file is larger than the cache or cannot be written.
+    pub fn insert_text(&mut self, file: &TextFile) -> io::Result<Vec<Uuid>> {
+        let data = serde_json::to_vec(file).map_err(io::Error::other)?;
+        self.store(file.id, CachedKind::Text, &file.title, &data)?;
+        self.evict(&[file.id])
+    }
+
+    /// Caches `file` as its original bytes, replacing any previous version. Returns the
+    /// ids evicted to make room.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file is larger than the cache, doesn't match its hash or
+    /// cannot be written.
+    pub fn insert_media(&mut self, file: &MediaFile) -> io::Result<Vec<Uuid>> {
+        let data = media_bytes(file)?;
+        self.store(file.id, CachedKind::Media, &file.title, &data)?;
+        self.evict(&[file.id])
+    }
+
+    /// Caches the text of `file` and its media. Returns the ids evicted to make room,
+    /// which are never parts of `file`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the parts don't fit in the cache together or any of them
+    /// cannot be cached.
+    pub fn insert_file(&mut self, file: &File) -> io::Result<Vec<Uuid>> {
+        let text = serde_json::to_vec(&file.text_file).map_err(io::Error::other)?;
+        let media = file
+            .media_files
+            .iter()
+            .map(|media| Ok((media, media_bytes(media)?)))
+            .collect::<io::Result<Vec<_>>>()?;
+        let size = text.len() + media.iter().map(|(_, data)| data.len()).sum::<usize>();
+        self.check_fits(size as u64)?;
+
+        let title = &file.text_file.title;
+        self.store(file.text_file.id, CachedKind::Text, title, &text)?;
+        for (media, data) in &media {
+            self.store(media.id, CachedKind::Media, &media.title, data)?;
+        }
+        let mut parts = vec![file.text_file.id];
+        parts.extend(file.media_files.iter().map(|media| media.id));
+        self.evict(&parts)
+    }
+
+    fn check_fits(&self, size: u64) -> io::Result<()> {
+        if size > self.max_bytes {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{size} bytes don't fit in a cache of {}", self.max_bytes),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Writes `data` as the file `id`, without making room for it.
+    fn store(&mut self, id: Uuid, kind: CachedKind, title: &str, data: &[u8]) -> io::Result<()> {
+        let size = data.len() as u64;
+        self.check_fits(size)?;
+        if let Some(previous) = self.index.entries.remove(&id) {
+            let _ = self.storage.delete(&previous.file_name());
+        }
+        self.index.clock += 1;
+        let entry = CacheEntry {
+            id,
+            kind,
+            title: title.to_string(),
+            size,
+            last_used: self.index.clock,
+        };
+        self.storage.put(&entry.file_name(), data)?;
+        let _ = self.index.entries.insert(id, entry);
+        Ok(())
+    }
+
+    /// Returns the cached text file `id`, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cached copy cannot be read or decoded.
+    pub fn get_text(&mut self, id: Uuid) -> io::Result<Option<TextFile>> {
+        let Some(data) = self.read(id, CachedKind::Text)? else {
+            return Ok(None);
+        };
+        serde_json::from_slice(&data)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Returns the cached media file `id`, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cached copy cannot be read.
+    pub fn get_media(&mut self, id: Uuid) -> io::Result<Option<MediaFile>> {
+        let Some(data) = self.read(id, CachedKind::Media)? else {
+            return Ok(None);
+        };
+        let title = self.index.entries[&id].title.clone();
+        let mut media = MediaFile::from_u8(title, &data);
+        media.id = id;
+        Ok(Some(media))
+    }
+
+    /// Reads the entry `id` of `kind`, making it the most recently used.
+    fn read(&mut self, id: Uuid, kind: CachedKind) -> io::Result<Option<Vec<u8>>> {
+        self.index.clock += 1;
+        let clock = self.index.clock;
+        let Some(entry) = self
+            .index
+            .entries
+            .get_mut(&id)
+            .filter(|entry| entry.kind == kind)
+        else {
+            return Ok(None);
+        };
+        entry.last_used = clock;
+        let file_name = entry.file_name();
+        let Some(data) = self.storage.get(&file_name)? else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("cached file {file_name} is missing"),
+            ));
+        };
+        self.save_index()?;
+        Ok(Some(data))
+    }
+
+    /// Removes the file `id` from the cache, returns whether it was cached.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be removed or the index cannot be written.
+    pub fn delete(&mut self, id: Uuid) -> io::Result<bool> {
+        let Some(entry) = self.index.entries.remove(&id) else {
+            return Ok(false);
+        };
+        let _ = self.storage.delete(&entry.file_name())?;
+        self.save_index()?;
+        Ok(true)
+    }
+
+    /// Evicts the least recently used files, `keep` excepted, until the cache fits its
+    /// limit, then persists the index. Returns the ids evicted.
+    fn evict(&mut self, keep: &[Uuid]) -> io::Result<Vec<Uuid>> {
+        let mut by_age = self
+            .index
+            .entries
+            .values()
+            .filter(|entry| !keep.contains(&entry.id))
+            .map(|entry| (entry.last_used, entry.id))
+            .collect::<Vec<_>>();
+        by_age.sort_unstable();
+        let mut size = self.size();
+        let mut evicted = Vec::new();
+        for (_, id) in by_age {
+            if size <= self.max_bytes {
+                break;
+            }
+            if let Some(entry) = self.index.entries.remove(&id) {
+                let _ = self.storage.delete(&entry.file_name())?;
+                size -= entry.size;
+                evicted.push(id);
+            }
+        }
+        self.save_index()?;
+        Ok(evicted)
+    }
+
+    fn save_index(&mut self) -> io::Result<()> {
+        let data = serde_json::to_vec_pretty(&self.index).map_err(io::Error::other)?;
+        self.storage.put(Self::INDEX_FILE, &data)
+    }
+}
+
+fn media_bytes(file: &MediaFile) -> io::Result<Vec<u8>> {
+    file.to_bytes()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+#[cfg(test)]
+mod file_cache_tests {
+    use super::*;
+    use crate::storage::MemoryStorage;
+    use std::fs;
+    use tempfile::tempdir;
+
+    #[test]
+    /// Tests inserting, reading, listing and deleting files, and reopening the cache
+    fn test_file_cache() {
+        let root = tempdir().unwrap();
+        let config = CommonConfig {
+            cache_root: root.path().to_path_buf(),
+            ..CommonConfig::default()
+        };
+        let mut cache = FileCache::from_config(&config, 3, 10_000).unwrap();
+        let dir = cache.dir().to_path_buf();
+        assert!(dir.starts_with(&config.cache_root));
+        let text = TextFile::new("Notes".to_string(), "hello".to_string(), vec![]);
+        let media = MediaFile::from_u8("photo.png".to_string(), &[3; 2000]);
+        let file = File::new(text.clone(), vec![media.clone()]);
+        assert!(cache.insert_file(&file).unwrap().is_empty());
+
+        assert_eq!(cache.get_text(text.id).unwrap(), Some(text.clone()));
+        assert_eq!(cache.get_media(media.id).unwrap(), Some(media.clone()));
+        assert_eq!(cache.get_media(text.id).unwrap(), None);
+        // media keep their bytes and extension on disk
+        let on_disk = fs::read(dir.join(format!("{}.png", media.id))).unwrap();
+        assert_eq!(on_disk, vec![3; 2000]);
+
+        let mut cache = FileCache::open(&dir, 10_000).unwrap();
+        let kinds = cache
+            .list()
+            .iter()
+            .map(|e| (e.id, e.kind))
+            .collect::<HashMap<_, _>>();
+        assert_eq!(kinds[&text.id], CachedKind::Text);
+        assert_eq!(kinds[&media.id], CachedKind::Media);
+        assert!(cache.delete(media.id).unwrap());
+        assert!(!cache.delete(media.id).unwrap());
+        assert_eq!(cache.list().len(), 1);
+        assert!(
+            cache
+                .insert_media(&MediaFile::from_u8("big".to_string(), &vec![0; 20_000]))
+                .is_err()
+        );
+    }
+
+    #[test]
+    /// Tests that the least recently used files are evicted once the cache is full
+    fn test_lru_eviction() {
+        let dir = tempdir().unwrap();
+        let mut cache = FileCache::open(dir.path(), 2500).unwrap();
+        let media = |name: &str| MediaFile::from_u8(name.to_string(), &[1; 1000]);
+        let (a, b, c) = (media("a"), media("b"), media("c"));
+        let _ = cache.insert_media(&a).unwrap();
+        let _ = cache.insert_media(&b).unwrap();
+        // reading `a` makes `b` the least recently used
+        assert!(cache.get_media(a.id).unwrap().is_some());
+        assert_eq!(cache.insert_media(&c).unwrap(), vec![b.id]);
+        assert!(cache.contains(a.id) && cache.contains(c.id));
+        assert_eq!(cache.size(), 2000);
+
+        assert_eq!(cache.set_max_bytes(1000).unwrap(), vec![a.id]);
+        let cache = FileCache::open(dir.path(), 1000).unwrap();
+        assert_eq!(
+            cache.list().iter().map(|e| e.id).collect::<Vec<_>>(),
+            vec![c.id]
+        );
+    }
+
+    #[test]
+    /// Tests that caching a file evicts other files only, and never a part of itself
+    fn test_file_eviction() {
+        let mut cache = FileCache::with_storage(MemoryStorage::new(), 2500).unwrap();
+        let old = MediaFile::from_u8("old".to_string(), &[1; 1000]);
+        let _ = cache.insert_media(&old).unwrap();
+        let text = TextFile::new("Notes".to_string(), "hello".to_string(), vec![]);
+        let media = MediaFile::from_u8("photo".to_string(), &[2; 1450]);
+        let file = File::new(text.clone(), vec![media.clone()]);
+        assert_eq!(cache.insert_file(&file).unwrap(), vec![old.id]);
+        assert!(cache.contains(text.id) && cache.contains(media.id));
+
+        let halves = ["a", "b"].map(|name| MediaFile::from_u8(name.to_string(), &[3; 1500]));
+        let big = File::new(text, halves.to_vec());
+        assert!(cache.insert_file(&big).is_err());
+        assert!(halves.iter().all(|half| !cache.contains(half.id)));
+        assert!(cache.contains(media.id));
+    }
+
+    #[test]
+    /// Tests a cache running without a disk
+    fn test_in_memory_cache() {
+        let mut cache = FileCache::with_storage(MemoryStorage::new(), 2500).unwrap();
+        let text = TextFile::new("Notes".to_string(), "hello".to_string(), vec![]);
+        let media = MediaFile::from_u8("photo.png".to_string(), &[3; 2000]);
+        let _ = cache.insert_text(&text).unwrap();
+        let _ = cache.insert_media(&media).unwrap();
+        assert_eq!(cache.get_text(text.id).unwrap(), Some(text.clone()));
+        let keys = cache.storage().list().unwrap();
+        assert!(keys.contains(&format!("{}.png", media.id)));
+        assert!(keys.contains(&"index.json".to_string()));
+
+        // reopening the same storage keeps the index
+        let storage = cache.storage().clone();
+        let mut cache = FileCache::with_storage(storage, 2500).unwrap();
+        assert_eq!(cache.get_media(media.id).unwrap(), Some(media));
+        assert!(cache.delete(text.id).unwrap());
+        assert_eq!(cache.list().len(), 1);
+    }
+}
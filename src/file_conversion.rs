@@ -1,7 +1,9 @@
-use std::fs::{self, File as StdFile};
+use std::fmt::Write;
+use std::fs;
+use std::io;
 use std::path::Path;
+use crate::storage::{FsStorage, StorageBackend};
 use crate::types::{MediaFile, TextFile, File};
-use std::io::Write;
 
 /// Saves a [`File`] into a directory named `cached_files_{notification_from}`.
 ///
@@ -12,21 +14,9 @@ use std::io::Write;
 ///
 /// Returns an error if the directory cannot be created or if the file cannot
 /// be created or written to.
-pub fn save_file(notification_from: &u8, file: &File) -> std::io::Result<()> {
-    let dir_name = format!("cached_files_{notification_from}");
-    let dir_path = Path::new(&dir_name);
-    fs::create_dir_all(dir_path)?;
-
-    let file_name = format!("{}_{}", file.text_file.id, file.text_file.title);
-    let file_path = dir_path.join(file_name);
-
-    let mut f = StdFile::create(file_path)?;
-    writeln!(f, "{}", file.text_file.content)?;
-    for media_file in &file.media_files {
-        writeln!(f, "MediaFile attached: {}_{}", media_file.id, media_file.title)?;
-    }
-    save_media_files(notification_from, &file.media_files)?;
-    Ok(())
+#[deprecated(note = "use `FileCache`, which indexes the files and bounds the cache size")]
+pub fn save_file(notification_from: &u8, file: &File) -> io::Result<()> {
+    save_file_in(&mut cached_files(*notification_from)?, file)
 }
 
 /// Saves a list of [`File`]s into `cached_files_{notification_from}` by
@@ -35,8 +25,10 @@ pub fn save_file(notification_from: &u8, file: &File) -> std::io::Result<()> {
 /// # Errors
 ///
 /// Returns an error if saving any single file fails.
-pub fn save_files(notification_from: &u8, files: &Vec<File>) -> std::io::Result<()> {
+#[deprecated(note = "use `FileCache`, which indexes the files and bounds the cache size")]
+pub fn save_files(notification_from: &u8, files: &Vec<File>) -> io::Result<()> {
     for file in files {
+        #[allow(deprecated)]
         save_file(notification_from, file)?;
     }
     Ok(())
@@ -51,20 +43,9 @@ pub fn save_files(notification_from: &u8, files: &Vec<File>) -> std::io::Result<
 ///
 /// Returns an error if the directory cannot be created or if the file cannot
 /// be created or written to.
-pub fn save_text_file(notification_from: &u8, file: &TextFile) -> std::io::Result<()> {
-    let dir_name = format!("cached_files_{notification_from}");
-    let dir_path = Path::new(&dir_name);
-    fs::create_dir_all(dir_path)?;
-
-    let file_name = format!("{}_{}", file.id, file.title);
-    let file_path = dir_path.join(file_name);
-
-    let mut f = StdFile::create(file_path)?;
-    writeln!(f, "{}", file.content)?;
-    for media_ref in &file.media_refs {
-        writeln!(f, "MediaFile attached: {}_{}", media_ref.location, media_ref.id)?;
-    }
-    Ok(())
+#[deprecated(note = "use `FileCache`, which indexes the files and bounds the cache size")]
+pub fn save_text_file(notification_from: &u8, file: &TextFile) -> io::Result<()> {
+    save_text_file_in(&mut cached_files(*notification_from)?, file)
 }
 
 /// Saves a list of [`TextFile`]s by delegating to [`save_text_file`].
@@ -72,8 +53,10 @@ pub fn save_text_file(notification_from: &u8, file: &TextFile) -> std::io::Resul
 /// # Errors
 ///
 /// Returns an error if saving any single file fails.
-pub fn save_text_files(notification_from: &u8, files: &Vec<TextFile>) -> std::io::Result<()> {
+#[deprecated(note = "use `FileCache`, which indexes the files and bounds the cache size")]
+pub fn save_text_files(notification_from: &u8, files: &Vec<TextFile>) -> io::Result<()> {
     for file in files {
+        #[allow(deprecated)]
         save_text_file(notification_from, file)?;
     }
     Ok(())
@@ -81,25 +64,16 @@ pub fn save_text_files(notification_from: &u8, files: &Vec<TextFile>) -> std::io
 
 /// Saves a single [`MediaFile`] into `cached_files_{notification_from}`.
 ///
-/// The file is written as `{id}_{title}` and its binary content flushed.
+/// The original bytes are written as `{id}_{title}`, with the extension of the
+/// recognized format appended when the title has none, so that the file opens.
 ///
 /// # Errors
 ///
-/// Returns an error if the directory cannot be created or if the file cannot
-/// be created or written to.
-pub fn save_media_file(notification_from: &u8, file: &MediaFile) -> std::io::Result<()> {
-    let dir_name = format!("cached_files_{notification_from}");
-    let dir_path = Path::new(&dir_name);
-    fs::create_dir_all(dir_path)?;
-
-    let file_name = format!("{}_{}", file.id, file.title);
-    let file_path = dir_path.join(file_name);
-
-    let mut f = StdFile::create(file_path)?;
-    for chunk in &file.content {
-        f.write_all(chunk)?;
-    }
-    Ok(())
+/// Returns an error if the directory cannot be created, if the file cannot
+/// be created or written to, or if its chunks don't match its hash.
+#[deprecated(note = "use `FileCache`, which indexes the files and bounds the cache size")]
+pub fn save_media_file(notification_from: &u8, file: &MediaFile) -> io::Result<()> {
+    save_media_file_in(&mut cached_files(*notification_from)?, file)
 }
 
 /// Saves a list of [`MediaFile`]s by delegating to [`save_media_file`].
@@ -107,13 +81,109 @@ pub fn save_media_file(notification_from: &u8, file: &MediaFile) -> std::io::Res
 /// # Errors
 ///
 /// Returns an error if saving any single file fails.
-pub fn save_media_files(notification_from: &u8, files: &[MediaFile]) -> std::io::Result<()> {
+#[deprecated(note = "use `FileCache`, which indexes the files and bounds the cache size")]
+pub fn save_media_files(notification_from: &u8, files: &[MediaFile]) -> io::Result<()> {
     for file in files {
+        #[allow(deprecated)]
         save_media_file(notification_from, file)?;
     }
     Ok(())
 }
 
+/// Directory `cached_files_{notification_from}` written by the deprecated helpers.
+fn cached_files(notification_from: u8) -> io::Result<FsStorage> {
+    FsStorage::open(format!("cached_files_{notification_from}"))
+}
+
+/// Saves a [`File`] into `storage` as `{id}_{title}`: the [`TextFile`] content with a
+/// line for each attached [`MediaFile`], which are saved as well.
+///
+/// # Errors
+///
+/// Returns an error if any of them cannot be saved.
+pub fn save_file_in(storage: &mut impl StorageBackend, file: &File) -> io::Result<()> {
+    let mut data = format!("{}\n", file.text_file.content);
+    for media_file in &file.media_files {
+        let _ = writeln!(
+            data,
+            "MediaFile attached: {}_{}",
+            media_file.id, media_file.title
+        );
+    }
+    storage.put(
+        &format!("{}_{}", file.text_file.id, file.text_file.title),
+        data.as_bytes(),
+    )?;
+    for media_file in &file.media_files {
+        save_media_file_in(storage, media_file)?;
+    }
+    Ok(())
+}
+
+/// Saves a [`TextFile`] into `storage` as `{id}_{title}`: its content with a line for
+/// each attached [`MediaReference`].
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be saved.
+pub fn save_text_file_in(storage: &mut impl StorageBackend, file: &TextFile) -> io::Result<()> {
+    let mut data = format!("{}\n", file.content);
+    for media_ref in &file.media_refs {
+        let _ = writeln!(
+            data,
+            "MediaFile attached: {}_{}",
+            media_ref.location, media_ref.id
+        );
+    }
+    storage.put(&format!("{}_{}", file.id, file.title), data.as_bytes())
+}
+
+/// Saves the original bytes of a [`MediaFile`] into `storage` as `{id}_{title}`, with
+/// the extension of the recognized format appended when the title has none.
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be saved or its chunks don't match its hash.
+pub fn save_media_file_in(storage: &mut impl StorageBackend, file: &MediaFile) -> io::Result<()> {
+    let data = file
+        .to_bytes()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut key = format!("{}_{}", file.id, file.title);
+    if Path::new(&file.title).extension().is_none() {
+        if let Some(extension) = file.extension() {
+            key = format!("{key}.{extension}");
+        }
+    }
+    storage.put(&key, &data)
+}
+
+/// Reads the file `key` of `storage` into a `MediaFile` titled `key`.
+///
+/// # Errors
+///
+/// Returns an error if the file is missing or cannot be read.
+pub fn storage_to_media_file(storage: &impl StorageBackend, key: &str) -> io::Result<MediaFile> {
+    let data = stored(storage, key)?;
+    Ok(MediaFile::from_u8(key.to_string(), &data))
+}
+
+/// Reads the file `key` of `storage` into a `TextFile` titled `key`.
+///
+/// # Errors
+///
+/// Returns an error if the file is missing, cannot be read or isn't UTF-8.
+pub fn storage_to_text_file(storage: &impl StorageBackend, key: &str) -> io::Result<TextFile> {
+    let content = String::from_utf8(stored(storage, key)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(TextFile::new(key.to_string(), content, vec![]))
+}
+
+fn stored(storage: &impl StorageBackend, key: &str) -> io::Result<Vec<u8>> {
+    storage.get(key)?.ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, format!("no stored file {key}"))
+    })
+}
+
 /// Converts a file path into a `MediaFile`.
 ///
 /// # Errors
@@ -154,7 +224,12 @@ mod file_conversion_tests {
     use std::fs;
     use std::io::Write;
     use tempfile::{NamedTempFile, tempdir};
-    use crate::file_conversion::{file_to_media_file, file_to_text_file};
+    use crate::file_conversion::{
+        file_to_media_file, file_to_text_file, save_file_in, storage_to_media_file,
+        storage_to_text_file,
+    };
+    use crate::storage::{MemoryStorage, StorageBackend};
+    use crate::types::{File, MediaFile, TextFile};
 
     #[test]
     /// Tests `file_to_text_file` conversion function
@@ -253,4 +328,28 @@ mod file_conversion_tests {
         let media_file = result.unwrap();
         assert_eq!(media_file.title, "test_document.txt");
     }
-}
\ No newline at end of file
+
+    #[test]
+    /// Tests saving files into a storage backend and reading them back
+    fn test_storage_conversion() {
+        let mut storage = MemoryStorage::new();
+        let text = TextFile::new("notes".to_string(), "hello".to_string(), vec![]);
+        let media = MediaFile::from_u8("photo.png".to_string(), &[7; 1500]);
+        let file = File::new(text.clone(), vec![media.clone()]);
+        save_file_in(&mut storage, &file).unwrap();
+
+        let text_key = format!("{}_notes", text.id);
+        let media_key = format!("{}_photo.png", media.id);
+        let mut keys = vec![text_key.clone(), media_key.clone()];
+        keys.sort();
+        assert_eq!(storage.list().unwrap(), keys);
+        let saved = storage_to_text_file(&storage, &text_key).unwrap();
+        assert_eq!(
+            saved.content,
+            format!("hello\nMediaFile attached: {media_key}\n")
+        );
+        let saved = storage_to_media_file(&storage, &media_key).unwrap();
+        assert_eq!(saved.to_bytes().unwrap(), vec![7; 1500]);
+        assert!(storage_to_media_file(&storage, "missing").is_err());
+    }
+}
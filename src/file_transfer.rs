@@ -0,0 +1,163 @@
+use crate::types::{WebRequest, WebResponse};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+/// Bytes of file data carried by each `file_chunk!` response.
+pub const FILE_CHUNK_SIZE: usize = 1024;
+
+/// Server side: the `file_chunk!` answering a `file_chunk?` for `offset` of the file
+/// `data`, or `None` if `offset` is past its end. An empty file is a single empty chunk.
+#[must_use]
+pub fn chunk_response(file_id: Uuid, data: &[u8], offset: u64) -> Option<WebResponse> {
+    let offset = usize::try_from(offset).ok()?;
+    if offset > 0 && offset >= data.len() {
+        return None;
+    }
+    let chunk_index = offset / FILE_CHUNK_SIZE;
+    let start = chunk_index * FILE_CHUNK_SIZE;
+    let end = (start + FILE_CHUNK_SIZE).min(data.len());
+    Some(WebResponse::FileChunkResponse {
+        file_id,
+        chunk_index: chunk_index as u64,
+        total_chunks: data.len().div_ceil(FILE_CHUNK_SIZE).max(1) as u64,
+        data: data[start..end].to_vec(),
+    })
+}
+
+/// Client side state of a chunked download: which chunks arrived and which are
+/// still to request, so that a transfer interrupted by losses resumes where it stopped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileTransfer {
+    file_id: Uuid,
+    // announced by the first chunk received
+    total_chunks: Option<u64>,
+    chunks: BTreeMap<u64, Vec<u8>>,
+}
+
+impl FileTransfer {
+    #[must_use]
+    pub fn new(file_id: Uuid) -> Self {
+        Self {
+            file_id,
+            total_chunks: None,
+            chunks: BTreeMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn file_id(&self) -> Uuid {
+        self.file_id
+    }
+
+    /// Returns the requests of the first `window` chunks still missing. Until the size
+    /// of the file is known, only its first chunk is requested. Requests lost on the way
+    /// are sent again by calling this again.
+    #[must_use]
+    pub fn requests(&self, window: usize) -> Vec<WebRequest> {
+        let total = self.total_chunks.unwrap_or(1);
+        (0..total)
+            .filter(|index| !self.chunks.contains_key(index))
+            .take(window)
+            .map(|index| WebRequest::FileChunkRequest {
+                file_id: self.file_id.to_string(),
+                offset: index * FILE_CHUNK_SIZE as u64,
+            })
+            .collect()
+    }
+
+    /// Records `response` if it is a new chunk of this file. Chunks of other files,
+    /// out of range, oversized or contradicting the announced total are ignored.
+    /// Returns whether the chunk was recorded.
+    pub fn handle_response(&mut self, response: &WebResponse) -> bool {
+        let WebResponse::FileChunkResponse {
+            file_id,
+            chunk_index,
+            total_chunks,
+            data,
+        } = response
+        else {
+            return false;
+        };
+        if *file_id != self.file_id
+            || *chunk_index >= *total_chunks
+            || data.len() > FILE_CHUNK_SIZE
+            || self
+                .total_chunks
+                .is_some_and(|total| total != *total_chunks)
+            || self.chunks.contains_key(chunk_index)
+        {
+            return false;
+        }
+        self.total_chunks = Some(*total_chunks);
+        let _ = self.chunks.insert(*chunk_index, data.clone());
+        true
+    }
+
+    /// Returns the chunks received and the total, once known.
+    #[must_use]
+    pub fn progress(&self) -> (u64, Option<u64>) {
+        (self.chunks.len() as u64, self.total_chunks)
+    }
+
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.total_chunks == Some(self.chunks.len() as u64)
+    }
+
+    /// Returns the file, once every chunk arrived.
+    #[must_use]
+    pub fn assemble(&self) -> Option<Vec<u8>> {
+        self.is_complete()
+            .then(|| self.chunks.values().flatten().copied().collect())
+    }
+}
+
+#[cfg(test)]
+mod file_transfer_tests {
+    use super::*;
+
+    #[test]
+    /// Tests that a transfer losing a chunk resumes and reassembles the file
+    fn test_resumed_transfer() {
+        let file_id = Uuid::new_v4();
+        let file = (0..2500)
+            .map(|i: u32| u8::try_from(i % 251).unwrap())
+            .collect::<Vec<_>>();
+        let serve = |request: &WebRequest| match request {
+            WebRequest::FileChunkRequest { offset, .. } => {
+                chunk_response(file_id, &file, *offset).unwrap()
+            }
+            _ => unreachable!(),
+        };
+
+        let mut transfer = FileTransfer::new(file_id);
+        let first = transfer.requests(8);
+        assert_eq!(first.len(), 1);
+        assert!(transfer.handle_response(&serve(&first[0])));
+        assert!(!transfer.handle_response(&serve(&first[0])));
+        assert_eq!(transfer.progress(), (1, Some(3)));
+
+        // the answer to the last chunk is lost
+        let requests = transfer.requests(8);
+        assert_eq!(requests.len(), 2);
+        assert!(transfer.handle_response(&serve(&requests[0])));
+        assert!(transfer.assemble().is_none());
+
+        let resumed = transfer.requests(8);
+        assert_eq!(resumed.len(), 1);
+        assert!(transfer.handle_response(&serve(&resumed[0])));
+        assert!(transfer.requests(8).is_empty());
+        assert_eq!(transfer.assemble(), Some(file));
+
+        assert!(matches!(
+            chunk_response(file_id, &[], 0),
+            Some(WebResponse::FileChunkResponse {
+                chunk_index: 0,
+                total_chunks: 1,
+                data,
+                ..
+            }) if data.is_empty()
+        ));
+        assert!(chunk_response(file_id, &[1], 1).is_none());
+    }
+}
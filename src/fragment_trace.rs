@@ -0,0 +1,147 @@
+use crate::ring_log::RingLog;
+use std::collections::HashMap;
+use std::time::Instant;
+use wg_internal::packet::NackType;
+
+/// Lifecycle step of an outgoing fragment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FragmentFate {
+    Sent,
+    Acked,
+    Nacked(NackType),
+    /// The fragment was given up on without being acknowledged
+    Expired,
+    /// The routing header of the fragment was rewritten after a failure
+    Rerouted,
+}
+
+impl FragmentFate {
+    fn is_final(self) -> bool {
+        matches!(self, Self::Acked | Self::Expired)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FragmentTraceEntry {
+    pub trace_id: u64,
+    pub session_id: u64,
+    pub fragment_index: u64,
+    pub fate: FragmentFate,
+    pub at: Instant,
+}
+
+/// Bounded history of fragment fates.
+///
+/// Every fragment gets a trace id the first time one of its fates is recorded,
+/// kept until it is acked or expires; the oldest entries are dropped once
+/// `capacity` is reached.
+#[derive(Debug, Clone)]
+pub struct FragmentTrace {
+    entries: RingLog<FragmentTraceEntry>,
+    live_ids: HashMap<(u64, u64), u64>,
+    next_id: u64,
+}
+
+impl Default for FragmentTrace {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+impl FragmentTrace {
+    pub const DEFAULT_CAPACITY: usize = 4096;
+
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: RingLog::new(capacity),
+            live_ids: HashMap::new(),
+            next_id: 0,
+        }
+    }
+
+    /// Records a new fate for a fragment and returns its trace id.
+    pub fn record(&mut self, session_id: u64, fragment_index: u64, fate: FragmentFate) -> u64 {
+        let key = (session_id, fragment_index);
+        let trace_id = if let Some(id) = self.live_ids.get(&key) {
+            *id
+        } else {
+            self.next_id += 1;
+            let _ = self.live_ids.insert(key, self.next_id);
+            self.next_id
+        };
+        if fate.is_final() {
+            let _ = self.live_ids.remove(&key);
+        }
+
+        let _ = self.entries.push(FragmentTraceEntry {
+            trace_id,
+            session_id,
+            fragment_index,
+            fate,
+            at: Instant::now(),
+        });
+        trace_id
+    }
+
+    /// Returns the recorded history of `session_id`, oldest first.
+    #[must_use]
+    pub fn for_session(&self, session_id: u64) -> Vec<FragmentTraceEntry> {
+        self.entries
+            .iter()
+            .filter(|e| e.session_id == session_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Returns the trace id of a fragment still in flight.
+    #[must_use]
+    pub fn trace_id(&self, session_id: u64, fragment_index: u64) -> Option<u64> {
+        self.live_ids.get(&(session_id, fragment_index)).copied()
+    }
+}
+
+#[cfg(test)]
+mod fragment_trace_tests {
+    use super::*;
+
+    #[test]
+    /// Tests that a fragment keeps its trace id until its final fate
+    fn test_trace_id_lifecycle() {
+        let mut trace = FragmentTrace::default();
+        let id = trace.record(7, 17, FragmentFate::Sent);
+        assert_eq!(
+            trace.record(7, 17, FragmentFate::Nacked(NackType::Dropped)),
+            id
+        );
+        assert_eq!(trace.record(7, 17, FragmentFate::Sent), id);
+        assert_eq!(trace.record(7, 17, FragmentFate::Acked), id);
+        assert_eq!(trace.trace_id(7, 17), None);
+
+        let fates = trace
+            .for_session(7)
+            .iter()
+            .map(|e| e.fate)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            fates,
+            vec![
+                FragmentFate::Sent,
+                FragmentFate::Nacked(NackType::Dropped),
+                FragmentFate::Sent,
+                FragmentFate::Acked
+            ]
+        );
+    }
+
+    #[test]
+    /// Tests that the oldest entries are dropped once the capacity is reached
+    fn test_bounded() {
+        let mut trace = FragmentTrace::new(2);
+        let _ = trace.record(1, 0, FragmentFate::Sent);
+        let _ = trace.record(2, 0, FragmentFate::Sent);
+        let _ = trace.record(2, 1, FragmentFate::Sent);
+        assert!(trace.for_session(1).is_empty());
+        assert_eq!(trace.for_session(2).len(), 2);
+    }
+}
@@ -0,0 +1,330 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use wg_internal::network::NodeId;
+
+/// Server-side liveness bookkeeping for registered clients.
+///
+/// Every request received from a client should [`touch`](Self::touch) it;
+/// clients silent for longer than the configured timeout are returned by
+/// [`prune_expired`](Self::prune_expired) and forgotten.
+#[derive(Debug, Clone)]
+pub struct KeepAliveTracker {
+    timeout: Duration,
+    last_seen: HashMap<NodeId, Instant>,
+}
+
+impl KeepAliveTracker {
+    #[must_use]
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            last_seen: HashMap::new(),
+        }
+    }
+
+    /// Records activity from `client`, registering it if unknown.
+    pub fn touch(&mut self, client: NodeId) {
+        let _ = self.last_seen.insert(client, Instant::now());
+    }
+
+    /// Stops tracking `client`, returns whether it was tracked.
+    pub fn forget(&mut self, client: NodeId) -> bool {
+        self.last_seen.remove(&client).is_some()
+    }
+
+    #[must_use]
+    pub fn is_alive(&self, client: NodeId) -> bool {
+        self.last_seen
+            .get(&client)
+            .is_some_and(|seen| seen.elapsed() <= self.timeout)
+    }
+
+    /// Returns the tracked clients, sorted by id.
+    #[must_use]
+    pub fn clients(&self) -> Vec<NodeId> {
+        let mut clients = self.last_seen.keys().copied().collect::<Vec<_>>();
+        clients.sort_unstable();
+        clients
+    }
+
+    /// Removes and returns every client whose last activity is older than the timeout.
+    pub fn prune_expired(&mut self) -> Vec<NodeId> {
+        let timeout = self.timeout;
+        let mut expired = self
+            .last_seen
+            .iter()
+            .filter(|(_, seen)| seen.elapsed() > timeout)
+            .map(|(id, _)| *id)
+            .collect::<Vec<_>>();
+        expired.sort_unstable();
+        for id in &expired {
+            let _ = self.last_seen.remove(id);
+        }
+        expired
+    }
+}
+
+/// Client-side schedule deciding when a keep-alive is due for each server.
+///
+/// Any message sent to a server counts as activity, so keep-alives are only
+/// emitted on otherwise idle connections.
+#[derive(Debug, Clone)]
+pub struct KeepAliveSchedule {
+    interval: Duration,
+    last_sent: HashMap<NodeId, Instant>,
+}
+
+impl KeepAliveSchedule {
+    #[must_use]
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            last_sent: HashMap::new(),
+        }
+    }
+
+    /// Records traffic sent to `server`, postponing its next keep-alive.
+    pub fn record_activity(&mut self, server: NodeId) {
+        self.record_activity_at(server, Instant::now());
+    }
+
+    /// Records traffic sent to `server` at `now`, see [`Self::record_activity`].
+    pub fn record_activity_at(&mut self, server: NodeId, now: Instant) {
+        let _ = self.last_sent.insert(server, now);
+    }
+
+    /// Stops scheduling keep-alives for `server`.
+    pub fn remove(&mut self, server: NodeId) {
+        let _ = self.last_sent.remove(&server);
+    }
+
+    /// Returns the servers for which a keep-alive is due and marks them as just sent.
+    pub fn due(&mut self) -> Vec<NodeId> {
+        self.due_at(Instant::now())
+    }
+
+    /// Returns the servers for which a keep-alive is due at `now` and marks them as
+    /// sent then.
+    pub fn due_at(&mut self, now: Instant) -> Vec<NodeId> {
+        let mut due = Vec::new();
+        for (server, last) in &mut self.last_sent {
+            if now.saturating_duration_since(*last) >= self.interval {
+                *last = now;
+                due.push(*server);
+            }
+        }
+        due.sort_unstable();
+        due
+    }
+}
+
+/// Heartbeats of a `RoutingHandler`, see `RoutingHandler::set_heartbeats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeartbeatConfig {
+    /// Time between two heartbeats to a peer
+    pub interval: Duration,
+    /// Heartbeats in a row a peer may leave unanswered before it is reported
+    pub max_missed: u32,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            max_missed: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct PeerLiveness {
+    last_seen: Instant,
+    // whether a heartbeat was sent since the peer was last heard from
+    probed: bool,
+    missed: u32,
+    reported: bool,
+}
+
+/// Node-side liveness of the watched peers, probed with heartbeats.
+///
+/// Heartbeats are keep-alives scheduled by a [`KeepAliveSchedule`]: any traffic from a
+/// peer answers its heartbeats and postpones the next one, so only silent peers are
+/// probed. [`poll`](Self::poll) tells which peers are due a heartbeat and which just
+/// missed too many in a row. A peer is reported once until it is heard from again.
+#[derive(Debug, Clone)]
+pub struct HeartbeatMonitor {
+    config: HeartbeatConfig,
+    schedule: KeepAliveSchedule,
+    peers: HashMap<NodeId, PeerLiveness>,
+    sent: u64,
+}
+
+impl HeartbeatMonitor {
+    #[must_use]
+    pub fn new(config: HeartbeatConfig) -> Self {
+        Self {
+            config,
+            schedule: KeepAliveSchedule::new(config.interval),
+            peers: HashMap::new(),
+            sent: 0,
+        }
+    }
+
+    #[must_use]
+    pub fn config(&self) -> HeartbeatConfig {
+        self.config
+    }
+
+    /// Starts watching `peer`, as if heard from at `now`. Returns whether it was new.
+    pub fn watch(&mut self, peer: NodeId, now: Instant) -> bool {
+        if self.peers.contains_key(&peer) {
+            return false;
+        }
+        let liveness = PeerLiveness {
+            last_seen: now,
+            probed: false,
+            missed: 0,
+            reported: false,
+        };
+        let _ = self.peers.insert(peer, liveness);
+        self.schedule.record_activity_at(peer, now);
+        true
+    }
+
+    /// Stops watching `peer`, returns whether it was watched.
+    pub fn unwatch(&mut self, peer: NodeId) -> bool {
+        self.schedule.remove(peer);
+        self.peers.remove(&peer).is_some()
+    }
+
+    /// Returns the watched peers, sorted by id.
+    #[must_use]
+    pub fn peers(&self) -> Vec<NodeId> {
+        let mut peers = self.peers.keys().copied().collect::<Vec<_>>();
+        peers.sort_unstable();
+        peers
+    }
+
+    /// Records traffic from `peer` at `now`, if watched.
+    pub fn record_activity(&mut self, peer: NodeId, now: Instant) {
+        if let Some(liveness) = self.peers.get_mut(&peer) {
+            liveness.last_seen = now;
+            liveness.probed = false;
+            liveness.missed = 0;
+            liveness.reported = false;
+            self.schedule.record_activity_at(peer, now);
+        }
+    }
+
+    /// Returns when `peer` was last heard from, if watched.
+    #[must_use]
+    pub fn last_seen(&self, peer: NodeId) -> Option<Instant> {
+        self.peers.get(&peer).map(|liveness| liveness.last_seen)
+    }
+
+    /// Returns how many heartbeats in a row `peer` left unanswered.
+    #[must_use]
+    pub fn missed(&self, peer: NodeId) -> u32 {
+        self.peers.get(&peer).map_or(0, |liveness| liveness.missed)
+    }
+
+    /// Returns how many heartbeats were due so far, all peers together.
+    #[must_use]
+    pub fn sent(&self) -> u64 {
+        self.sent
+    }
+
+    /// Returns the peers due a heartbeat at `now`, marked as sent, and the peers that
+    /// just missed too many, both sorted by id.
+    pub fn poll(&mut self, now: Instant) -> (Vec<NodeId>, Vec<NodeId>) {
+        let due = self.schedule.due_at(now);
+        let mut unresponsive = Vec::new();
+        for peer in &due {
+            let Some(liveness) = self.peers.get_mut(peer) else {
+                continue;
+            };
+            if liveness.probed {
+                liveness.missed += 1;
+            }
+            liveness.probed = true;
+            if liveness.missed >= self.config.max_missed && !liveness.reported {
+                liveness.reported = true;
+                unresponsive.push(*peer);
+            }
+        }
+        self.sent += due.len() as u64;
+        (due, unresponsive)
+    }
+}
+
+#[cfg(test)]
+mod keepalive_tests {
+    use super::*;
+
+    #[test]
+    /// Tests that silent clients are pruned while fresh ones are kept
+    fn test_prune_expired() {
+        let mut tracker = KeepAliveTracker::new(Duration::ZERO);
+        tracker.touch(3);
+        tracker.touch(1);
+        std::thread::sleep(Duration::from_millis(2));
+
+        assert_eq!(tracker.prune_expired(), vec![1, 3]);
+        assert!(tracker.clients().is_empty());
+
+        let mut tracker = KeepAliveTracker::new(Duration::from_mins(1));
+        tracker.touch(1);
+        assert!(tracker.prune_expired().is_empty());
+        assert!(tracker.is_alive(1));
+        assert!(tracker.forget(1));
+        assert!(!tracker.is_alive(1));
+    }
+
+    #[test]
+    /// Tests that keep-alives are only due on idle connections
+    fn test_schedule_due() {
+        let mut schedule = KeepAliveSchedule::new(Duration::from_mins(1));
+        schedule.record_activity(5);
+        assert!(schedule.due().is_empty());
+
+        let mut schedule = KeepAliveSchedule::new(Duration::ZERO);
+        schedule.record_activity(5);
+        schedule.record_activity(2);
+        assert_eq!(schedule.due(), vec![2, 5]);
+        schedule.remove(2);
+        assert_eq!(schedule.due(), vec![5]);
+    }
+
+    #[test]
+    /// Tests that peers missing heartbeats in a row are reported once until heard from
+    fn test_heartbeat_monitor() {
+        let config = HeartbeatConfig {
+            interval: Duration::from_secs(1),
+            max_missed: 2,
+        };
+        let mut monitor = HeartbeatMonitor::new(config);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        assert!(monitor.watch(4, start));
+        assert!(monitor.watch(7, start));
+        assert_eq!(monitor.poll(at(0)), (vec![], vec![]));
+        assert_eq!(monitor.poll(at(1)), (vec![4, 7], vec![]));
+        assert_eq!(monitor.poll(at(1)), (vec![], vec![]));
+        assert_eq!(monitor.sent(), 2);
+
+        // traffic answers the heartbeat and postpones the next one
+        monitor.record_activity(4, at(1));
+        assert_eq!(monitor.poll(at(2)), (vec![4, 7], vec![]));
+        assert_eq!((monitor.missed(4), monitor.missed(7)), (0, 1));
+        assert_eq!(monitor.poll(at(3)), (vec![4, 7], vec![7]));
+        assert_eq!(monitor.poll(at(4)), (vec![4, 7], vec![4]));
+        assert_eq!(monitor.poll(at(5)), (vec![4, 7], vec![]));
+
+        monitor.record_activity(7, at(5));
+        assert_eq!(monitor.missed(7), 0);
+        assert_eq!(monitor.last_seen(7), Some(at(5)));
+        assert_eq!(monitor.poll(at(5)), (vec![], vec![]));
+        assert!(monitor.unwatch(4));
+        assert_eq!(monitor.peers(), vec![7]);
+    }
+}
@@ -4,11 +4,69 @@ pub mod types;
 pub mod assembler;
 pub mod routing_handler;
 pub mod packet_processor;
+pub mod packet_queue;
+pub mod message_queue;
 pub mod file_conversion;
+pub mod file_cache;
+pub mod storage;
+pub mod media_store;
+pub mod render;
+pub mod file_transfer;
+pub mod web_browser;
+pub mod keepalive;
+pub mod clock;
+pub mod codec;
+pub mod fragment_trace;
+pub mod node_state;
+pub mod selfcheck;
+pub mod metrics;
+pub mod config;
+pub mod conformance;
+pub mod transform;
+pub mod discovery;
+pub mod congestion;
+pub mod route_policy;
+pub mod blacklist;
+pub mod publish;
+pub mod catalog;
+pub mod chat_rooms;
+pub mod chat_history;
+pub mod client_registry;
+pub mod ring_log;
+pub mod session;
+pub mod session_buffer;
+pub mod negotiation;
+#[cfg(feature = "compat")]
+pub mod compat;
+#[cfg(feature = "async")]
+pub mod async_processor;
+#[cfg(feature = "bench")]
+pub mod bench;
+#[cfg(feature = "crypto")]
+pub mod secure_channel;
+#[cfg(feature = "recorder")]
+pub mod packet_recorder;
+#[cfg(feature = "compression")]
+pub mod compression;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(any(test, feature = "testing", feature = "bench"))]
+mod simulation;
 
 pub use routing_handler::RoutingHandler;
 pub use assembler::FragmentAssembler;
 pub use packet_processor::Processor;
+#[cfg(feature = "async")]
+pub use async_processor::AsyncProcessor;
+#[cfg(feature = "crypto")]
+pub use secure_channel::SecureChannel;
+#[cfg(feature = "recorder")]
+pub use packet_recorder::{PacketRecorder, PacketReplayer};
+pub use selfcheck::selfcheck;
+pub use session::SessionHandle;
+pub use file_cache::FileCache;
+pub use media_store::MediaStore;
+pub use web_browser::WebBrowser;
 
 
 
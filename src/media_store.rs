@@ -0,0 +1,257 @@
+use crate::catalog::sha256_hex;
+use crate::file_cache::{CachedKind, FileCache};
+use crate::node_state::NodeState;
+use crate::types::{MediaFile, WebRequest, WebResponse};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Media known to a [`MediaStore`] under its id.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+struct StoredMedia {
+    title: String,
+    /// Hex encoded SHA-256 of the bytes, the key of the blob
+    hash: String,
+}
+
+/// Media files of a media server, stored once per content.
+///
+/// Every media is a reference from its id to a blob named after the SHA-256 of its
+/// bytes, so the same image uploaded under several ids takes space once. Blobs live in
+/// a [`FileCache`] and the references in a `media_store.json` manifest next to it;
+/// blobs evicted by the cache size limit leave their references dangling, which
+/// [`Self::get_by_id`] reports as missing. [`Self::gc`] removes the blobs no id refers
+/// to anymore.
+#[derive(Debug)]
+pub struct MediaStore {
+    cache: FileCache,
+    media: BTreeMap<Uuid, StoredMedia>,
+}
+
+impl MediaStore {
+    const MANIFEST_FILE: &'static str = "media_store.json";
+
+    /// Opens the store in `dir`, creating it on first use, holding at most `max_bytes`
+    /// of blobs.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the cache cannot be opened or the manifest is corrupted.
+    pub fn open(dir: impl AsRef<Path>, max_bytes: u64) -> io::Result<Self> {
+        Self::with_cache(FileCache::open(dir, max_bytes)?)
+    }
+
+    /// Opens the store kept in the state directory of a node.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be opened, see [`Self::open`].
+    pub fn for_node(state: &NodeState, max_bytes: u64) -> io::Result<Self> {
+        Self::open(state.media_dir(), max_bytes)
+    }
+
+    fn with_cache(cache: FileCache) -> io::Result<Self> {
+        let media = match fs::read(cache.dir().join(Self::MANIFEST_FILE)) {
+            Ok(data) => serde_json::from_slice(&data)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { cache, media })
+    }
+
+    /// Id of the blob holding the bytes of SHA-256 `hash`.
+    fn blob_id(hash: &str) -> Option<Uuid> {
+        hash.get(..32)
+            .and_then(|prefix| Uuid::try_parse(prefix).ok())
+    }
+
+    /// Returns whether a blob with SHA-256 `hash` is stored.
+    #[must_use]
+    pub fn contains(&self, hash: &str) -> bool {
+        Self::blob_id(hash).is_some_and(|id| self.cache.contains(id))
+    }
+
+    /// Returns the hash of the content of media `id`, if known.
+    #[must_use]
+    pub fn hash_of(&self, id: Uuid) -> Option<&str> {
+        self.media.get(&id).map(|media| media.hash.as_str())
+    }
+
+    /// Lists the ids of the stored media.
+    #[must_use]
+    pub fn ids(&self) -> Vec<Uuid> {
+        self.media.keys().copied().collect()
+    }
+
+    /// Stores `file` under its id, its bytes only if no blob holds them yet. Returns the
+    /// hash of the content.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file doesn't match its recorded hash, or the blob or the
+    /// manifest cannot be written.
+    pub fn insert(&mut self, file: &MediaFile) -> io::Result<String> {
+        let data = file
+            .to_bytes()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let hash = sha256_hex(&data);
+        let blob_id = Self::blob_id(&hash).ok_or_else(|| io::Error::other("invalid hash"))?;
+        if !self.cache.contains(blob_id) {
+            let mut blob = MediaFile::from_bytes(String::new(), &data);
+            blob.id = blob_id;
+            let _ = self.cache.insert_media(&blob)?;
+        }
+        let stored = StoredMedia {
+            title: file.title.clone(),
+            hash: hash.clone(),
+        };
+        let _ = self.media.insert(file.id, stored);
+        self.save_manifest()?;
+        Ok(hash)
+    }
+
+    /// Returns media `id`, `None` if unknown or its blob is gone.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the blob cannot be read.
+    pub fn get_by_id(&mut self, id: Uuid) -> io::Result<Option<MediaFile>> {
+        let Some(stored) = self.media.get(&id).cloned() else {
+            return Ok(None);
+        };
+        let Some(blob_id) = Self::blob_id(&stored.hash) else {
+            return Ok(None);
+        };
+        let Some(blob) = self.cache.get_media(blob_id)? else {
+            return Ok(None);
+        };
+        let mut media = MediaFile::from_bytes(stored.title, &blob.get_content().concat());
+        media.id = id;
+        Ok(Some(media))
+    }
+
+    /// Forgets media `id`, its blob staying until the next [`Self::gc`]. Returns whether
+    /// it was stored.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the manifest cannot be written.
+    pub fn remove(&mut self, id: Uuid) -> io::Result<bool> {
+        if self.media.remove(&id).is_none() {
+            return Ok(false);
+        }
+        self.save_manifest()?;
+        Ok(true)
+    }
+
+    /// Deletes the blobs no media refers to, returns how many were deleted.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a blob cannot be removed.
+    pub fn gc(&mut self) -> io::Result<usize> {
+        let referenced = self
+            .media
+            .values()
+            .filter_map(|media| Self::blob_id(&media.hash))
+            .collect::<HashSet<_>>();
+        let unreferenced = self
+            .cache
+            .list()
+            .iter()
+            .filter(|entry| entry.kind == CachedKind::Media && !referenced.contains(&entry.id))
+            .map(|entry| entry.id)
+            .collect::<Vec<_>>();
+        for id in &unreferenced {
+            let _ = self.cache.delete(*id)?;
+        }
+        Ok(unreferenced.len())
+    }
+
+    /// Answers the media requests of the media server role, `MediaQuery` and
+    /// `UploadMediaFile`, `None` for other requests.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the store cannot be read or written.
+    pub fn handle(&mut self, request: &WebRequest) -> io::Result<Option<WebResponse>> {
+        let response = match request {
+            WebRequest::MediaQuery { media_id } => match Uuid::try_parse(media_id) {
+                Ok(id) => match self.get_by_id(id)? {
+                    Some(media) => WebResponse::MediaFile {
+                        media_data: media.get_content().concat(),
+                    },
+                    None => WebResponse::ErrorFileNotFound(id),
+                },
+                Err(_) => WebResponse::BadUuid(media_id.clone()),
+            },
+            WebRequest::UploadMediaFile { file } => {
+                let _ = self.insert(file)?;
+                WebResponse::UploadAccepted { file_id: file.id }
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(response))
+    }
+
+    fn save_manifest(&self) -> io::Result<()> {
+        let data = serde_json::to_vec_pretty(&self.media).map_err(io::Error::other)?;
+        fs::write(self.cache.dir().join(Self::MANIFEST_FILE), data)
+    }
+}
+
+#[cfg(test)]
+mod media_store_tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    /// Tests that equal contents share a blob, which garbage collection removes once unreferenced
+    fn test_media_store() {
+        let dir = tempdir().unwrap();
+        let mut store = MediaStore::open(dir.path(), 10_000).unwrap();
+        let logo = MediaFile::from_bytes("logo.png".to_string(), &[7; 1500]);
+        let copy = MediaFile::from_bytes("copy.png".to_string(), &[7; 1500]);
+        let other = MediaFile::from_bytes("other.png".to_string(), &[8; 1500]);
+        let hash = store.insert(&logo).unwrap();
+        assert_eq!(store.insert(&copy).unwrap(), hash);
+        let _ = store.insert(&other).unwrap();
+        assert!(store.contains(&hash));
+        assert_eq!(store.hash_of(copy.id), Some(hash.as_str()));
+        // two blobs for three media
+        assert_eq!(store.cache.size(), 3000);
+
+        let found = store.get_by_id(copy.id).unwrap().unwrap();
+        assert_eq!((found.id, found.title.as_str()), (copy.id, "copy.png"));
+        assert_eq!(found.to_bytes().unwrap(), vec![7; 1500]);
+
+        let query = WebRequest::MediaQuery {
+            media_id: logo.id.to_string(),
+        };
+        let Some(WebResponse::MediaFile { media_data }) = store.handle(&query).unwrap() else {
+            panic!("expected the media");
+        };
+        assert_eq!(media_data, vec![7; 1500]);
+        assert!(
+            store
+                .handle(&WebRequest::ServerTypeQuery)
+                .unwrap()
+                .is_none()
+        );
+
+        // the shared blob stays while a media refers to it
+        assert!(store.remove(logo.id).unwrap());
+        assert!(store.remove(other.id).unwrap());
+        assert_eq!(store.gc().unwrap(), 1);
+        assert!(store.contains(&hash));
+
+        let mut store = MediaStore::open(dir.path(), 10_000).unwrap();
+        assert_eq!(store.ids(), vec![copy.id]);
+        assert!(store.get_by_id(logo.id).unwrap().is_none());
+        assert!(store.get_by_id(copy.id).unwrap().is_some());
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::{HashMap, VecDeque};
+use wg_internal::network::NodeId;
+
+/// Message waiting in a [`MessageQueue`] for the previous one to its destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedMessage {
+    pub session_id: u64,
+    pub data: Vec<u8>,
+}
+
+/// Outgoing messages of a `RoutingHandler` with ordered sends, by destination.
+///
+/// A single session per destination is in flight: the next message to the same
+/// destination only starts once it is delivered or given up, so messages arrive in
+/// the order they were sent even when fragments are retransmitted.
+#[derive(Debug, Clone, Default)]
+pub struct MessageQueue {
+    // whether new messages are queued, the ones already queued wait for their turn anyway
+    ordered: bool,
+    in_flight: HashMap<NodeId, u64>,
+    queued: HashMap<NodeId, VecDeque<QueuedMessage>>,
+}
+
+impl MessageQueue {
+    /// Returns whether new messages wait for the previous one to their destination.
+    #[must_use]
+    pub fn is_ordered(&self) -> bool {
+        self.ordered
+    }
+
+    pub(crate) fn set_ordered(&mut self, ordered: bool) {
+        self.ordered = ordered;
+    }
+
+    /// Returns the session in flight to `destination`, if any.
+    #[must_use]
+    pub fn in_flight(&self, destination: NodeId) -> Option<u64> {
+        self.in_flight.get(&destination).copied()
+    }
+
+    /// Returns how many messages wait for the session in flight to `destination`.
+    #[must_use]
+    pub fn depth(&self, destination: NodeId) -> usize {
+        self.queued.get(&destination).map_or(0, VecDeque::len)
+    }
+
+    /// Returns how many messages wait, all destinations together.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.queued.values().map(VecDeque::len).sum()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.queued.is_empty()
+    }
+
+    /// Returns the sessions waiting for `destination`, in sending order.
+    #[must_use]
+    pub fn queued_sessions(&self, destination: NodeId) -> Vec<u64> {
+        self.queued
+            .get(&destination)
+            .map_or_else(Vec::new, |queued| {
+                queued.iter().map(|message| message.session_id).collect()
+            })
+    }
+
+    /// Returns whether `session_id` must wait before being sent to `destination`,
+    /// recording it as in flight if it may go now. Never waits unless ordered.
+    pub(crate) fn must_wait(&mut self, destination: NodeId, session_id: u64) -> bool {
+        if !self.ordered {
+            return false;
+        }
+        if let Some(in_flight) = self.in_flight.get(&destination) {
+            return *in_flight != session_id;
+        }
+        let _ = self.in_flight.insert(destination, session_id);
+        false
+    }
+
+    /// Returns the sessions waiting, all destinations together, in no particular order.
+    pub(crate) fn sessions(&self) -> impl Iterator<Item = u64> + '_ {
+        self.queued
+            .values()
+            .flatten()
+            .map(|message| message.session_id)
+    }
+
+    pub(crate) fn push(&mut self, destination: NodeId, message: QueuedMessage) {
+        self.queued
+            .entry(destination)
+            .or_default()
+            .push_back(message);
+    }
+
+    /// Marks `session_id` as settled, returning the next message to send to its
+    /// destination, now in flight, if it was the session in flight.
+    pub(crate) fn finish(&mut self, session_id: u64) -> Option<(NodeId, QueuedMessage)> {
+        let destination = self
+            .in_flight
+            .iter()
+            .find(|(_, in_flight)| **in_flight == session_id)
+            .map(|(destination, _)| *destination)?;
+        let _ = self.in_flight.remove(&destination);
+        let queued = self.queued.get_mut(&destination)?;
+        let next = queued.pop_front()?;
+        if queued.is_empty() {
+            let _ = self.queued.remove(&destination);
+        }
+        let _ = self.in_flight.insert(destination, next.session_id);
+        Some((destination, next))
+    }
+
+    /// Removes the waiting message of `session_id`, returns whether it was waiting.
+    pub(crate) fn cancel(&mut self, session_id: u64) -> bool {
+        let mut cancelled = false;
+        self.queued.retain(|_, queued| {
+            let before = queued.len();
+            queued.retain(|message| message.session_id != session_id);
+            cancelled |= queued.len() != before;
+            !queued.is_empty()
+        });
+        cancelled
+    }
+}
@@ -0,0 +1,29 @@
+use crate::types::NodeStats;
+
+/// Traffic counters of a node since it started, answered to `NodeCommand::QueryMetrics`.
+///
+/// On top of the `NodeStats` of the node, which summarize its activity, the counters
+/// follow the fragments through the network: how many left and arrived and how many a
+/// drone reported dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub stats: NodeStats,
+    pub fragments_sent: u64,
+    pub fragments_received: u64,
+    // fragments of this node a drone answered with a `Dropped` Nack
+    pub fragments_dropped: u64,
+}
+
+impl Metrics {
+    /// Returns the share of the fragments sent that were reported dropped, 0.0 when
+    /// nothing was sent.
+    #[must_use]
+    pub fn drop_rate(&self) -> f64 {
+        if self.fragments_sent == 0 {
+            return 0.0;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let rate = self.fragments_dropped as f64 / self.fragments_sent as f64;
+        rate
+    }
+}
@@ -0,0 +1,170 @@
+use crate::types::ProtocolVersion;
+use std::collections::{HashMap, HashSet};
+use wg_internal::network::NodeId;
+
+/// Version of the protocol implemented by this crate.
+pub const CURRENT_PROTOCOL_VERSION: u32 = 1;
+
+/// Outcome of the handshake with a peer, as tracked by a [`VersionNegotiator`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Negotiation {
+    Agreed(u32),
+    /// The peer speaks none of our versions
+    Unsupported,
+}
+
+/// Protocol version handshake with the servers and clients a node talks to.
+///
+/// `Processor::handle_packet` greets every peer with a [`ProtocolVersion::Hello`] the
+/// first time a fragment arrives from it and answers the hellos of others, when
+/// `Processor::version_negotiator` provides a negotiator. Higher layers read the
+/// outcome with [`Self::agreed`] to pick an encoding both sides understand.
+#[derive(Debug, Clone)]
+pub struct VersionNegotiator {
+    supported: Vec<u32>,
+    greeted: HashSet<NodeId>,
+    peers: HashMap<NodeId, Negotiation>,
+}
+
+impl Default for VersionNegotiator {
+    fn default() -> Self {
+        Self::new(vec![CURRENT_PROTOCOL_VERSION])
+    }
+}
+
+impl VersionNegotiator {
+    /// Creates a negotiator speaking the `supported` versions.
+    #[must_use]
+    pub fn new(mut supported: Vec<u32>) -> Self {
+        supported.sort_unstable();
+        supported.dedup();
+        Self {
+            supported,
+            greeted: HashSet::new(),
+            peers: HashMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn supported(&self) -> &[u32] {
+        &self.supported
+    }
+
+    /// Returns the version agreed with `peer`, `None` until the handshake completes or
+    /// if there is none in common.
+    #[must_use]
+    pub fn agreed(&self, peer: NodeId) -> Option<u32> {
+        match self.peers.get(&peer) {
+            Some(Negotiation::Agreed(version)) => Some(*version),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub fn negotiation(&self, peer: NodeId) -> Option<Negotiation> {
+        self.peers.get(&peer).copied()
+    }
+
+    /// Returns the hello to send to `peer` on first contact, `None` if it was already
+    /// greeted or the handshake already happened. Record the hello once sent with
+    /// [`Self::mark_greeted`], until then `peer` is greeted on every contact.
+    #[must_use]
+    pub fn greet(&self, peer: NodeId) -> Option<ProtocolVersion> {
+        if self.peers.contains_key(&peer) || self.greeted.contains(&peer) {
+            return None;
+        }
+        Some(ProtocolVersion::Hello {
+            supported: self.supported.clone(),
+        })
+    }
+
+    /// Records that the hello of [`Self::greet`] was sent to `peer`.
+    pub fn mark_greeted(&mut self, peer: NodeId) {
+        let _ = self.greeted.insert(peer);
+    }
+
+    /// Handles the handshake message `message` of `from`, returning the reply to send
+    /// back if any. Hellos are answered with the highest version in common.
+    pub fn handle(&mut self, from: NodeId, message: &ProtocolVersion) -> Option<ProtocolVersion> {
+        let _ = self.greeted.insert(from);
+        match message {
+            ProtocolVersion::Hello { supported } => {
+                let common = self
+                    .supported
+                    .iter()
+                    .rev()
+                    .find(|version| supported.contains(version))
+                    .copied();
+                if let Some(version) = common {
+                    let _ = self.peers.insert(from, Negotiation::Agreed(version));
+                    Some(ProtocolVersion::Agreed { version })
+                } else {
+                    let _ = self.peers.insert(from, Negotiation::Unsupported);
+                    Some(ProtocolVersion::Unsupported {
+                        supported: self.supported.clone(),
+                    })
+                }
+            }
+            ProtocolVersion::Agreed { version } => {
+                let outcome = if self.supported.contains(version) {
+                    Negotiation::Agreed(*version)
+                } else {
+                    Negotiation::Unsupported
+                };
+                let _ = self.peers.insert(from, outcome);
+                None
+            }
+            ProtocolVersion::Unsupported { .. } => {
+                let _ = self.peers.insert(from, Negotiation::Unsupported);
+                None
+            }
+        }
+    }
+
+    /// Forgets `peer`, so that it is greeted again on the next contact.
+    pub fn forget(&mut self, peer: NodeId) {
+        let _ = self.greeted.remove(&peer);
+        let _ = self.peers.remove(&peer);
+    }
+}
+
+#[cfg(test)]
+mod negotiation_tests {
+    use super::*;
+
+    #[test]
+    /// Tests that both sides agree on the highest common version, or on none
+    fn test_version_negotiation() {
+        let mut client = VersionNegotiator::new(vec![2, 1, 3]);
+        let mut server = VersionNegotiator::new(vec![1, 2]);
+
+        let hello = client.greet(5).unwrap();
+        assert_eq!(
+            hello,
+            ProtocolVersion::Hello {
+                supported: vec![1, 2, 3]
+            }
+        );
+        // greeted only once, after the hello was sent
+        assert_eq!(client.greet(5), Some(hello.clone()));
+        client.mark_greeted(5);
+        assert_eq!(client.greet(5), None);
+        let reply = server.handle(4, &hello).unwrap();
+        assert_eq!(reply, ProtocolVersion::Agreed { version: 2 });
+        assert_eq!(server.greet(4), None);
+        assert_eq!(client.handle(5, &reply), None);
+        assert_eq!(client.agreed(5), Some(2));
+        assert_eq!(server.agreed(4), Some(2));
+
+        let mut legacy = VersionNegotiator::new(vec![7]);
+        let reply = legacy.handle(4, &client.greet(6).unwrap()).unwrap();
+        assert_eq!(reply, ProtocolVersion::Unsupported { supported: vec![7] });
+        let _ = client.handle(6, &reply);
+        assert_eq!(client.agreed(6), None);
+        assert_eq!(client.negotiation(6), Some(Negotiation::Unsupported));
+
+        client.forget(6);
+        assert_eq!(client.negotiation(6), None);
+        assert!(client.greet(6).is_some());
+    }
+}
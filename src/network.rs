@@ -1,41 +1,220 @@
 use crossbeam_channel::SendError;
+use thiserror::Error;
+use crate::selfcheck::Diagnostic;
 use wg_internal::network::NodeId;
 use wg_internal::packet::NodeType;
-use std::{collections::{HashMap, HashSet, VecDeque}, fmt::Display};
+use smallvec::SmallVec;
+use std::cmp::Reverse;
+use std::time::{Duration, Instant};
+use std::{collections::{BTreeMap, BTreeSet, BinaryHeap, HashMap, HashSet, VecDeque}, fmt::Display, fmt::Write};
+use std::fs;
+use std::path::Path;
+use wg_internal::config::Config;
 
-#[derive(Debug)]
-pub enum NetworkError {
-    TopologyError,
-    PathNotFound(u8),
-    NodeNotFound(u8),
-    NodeIsNotANeighbor(u8),
-    SendError(String),
-    ControllerDisconnected,
+/// Failures while choosing, following or being allowed a route.
+#[derive(Debug, Error)]
+pub enum RoutingError {
+    #[error("Path not found for node {destination}")]
+    PathNotFound { destination: NodeId },
+    #[error("Route search towards node {destination} exceeded its budget")]
+    SearchBudgetExceeded { destination: NodeId },
+    #[error("Packet has no destination specified")]
     NoDestination,
-    NoNeighborAssigned
+    #[error("Node {node} is not a neighbor")]
+    NodeIsNotANeighbor { node: NodeId },
+    #[error("Payload of {len} bytes does not fit in a single fragment")]
+    PayloadTooLarge { len: usize },
+    #[error("Byte quota towards node {destination} exceeded")]
+    QuotaExceeded { destination: NodeId },
+    // sealed message that failed authentication, was replayed or came from a node without keys
+    #[error("Message from node {from} could not be decrypted")]
+    Undecryptable { from: NodeId },
+    // send refused because the node is shutting down gracefully
+    #[error("Node is shutting down")]
+    ShuttingDown,
 }
 
-impl Display for NetworkError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl RoutingError {
+    /// Stable machine-readable code of the error.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::PathNotFound { .. } => "routing.path_not_found",
+            Self::SearchBudgetExceeded { .. } => "routing.search_budget_exceeded",
+            Self::NoDestination => "routing.no_destination",
+            Self::NodeIsNotANeighbor { .. } => "routing.not_a_neighbor",
+            Self::PayloadTooLarge { .. } => "routing.payload_too_large",
+            Self::QuotaExceeded { .. } => "routing.quota_exceeded",
+            Self::Undecryptable { .. } => "routing.undecryptable",
+            Self::ShuttingDown => "routing.shutting_down",
+        }
+    }
+}
+
+/// Failures of the topology, as known by the network view or the simulations.
+#[derive(Debug, Error)]
+pub enum TopologyError {
+    #[error("Topology error")]
+    Invalid,
+    #[error("Node {node} not found")]
+    NodeNotFound { node: NodeId },
+    #[error("No neighbor assigned")]
+    NoNeighborAssigned,
+    #[error("Topology file unreadable: {reason}")]
+    Unreadable { reason: String },
+}
+
+impl TopologyError {
+    /// Stable machine-readable code of the error.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
         match self {
-            Self::TopologyError => write!(f, "Topology error"),
-            Self::PathNotFound(id) => write!(f, "Path not found for node {id}"),
-            Self::NodeNotFound(id) => write!(f, "Node {id} not found"),
-            Self::NodeIsNotANeighbor(id) => write!(f, "Node {id} is not a neighbor"),
-            Self::SendError(msg) => write!(f, "Send error: {msg}"),
-            Self::ControllerDisconnected => write!(f, "Controller disconnected"),
-            Self::NoDestination => write!(f, "Packet has no destination specified"),
-            Self::NoNeighborAssigned => write!(f, "No neighbor assigned"),
+            Self::Invalid => "topology.invalid",
+            Self::NodeNotFound { .. } => "topology.node_not_found",
+            Self::NoNeighborAssigned => "topology.no_neighbor",
+            Self::Unreadable { .. } => "topology.unreadable",
         }
     }
 }
 
-impl std::error::Error for NetworkError {}
+/// Failures of the channels towards neighbors and controller, and of the encoding of
+/// what is sent through them.
+#[derive(Debug, Error)]
+pub enum ChannelError {
+    #[error("Send error: {0}")]
+    Send(String),
+    #[error("Controller disconnected")]
+    ControllerDisconnected,
+    #[error("Channel to node {neighbor} is full and so is its backlog")]
+    Full { neighbor: NodeId },
+    #[error("Encoding error: {0}")]
+    Encoding(String),
+}
+
+impl ChannelError {
+    /// Stable machine-readable code of the error.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Send(_) => "channel.send",
+            Self::ControllerDisconnected => "channel.controller_disconnected",
+            Self::Full { .. } => "channel.full",
+            Self::Encoding(_) => "channel.encoding",
+        }
+    }
+}
+
+impl<T: Send + std::fmt::Debug> From<SendError<T>> for ChannelError {
+    fn from(value: SendError<T>) -> Self {
+        ChannelError::Send(format!("{value:?}"))
+    }
+}
+
+/// Any error of the crate, split by the layer that failed so that it can be matched
+/// downstream. Errors of a session carry it as [`NetworkError::Context`].
+#[derive(Debug, Error)]
+pub enum NetworkError {
+    #[error(transparent)]
+    Routing(#[from] RoutingError),
+    #[error(transparent)]
+    Topology(#[from] TopologyError),
+    #[error(transparent)]
+    Channel(#[from] ChannelError),
+    // `source` with the session and destination it happened for
+    #[error("{source}{}", describe_context(*session_id, *destination))]
+    Context {
+        session_id: Option<u64>,
+        destination: Option<NodeId>,
+        source: Box<NetworkError>,
+    },
+}
+
+impl NetworkError {
+    /// Returns the error with the context stripped.
+    #[must_use]
+    pub fn root(&self) -> &NetworkError {
+        match self {
+            Self::Context { source, .. } => source.root(),
+            _ => self,
+        }
+    }
+
+    /// Stable machine-readable code of the error, e.g. `routing.path_not_found`.
+    #[must_use]
+    pub fn code(&self) -> &'static str {
+        match self.root() {
+            Self::Routing(e) => e.code(),
+            Self::Topology(e) => e.code(),
+            Self::Channel(e) => e.code(),
+            Self::Context { .. } => unreachable!("root strips the context"),
+        }
+    }
+
+    /// Returns the session the error happened in, if known.
+    #[must_use]
+    pub fn session_id(&self) -> Option<u64> {
+        match self {
+            Self::Context { session_id, source, .. } => session_id.or_else(|| source.session_id()),
+            _ => None,
+        }
+    }
+
+    /// Returns the node the failed operation was addressed to, if known.
+    #[must_use]
+    pub fn destination(&self) -> Option<NodeId> {
+        match self {
+            Self::Context { destination, source, .. } => destination.or_else(|| source.destination()),
+            Self::Routing(
+                RoutingError::PathNotFound { destination }
+                | RoutingError::SearchBudgetExceeded { destination }
+                | RoutingError::QuotaExceeded { destination },
+            ) => Some(*destination),
+            _ => None,
+        }
+    }
+
+    /// Attaches `session_id` to the error.
+    #[must_use]
+    pub fn in_session(self, session_id: u64) -> Self {
+        self.with_context(Some(session_id), None)
+    }
+
+    /// Attaches `destination` to the error.
+    #[must_use]
+    pub fn towards(self, destination: NodeId) -> Self {
+        self.with_context(None, Some(destination))
+    }
 
+    fn with_context(self, session: Option<u64>, to: Option<NodeId>) -> Self {
+        match self {
+            Self::Context { session_id, destination, source } => Self::Context {
+                session_id: session_id.or(session),
+                destination: destination.or(to),
+                source,
+            },
+            error => Self::Context {
+                session_id: session,
+                destination: to,
+                source: Box::new(error),
+            },
+        }
+    }
+}
+
+fn describe_context(session_id: Option<u64>, destination: Option<NodeId>) -> String {
+    let mut context = String::new();
+    if let Some(session_id) = session_id {
+        let _ = write!(context, " in session {session_id}");
+    }
+    if let Some(destination) = destination {
+        let _ = write!(context, " towards node {destination}");
+    }
+    context
+}
 
 impl<T: Send + std::fmt::Debug> From<SendError<T>> for NetworkError {
     fn from(value: SendError<T>) -> Self {
-        NetworkError::SendError(format!("{value:?}"))
+        ChannelError::from(value).into()
     }
 }
 
@@ -86,16 +265,204 @@ impl std::fmt::Debug for Node{
     }
 }
 
+/// Path quality observed for a node through the flood responses that mentioned it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeMetadata {
+    /// Fewest hops between the local node and this node
+    pub hops: usize,
+    /// Fastest time between a flood start and a response whose path crossed this node
+    pub latency: Duration,
+}
+
+/// Hops kept inline by a [`Route`], enough for the routes of usual topologies.
+pub const ROUTE_INLINE_HOPS: usize = 16;
+
+/// Route found by a path search, from the start to the destination. Routes are short,
+/// so they don't allocate unless they exceed [`ROUTE_INLINE_HOPS`] hops.
+pub type Route = SmallVec<[NodeId; ROUTE_INLINE_HOPS]>;
+
+/// Limits of a route search, so that pathological topologies can't stall the caller.
+/// The default budget is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchBudget {
+    /// Nodes the search may expand at most
+    pub max_expansions: Option<usize>,
+    /// Time the search may take at most
+    pub time_limit: Option<Duration>,
+}
+
+/// Fragments delivered and dropped across a link, decayed as they age.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LinkStats {
+    pub delivered: f64,
+    pub dropped: f64,
+    updated: Instant,
+}
+
+impl LinkStats {
+    /// Returns the counts as of `now`, halved every `half_life` since the last update.
+    fn decayed(mut self, now: Instant, half_life: Duration) -> Self {
+        let elapsed = now.saturating_duration_since(self.updated);
+        if !half_life.is_zero() {
+            let factor = 0.5_f64.powf(elapsed.as_secs_f64() / half_life.as_secs_f64());
+            self.delivered *= factor;
+            self.dropped *= factor;
+        }
+        self.updated = self.updated.max(now);
+        self
+    }
+}
+
+/// Link quality estimated from the Acks and `Dropped` Nacks of the fragments sent.
+///
+/// Every Ack counts a delivery on each link of the route of its fragment, while a
+/// `Dropped` Nack counts a delivery on the links before the drone that dropped the
+/// fragment and a drop on the link into it. Counts fade with a half-life, so that a
+/// drone recovering is trusted again.
+#[derive(Debug, Clone)]
+pub struct EdgeStats {
+    // by directed link, indexed by the node it leads into then the node it leaves
+    links: HashMap<NodeId, HashMap<NodeId, LinkStats>>,
+    half_life: Duration,
+}
+
+impl Default for EdgeStats {
+    fn default() -> Self {
+        Self { links: HashMap::new(), half_life: Self::DEFAULT_HALF_LIFE }
+    }
+}
+
+impl EdgeStats {
+    pub const DEFAULT_HALF_LIFE: Duration = Duration::from_secs(30);
+
+    /// Halves the weight of the measurements every `half_life`, zero to never forget them.
+    pub fn set_half_life(&mut self, half_life: Duration) {
+        self.half_life = half_life;
+    }
+
+    #[must_use]
+    pub fn half_life(&self) -> Duration {
+        self.half_life
+    }
+
+    fn record(&mut self, from: NodeId, to: NodeId, delivered: bool, now: Instant) {
+        let half_life = self.half_life;
+        let link = self
+            .links
+            .entry(to)
+            .or_default()
+            .entry(from)
+            .or_insert(LinkStats { delivered: 0.0, dropped: 0.0, updated: now });
+        *link = link.decayed(now, half_life);
+        if delivered {
+            link.delivered += 1.0;
+        } else {
+            link.dropped += 1.0;
+        }
+    }
+
+    /// Records that a fragment crossed every link of `route`.
+    pub(crate) fn record_delivery(&mut self, route: &[NodeId], now: Instant) {
+        for hop in route.windows(2) {
+            self.record(hop[0], hop[1], true, now);
+        }
+    }
+
+    /// Records that a fragment sent along `route` was dropped by `dropped_by`.
+    pub(crate) fn record_drop(&mut self, route: &[NodeId], dropped_by: NodeId, now: Instant) {
+        let Some(position) = route.iter().position(|hop| *hop == dropped_by).filter(|p| *p > 0) else {
+            return;
+        };
+        self.record_delivery(&route[..position], now);
+        self.record(route[position - 1], dropped_by, false, now);
+    }
+
+    /// Returns the counts of the link from `from` to `to` as of `now`.
+    #[must_use]
+    pub fn link(&self, from: NodeId, to: NodeId, now: Instant) -> Option<LinkStats> {
+        self.links.get(&to)?.get(&from).map(|link| link.decayed(now, self.half_life))
+    }
+
+    /// Estimated probability that `drone` drops a fragment it receives, from the links
+    /// into it, `None` without measurements.
+    #[must_use]
+    pub fn drop_probability(&self, drone: NodeId, now: Instant) -> Option<f64> {
+        let (delivered, dropped) = self
+            .links
+            .get(&drone)?
+            .values()
+            .map(|link| link.decayed(now, self.half_life))
+            .fold((0.0, 0.0), |(delivered, dropped), link| (delivered + link.delivered, dropped + link.dropped));
+        let total = delivered + dropped;
+        (total > f64::EPSILON).then(|| dropped / total)
+    }
+
+    fn forget_node(&mut self, node_id: NodeId) {
+        let _ = self.links.remove(&node_id);
+        self.links.retain(|_, into| {
+            let _ = into.remove(&node_id);
+            !into.is_empty()
+        });
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Network {
-    pub nodes: Vec<Node>
+    pub nodes: Vec<Node>,
+    metadata: HashMap<NodeId, NodeMetadata>,
+    edge_stats: EdgeStats,
 }
 
 impl Network {
+    /// Cost of a hop in [`Self::find_path`], so that hop count decides between nodes
+    /// without latency measurements.
+    const HOP_COST: Duration = Duration::from_millis(1);
+    /// Extra cost in [`Self::find_path`] of a drone certain to drop fragments, scaled by
+    /// its estimated drop probability.
+    const DROP_COST: Duration = Duration::from_millis(100);
+
     #[must_use]
     pub(crate) fn new(root: Node) -> Self {
         let nodes = vec![root];
-        Self { nodes }
+        Self { nodes, ..Self::default() }
+    }
+
+    /// Builds the ground truth described by the simulation TOML topology file at `path`
+    /// (`[[drone]]`, `[[client]]` and `[[server]]` tables), to compare flooded views
+    /// against with [`Self::diff`]. See [`Topology::from_config`].
+    /// # Errors
+    /// `Unreadable` if the file cannot be read or is not a valid topology.
+    pub fn from_config(path: impl AsRef<Path>) -> Result<Self, TopologyError> {
+        let unreadable = |reason: String| TopologyError::Unreadable { reason };
+        let text = fs::read_to_string(path).map_err(|e| unreadable(e.to_string()))?;
+        let config: Config = toml::from_str(&text).map_err(|e| unreadable(e.to_string()))?;
+        Ok(Topology::from_config(&config).to_network())
+    }
+
+    /// Records that a flood response crossed `node_id` `hops` hops away from the local
+    /// node, `latency` after the flood started. The best values seen are kept.
+    pub(crate) fn annotate(&mut self, node_id: NodeId, hops: usize, latency: Duration) {
+        self.metadata
+            .entry(node_id)
+            .and_modify(|m| {
+                m.hops = m.hops.min(hops);
+                m.latency = m.latency.min(latency);
+            })
+            .or_insert(NodeMetadata { hops, latency });
+    }
+
+    #[must_use]
+    pub fn node_metadata(&self, node_id: NodeId) -> Option<NodeMetadata> {
+        self.metadata.get(&node_id).copied()
+    }
+
+    #[must_use]
+    pub fn edge_stats(&self) -> &EdgeStats {
+        &self.edge_stats
+    }
+
+    pub(crate) fn edge_stats_mut(&mut self) -> &mut EdgeStats {
+        &mut self.edge_stats
     }
 
 
@@ -108,8 +475,10 @@ impl Network {
         for adj in new_node.get_adjacents() {
             if let Some(node) = self.nodes.iter_mut().find(|n| n.id == *adj) {
                 match (new_node.get_node_type(), node.get_node_type()) {
-                    (_, NodeType::Drone) | (NodeType::Drone, _) => {
-                        node.add_adjacent(*adj);
+                    (_, NodeType::Drone) | (NodeType::Drone, _)
+                        if !node.get_adjacents().contains(&new_node.id) =>
+                    {
+                        node.add_adjacent(new_node.id);
                     }
                     _ => {}
                 }
@@ -128,12 +497,14 @@ impl Network {
         if let Some(index_to_remove) = self.nodes.iter().position(|n| n.id == node_id) {
             let _ = self.nodes.remove(index_to_remove);
         }
+        let _ = self.metadata.remove(&node_id);
+        self.edge_stats.forget_node(node_id);
     }
 
     /// Updates the node's adjacents with the provided list.
     /// # Errors
     /// If the node is not found, returns an error.
-    pub(crate) fn update_node(&mut self, node_id: NodeId, adjacents: Vec<NodeId>) -> Result<(), NetworkError> {
+    pub(crate) fn update_node(&mut self, node_id: NodeId, adjacents: Vec<NodeId>) -> Result<(), TopologyError> {
         if let Some(node) = self.nodes.iter_mut().find(|n| n.id == node_id) {
             for adj in adjacents {
                 if !node.get_adjacents().contains(&adj) {
@@ -146,7 +517,20 @@ impl Network {
 
             return Ok(());
         }
-        Err(NetworkError::NodeNotFound(node_id))
+        Err(TopologyError::NodeNotFound { node: node_id })
+    }
+
+    /// Removes the link between `a` and `b` from both ends, returns whether it was known.
+    pub(crate) fn remove_edge(&mut self, a: NodeId, b: NodeId) -> bool {
+        let mut removed = false;
+        for node in &mut self.nodes {
+            let other = if node.id == a { b } else if node.id == b { a } else { continue };
+            if node.get_adjacents().contains(&other) {
+                node.remove_adjacent(other);
+                removed = true;
+            }
+        }
+        removed
     }
 
     pub(crate) fn change_node_type(&mut self, id: NodeId, new_type: NodeType) {
@@ -156,27 +540,123 @@ impl Network {
     }
 
 
+    /// Checks that `root` is in the view exactly once and that links involving a drone
+    /// are known from both ends.
+    pub(crate) fn selfcheck(&self, root: NodeId) -> Vec<Diagnostic> {
+        let mut diagnostics = Vec::new();
+        let mut seen = HashSet::new();
+        for node in &self.nodes {
+            if !seen.insert(node.id) {
+                diagnostics.push(Diagnostic::DuplicateNode(node.id));
+            }
+        }
+        if !seen.contains(&root) {
+            diagnostics.push(Diagnostic::MissingSelf);
+        }
+
+        for node in &self.nodes {
+            for adj in node.get_adjacents() {
+                if *adj == node.id {
+                    diagnostics.push(Diagnostic::SelfLoop(node.id));
+                    continue;
+                }
+                let Some(other) = self.nodes.iter().find(|n| n.id == *adj) else {
+                    continue; // not discovered yet
+                };
+                let involves_drone = node.get_node_type() == NodeType::Drone
+                    || other.get_node_type() == NodeType::Drone;
+                if involves_drone && !other.get_adjacents().contains(&node.id) {
+                    diagnostics.push(Diagnostic::AsymmetricLink(node.id, other.id));
+                }
+            }
+        }
+        diagnostics
+    }
+
     /// Finds a path from `start` to `destination` where intermediate nodes must be drones.
+    /// Among the valid paths, the one whose nodes answered floods the fastest and dropped
+    /// the fewest fragments is preferred; without measurements this is the path with the
+    /// fewest hops.
+    #[must_use]
+    pub fn find_path(&self, start: NodeId, destination: NodeId) -> Option<Route> {
+        self.find_path_avoiding(start, destination, &HashSet::new(), SearchBudget::default())
+            .ok()
+            .flatten()
+    }
+
+    /// Same as [`Self::find_path`], giving up once `budget` is spent.
+    /// # Errors
+    /// `SearchBudgetExceeded` if the budget ran out before the search completed.
+    pub(crate) fn find_path_within(&self, start: NodeId, destination: NodeId, budget: SearchBudget) -> Result<Option<Route>, RoutingError> {
+        self.find_path_avoiding(start, destination, &HashSet::new(), budget)
+    }
+
+    /// Finds a path from `start` to `destination` sharing no intermediate node with `primary`,
+    /// `None` if there is none or if `primary` has no intermediate node to avoid.
     #[must_use]
-    pub(crate) fn find_path(&self, start: NodeId, destination: NodeId) -> Option<Vec<NodeId>> {
-        let mut visited = HashSet::new();
-        let mut queue = VecDeque::new();
+    pub(crate) fn find_disjoint_path(&self, start: NodeId, destination: NodeId, primary: &[NodeId], budget: SearchBudget) -> Option<Route> {
+        if primary.len() <= 2 {
+            return None;
+        }
+        let avoid = primary[1..primary.len() - 1].iter().copied().collect();
+        self.find_path_avoiding(start, destination, &avoid, budget).ok().flatten()
+    }
+
+    /// Finds up to `k` paths from `start` to `destination` sharing no intermediate node,
+    /// shortest first as ranked by [`Self::find_path`]. A direct link is never followed
+    /// by other paths, as none could avoid its intermediate nodes. Each search is bounded
+    /// by `budget`, the paths found before one runs out of it are returned.
+    #[must_use]
+    pub fn k_shortest_paths(&self, start: NodeId, destination: NodeId, k: usize, budget: SearchBudget) -> Vec<Route> {
+        let mut paths = Vec::new();
+        let mut avoid = HashSet::new();
+        while paths.len() < k {
+            let Some(path) = self.find_path_avoiding(start, destination, &avoid, budget).ok().flatten() else {
+                break;
+            };
+            if path.len() <= 2 {
+                paths.push(path);
+                break;
+            }
+            avoid.extend(path[1..path.len() - 1].iter().copied());
+            paths.push(path);
+        }
+        paths
+    }
+
+    fn find_path_avoiding(&self, start: NodeId, destination: NodeId, avoid: &HashSet<NodeId>, budget: SearchBudget) -> Result<Option<Route>, RoutingError> {
+        let started = Instant::now();
+        let mut expansions = 0;
+        let mut visited = avoid.clone();
+        let mut queue = BinaryHeap::new();
         let mut parent_map = HashMap::new();
+        let mut best = HashMap::new();
 
-        queue.push_back(start);
-        visited.insert(start);
+        queue.push(Reverse((Duration::ZERO, start)));
+        let _ = best.insert(start, Duration::ZERO);
 
-        while let Some(current) = queue.pop_front() {
+        while let Some(Reverse((cost, current))) = queue.pop() {
+            if !visited.insert(current) {
+                continue;
+            }
             if current == destination {
                 // reconstruct path
-                let mut path = vec![destination];
+                let mut path = Route::new();
+                path.push(destination);
                 let mut cur = destination;
                 while let Some(&parent) = parent_map.get(&cur) {
                     path.push(parent);
                     cur = parent;
                 }
                 path.reverse();
-                return Some(path);
+                return Ok(Some(path));
+            }
+
+            expansions += 1;
+            let exhausted = budget.max_expansions.is_some_and(|max| expansions > max)
+                || budget.time_limit.is_some_and(|limit| started.elapsed() > limit);
+            if exhausted {
+                return Err(RoutingError::SearchBudgetExceeded { destination });
             }
 
             if let Some(node) = self.nodes.iter().find(|n| n.id == current) {
@@ -188,15 +668,20 @@ impl Network {
                     if let Some(neigh_node) = self.nodes.iter().find(|n| n.id == *neighbor) {
                         // Only allow stepping into the destination or into a drone
                         if *neighbor == destination || neigh_node.get_node_type() == NodeType::Drone {
-                            visited.insert(*neighbor);
-                            parent_map.insert(neighbor, current);
-                            queue.push_back(*neighbor);
+                            let latency = self.metadata.get(neighbor).map_or(Duration::ZERO, |m| m.latency);
+                            let drops = self.edge_stats.drop_probability(*neighbor, started).unwrap_or_default();
+                            let next_cost = cost + Self::HOP_COST + latency + Self::DROP_COST.mul_f64(drops);
+                            if best.get(neighbor).is_none_or(|c| next_cost < *c) {
+                                let _ = best.insert(*neighbor, next_cost);
+                                let _ = parent_map.insert(*neighbor, current);
+                                queue.push(Reverse((next_cost, *neighbor)));
+                            }
                         }
                     }
                 }
             }
         }
-        None
+        Ok(None)
     }
 
     #[must_use]
@@ -236,11 +721,316 @@ impl Network {
         }
 
     }
+
+    /// Returns the nodes the owner of the view (its first node) can send packets to, in
+    /// ascending order: the nodes linked to it through drones only.
+    #[must_use]
+    pub fn reachable_nodes(&self) -> Vec<NodeId> {
+        let Some(root) = self.nodes.first().map(Node::get_id) else {
+            return Vec::new();
+        };
+        let mut reached = HashSet::from([root]);
+        let mut queue = VecDeque::from([root]);
+        while let Some(current) = queue.pop_front() {
+            let Some(node) = self.nodes.iter().find(|n| n.id == current) else {
+                continue;
+            };
+            // clients and servers never forward packets
+            if current != root && node.get_node_type() != NodeType::Drone {
+                continue;
+            }
+            for adjacent in node.get_adjacents() {
+                if self.nodes.iter().any(|n| n.id == *adjacent) && reached.insert(*adjacent) {
+                    queue.push_back(*adjacent);
+                }
+            }
+        }
+        let _ = reached.remove(&root);
+        let mut reachable = reached.into_iter().collect::<Vec<_>>();
+        reachable.sort_unstable();
+        reachable
+    }
+
+    /// Returns whether the owner of the view can send packets to `dest`, see
+    /// [`Self::reachable_nodes`].
+    #[must_use]
+    pub fn is_reachable(&self, dest: NodeId) -> bool {
+        self.reachable_nodes().contains(&dest)
+    }
+
+    /// Compares the view with `other`, e.g. a node's view with the ground truth: the
+    /// `extra_*` fields list what `other` adds, the `missing_*` ones what it lacks.
+    /// A link is known as soon as one of its ends lists it.
+    #[must_use]
+    pub fn diff(&self, other: &Network) -> TopologyDiff {
+        Topology::from_network(self).diff(&Topology::from_network(other))
+    }
+
+    /// Reconciles the view with `other`, e.g. views from before and after a crash:
+    /// nodes and links known by either are kept, `other` deciding the type of the
+    /// nodes both know, and the best path quality seen by either is kept.
+    pub fn merge(&mut self, other: &Network) {
+        for theirs in &other.nodes {
+            let Some(node) = self.nodes.iter_mut().find(|n| n.id == theirs.id) else {
+                self.nodes.push(theirs.clone());
+                continue;
+            };
+            node.kind = theirs.kind;
+            for adj in theirs.get_adjacents() {
+                if !node.get_adjacents().contains(adj) {
+                    node.add_adjacent(*adj);
+                }
+            }
+        }
+        for (node_id, metadata) in &other.metadata {
+            self.annotate(*node_id, metadata.hops, metadata.latency);
+        }
+    }
+
+    /// Returns the undirected links of the view, each once as `(lower id, higher id)`, sorted.
+    fn links(&self) -> Vec<(NodeId, NodeId)> {
+        let mut links = self
+            .nodes
+            .iter()
+            .flat_map(|n| n.get_adjacents().iter().map(|adj| (n.id.min(*adj), n.id.max(*adj))))
+            .filter(|(a, b)| a != b)
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        links.sort_unstable();
+        links
+    }
+
+    /// Estimated latency of the link between `a` and `b`: the difference between the
+    /// latencies observed for its endpoints, `None` unless both were annotated by a flood.
+    fn link_latency(&self, a: NodeId, b: NodeId) -> Option<Duration> {
+        let (a, b) = (self.metadata.get(&a)?, self.metadata.get(&b)?);
+        Some(a.latency.abs_diff(b.latency))
+    }
+
+    fn sorted_nodes(&self) -> Vec<&Node> {
+        let mut nodes = self.nodes.iter().collect::<Vec<_>>();
+        nodes.sort_by_key(|n| n.id);
+        nodes
+    }
+
+    /// Renders the view as a Graphviz DOT undirected graph, styling nodes by type
+    /// (clients as boxes, drones as ellipses, servers as double octagons).
+    /// With `link_metrics`, links are labelled with their estimated latency.
+    #[must_use]
+    pub fn to_dot(&self, link_metrics: bool) -> String {
+        let mut dot = String::from("graph network {\n");
+        for node in self.sorted_nodes() {
+            let (shape, color) = match node.get_node_type() {
+                NodeType::Client => ("box", "steelblue"),
+                NodeType::Drone => ("ellipse", "gray40"),
+                NodeType::Server => ("doubleoctagon", "darkorange"),
+            };
+            let _ = writeln!(
+                dot,
+                "    {} [label=\"{} ({:?})\", shape={shape}, color={color}];",
+                node.id, node.id, node.get_node_type()
+            );
+        }
+        for (a, b) in self.links() {
+            match self.link_latency(a, b).filter(|_| link_metrics) {
+                Some(latency) => {
+                    let _ = writeln!(dot, "    {a} -- {b} [label=\"{}ms\"];", latency.as_millis());
+                }
+                None => {
+                    let _ = writeln!(dot, "    {a} -- {b};");
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the view as an undirected `GraphML` document, nodes carrying their type.
+    /// With `link_metrics`, links carry their estimated latency in milliseconds.
+    #[must_use]
+    pub fn to_graphml(&self, link_metrics: bool) -> String {
+        let mut xml = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"type\" for=\"node\" attr.name=\"type\" attr.type=\"string\"/>\n",
+            "  <key id=\"latency_ms\" for=\"edge\" attr.name=\"latency_ms\" attr.type=\"long\"/>\n",
+            "  <graph id=\"network\" edgedefault=\"undirected\">\n",
+        ));
+        for node in self.sorted_nodes() {
+            let _ = writeln!(
+                xml,
+                "    <node id=\"n{}\"><data key=\"type\">{:?}</data></node>",
+                node.id,
+                node.get_node_type()
+            );
+        }
+        for (a, b) in self.links() {
+            match self.link_latency(a, b).filter(|_| link_metrics) {
+                Some(latency) => {
+                    let _ = writeln!(
+                        xml,
+                        "    <edge source=\"n{a}\" target=\"n{b}\"><data key=\"latency_ms\">{}</data></edge>",
+                        latency.as_millis()
+                    );
+                }
+                None => {
+                    let _ = writeln!(xml, "    <edge source=\"n{a}\" target=\"n{b}\"/>");
+                }
+            }
+        }
+        xml.push_str("  </graph>\n</graphml>\n");
+        xml
+    }
+}
+
+/// Undirected graph of nodes and links, used to compare network views.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Topology {
+    nodes: BTreeMap<NodeId, NodeType>,
+    // every link stored once, smallest id first
+    edges: BTreeSet<(NodeId, NodeId)>,
+}
+
+impl Topology {
+    /// Builds the topology described by the bootstrap configuration.
+    #[must_use]
+    pub fn from_config(config: &Config) -> Self {
+        let mut topology = Self::default();
+        for drone in &config.drone {
+            let _ = topology.nodes.insert(drone.id, NodeType::Drone);
+            for adj in &drone.connected_node_ids {
+                topology.add_edge(drone.id, *adj);
+            }
+        }
+        for client in &config.client {
+            let _ = topology.nodes.insert(client.id, NodeType::Client);
+            for adj in &client.connected_drone_ids {
+                topology.add_edge(client.id, *adj);
+            }
+        }
+        for server in &config.server {
+            let _ = topology.nodes.insert(server.id, NodeType::Server);
+            for adj in &server.connected_drone_ids {
+                topology.add_edge(server.id, *adj);
+            }
+        }
+        topology
+    }
+
+    /// Builds the topology known by a network view, a link being known
+    /// as soon as one of its ends lists it.
+    #[must_use]
+    pub fn from_network(network: &Network) -> Self {
+        let mut topology = Self::default();
+        for node in &network.nodes {
+            let _ = topology.nodes.insert(node.get_id(), node.get_node_type());
+            for adj in node.get_adjacents() {
+                topology.add_edge(node.get_id(), *adj);
+            }
+        }
+        topology
+    }
+
+    fn add_edge(&mut self, a: NodeId, b: NodeId) {
+        let _ = self.edges.insert((a.min(b), a.max(b)));
+    }
+
+    /// Returns the nodes linked to `node`, in ascending id order.
+    #[must_use]
+    pub fn neighbors(&self, node: NodeId) -> Vec<NodeId> {
+        self.edges
+            .iter()
+            .filter_map(|&(a, b)| match (a == node, b == node) {
+                (true, false) => Some(b),
+                (false, true) => Some(a),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Builds the network view holding every node of the topology with its links.
+    #[must_use]
+    pub fn to_network(&self) -> Network {
+        let nodes = self
+            .nodes
+            .iter()
+            .map(|(&id, &kind)| Node::new(id, kind, self.neighbors(id)))
+            .collect();
+        Network { nodes, ..Network::default() }
+    }
+
+    /// Returns what `actual` lacks or has in excess with respect to `self`.
+    #[must_use]
+    pub fn diff(&self, actual: &Topology) -> TopologyDiff {
+        let mut diff = TopologyDiff::default();
+        for (id, kind) in &self.nodes {
+            match actual.nodes.get(id) {
+                None => diff.missing_nodes.push(*id),
+                Some(found) if found != kind => diff.wrong_types.push((*id, *kind, *found)),
+                Some(_) => {}
+            }
+        }
+        diff.extra_nodes = actual
+            .nodes
+            .keys()
+            .filter(|id| !self.nodes.contains_key(id))
+            .copied()
+            .collect();
+        diff.missing_edges = self.edges.difference(&actual.edges).copied().collect();
+        diff.extra_edges = actual.edges.difference(&self.edges).copied().collect();
+        diff
+    }
+}
+
+/// Differences between an expected and an actual [`Topology`]: with two views of the
+/// same network, what the second one removed (`missing_*`) and added (`extra_*`).
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TopologyDiff {
+    pub missing_nodes: Vec<NodeId>,
+    pub extra_nodes: Vec<NodeId>,
+    // (node, expected type, actual type)
+    pub wrong_types: Vec<(NodeId, NodeType, NodeType)>,
+    pub missing_edges: Vec<(NodeId, NodeId)>,
+    pub extra_edges: Vec<(NodeId, NodeId)>,
+}
+
+impl TopologyDiff {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.missing_nodes.is_empty()
+            && self.extra_nodes.is_empty()
+            && self.wrong_types.is_empty()
+            && self.missing_edges.is_empty()
+            && self.extra_edges.is_empty()
+    }
+}
+
+impl Display for TopologyDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.missing_nodes.is_empty() {
+            writeln!(f, "  missing nodes: {:?}", self.missing_nodes)?;
+        }
+        if !self.extra_nodes.is_empty() {
+            writeln!(f, "  unexpected nodes: {:?}", self.extra_nodes)?;
+        }
+        for (id, expected, actual) in &self.wrong_types {
+            writeln!(f, "  node {id} is a {actual:?}, expected a {expected:?}")?;
+        }
+        if !self.missing_edges.is_empty() {
+            writeln!(f, "  missing links: {:?}", self.missing_edges)?;
+        }
+        if !self.extra_edges.is_empty() {
+            writeln!(f, "  unexpected links: {:?}", self.extra_edges)?;
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use smallvec::smallvec;
 
     #[test]
     /// Tests adding a node to the network
@@ -255,6 +1045,20 @@ mod tests {
         assert!(network.nodes.iter().any(|n| n.id == 2));
     }
 
+    #[test]
+    /// Tests that adding a node links it back from its drone adjacents
+    fn test_add_node_symmetric() {
+        let root = Node::new(1, NodeType::Drone, vec![]);
+        let mut network = Network::new(root);
+        network.add_node(Node::new(2, NodeType::Client, vec![1]));
+
+        assert_eq!(network.nodes[0].get_adjacents(), &vec![2]);
+        assert!(network.selfcheck(1).is_empty());
+
+        network.nodes[0].add_adjacent(1);
+        assert_eq!(network.selfcheck(3), vec![Diagnostic::MissingSelf, Diagnostic::SelfLoop(1)]);
+    }
+
     #[test]
     /// Tests removing a node from the network
     fn test_remove_node() {
@@ -295,6 +1099,237 @@ mod tests {
         assert_eq!(network.nodes[0].get_node_type(), NodeType::Drone);
     }
 
+    #[test]
+    /// Tests that the path through the nodes answering floods faster is preferred
+    fn test_path_prefers_low_latency() {
+        let nodes = vec![
+            Node { id: 1, kind: NodeType::Client, adjacents: vec![2, 3] },
+            Node { id: 2, kind: NodeType::Drone, adjacents: vec![1, 4] },
+            Node { id: 3, kind: NodeType::Drone, adjacents: vec![1, 4] },
+            Node { id: 4, kind: NodeType::Server, adjacents: vec![2, 3] },
+        ];
+        let mut graph = Network::default();
+        for node in nodes {
+            graph.add_node(node);
+        }
+        assert_eq!(graph.find_path(1, 4), Some(smallvec![1, 2, 4]));
+
+        graph.annotate(2, 1, Duration::from_millis(40));
+        graph.annotate(3, 1, Duration::from_millis(5));
+        graph.annotate(3, 2, Duration::from_millis(9));
+        assert_eq!(graph.find_path(1, 4), Some(smallvec![1, 3, 4]));
+        assert_eq!(
+            graph.node_metadata(3),
+            Some(NodeMetadata { hops: 1, latency: Duration::from_millis(5) })
+        );
+    }
+
+    #[test]
+    /// Tests that drones dropping fragments are avoided until their drops fade
+    fn test_path_avoids_lossy_drones() {
+        let nodes = vec![
+            Node { id: 1, kind: NodeType::Client, adjacents: vec![2, 3] },
+            Node { id: 2, kind: NodeType::Drone, adjacents: vec![1, 4] },
+            Node { id: 3, kind: NodeType::Drone, adjacents: vec![1, 4] },
+            Node { id: 4, kind: NodeType::Server, adjacents: vec![2, 3] },
+        ];
+        let mut graph = Network::default();
+        for node in nodes {
+            graph.add_node(node);
+        }
+        let now = Instant::now();
+        let stats = graph.edge_stats_mut();
+        stats.record_drop(&[1, 2, 4], 2, now);
+        stats.record_delivery(&[1, 2, 4], now);
+        stats.record_delivery(&[1, 3, 4], now);
+        assert_eq!(stats.drop_probability(2, now), Some(0.5));
+        assert_eq!(stats.drop_probability(3, now), Some(0.0));
+        assert_eq!(stats.drop_probability(4, now), Some(0.0));
+        assert_eq!(stats.drop_probability(5, now), None);
+        let link = stats.link(1, 2, now).unwrap();
+        assert_eq!((link.delivered, link.dropped), (1.0, 1.0));
+        assert_eq!(graph.find_path(1, 4), Some(smallvec![1, 3, 4]));
+
+        // an old drop weighs half as much after each half-life
+        let stats = graph.edge_stats_mut();
+        stats.set_half_life(Duration::from_secs(1));
+        let later = now + Duration::from_secs(2);
+        stats.record_delivery(&[1, 2], later);
+        let link = stats.link(1, 2, later).unwrap();
+        assert_eq!((link.delivered, link.dropped), (1.25, 0.25));
+
+        graph.remove_node(2);
+        assert_eq!(graph.edge_stats().drop_probability(2, later), None);
+    }
+
+    #[test]
+    /// Tests that the backup path avoids the intermediate nodes of the primary one
+    fn test_disjoint_path() {
+        let nodes = vec![
+            Node { id: 1, kind: NodeType::Client, adjacents: vec![2, 3] },
+            Node { id: 2, kind: NodeType::Drone, adjacents: vec![1, 4, 5] },
+            Node { id: 3, kind: NodeType::Drone, adjacents: vec![1, 5] },
+            Node { id: 4, kind: NodeType::Drone, adjacents: vec![2, 6] },
+            Node { id: 5, kind: NodeType::Drone, adjacents: vec![2, 3, 6] },
+            Node { id: 6, kind: NodeType::Server, adjacents: vec![4, 5] },
+        ];
+        let mut graph = Network::default();
+        for node in nodes {
+            graph.add_node(node);
+        }
+        let budget = SearchBudget::default();
+        assert_eq!(graph.find_disjoint_path(1, 6, &[1, 2, 4, 6], budget), Some(smallvec![1, 3, 5, 6]));
+        assert_eq!(graph.find_disjoint_path(1, 6, &[1, 2, 5, 6], budget), None);
+        assert_eq!(graph.find_disjoint_path(1, 2, &[1, 2], budget), None);
+    }
+
+    #[test]
+    /// Tests that the k shortest paths share no intermediate node and stop after a direct link
+    fn test_k_shortest_paths() {
+        let nodes = vec![
+            Node { id: 1, kind: NodeType::Client, adjacents: vec![2, 3, 6] },
+            Node { id: 2, kind: NodeType::Drone, adjacents: vec![1, 4, 5] },
+            Node { id: 3, kind: NodeType::Drone, adjacents: vec![1, 5] },
+            Node { id: 4, kind: NodeType::Drone, adjacents: vec![2, 7] },
+            Node { id: 5, kind: NodeType::Drone, adjacents: vec![2, 3, 7] },
+            Node { id: 6, kind: NodeType::Drone, adjacents: vec![1] },
+            Node { id: 7, kind: NodeType::Server, adjacents: vec![4, 5] },
+        ];
+        let mut graph = Network::default();
+        for node in nodes {
+            graph.add_node(node);
+        }
+        let paths: Vec<Route> = vec![smallvec![1, 2, 4, 7], smallvec![1, 3, 5, 7]];
+        let budget = SearchBudget::default();
+        assert_eq!(graph.k_shortest_paths(1, 7, 3, budget), paths);
+        assert_eq!(graph.k_shortest_paths(1, 7, 1, budget), paths[..1]);
+        let direct: Route = smallvec![1, 6];
+        assert_eq!(graph.k_shortest_paths(1, 6, 3, budget), vec![direct]);
+        assert!(graph.k_shortest_paths(1, 8, 3, budget).is_empty());
+        let exhausted = SearchBudget { max_expansions: Some(0), ..budget };
+        assert!(graph.k_shortest_paths(1, 7, 3, exhausted).is_empty());
+    }
+
+    #[test]
+    /// Tests that only the nodes linked through drones are reachable
+    fn test_reachability() {
+        let mut graph = Network::new(Node::new(1, NodeType::Client, vec![2, 5]));
+        graph.add_node(Node::new(2, NodeType::Drone, vec![1, 3]));
+        graph.add_node(Node::new(3, NodeType::Server, vec![2, 4]));
+        graph.add_node(Node::new(4, NodeType::Drone, vec![3]));
+        graph.add_node(Node::new(5, NodeType::Server, vec![1]));
+        // 4 hangs off server 3, which doesn't forward
+        assert_eq!(graph.reachable_nodes(), vec![2, 3, 5]);
+        assert!(graph.is_reachable(3));
+        assert!(!graph.is_reachable(4));
+
+        graph.remove_node(2);
+        assert_eq!(graph.reachable_nodes(), vec![5]);
+        assert!(!graph.is_reachable(3));
+    }
+
+    #[test]
+    /// Tests that a search running out of budget fails instead of completing
+    fn test_search_budget() {
+        let mut graph = Network::new(Node::new(0, NodeType::Client, vec![1]));
+        for id in 1..100 {
+            graph.add_node(Node::new(id, NodeType::Drone, vec![id - 1, id + 1]));
+        }
+        graph.add_node(Node::new(100, NodeType::Server, vec![99]));
+
+        let budget = SearchBudget { max_expansions: Some(50), time_limit: None };
+        assert!(matches!(
+            graph.find_path_within(0, 100, budget),
+            Err(RoutingError::SearchBudgetExceeded { destination: 100 })
+        ));
+        let budget = SearchBudget { max_expansions: Some(100), time_limit: None };
+        assert_eq!(graph.find_path_within(0, 100, budget).unwrap().map(|p| p.len()), Some(101));
+        assert_eq!(graph.find_path_within(0, 100, SearchBudget::default()).unwrap().map(|p| p.len()), Some(101));
+    }
+
+    #[test]
+    /// Tests that two views are compared link by link and reconciled by a merge
+    fn test_diff_and_merge() {
+        let mut before = Network::new(Node::new(1, NodeType::Client, vec![2]));
+        before.add_node(Node::new(2, NodeType::Drone, vec![1, 3]));
+        before.add_node(Node::new(3, NodeType::Drone, vec![2]));
+        before.annotate(3, 2, Duration::from_millis(30));
+        let mut after = Network::new(Node::new(1, NodeType::Client, vec![2]));
+        after.add_node(Node::new(2, NodeType::Drone, vec![1, 4]));
+        after.add_node(Node::new(4, NodeType::Server, vec![2]));
+        after.annotate(4, 2, Duration::from_millis(10));
+
+        let diff = before.diff(&after);
+        assert_eq!(diff.missing_nodes, vec![3]);
+        assert_eq!(diff.extra_nodes, vec![4]);
+        assert_eq!(diff.missing_edges, vec![(2, 3)]);
+        assert_eq!(diff.extra_edges, vec![(2, 4)]);
+        assert!(before.diff(&before).is_empty());
+
+        before.merge(&after);
+        let merged = before.diff(&after);
+        assert_eq!(merged.extra_nodes, Vec::<NodeId>::new());
+        assert_eq!(merged.missing_nodes, vec![3]);
+        assert!(merged.extra_edges.is_empty());
+        assert_eq!(before.nodes.len(), 4);
+        assert!(before.find_path(1, 4).is_some());
+        assert!(before.node_metadata(4).is_some());
+
+        // the type of the merged view wins
+        let mut promoted = after.clone();
+        promoted.add_node(Node::new(3, NodeType::Server, vec![]));
+        assert_eq!(before.diff(&promoted).wrong_types, vec![(3, NodeType::Drone, NodeType::Server)]);
+        before.merge(&promoted);
+        assert!(before.diff(&promoted).wrong_types.is_empty());
+    }
+
+    #[test]
+    /// Tests that the topology file is the ground truth flooded views are compared to
+    fn test_from_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("topology.toml");
+        fs::write(
+            &path,
+            r"
+            [[drone]]
+            id = 2
+            connected_node_ids = [1, 3, 4]
+            pdr = 0.1
+
+            [[drone]]
+            id = 3
+            connected_node_ids = [2, 4]
+            pdr = 0.0
+
+            [[client]]
+            id = 1
+            connected_drone_ids = [2]
+
+            [[server]]
+            id = 4
+            connected_drone_ids = [2, 3]
+            ",
+        )
+        .unwrap();
+        let truth = Network::from_config(&path).unwrap();
+        assert_eq!(truth.nodes.len(), 4);
+        assert_eq!(truth.get_servers(), Some(vec![4]));
+
+        // a client that only heard back through drone 2
+        let mut view = Network::new(Node::new(1, NodeType::Client, vec![2]));
+        view.add_node(Node::new(2, NodeType::Drone, vec![1, 4]));
+        view.add_node(Node::new(4, NodeType::Server, vec![2]));
+        let diff = truth.diff(&view);
+        assert_eq!(diff.missing_nodes, vec![3]);
+        assert_eq!(diff.missing_edges, vec![(2, 3), (3, 4)]);
+        assert!(diff.extra_edges.is_empty());
+
+        assert!(matches!(
+            Network::from_config(dir.path().join("missing.toml")),
+            Err(TopologyError::Unreadable { .. })
+        ));
+    }
+
     #[test]
     fn test_direct_client_to_server() {
         let nodes = vec![
@@ -308,7 +1343,7 @@ mod tests {
             graph.add_node(node);
         }
         let path = graph.find_path(1, 2);
-        assert_eq!(path, Some(vec![1, 2]));
+        assert_eq!(path, Some(smallvec![1, 2]));
     }
 
     #[test]
@@ -323,7 +1358,7 @@ mod tests {
         for node in nodes {
             graph.add_node(node);
         }        let path = graph.find_path(1, 3);
-        assert_eq!(path, Some(vec![1, 2, 3]));
+        assert_eq!(path, Some(smallvec![1, 2, 3]));
     }
 
     #[test]
@@ -357,6 +1392,54 @@ mod tests {
             graph.add_node(node);
         }
         let path = graph.find_path(1, 5);
-        assert_eq!(path, Some(vec![1, 4, 5])); // must avoid node 2 because it's not a drone
+        assert_eq!(path, Some(smallvec![1, 4, 5])); // must avoid node 2 because it's not a drone
+    }
+
+    #[test]
+    /// Tests the DOT and `GraphML` exports of a small view
+    fn test_export() {
+        let mut graph = Network::new(Node::new(1, NodeType::Client, vec![2]));
+        graph.add_node(Node::new(2, NodeType::Drone, vec![1, 3]));
+        graph.add_node(Node::new(3, NodeType::Server, vec![2]));
+        graph.annotate(2, 1, Duration::from_millis(3));
+        graph.annotate(3, 2, Duration::from_millis(10));
+
+        let dot = graph.to_dot(false);
+        assert!(dot.starts_with("graph network {"));
+        assert!(dot.contains("1 [label=\"1 (Client)\", shape=box"));
+        assert!(dot.contains("3 [label=\"3 (Server)\", shape=doubleoctagon"));
+        assert!(dot.contains("    1 -- 2;\n    2 -- 3;\n"));
+        assert!(graph.to_dot(true).contains("2 -- 3 [label=\"7ms\"];"));
+
+        let xml = graph.to_graphml(true);
+        assert!(xml.contains("<node id=\"n2\"><data key=\"type\">Drone</data></node>"));
+        assert!(xml.contains("<edge source=\"n1\" target=\"n2\"/>"));
+        assert!(xml.contains(
+            "<edge source=\"n2\" target=\"n3\"><data key=\"latency_ms\">7</data></edge>"
+        ));
+        assert_eq!(xml.matches("<edge ").count(), 2);
+    }
+
+    #[test]
+    /// Tests the error codes and the session and destination context of errors
+    fn test_error_context() {
+        let error = NetworkError::from(RoutingError::PathNotFound { destination: 4 });
+        assert_eq!(error.code(), "routing.path_not_found");
+        assert_eq!(error.destination(), Some(4));
+        assert_eq!(error.session_id(), None);
+
+        let error = error.in_session(9);
+        assert_eq!(error.code(), "routing.path_not_found");
+        assert_eq!((error.session_id(), error.destination()), (Some(9), Some(4)));
+        assert!(matches!(
+            error.root(),
+            NetworkError::Routing(RoutingError::PathNotFound { destination: 4 })
+        ));
+        assert_eq!(error.to_string(), "Path not found for node 4 in session 9");
+
+        let error = NetworkError::from(ChannelError::ControllerDisconnected).in_session(3).towards(2);
+        assert_eq!(error.code(), "channel.controller_disconnected");
+        assert_eq!(error.to_string(), "Controller disconnected in session 3 towards node 2");
+        assert!(std::error::Error::source(&error).is_some());
     }
 }
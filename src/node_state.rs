@@ -0,0 +1,198 @@
+use crate::config::CommonConfig;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+use wg_internal::network::NodeId;
+
+/// Identity persisted in the state directory, stable across restarts of the node.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NodeIdentity {
+    pub node_id: NodeId,
+    pub instance: Uuid,
+    // seconds since the unix epoch
+    pub created_at: u64,
+}
+
+/// Durable state of a node, rooted at `{root}/node_{id}`.
+///
+/// Every persistent component (file cache, chat storage, transfer resume data,
+/// crypto keys) gets its own subdirectory, so that restarting a simulated node
+/// restores all of them coherently and wiping it clears all of them at once.
+#[derive(Debug, Clone)]
+pub struct NodeState {
+    dir: PathBuf,
+    identity: NodeIdentity,
+}
+
+impl NodeState {
+    const IDENTITY_FILE: &'static str = "identity.json";
+    const CACHE_DIR: &'static str = "cache";
+    const CHAT_DIR: &'static str = "chat";
+    const TRANSFERS_DIR: &'static str = "transfers";
+    const KEYS_DIR: &'static str = "keys";
+    const MEDIA_DIR: &'static str = "media";
+
+    fn node_dir(root: &Path, id: NodeId) -> PathBuf {
+        root.join(format!("node_{id}"))
+    }
+
+    /// Returns whether `id` already has a state directory under `root`.
+    #[must_use]
+    pub fn exists(root: impl AsRef<Path>, id: NodeId) -> bool {
+        Self::node_dir(root.as_ref(), id)
+            .join(Self::IDENTITY_FILE)
+            .is_file()
+    }
+
+    /// Opens the state directory of `id` under the `cache_root` of `config`, see
+    /// [`Self::init`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state directory cannot be opened.
+    pub fn from_config(config: &CommonConfig, id: NodeId) -> io::Result<Self> {
+        Self::init(&config.cache_root, id)
+    }
+
+    /// Opens the state directory of `id` under `root`, creating it on first use.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directories cannot be created or the existing
+    /// identity cannot be read.
+    pub fn init(root: impl AsRef<Path>, id: NodeId) -> io::Result<Self> {
+        if Self::exists(root.as_ref(), id) {
+            return Self::load(root, id);
+        }
+
+        let dir = Self::node_dir(root.as_ref(), id);
+        for sub in [
+            Self::CACHE_DIR,
+            Self::CHAT_DIR,
+            Self::TRANSFERS_DIR,
+            Self::KEYS_DIR,
+            Self::MEDIA_DIR,
+        ] {
+            fs::create_dir_all(dir.join(sub))?;
+        }
+
+        let identity = NodeIdentity {
+            node_id: id,
+            instance: Uuid::new_v4(),
+            created_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        };
+        let data = serde_json::to_vec_pretty(&identity).map_err(io::Error::other)?;
+        fs::write(dir.join(Self::IDENTITY_FILE), data)?;
+
+        Ok(Self { dir, identity })
+    }
+
+    /// Loads the existing state directory of `id` under `root`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the state was never initialized, if the identity
+    /// file is corrupted or if it belongs to a different node.
+    pub fn load(root: impl AsRef<Path>, id: NodeId) -> io::Result<Self> {
+        let dir = Self::node_dir(root.as_ref(), id);
+        let data = fs::read(dir.join(Self::IDENTITY_FILE))?;
+        let identity: NodeIdentity = serde_json::from_slice(&data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        if identity.node_id != id {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("state directory belongs to node {}", identity.node_id),
+            ));
+        }
+        Ok(Self { dir, identity })
+    }
+
+    /// Deletes the whole state directory, identity included.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be removed.
+    pub fn wipe(self) -> io::Result<()> {
+        fs::remove_dir_all(&self.dir)
+    }
+
+    #[must_use]
+    pub fn id(&self) -> NodeId {
+        self.identity.node_id
+    }
+
+    #[must_use]
+    pub fn identity(&self) -> &NodeIdentity {
+        &self.identity
+    }
+
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    #[must_use]
+    pub fn cache_dir(&self) -> PathBuf {
+        self.dir.join(Self::CACHE_DIR)
+    }
+
+    #[must_use]
+    pub fn chat_dir(&self) -> PathBuf {
+        self.dir.join(Self::CHAT_DIR)
+    }
+
+    #[must_use]
+    pub fn transfers_dir(&self) -> PathBuf {
+        self.dir.join(Self::TRANSFERS_DIR)
+    }
+
+    #[must_use]
+    pub fn keys_dir(&self) -> PathBuf {
+        self.dir.join(Self::KEYS_DIR)
+    }
+
+    #[must_use]
+    pub fn media_dir(&self) -> PathBuf {
+        self.dir.join(Self::MEDIA_DIR)
+    }
+}
+
+#[cfg(test)]
+mod node_state_tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    /// Tests that a restarted node gets back the same identity and layout
+    fn test_init_and_reload() {
+        let root = tempdir().unwrap();
+        assert!(!NodeState::exists(root.path(), 4));
+        assert!(NodeState::load(root.path(), 4).is_err());
+
+        let state = NodeState::init(root.path(), 4).unwrap();
+        assert!(state.cache_dir().is_dir());
+        assert!(state.keys_dir().is_dir());
+        assert!(state.media_dir().is_dir());
+        fs::write(state.chat_dir().join("history"), b"hi").unwrap();
+
+        let reloaded = NodeState::init(root.path(), 4).unwrap();
+        assert_eq!(reloaded.identity(), state.identity());
+        assert!(reloaded.chat_dir().join("history").is_file());
+    }
+
+    #[test]
+    /// Tests that wiping removes every component of the state
+    fn test_wipe() {
+        let root = tempdir().unwrap();
+        let state = NodeState::init(root.path(), 9).unwrap();
+        let dir = state.dir().to_path_buf();
+        state.wipe().unwrap();
+        assert!(!dir.exists());
+        assert!(!NodeState::exists(root.path(), 9));
+    }
+}
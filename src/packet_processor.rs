@@ -1,81 +1,862 @@
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::{Arc, Barrier};
+use std::time::{Duration, Instant};
 
-use crate::{FragmentAssembler, RoutingHandler, network::NetworkError, types::Command};
+use crate::{
+    FragmentAssembler, RoutingHandler,
+    discovery::ServiceDiscovery,
+    metrics::Metrics,
+    negotiation::VersionNegotiator,
+    network::NetworkError,
+    node_state::NodeState,
+    packet_queue::PriorityPacketQueue,
+    routing_handler::is_reserved_control_fragment,
+    selfcheck,
+    transform::{PacketDirection, SharedMiddleware, run_middlewares},
+    types::{
+        AggregateAck, Command, Heartbeat, NodeCommand, NodeStats, ProtocolVersion,
+        TerminationReason,
+    },
+};
 
-use crossbeam_channel::{Receiver, select_biased};
+use crossbeam_channel::{Receiver, never, select_biased};
 use wg_internal::{
     network::NodeId,
     packet::{Packet, PacketType},
 };
 
+/// How often [`Processor::tick`] is called by [`Processor::run`].
+pub const TICK_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often [`Processor::run`] verifies the node invariants with [`selfcheck`].
+pub const SELFCHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Packets [`Processor::run`] takes from a [`PriorityPacketQueue`] before checking
+/// for commands again.
+pub const PACKET_BATCH: usize = 32;
+
 pub trait Processor: Send {
     fn controller_recv(&self) -> &Receiver<Box<dyn Command>>;
     fn packet_recv(&self) -> &Receiver<Packet>;
+    /// Channel dedicated to control packets (Ack, Nack, floods), if the topology wiring provides one.
+    /// Control packets received here are served before the data fragments waiting in `packet_recv`.
+    fn control_packet_recv(&self) -> Option<&Receiver<Packet>> {
+        None
+    }
     fn assembler(&mut self) -> &mut FragmentAssembler;
     fn routing_handler(&mut self) -> &mut RoutingHandler;
+    /// Persistent state of the node, if any. When provided, [`Processor::run`] saves the
+    /// unacknowledged sessions there on exit and resumes them on the next start.
+    fn node_state(&self) -> Option<&NodeState> {
+        None
+    }
+    /// Backlog of received packets, if any. When provided, [`Processor::run`] moves the
+    /// packets waiting in `packet_recv` into it and serves them by priority, so that
+    /// Acks, Nacks and floods don't wait behind a burst of data fragments.
+    fn packet_queue(&mut self) -> Option<&mut PriorityPacketQueue> {
+        None
+    }
+    /// Protocol version handshake of the node, if any. When provided,
+    /// [`Processor::handle_packet`] greets every peer a fragment arrives from and
+    /// consumes the handshake control fragments instead of passing them on.
+    fn version_negotiator(&mut self) -> Option<&mut VersionNegotiator> {
+        None
+    }
+    /// Server discovery of the node, if any. When provided, [`Processor::tick`] queries
+    /// the type of the servers found by every flood and [`Processor::handle_packet`]
+    /// consumes their answers instead of passing them on.
+    fn service_discovery(&mut self) -> Option<&mut ServiceDiscovery> {
+        None
+    }
+
+    /// Middleware chain of the node, run in order on the packets received before
+    /// [`Processor::handle_packet`] and on the packets sent to neighbors. Defaults to
+    /// the chain of the routing handler, which applies it to the packets it sends.
+    fn middlewares(&mut self) -> &mut Vec<SharedMiddleware> {
+        self.routing_handler().middlewares()
+    }
 
     fn handle_msg(&mut self, msg: Vec<u8>, from: NodeId, session_id: u64);
+    /// Handles the payload of a reserved control fragment ("fragment 0 of 0") received
+    /// from `from`. Such fragments are acknowledged but never reach the assembler.
+    fn handle_control_fragment(&mut self, _payload: Vec<u8>, _from: NodeId, _session_id: u64) {}
     fn handle_command(&mut self, cmd: Box<dyn Command>) -> bool;
 
     /// Handles a packet in a standard way
     /// # Errors
     /// returns an Errors if handling fails
     fn handle_packet(&mut self, pkt: Packet) -> Result<(), NetworkError> {
-        let router = self.routing_handler();
-        match pkt.pack_type {
-            PacketType::MsgFragment(fragment) => {
-                let idx = fragment.fragment_index;
-                let mut shr = pkt.routing_header.clone();
-                shr.reverse();
-                shr.hop_index = 1;
-                self.routing_handler().send_ack(shr, pkt.session_id, idx)?;
-                if let Some(msg) = self.assembler().add_fragment(
-                    fragment,
-                        pkt.session_id,
-                    pkt.routing_header.hops[0],
-                ) {
-                    self.handle_msg(msg, pkt.routing_header.hops[0], pkt.session_id);
-                }
-            }
-            PacketType::Ack(ack) => {
-                router.handle_ack(&ack, pkt.session_id, pkt.routing_header.hops[0]);
+        dispatch_packet(&mut SyncNode(self), pkt)
+    }
+
+    /// Hands the messages released by ordered delivery over to `handle_msg`.
+    fn deliver_ready_messages(&mut self) {
+        deliver_ready(&mut SyncNode(self));
+    }
+
+    /// Periodic housekeeping, called by [`Processor::run`] every [`TICK_INTERVAL`].
+    /// The default implementation sends the fragments held back by session rates,
+    /// queries the servers found by a settled flood (see [`ServiceDiscovery::poll`]),
+    /// drops the stale incomplete messages (see `FragmentAssembler::evict_stale`)
+    /// and releases the messages whose ordering hold expired.
+    fn tick(&mut self) {
+        let _ = self.routing_handler().tick();
+        discover_servers(&mut SyncNode(self));
+        let _ = self.assembler().evict_stale();
+        self.deliver_ready_messages();
+    }
+
+    /// Returns the statistics of the node, combining routing and assembler counters.
+    fn node_stats(&mut self) -> NodeStats {
+        combined_stats(&mut SyncNode(self))
+    }
+
+    /// Returns the traffic counters of the node, combining routing and assembler counters.
+    fn metrics(&mut self) -> Metrics {
+        combined_metrics(&mut SyncNode(self))
+    }
+
+    /// Answers the `NodeCommand::QueryTopology`, `NodeCommand::QueryStats`,
+    /// `NodeCommand::QueryMetrics` and `NodeCommand::QueryBlacklist` of the controller with
+    /// a snapshot of the network view, [`Processor::node_stats`], [`Processor::metrics`]
+    /// or the blacklisted nodes.
+    /// Called by [`Processor::run`] before `handle_command`, returns whether `cmd` was
+    /// a query.
+    fn answer_query(&mut self, cmd: &dyn Command) -> bool {
+        match cmd.as_any().downcast_ref::<NodeCommand>() {
+            Some(NodeCommand::QueryTopology(reply)) => {
+                let _ = reply.send(self.routing_handler().network_view().clone());
             }
-            PacketType::Nack(nack) => {
-                router.handle_nack(&nack, pkt.session_id, pkt.routing_header.hops[0])?;
+            Some(NodeCommand::QueryStats(reply)) => {
+                let _ = reply.send(self.node_stats());
             }
-            PacketType::FloodRequest(flood_request) => {
-                router.handle_flood_request(flood_request, pkt.session_id)?;
+            Some(NodeCommand::QueryMetrics(reply)) => {
+                let _ = reply.send(self.metrics());
             }
-            PacketType::FloodResponse(flood_response) => {
-                let _ = router.handle_flood_response(&flood_response);
+            Some(NodeCommand::QueryBlacklist(reply)) => {
+                let _ = reply.send(self.routing_handler().blacklist());
             }
+            _ => return false,
         }
-        Ok(())
+        true
     }
 
+    /// Runs the node until it is shut down, fails or panics, then notifies the
+    /// controller with a `NodeEvent::Terminated` carrying the reason and the closing statistics.
     fn run(&mut self, barrier: Arc<Barrier>) {
         barrier.wait();
+        let reason = match panic::catch_unwind(AssertUnwindSafe(|| self.event_loop())) {
+            Ok(reason) => reason,
+            Err(payload) => TerminationReason::Panic(
+                payload
+                    .downcast_ref::<&str>()
+                    .map(ToString::to_string)
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default(),
+            ),
+        };
+        if let Some(state) = self.node_state().cloned() {
+            let _ = self.routing_handler().save_buffer(&state);
+        }
+        let stats = self.node_stats();
+        self.routing_handler().notify_terminated(reason, stats);
+    }
+
+    /// Processes commands and packets until the node has to stop, returning why.
+    fn event_loop(&mut self) -> TerminationReason {
+        let _ = selfcheck(self);
         let _ = self.routing_handler().start_flood(None);
+        if let Some(state) = self.node_state().cloned() {
+            let _ = self.routing_handler().load_buffer(&state);
+        }
+        let no_control_channel = never();
+        let mut last_tick = Instant::now();
+        let mut last_selfcheck = Instant::now();
         loop {
+            if last_tick.elapsed() >= TICK_INTERVAL {
+                last_tick = Instant::now();
+                self.tick();
+            }
+            if last_selfcheck.elapsed() >= SELFCHECK_INTERVAL {
+                last_selfcheck = Instant::now();
+                let _ = selfcheck(self);
+            }
+
+            let wait = if self.packet_queue().is_some_and(|queue| !queue.is_empty()) {
+                Duration::ZERO
+            } else {
+                TICK_INTERVAL
+            };
             select_biased! {
                 recv(self.controller_recv()) -> cmd => {
                     if let Ok(cmd) = cmd {
-                        if self.handle_command(cmd) {
+                        if let Some(grace) = graceful_shutdown(cmd.as_ref()) {
+                            self.routing_handler().begin_shutdown(grace);
+                        } else if !self.answer_query(cmd.as_ref()) && self.handle_command(cmd) {
                             // Terminate if handle_command returns true
-                            println!("Terminating");
-                            return;
+                            #[cfg(feature = "telemetry")]
+                            tracing::info!("node terminating");
+                            return TerminationReason::Shutdown;
+                        }
+                    }
+                }
+
+                recv(self.control_packet_recv().unwrap_or(&no_control_channel)) -> pkt => {
+                    if let Ok(pkt) = pkt {
+                        if let Err(e) = receive_packet(self, pkt) {
+                            return TerminationReason::Error(e.to_string());
                         }
                     }
                 }
 
                 recv(self.packet_recv()) -> pkt => {
                     if let Ok(pkt) = pkt {
-                        if self.handle_packet(pkt).is_err() {
-                            return;
+                        if let Some(queue) = self.packet_queue() {
+                            queue.push(pkt);
+                        } else if let Err(e) = receive_packet(self, pkt) {
+                            return TerminationReason::Error(e.to_string());
                         }
                     }
                 }
+
+                // wake up to tick even when no packet arrives, at once if packets are queued
+                default(wait) => {}
+            }
+            if let Err(e) = serve_queued_packets(self) {
+                return TerminationReason::Error(e.to_string());
+            }
+            if self.routing_handler().poll_shutdown() {
+                return TerminationReason::Shutdown;
             }
         }
     }
 }
+
+/// Returns the grace period of a `NodeCommand::GracefulShutdown`, which the run loops
+/// serve themselves instead of passing it to `handle_command`.
+pub(crate) fn graceful_shutdown(cmd: &dyn Command) -> Option<Duration> {
+    match cmd.as_any().downcast_ref::<NodeCommand>() {
+        Some(NodeCommand::GracefulShutdown(grace)) => Some(*grace),
+        _ => None,
+    }
+}
+
+/// Parts of a node the standard packet handling relies on, shared by [`Processor`]
+/// and the async processor.
+pub(crate) trait NodeCore {
+    fn assembler(&mut self) -> &mut FragmentAssembler;
+    fn routing_handler(&mut self) -> &mut RoutingHandler;
+    fn version_negotiator(&mut self) -> Option<&mut VersionNegotiator>;
+    fn service_discovery(&mut self) -> Option<&mut ServiceDiscovery>;
+    fn handle_msg(&mut self, msg: Vec<u8>, from: NodeId, session_id: u64);
+    fn handle_control_fragment(&mut self, payload: Vec<u8>, from: NodeId, session_id: u64);
+    fn deliver_ready_messages(&mut self);
+}
+
+/// [`NodeCore`] view of a [`Processor`].
+pub(crate) struct SyncNode<'a, P: ?Sized>(pub(crate) &'a mut P);
+
+impl<P: Processor + ?Sized> NodeCore for SyncNode<'_, P> {
+    fn assembler(&mut self) -> &mut FragmentAssembler {
+        self.0.assembler()
+    }
+    fn routing_handler(&mut self) -> &mut RoutingHandler {
+        self.0.routing_handler()
+    }
+    fn version_negotiator(&mut self) -> Option<&mut VersionNegotiator> {
+        self.0.version_negotiator()
+    }
+    fn service_discovery(&mut self) -> Option<&mut ServiceDiscovery> {
+        self.0.service_discovery()
+    }
+    fn handle_msg(&mut self, msg: Vec<u8>, from: NodeId, session_id: u64) {
+        self.0.handle_msg(msg, from, session_id);
+    }
+    fn handle_control_fragment(&mut self, payload: Vec<u8>, from: NodeId, session_id: u64) {
+        self.0.handle_control_fragment(payload, from, session_id);
+    }
+    fn deliver_ready_messages(&mut self) {
+        self.0.deliver_ready_messages();
+    }
+}
+
+/// Moves the packets waiting in the channel of `node` into its packet queue, if it has
+/// one, and handles up to [`PACKET_BATCH`] of them by priority. The messages they
+/// complete are delivered once the batch is over, round-robin by sender.
+fn serve_queued_packets<P: Processor + ?Sized>(node: &mut P) -> Result<(), NetworkError> {
+    if node.packet_queue().is_none() {
+        return Ok(());
+    }
+    let arrived = node.packet_recv().try_iter().collect::<Vec<_>>();
+    if let Some(queue) = node.packet_queue() {
+        for packet in arrived {
+            queue.push(packet);
+        }
+    }
+    node.assembler().defer_delivery(true);
+    let mut result = Ok(());
+    for _ in 0..PACKET_BATCH {
+        let Some(packet) = node.packet_queue().and_then(PriorityPacketQueue::pop) else {
+            break;
+        };
+        result = receive_packet(node, packet);
+        if result.is_err() {
+            break;
+        }
+    }
+    node.assembler().defer_delivery(false);
+    node.deliver_ready_messages();
+    result
+}
+
+/// Passes `pkt` through the middlewares of `node`, then to `handle_packet` unless one
+/// of them dropped it.
+fn receive_packet<P: Processor + ?Sized>(node: &mut P, pkt: Packet) -> Result<(), NetworkError> {
+    match run_middlewares(node.middlewares(), pkt, PacketDirection::Received) {
+        Some(pkt) => node.handle_packet(pkt),
+        None => Ok(()),
+    }
+}
+
+/// Standard handling of `pkt`, see [`Processor::handle_packet`].
+#[cfg_attr(
+    feature = "telemetry",
+    tracing::instrument(
+        level = "trace",
+        skip_all,
+        fields(session_id = pkt.session_id, from = ?pkt.routing_header.hops.first())
+    )
+)]
+pub(crate) fn dispatch_packet<N: NodeCore + ?Sized>(
+    node: &mut N,
+    pkt: Packet,
+) -> Result<(), NetworkError> {
+    let router = node.routing_handler();
+    router.tap_packet(PacketDirection::Received, &pkt);
+    // fragments this node is not the destination of are nacked back, as drones do
+    if let PacketType::MsgFragment(_) = &pkt.pack_type
+        && let Some(nack_type) = router.misdelivery(&pkt.routing_header)
+    {
+        return router.send_nack(&pkt, nack_type);
+    }
+    match pkt.pack_type {
+        PacketType::MsgFragment(fragment) => {
+            router.record_received(pkt.routing_header.hops[0], fragment.data.len() as u64);
+            router.acknowledge_fragment(&pkt.routing_header, pkt.session_id, &fragment)?;
+            if is_reserved_control_fragment(&fragment) {
+                let len = usize::from(fragment.length).min(fragment.data.len());
+                let payload = fragment.data[..len].to_vec();
+                let from = pkt.routing_header.hops[0];
+                if let Ok(ack) = serde_json::from_slice::<AggregateAck>(&payload) {
+                    router.handle_aggregate_ack(&ack, from);
+                } else if serde_json::from_slice::<Heartbeat>(&payload).is_ok() {
+                    // already answered by the Ack of the fragment
+                } else if !negotiate_version(node, &payload, from) {
+                    node.handle_control_fragment(payload, from, pkt.session_id);
+                }
+                return Ok(());
+            }
+            let from = pkt.routing_header.hops[0];
+            greet(node, from);
+            let before = node.assembler().progress(pkt.session_id, from);
+            let msg = node.assembler().add_fragment(fragment, pkt.session_id, from);
+            // completed messages are reported by the node handling them, duplicates and
+            // fragments past the total don't progress
+            if msg.is_none()
+                && let Some((received, total)) = node.assembler().progress(pkt.session_id, from)
+                && received > before.map_or(0, |(received, _)| received)
+            {
+                node.routing_handler()
+                    .notify_receive_progress(from, pkt.session_id, received, total);
+            }
+            if let Some(msg) = msg {
+                let msg = node.routing_handler().transform_incoming(msg, from);
+                hand_over(node, msg, from, pkt.session_id);
+            }
+            node.deliver_ready_messages();
+        }
+        PacketType::Ack(ack) => {
+            router.handle_ack(&ack, pkt.session_id, pkt.routing_header.hops[0]);
+        }
+        PacketType::Nack(nack) => {
+            router.handle_nack(&nack, pkt.session_id, pkt.routing_header.hops[0])?;
+        }
+        PacketType::FloodRequest(flood_request) => {
+            router.handle_flood_request(flood_request, pkt.session_id)?;
+        }
+        PacketType::FloodResponse(flood_response) => {
+            let _ = router.handle_flood_response(&flood_response);
+        }
+    }
+    Ok(())
+}
+
+/// Consumes `payload` if `node` negotiates versions and it is a [`ProtocolVersion`]
+/// handshake, answering it. Returns whether it was consumed.
+fn negotiate_version<N: NodeCore + ?Sized>(node: &mut N, payload: &[u8], from: NodeId) -> bool {
+    let Some(negotiator) = node.version_negotiator() else {
+        return false;
+    };
+    let Ok(message) = serde_json::from_slice::<ProtocolVersion>(payload) else {
+        return false;
+    };
+    let reply = negotiator.handle(from, &message);
+    // a peer left without our reply is greeted again on its next fragment
+    if let Some(reply) = reply
+        && !send_version(node, &reply, from)
+        && let Some(negotiator) = node.version_negotiator()
+    {
+        negotiator.forget(from);
+    }
+    true
+}
+
+/// Greets `peer` if `node` negotiates versions and did not yet. A hello that cannot be
+/// sent, e.g. for lack of a route, is sent again on the next fragment of `peer`.
+fn greet<N: NodeCore + ?Sized>(node: &mut N, peer: NodeId) {
+    let Some(hello) = node
+        .version_negotiator()
+        .and_then(|negotiator| negotiator.greet(peer))
+    else {
+        return;
+    };
+    if send_version(node, &hello, peer)
+        && let Some(negotiator) = node.version_negotiator()
+    {
+        negotiator.mark_greeted(peer);
+    }
+}
+
+/// Sends the handshake message `message` to `to`, returns whether it was sent.
+fn send_version<N: NodeCore + ?Sized>(node: &mut N, message: &ProtocolVersion, to: NodeId) -> bool {
+    serde_json::to_vec(message).is_ok_and(|payload| {
+        node.routing_handler()
+            .send_control_fragment(&payload, to)
+            .is_ok()
+    })
+}
+
+pub(crate) fn deliver_ready<N: NodeCore + ?Sized>(node: &mut N) {
+    for (session_id, from, msg) in node.assembler().take_ready() {
+        let msg = node.routing_handler().transform_incoming(msg, from);
+        hand_over(node, msg, from, session_id);
+    }
+}
+
+/// Passes `msg` to `handle_msg` unless it is a [`Heartbeat`], already answered by the
+/// Ack of its fragment, or a server type the service discovery of `node` consumes.
+fn hand_over<N: NodeCore + ?Sized>(node: &mut N, msg: Vec<u8>, from: NodeId, session_id: u64) {
+    if serde_json::from_slice::<Heartbeat>(&msg).is_ok() {
+        return;
+    }
+    let consumed = with_discovery(node, |discovery, router| {
+        discovery.handle_response(router, from, &msg)
+    });
+    if consumed != Some(true) {
+        node.handle_msg(msg, from, session_id);
+    }
+}
+
+/// Queries the servers found by the latest flood, if `node` runs a service discovery.
+/// Servers a query can't reach are queried again after the next flood.
+pub(crate) fn discover_servers<N: NodeCore + ?Sized>(node: &mut N) {
+    let _ = with_discovery(node, ServiceDiscovery::poll);
+}
+
+/// Runs `f` on the service discovery of `node` and its router, `None` without one.
+fn with_discovery<N: NodeCore + ?Sized, T>(
+    node: &mut N,
+    f: impl FnOnce(&mut ServiceDiscovery, &mut RoutingHandler) -> T,
+) -> Option<T> {
+    let mut discovery = mem::take(node.service_discovery()?);
+    let result = f(&mut discovery, node.routing_handler());
+    if let Some(slot) = node.service_discovery() {
+        *slot = discovery;
+    }
+    Some(result)
+}
+
+pub(crate) fn combined_stats<N: NodeCore + ?Sized>(node: &mut N) -> NodeStats {
+    let assembler = node.assembler().stats();
+    NodeStats {
+        messages_delivered: assembler.messages_delivered,
+        duplicate_messages: assembler.duplicate_messages,
+        ..node.routing_handler().stats()
+    }
+}
+
+pub(crate) fn combined_metrics<N: NodeCore + ?Sized>(node: &mut N) -> Metrics {
+    Metrics {
+        stats: combined_stats(node),
+        ..node.routing_handler().metrics()
+    }
+}
+
+#[cfg(test)]
+mod packet_processor_tests {
+    use super::*;
+    use crate::routing_handler::reserved_control_fragment;
+    use crate::transform::PacketMiddleware;
+    use crate::types::{Event, NodeEvent};
+    use crossbeam_channel::{Sender, unbounded};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use wg_internal::network::SourceRoutingHeader;
+    use wg_internal::packet::{FloodResponse, Fragment, Nack, NackType, NodeType};
+
+    struct TestNode {
+        controller_recv: Receiver<Box<dyn Command>>,
+        packet_recv: Receiver<Packet>,
+        assembler: FragmentAssembler,
+        router: RoutingHandler,
+        control_payloads: Vec<Vec<u8>>,
+        // messages handled as (sender, session_id)
+        messages: Vec<(NodeId, u64)>,
+        queue: Option<PriorityPacketQueue>,
+        negotiator: Option<VersionNegotiator>,
+    }
+
+    impl Processor for TestNode {
+        fn controller_recv(&self) -> &Receiver<Box<dyn Command>> {
+            &self.controller_recv
+        }
+        fn packet_recv(&self) -> &Receiver<Packet> {
+            &self.packet_recv
+        }
+        fn assembler(&mut self) -> &mut FragmentAssembler {
+            &mut self.assembler
+        }
+        fn routing_handler(&mut self) -> &mut RoutingHandler {
+            &mut self.router
+        }
+        fn packet_queue(&mut self) -> Option<&mut PriorityPacketQueue> {
+            self.queue.as_mut()
+        }
+        fn version_negotiator(&mut self) -> Option<&mut VersionNegotiator> {
+            self.negotiator.as_mut()
+        }
+        fn handle_msg(&mut self, _msg: Vec<u8>, from: NodeId, session_id: u64) {
+            self.messages.push((from, session_id));
+        }
+        fn handle_control_fragment(&mut self, payload: Vec<u8>, _from: NodeId, _session_id: u64) {
+            self.control_payloads.push(payload);
+        }
+        fn handle_command(&mut self, cmd: Box<dyn Command>) -> bool {
+            cmd.into_any().downcast::<bool>().map_or(true, |panics| {
+                assert!(!*panics, "asked to panic");
+                true
+            })
+        }
+    }
+
+    fn test_node(controller_send: Sender<Box<dyn Event>>) -> (TestNode, Sender<Box<dyn Command>>) {
+        let (cmd_send, cmd_recv): (Sender<Box<dyn Command>>, _) = unbounded();
+        let (_packet_send, packet_recv) = unbounded();
+        let node = TestNode {
+            controller_recv: cmd_recv,
+            packet_recv,
+            assembler: FragmentAssembler::default(),
+            router: RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send),
+            control_payloads: Vec::new(),
+            messages: Vec::new(),
+            queue: None,
+            negotiator: None,
+        };
+        (node, cmd_send)
+    }
+
+    fn run_node(cmd: bool) -> NodeEvent {
+        let (controller_send, controller_recv) = unbounded::<Box<dyn Event>>();
+        let (mut node, cmd_send) = test_node(controller_send);
+        cmd_send.send(Box::new(cmd)).unwrap();
+        node.run(Arc::new(Barrier::new(1)));
+        let event = controller_recv
+            .try_iter()
+            .last()
+            .unwrap()
+            .into_any()
+            .downcast::<NodeEvent>()
+            .unwrap();
+        *event
+    }
+
+    #[test]
+    /// Tests that the run loop reports why it exited along with the node stats
+    fn test_terminated_event() {
+        assert!(matches!(
+            run_node(false),
+            NodeEvent::Terminated {
+                notification_from: 1,
+                reason: TerminationReason::Shutdown,
+                stats: NodeStats { floods_started: 1, .. },
+            }
+        ));
+        assert!(matches!(
+            run_node(true),
+            NodeEvent::Terminated { reason: TerminationReason::Panic(msg), .. } if msg == "asked to panic"
+        ));
+    }
+
+    #[test]
+    /// Tests that the run loop answers topology and statistics queries itself
+    fn test_controller_queries() {
+        let (controller_send, _controller_recv) = unbounded::<Box<dyn Event>>();
+        let (mut node, cmd_send) = test_node(controller_send);
+        let (neighbor_send, neighbor_recv) = unbounded();
+        node.router.add_neighbor(2, neighbor_send);
+        let (topology_send, topology_recv) = unbounded();
+        let (stats_send, stats_recv) = unbounded();
+        let (metrics_send, metrics_recv) = unbounded();
+        let (blacklist_send, blacklist_recv) = unbounded();
+        node.router.blacklist_node(5, Duration::from_mins(1));
+        cmd_send.send(Box::new(NodeCommand::QueryTopology(topology_send))).unwrap();
+        cmd_send.send(Box::new(NodeCommand::QueryStats(stats_send))).unwrap();
+        cmd_send.send(Box::new(NodeCommand::QueryMetrics(metrics_send))).unwrap();
+        cmd_send.send(Box::new(NodeCommand::QueryBlacklist(blacklist_send))).unwrap();
+        cmd_send.send(Box::new(false)).unwrap();
+        node.run(Arc::new(Barrier::new(1)));
+
+        let topology = topology_recv.try_recv().unwrap();
+        let root = topology.nodes.iter().find(|node| node.get_id() == 1).unwrap();
+        assert_eq!(root.get_adjacents(), &vec![2]);
+        let stats = stats_recv.try_recv().unwrap();
+        assert_eq!(stats.floods_started, 1);
+        assert_eq!(stats.packets_sent, 1);
+        assert_eq!(stats.retransmissions, 0);
+        let metrics = metrics_recv.try_recv().unwrap();
+        assert_eq!(metrics.stats, stats);
+        assert_eq!(metrics.fragments_sent, 0);
+        let [(blacklisted, _)] = blacklist_recv.try_recv().unwrap().try_into().unwrap();
+        assert_eq!(blacklisted, 5);
+        // the flood request of the startup
+        assert_eq!(neighbor_recv.len(), 1);
+    }
+
+    #[test]
+    /// Tests that the run loop serves a graceful shutdown itself and exits once it completes
+    fn test_graceful_shutdown_exits() {
+        let (controller_send, controller_recv) = unbounded::<Box<dyn Event>>();
+        let (mut node, cmd_send) = test_node(controller_send);
+        let shutdown = NodeCommand::GracefulShutdown(Duration::from_mins(1));
+        cmd_send.send(Box::new(shutdown)).unwrap();
+        node.run(Arc::new(Barrier::new(1)));
+        let events = controller_recv
+            .try_iter()
+            .filter_map(|event| event.into_any().downcast::<NodeEvent>().ok())
+            .map(|event| *event)
+            .collect::<Vec<_>>();
+        assert!(matches!(
+            events.as_slice(),
+            [
+                ..,
+                NodeEvent::ShutdownComplete { undelivered_sessions, .. },
+                NodeEvent::Terminated { reason: TerminationReason::Shutdown, .. },
+            ] if undelivered_sessions.is_empty()
+        ));
+    }
+
+    #[test]
+    /// Tests that reserved control fragments are acknowledged and handed to their own hook
+    fn test_control_fragment() {
+        let (controller_send, _controller_recv) = unbounded::<Box<dyn Event>>();
+        let (mut node, _cmd_send) = test_node(controller_send);
+        let (neighbor_send, neighbor_recv) = unbounded();
+        node.router.add_neighbor(2, neighbor_send);
+
+        let fragment = reserved_control_fragment(b"ping").unwrap();
+        let header = SourceRoutingHeader::new(vec![2, 1], 1);
+        node.handle_packet(Packet::new_fragment(header, 9, fragment)).unwrap();
+        assert_eq!(node.control_payloads, vec![b"ping".to_vec()]);
+        assert!(matches!(neighbor_recv.try_recv().unwrap().pack_type, PacketType::Ack(_)));
+    }
+
+    #[test]
+    /// Tests that only the fragments accepted by the assembler report the progress
+    fn test_receive_progress() {
+        let (controller_send, controller_recv) = unbounded::<Box<dyn Event>>();
+        let (mut node, _cmd_send) = test_node(controller_send);
+        let (neighbor_send, _neighbor_recv) = unbounded();
+        node.router.add_neighbor(2, neighbor_send);
+
+        let header = SourceRoutingHeader::new(vec![2, 1], 1);
+        for index in [0, 0, 5, 1] {
+            let fragment = Fragment::new(index, 3, [0; 128]);
+            node.handle_packet(Packet::new_fragment(header.clone(), 9, fragment))
+                .unwrap();
+        }
+        let progress = controller_recv
+            .try_iter()
+            .filter_map(|event| event.into_any().downcast::<NodeEvent>().ok())
+            .filter_map(|event| match *event {
+                NodeEvent::ReceiveProgress { received, .. } => Some(received),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(progress, vec![1, 2]);
+    }
+
+    #[test]
+    /// Tests that fragments meant for another node are nacked instead of assembled
+    fn test_misdelivered_fragment() {
+        let (controller_send, _controller_recv) = unbounded::<Box<dyn Event>>();
+        let (mut node, _cmd_send) = test_node(controller_send);
+        let (neighbor_send, neighbor_recv) = unbounded();
+        node.router.add_neighbor(2, neighbor_send);
+
+        for hops in [vec![2, 1, 5], vec![2, 7]] {
+            let header = SourceRoutingHeader::new(hops, 1);
+            let fragment = Fragment::new(4, 5, [0; 128]);
+            node.handle_packet(Packet::new_fragment(header, 9, fragment)).unwrap();
+            let nack = neighbor_recv.try_recv().unwrap();
+            assert_eq!(nack.routing_header.hops, vec![1, 2]);
+            assert!(matches!(
+                nack.pack_type,
+                PacketType::Nack(Nack {
+                    fragment_index: 4,
+                    nack_type: NackType::UnexpectedRecipient(1)
+                })
+            ));
+        }
+        assert!(neighbor_recv.try_recv().is_err());
+        assert_eq!(node.router.stats().nacks_sent, 2);
+    }
+
+    #[test]
+    /// Tests that peers are greeted on first contact and handshake fragments are consumed
+    fn test_version_handshake() {
+        let (controller_send, _controller_recv) = unbounded::<Box<dyn Event>>();
+        let (mut node, _cmd_send) = test_node(controller_send);
+        node.negotiator = Some(VersionNegotiator::new(vec![1, 2]));
+        let (neighbor_send, neighbor_recv) = unbounded();
+        node.router.add_neighbor(2, neighbor_send);
+        let control_payload = |packet: Packet| match packet.pack_type {
+            PacketType::MsgFragment(fragment) if is_reserved_control_fragment(&fragment) => {
+                fragment.data[..usize::from(fragment.length)].to_vec()
+            }
+            other => panic!("unexpected {other:?}"),
+        };
+
+        // greeted on the first fragment, even of an incomplete message, once a route is
+        // known to send the hello along
+        let header = SourceRoutingHeader::new(vec![2, 1], 1);
+        let fragment = Fragment::new(0, 2, [0; 128]);
+        node.handle_packet(Packet::new_fragment(header.clone(), 3, fragment))
+            .unwrap();
+        assert!(node.negotiator.as_ref().unwrap().greet(2).is_some());
+        node.router.start_flood(None).unwrap();
+        let flood_response = FloodResponse {
+            flood_id: 1,
+            path_trace: vec![(1, NodeType::Client), (2, NodeType::Server)],
+        };
+        node.router.handle_flood_response(&flood_response).unwrap();
+        let _ = neighbor_recv.try_iter().count();
+
+        for (session_id, index) in [(3, 1), (4, 0)] {
+            let fragment = Fragment::new(index, 2, [0; 128]);
+            node.handle_packet(Packet::new_fragment(header.clone(), session_id, fragment))
+                .unwrap();
+        }
+        let sent = neighbor_recv.try_iter().collect::<Vec<_>>();
+        // two Acks and a single hello
+        assert_eq!(sent.len(), 3);
+        let hello = control_payload(sent[1].clone());
+        assert_eq!(
+            serde_json::from_slice::<ProtocolVersion>(&hello).unwrap(),
+            ProtocolVersion::Hello {
+                supported: vec![1, 2]
+            }
+        );
+
+        let agreed = serde_json::to_vec(&ProtocolVersion::Agreed { version: 2 }).unwrap();
+        let fragment = reserved_control_fragment(&agreed).unwrap();
+        node.handle_packet(Packet::new_fragment(header, 9, fragment)).unwrap();
+        assert_eq!(node.negotiator.as_ref().unwrap().agreed(2), Some(2));
+        assert!(node.control_payloads.is_empty());
+    }
+
+    #[test]
+    /// Tests that queued packets are taken from the channel and served in bounded batches
+    fn test_packet_batches() {
+        let (controller_send, _controller_recv) = unbounded::<Box<dyn Event>>();
+        let (mut node, _cmd_send) = test_node(controller_send);
+        let (packet_send, packet_recv) = unbounded();
+        node.packet_recv = packet_recv;
+        node.queue = Some(PriorityPacketQueue::new());
+        for session_id in 0..=PACKET_BATCH as u64 {
+            let fragment = reserved_control_fragment(&[1]).unwrap();
+            let header = SourceRoutingHeader::new(vec![2, 1], 1);
+            packet_send.send(Packet::new_fragment(header, session_id, fragment)).unwrap();
+        }
+
+        serve_queued_packets(&mut node).unwrap();
+        assert_eq!(node.control_payloads.len(), PACKET_BATCH);
+        assert!(node.packet_recv.is_empty());
+        assert_eq!(node.queue.as_ref().map(PriorityPacketQueue::len), Some(1));
+        serve_queued_packets(&mut node).unwrap();
+        assert_eq!(node.control_payloads.len(), PACKET_BATCH + 1);
+    }
+
+    #[test]
+    /// Tests that the messages completed by a batch are delivered round-robin by sender
+    fn test_batch_delivery_fairness() {
+        let (controller_send, _controller_recv) = unbounded::<Box<dyn Event>>();
+        let (mut node, _cmd_send) = test_node(controller_send);
+        let (neighbor_send, _neighbor_recv) = unbounded();
+        node.router.add_neighbor(2, neighbor_send);
+        let (packet_send, packet_recv) = unbounded();
+        node.packet_recv = packet_recv;
+        node.queue = Some(PriorityPacketQueue::new());
+        for (sender, session_id) in [(5, 2), (5, 1), (5, 3), (3, 8), (4, 6), (3, 7)] {
+            let header = SourceRoutingHeader::new(vec![sender, 2, 1], 2);
+            let fragment = Fragment::new(0, 1, [0; 128]);
+            packet_send
+                .send(Packet::new_fragment(header, session_id, fragment))
+                .unwrap();
+        }
+
+        serve_queued_packets(&mut node).unwrap();
+        assert_eq!(
+            node.messages,
+            vec![(3, 7), (4, 6), (5, 1), (3, 8), (5, 2), (5, 3)]
+        );
+    }
+
+    /// Lets the packets of even sessions in and swallows every packet sent.
+    #[derive(Debug, Default)]
+    struct EvenSessionsOnly {
+        swallowed: usize,
+    }
+
+    impl PacketMiddleware for EvenSessionsOnly {
+        fn incoming(&mut self, packet: Packet) -> Option<Packet> {
+            packet.session_id.is_multiple_of(2).then_some(packet)
+        }
+        fn outgoing(&mut self, _packet: Packet) -> Option<Packet> {
+            self.swallowed += 1;
+            None
+        }
+    }
+
+    #[test]
+    /// Tests that middlewares filter the packets received and sent by the node
+    fn test_middlewares() {
+        let (controller_send, _controller_recv) = unbounded::<Box<dyn Event>>();
+        let (mut node, _cmd_send) = test_node(controller_send);
+        let (neighbor_send, neighbor_recv) = unbounded();
+        node.router.add_neighbor(2, neighbor_send);
+        let middleware = Arc::new(Mutex::new(EvenSessionsOnly::default()));
+        node.middlewares().push(middleware.clone());
+
+        for session_id in 0..4 {
+            let fragment = reserved_control_fragment(&[1]).unwrap();
+            let header = SourceRoutingHeader::new(vec![2, 1], 1);
+            let packet = Packet::new_fragment(header, session_id, fragment);
+            receive_packet(&mut node, packet).unwrap();
+        }
+        assert_eq!(node.control_payloads.len(), 2);
+        // the Acks of the packets let in were swallowed on their way out
+        assert_eq!(middleware.lock().unwrap().swallowed, 2);
+        assert!(neighbor_recv.is_empty());
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use wg_internal::packet::{Packet, PacketType};
+
+/// Type of a packet, as configured in a [`PriorityPacketQueue`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PacketKind {
+    MsgFragment,
+    Ack,
+    Nack,
+    FloodRequest,
+    FloodResponse,
+}
+
+impl PacketKind {
+    #[must_use]
+    pub fn of(packet: &Packet) -> Self {
+        match packet.pack_type {
+            PacketType::MsgFragment(_) => Self::MsgFragment,
+            PacketType::Ack(_) => Self::Ack,
+            PacketType::Nack(_) => Self::Nack,
+            PacketType::FloodRequest(_) => Self::FloodRequest,
+            PacketType::FloodResponse(_) => Self::FloodResponse,
+        }
+    }
+}
+
+/// Backlog of received packets served by priority, so that the packets routing
+/// recovery depends on don't wait behind a burst of data fragments.
+///
+/// Packets of a lower priority value are served first, in arrival order among equals.
+/// By default Acks, Nacks and flood responses come first, then flood requests, then
+/// fragments. Used by `Processor::run` when `Processor::packet_queue` provides one.
+#[derive(Debug, Clone)]
+pub struct PriorityPacketQueue {
+    priorities: HashMap<PacketKind, u8>,
+    queues: BTreeMap<u8, VecDeque<Packet>>,
+    len: usize,
+}
+
+impl Default for PriorityPacketQueue {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PriorityPacketQueue {
+    #[must_use]
+    pub fn new() -> Self {
+        let priorities = HashMap::from([
+            (PacketKind::Ack, 0),
+            (PacketKind::Nack, 0),
+            (PacketKind::FloodResponse, 0),
+            (PacketKind::FloodRequest, 1),
+            (PacketKind::MsgFragment, 2),
+        ]);
+        Self {
+            priorities,
+            queues: BTreeMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Serves the packets of `kind` with `priority`, lower values first.
+    #[must_use]
+    pub fn with_priority(mut self, kind: PacketKind, priority: u8) -> Self {
+        self.set_priority(kind, priority);
+        self
+    }
+
+    /// Serves the packets of `kind` with `priority`, lower values first. Packets
+    /// already queued keep their place.
+    pub fn set_priority(&mut self, kind: PacketKind, priority: u8) {
+        let _ = self.priorities.insert(kind, priority);
+    }
+
+    #[must_use]
+    pub fn priority(&self, kind: PacketKind) -> u8 {
+        self.priorities.get(&kind).copied().unwrap_or(u8::MAX)
+    }
+
+    pub fn push(&mut self, packet: Packet) {
+        let priority = self.priority(PacketKind::of(&packet));
+        self.queues.entry(priority).or_default().push_back(packet);
+        self.len += 1;
+    }
+
+    /// Takes the oldest packet of the most urgent priority.
+    pub fn pop(&mut self) -> Option<Packet> {
+        let mut queue = self.queues.first_entry()?;
+        let packet = queue.get_mut().pop_front();
+        if queue.get().is_empty() {
+            let _ = queue.remove();
+        }
+        self.len -= usize::from(packet.is_some());
+        packet
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+#[cfg(test)]
+mod packet_queue_tests {
+    use super::*;
+    use wg_internal::network::SourceRoutingHeader;
+    use wg_internal::packet::{Fragment, Nack, NackType};
+
+    #[test]
+    /// Tests that control packets overtake queued fragments, in arrival order among equals
+    fn test_priority_queue() {
+        let header = SourceRoutingHeader::empty_route;
+        let fragment = |session_id| {
+            Packet::new_fragment(header(), session_id, Fragment::new(0, 1, [0; 128]))
+        };
+        let nack = Nack {
+            fragment_index: 0,
+            nack_type: NackType::Dropped,
+        };
+        let mut queue = PriorityPacketQueue::new();
+        queue.push(fragment(1));
+        queue.push(fragment(2));
+        queue.push(Packet::new_ack(header(), 3, 0));
+        queue.push(Packet::new_nack(header(), 4, nack));
+        assert_eq!(queue.len(), 4);
+        let order = std::iter::from_fn(|| queue.pop())
+            .map(|packet| packet.session_id)
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![3, 4, 1, 2]);
+        assert!(queue.is_empty());
+
+        // fragments first, Acks last
+        let mut queue = PriorityPacketQueue::default()
+            .with_priority(PacketKind::MsgFragment, 0)
+            .with_priority(PacketKind::Ack, 9);
+        queue.push(Packet::new_ack(header(), 3, 0));
+        queue.push(fragment(1));
+        assert_eq!(queue.pop().map(|p| p.session_id), Some(1));
+        assert_eq!(queue.pop().map(|p| p.session_id), Some(3));
+        assert!(queue.pop().is_none());
+    }
+}
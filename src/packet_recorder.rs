@@ -0,0 +1,199 @@
+//! Recording of the packets of a node to a JSONL trace, and replay of the trace into
+//! `Processor::handle_packet` to reproduce a bug without the rest of the network.
+//!
+//! Every line of a trace is a [`RecordedPacket`], the packet being stored as a
+//! [`WirePacket`], so traces recorded by nodes pinning another `wg_internal` release
+//! can be replayed as well.
+
+use crate::Processor;
+use crate::compat::WirePacket;
+use crate::network::NetworkError;
+use crate::transform::{PacketDirection, PacketTap};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use std::time::Instant;
+use wg_internal::network::NodeId;
+use wg_internal::packet::Packet;
+
+/// A packet sent or received by a node, as stored in a trace.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RecordedPacket {
+    /// Milliseconds since the recording started
+    pub at_ms: u64,
+    /// Node that recorded the packet
+    pub node: NodeId,
+    pub direction: PacketDirection,
+    pub packet: WirePacket,
+}
+
+/// [`PacketTap`] appending every packet of a node to a JSONL trace.
+///
+/// Set it with `RoutingHandler::set_packet_tap`. Lines are written as packets go,
+/// so the trace is complete even if the node panics.
+#[derive(Debug)]
+pub struct PacketRecorder {
+    node: NodeId,
+    started: Instant,
+    out: LineWriter<File>,
+    write_errors: u64,
+}
+
+impl PacketRecorder {
+    /// Starts recording the packets of `node` to `path`, appending to the trace
+    /// already there.
+    /// # Errors
+    /// Returns any error returned while opening `path`.
+    pub fn create(path: impl AsRef<Path>, node: NodeId) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            node,
+            started: Instant::now(),
+            out: LineWriter::new(file),
+            write_errors: 0,
+        })
+    }
+
+    /// Returns how many packets could not be written to the trace.
+    #[must_use]
+    pub fn write_errors(&self) -> u64 {
+        self.write_errors
+    }
+
+    fn write(&mut self, direction: PacketDirection, packet: &Packet) -> io::Result<()> {
+        let entry = RecordedPacket {
+            at_ms: u64::try_from(self.started.elapsed().as_millis()).unwrap_or(u64::MAX),
+            node: self.node,
+            direction,
+            packet: WirePacket::from(packet),
+        };
+        serde_json::to_writer(&mut self.out, &entry)?;
+        self.out.write_all(b"\n")
+    }
+}
+
+impl PacketTap for PacketRecorder {
+    fn on_packet(&mut self, direction: PacketDirection, packet: &Packet) {
+        if self.write(direction, packet).is_err() {
+            self.write_errors += 1;
+        }
+    }
+}
+
+/// Trace recorded by a [`PacketRecorder`], ready to be fed back into a node.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PacketReplayer {
+    entries: Vec<RecordedPacket>,
+}
+
+impl PacketReplayer {
+    /// Reads the trace at `path`.
+    /// # Errors
+    /// Returns any error returned while reading `path`, `InvalidData` if a line is not
+    /// a [`RecordedPacket`].
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut entries = Vec::new();
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            if !line.trim().is_empty() {
+                entries.push(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    #[must_use]
+    pub fn entries(&self) -> &[RecordedPacket] {
+        &self.entries
+    }
+
+    /// Feeds the packets `node_id` received, in recorded order, into the
+    /// `handle_packet` of `node` and returns how many were replayed. Timing is not
+    /// reproduced, packets are handled back to back.
+    /// # Errors
+    /// Returns the first error returned while converting or handling a packet.
+    pub fn replay<P: Processor + ?Sized>(
+        &self,
+        node_id: NodeId,
+        node: &mut P,
+    ) -> Result<usize, NetworkError> {
+        let mut replayed = 0;
+        for entry in &self.entries {
+            if entry.node == node_id && entry.direction == PacketDirection::Received {
+                node.handle_packet(Packet::try_from(entry.packet.clone())?)?;
+                replayed += 1;
+            }
+        }
+        Ok(replayed)
+    }
+}
+
+#[cfg(test)]
+mod packet_recorder_tests {
+    use super::*;
+    use crate::testing::LoopbackNode;
+    use crossbeam_channel::{Receiver, unbounded};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use wg_internal::network::SourceRoutingHeader;
+    use wg_internal::packet::{FloodRequest, NodeType, PacketType};
+
+    fn loopback() -> (LoopbackNode, Receiver<Packet>) {
+        let (controller_send, _) = unbounded();
+        let (_, cmd_recv) = unbounded();
+        let (_, packet_recv) = unbounded();
+        let (client_send, client_recv) = unbounded();
+        let node = LoopbackNode::new(
+            1,
+            controller_send,
+            cmd_recv,
+            packet_recv,
+            HashMap::from([(2, client_send)]),
+        );
+        (node, client_recv)
+    }
+
+    #[test]
+    /// Tests that a recorded flood replays into a fresh node the same way
+    fn test_record_and_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("node1.jsonl");
+        let flood = Packet::new_flood_request(
+            SourceRoutingHeader::empty_route(),
+            1,
+            FloodRequest {
+                flood_id: 1,
+                initiator_id: 2,
+                path_trace: vec![(2, NodeType::Client)],
+            },
+        );
+
+        let (mut node, client_recv) = loopback();
+        let recorder = Arc::new(Mutex::new(PacketRecorder::create(&path, 1).unwrap()));
+        node.routing_handler()
+            .set_packet_tap(Some(recorder.clone()));
+        node.handle_packet(flood.clone()).unwrap();
+        let response = client_recv.try_recv().unwrap();
+        assert_eq!(recorder.lock().unwrap().write_errors(), 0);
+
+        let trace = PacketReplayer::load(&path).unwrap();
+        let directions = trace
+            .entries()
+            .iter()
+            .map(|entry| entry.direction)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            directions,
+            vec![PacketDirection::Received, PacketDirection::Sent]
+        );
+        assert_eq!(trace.entries()[0].packet, WirePacket::from(&flood));
+        assert_eq!(trace.entries()[1].packet, WirePacket::from(&response));
+
+        let (mut fresh, client_recv) = loopback();
+        assert_eq!(trace.replay(1, &mut fresh).unwrap(), 1);
+        let replayed = client_recv.try_recv().unwrap();
+        assert!(matches!(replayed.pack_type, PacketType::FloodResponse(_)));
+        assert_eq!(trace.replay(5, &mut fresh).unwrap(), 0);
+    }
+}
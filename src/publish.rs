@@ -0,0 +1,169 @@
+use crate::RoutingHandler;
+use crate::network::{ChannelError, NetworkError, RoutingError};
+use crate::types::{File, MediaReference, TextFile, WebRequest};
+use wg_internal::network::NodeId;
+
+/// Where the parts of a [`File`] were uploaded by [`publish`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishedFile {
+    /// The text file, on the text server
+    pub text: MediaReference,
+    /// Every media file of the file, on the media server it was uploaded to
+    pub media: Vec<MediaReference>,
+}
+
+/// Uploads the text of `file` to `text_server` and spreads its media files over
+/// `media_servers` in turn. The uploaded text references the media at their new
+/// location; references to media not part of `file` are left untouched.
+/// # Errors
+/// `NoDestination` if `file` has media files but `media_servers` is empty,
+/// or any error returned while sending the uploads.
+pub fn publish(
+    router: &mut RoutingHandler,
+    file: &File,
+    text_server: NodeId,
+    media_servers: &[NodeId],
+) -> Result<PublishedFile, NetworkError> {
+    let (text_file, media) = assign(file, media_servers)?;
+
+    for (media_file, media_ref) in file.media_files.iter().zip(&media) {
+        let upload = WebRequest::UploadMediaFile {
+            file: media_file.clone(),
+        };
+        router.send_message(&encode(&upload)?, Some(media_ref.location), None)?;
+    }
+    let upload = WebRequest::UploadTextFile { file: text_file };
+    router.send_message(&encode(&upload)?, Some(text_server), None)?;
+
+    Ok(PublishedFile {
+        text: MediaReference {
+            location: text_server,
+            id: file.text_file.id,
+        },
+        media,
+    })
+}
+
+/// Picks the media server of every media file, in order, and rewrites the references
+/// of the text accordingly.
+fn assign(
+    file: &File,
+    media_servers: &[NodeId],
+) -> Result<(TextFile, Vec<MediaReference>), RoutingError> {
+    if media_servers.is_empty() && !file.media_files.is_empty() {
+        return Err(RoutingError::NoDestination);
+    }
+    let media = file
+        .media_files
+        .iter()
+        .zip(media_servers.iter().cycle())
+        .map(|(media_file, server)| MediaReference {
+            location: *server,
+            id: media_file.id,
+        })
+        .collect::<Vec<_>>();
+
+    let mut text_file = file.text_file.clone();
+    for media_ref in &mut text_file.media_refs {
+        if let Some(published) = media.iter().find(|m| m.id == media_ref.id) {
+            media_ref.location = published.location;
+        }
+    }
+    Ok((text_file, media))
+}
+
+fn encode(request: &WebRequest) -> Result<Vec<u8>, ChannelError> {
+    serde_json::to_vec(request).map_err(|e| ChannelError::Encoding(e.to_string()))
+}
+
+#[cfg(test)]
+mod publish_tests {
+    use super::*;
+    use crate::FragmentAssembler;
+    use crate::types::MediaFile;
+    use crossbeam_channel::{Receiver, unbounded};
+    use std::collections::HashMap;
+    use wg_internal::packet::{FloodResponse, NodeType, Packet, PacketType};
+
+    fn uploads(receiver: &Receiver<Packet>, from: NodeId) -> Vec<WebRequest> {
+        let mut assembler = FragmentAssembler::default();
+        receiver
+            .try_iter()
+            .filter_map(|packet| match packet.pack_type {
+                PacketType::MsgFragment(fragment) => {
+                    assembler.add_fragment(fragment, packet.session_id, from)
+                }
+                _ => None,
+            })
+            .map(|msg| serde_json::from_slice(&msg).unwrap())
+            .collect()
+    }
+
+    #[test]
+    /// Tests that media are spread over the media servers and referenced at their location
+    fn test_publish() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut router = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let mut receivers = HashMap::new();
+        for server in [2, 3, 4] {
+            let (sender, receiver) = unbounded();
+            router.add_neighbor(server, sender);
+            let _ = receivers.insert(server, receiver);
+        }
+        router.start_flood(None).unwrap();
+        for server in [2, 3, 4] {
+            router
+                .handle_flood_response(&FloodResponse {
+                    flood_id: 1,
+                    path_trace: vec![(1, NodeType::Client), (server, NodeType::Server)],
+                })
+                .unwrap();
+            let _ = receivers[&server].try_iter().count();
+        }
+
+        let image = MediaFile::new("image".to_string(), vec![vec![1; 300]]);
+        let sound = MediaFile::new("sound".to_string(), vec![vec![2; 10]]);
+        let elsewhere = MediaReference::new(9);
+        let refs = vec![
+            MediaReference::new(0),
+            MediaReference::new(0),
+            elsewhere.clone(),
+        ];
+        let mut text = TextFile::new("title".to_string(), "text".to_string(), refs);
+        text.media_refs[0].id = image.id;
+        text.media_refs[1].id = sound.id;
+        let file = File::new(text, vec![image.clone(), sound.clone()]);
+
+        assert!(matches!(
+            publish(&mut router, &file, 2, &[]),
+            Err(NetworkError::Routing(RoutingError::NoDestination))
+        ));
+        let published = publish(&mut router, &file, 2, &[3, 4]).unwrap();
+        assert_eq!(published.text.location, 2);
+        assert_eq!(
+            published
+                .media
+                .iter()
+                .map(|r| (r.location, r.id))
+                .collect::<Vec<_>>(),
+            vec![(3, image.id), (4, sound.id)]
+        );
+
+        let text_uploads = uploads(&receivers[&2], 2);
+        let [WebRequest::UploadTextFile { file: uploaded }] = text_uploads.as_slice() else {
+            panic!("expected the text upload");
+        };
+        assert_eq!(
+            uploaded.media_refs,
+            vec![
+                published.media[0].clone(),
+                published.media[1].clone(),
+                elsewhere
+            ]
+        );
+        assert!(matches!(
+            uploads(&receivers[&3], 3)[..],
+            [WebRequest::UploadMediaFile { ref file }] if *file == image
+        ));
+    }
+}
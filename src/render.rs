@@ -0,0 +1,210 @@
+//! Presentation of a [`File`] for web-browser clients.
+//!
+//! The content of a text file refers to its media with `{{media:<uuid>}}` placeholders,
+//! which are replaced by the media received so far: images are shown, other media
+//! linked. Referenced media still being fetched are rendered as a placeholder, so a
+//! page can be shown at once and rendered again as its media arrive.
+
+use crate::types::{File, MediaFile};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use std::fmt::Write;
+use uuid::Uuid;
+
+/// Opening of a media placeholder in the content of a text file.
+pub const MEDIA_PLACEHOLDER: &str = "{{media:";
+
+/// How the media of a rendered document are included.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MediaEmbedding {
+    /// As base64 `data:` URIs, the document standing on its own
+    Inline,
+    /// As links to `{base}/{id}.{extension}`, e.g. where a `FileCache` keeps them
+    Link { base: String },
+}
+
+/// Output format of [`render`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderFormat {
+    Html,
+    Markdown,
+}
+
+/// Piece of the content of a text file.
+enum Segment<'a> {
+    Text(&'a str),
+    Media(Uuid),
+}
+
+/// Splits `content` on the media placeholders whose id parses, other text included.
+fn segments(content: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = content;
+    while let Some(start) = rest.find(MEDIA_PLACEHOLDER) {
+        let after = &rest[start + MEDIA_PLACEHOLDER.len()..];
+        let Some((id, end)) = after.find("}}").and_then(|end| {
+            Uuid::try_parse(after[..end].trim())
+                .ok()
+                .map(|id| (id, end))
+        }) else {
+            segments.push(Segment::Text(&rest[..start + MEDIA_PLACEHOLDER.len()]));
+            rest = after;
+            continue;
+        };
+        segments.push(Segment::Text(&rest[..start]));
+        segments.push(Segment::Media(id));
+        rest = &after[end + 2..];
+    }
+    segments.push(Segment::Text(rest));
+    segments
+}
+
+/// Returns the MIME type of `media` from its extension.
+#[must_use]
+pub fn mime_type(media: &MediaFile) -> &'static str {
+    match media.extension().map(str::to_ascii_lowercase).as_deref() {
+        Some("png") => "image/png",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("bmp") => "image/bmp",
+        Some("webp") => "image/webp",
+        Some("svg") => "image/svg+xml",
+        Some("pdf") => "application/pdf",
+        Some("mp3") => "audio/mpeg",
+        Some("txt") => "text/plain",
+        _ => "application/octet-stream",
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    text.chars()
+        .fold(String::with_capacity(text.len()), |mut out, c| {
+            match c {
+                '&' => out.push_str("&amp;"),
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '"' => out.push_str("&quot;"),
+                '\'' => out.push_str("&#39;"),
+                c => out.push(c),
+            }
+            out
+        })
+}
+
+fn media_url(media: &MediaFile, embedding: &MediaEmbedding) -> String {
+    match embedding {
+        MediaEmbedding::Inline => format!(
+            "data:{};base64,{}",
+            mime_type(media),
+            STANDARD.encode(media.get_content().concat())
+        ),
+        MediaEmbedding::Link { base } => match media.extension() {
+            Some(extension) => format!("{}/{}.{extension}", base.trim_end_matches('/'), media.id),
+            None => format!("{}/{}", base.trim_end_matches('/'), media.id),
+        },
+    }
+}
+
+fn media_html(file: &File, id: Uuid, embedding: &MediaEmbedding) -> String {
+    let Some(media) = file.media_files.iter().find(|media| media.id == id) else {
+        return format!("<span class=\"media-placeholder\" data-media=\"{id}\">Loading…</span>");
+    };
+    let url = media_url(media, embedding);
+    let title = escape_html(&media.title);
+    if mime_type(media).starts_with("image/") {
+        format!("<img src=\"{url}\" alt=\"{title}\">")
+    } else {
+        format!("<a href=\"{url}\" download=\"{title}\">{title}</a>")
+    }
+}
+
+fn media_markdown(file: &File, id: Uuid, embedding: &MediaEmbedding) -> String {
+    let Some(media) = file.media_files.iter().find(|media| media.id == id) else {
+        return format!("*[loading media {id}]*");
+    };
+    let url = media_url(media, embedding);
+    let title = media.title.replace(['[', ']'], "");
+    if mime_type(media).starts_with("image/") {
+        format!("![{title}]({url})")
+    } else {
+        format!("[{title}]({url})")
+    }
+}
+
+/// Renders `file` as a document of `format`, its media placeholders replaced by the
+/// media received so far. Placeholders of media the text doesn't reference are kept
+/// as they are.
+#[must_use]
+pub fn render(file: &File, format: RenderFormat, embedding: &MediaEmbedding) -> String {
+    let text = &file.text_file;
+    let referenced = |id: Uuid| text.media_refs.iter().any(|r| r.id == id);
+    let mut body = String::new();
+    for segment in segments(&text.content) {
+        let piece = match (segment, format) {
+            (Segment::Media(id), RenderFormat::Html) if referenced(id) => {
+                media_html(file, id, embedding)
+            }
+            (Segment::Media(id), RenderFormat::Markdown) if referenced(id) => {
+                media_markdown(file, id, embedding)
+            }
+            (Segment::Media(id), _) => format!("{MEDIA_PLACEHOLDER}{id}}}}}"),
+            (Segment::Text(text), RenderFormat::Html) => escape_html(text),
+            (Segment::Text(text), RenderFormat::Markdown) => text.to_string(),
+        };
+        body.push_str(&piece);
+    }
+
+    match format {
+        RenderFormat::Markdown => format!("# {}\n\n{body}\n", text.title),
+        RenderFormat::Html => {
+            let title = escape_html(&text.title);
+            let mut html = format!(
+                "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n"
+            );
+            for paragraph in body.split("\n\n").filter(|p| !p.trim().is_empty()) {
+                let _ = writeln!(html, "<p>{}</p>", paragraph.trim().replace('\n', "<br>\n"));
+            }
+            html.push_str("</body>\n</html>\n");
+            html
+        }
+    }
+}
+
+#[cfg(test)]
+mod render_tests {
+    use super::*;
+    use crate::types::{MediaReference, TextFile};
+
+    #[test]
+    /// Tests that placeholders are replaced by the media received, and kept for the others
+    fn test_render() {
+        let (image_ref, sound_ref) = (MediaReference::new(2), MediaReference::new(3));
+        let content = format!(
+            "A <b>cat</b>:\n{{{{media:{}}}}}\n\nIts voice {{{{media:{}}}}} and {{{{media:oops}}}}",
+            image_ref.id, sound_ref.id
+        );
+        let text = TextFile::new(
+            "Cats & dogs".to_string(),
+            content,
+            vec![image_ref.clone(), sound_ref.clone()],
+        );
+        let mut image = MediaFile::from_bytes("cat.png".to_string(), b"\x89PNG\r\n\x1a\n");
+        image.id = image_ref.id;
+        let mut file = File::with_placeholders(text);
+        assert!(file.add_media(image));
+
+        let html = render(&file, RenderFormat::Html, &MediaEmbedding::Inline);
+        assert!(html.contains("<title>Cats &amp; dogs</title>"));
+        assert!(html.contains("<p>A &lt;b&gt;cat&lt;/b&gt;:<br>\n<img src=\"data:image/png;base64,iVBORw0KGgo=\" alt=\"cat.png\"></p>"));
+        assert!(html.contains(&format!("data-media=\"{}\">Loading…</span>", sound_ref.id)));
+        assert!(html.contains("{{media:oops}}"));
+
+        let link = MediaEmbedding::Link {
+            base: "media/".to_string(),
+        };
+        let markdown = render(&file, RenderFormat::Markdown, &link);
+        assert!(markdown.starts_with("# Cats & dogs\n\nA <b>cat</b>:\n"));
+        assert!(markdown.contains(&format!("![cat.png](media/{}.png)", image_ref.id)));
+        assert!(markdown.contains(&format!("*[loading media {}]*", sound_ref.id)));
+    }
+}
@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+
+/// Fixed-capacity log keeping the latest `capacity` entries.
+///
+/// The storage is allocated once, appending is O(1) and evicts the oldest entry once
+/// the log is full, so an always-on history never grows during long runs.
+/// A log of capacity 0 records nothing.
+#[derive(Debug, Clone)]
+pub struct RingLog<T> {
+    capacity: usize,
+    entries: VecDeque<T>,
+    // entries evicted since the log was created or cleared
+    evicted: u64,
+}
+
+impl<T> RingLog<T> {
+    #[must_use]
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            evicted: 0,
+        }
+    }
+
+    /// Appends `entry`, returning the entry evicted to make room for it.
+    pub fn push(&mut self, entry: T) -> Option<T> {
+        if self.capacity == 0 {
+            self.evicted += 1;
+            return Some(entry);
+        }
+        let evicted = if self.entries.len() == self.capacity {
+            self.evicted += 1;
+            self.entries.pop_front()
+        } else {
+            None
+        };
+        self.entries.push_back(entry);
+        evicted
+    }
+
+    /// Returns the entries, oldest first.
+    #[must_use]
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + ExactSizeIterator {
+        self.entries.iter()
+    }
+
+    #[must_use]
+    pub fn latest(&self) -> Option<&T> {
+        self.entries.back()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    #[must_use]
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns how many entries were evicted to make room for newer ones.
+    #[must_use]
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.evicted = 0;
+    }
+}
+
+impl<'a, T> IntoIterator for &'a RingLog<T> {
+    type Item = &'a T;
+    type IntoIter = std::collections::vec_deque::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+#[cfg(test)]
+mod ring_log_tests {
+    use super::*;
+
+    #[test]
+    /// Tests that the oldest entries are evicted once the capacity is reached
+    fn test_eviction() {
+        let mut log = RingLog::new(3);
+        for i in 0..3 {
+            assert_eq!(log.push(i), None);
+        }
+        assert_eq!(log.push(3), Some(0));
+        assert_eq!(log.iter().copied().collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert_eq!(log.latest(), Some(&3));
+        assert_eq!((log.len(), log.evicted()), (3, 1));
+
+        let mut disabled = RingLog::new(0);
+        assert_eq!(disabled.push(1), Some(1));
+        assert!(disabled.is_empty());
+
+        log.clear();
+        assert!(log.is_empty());
+        assert_eq!(log.evicted(), 0);
+    }
+}
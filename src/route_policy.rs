@@ -0,0 +1,209 @@
+//! Pluggable path selection for the routes of a `RoutingHandler`.
+//!
+//! Without a policy the router uses its own search, ranking paths by hop count,
+//! measured latency and drop estimates within its search budget. A [`RoutePolicy`]
+//! given to `RoutingHandler::with_route_policy` or `set_route_policy` replaces it, so
+//! that nodes can experiment with other strategies without patching the crate.
+
+use crate::network::{Network, Node, SearchBudget};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+use wg_internal::network::NodeId;
+use wg_internal::packet::NodeType;
+
+/// Strategy picking the route of the messages sent to a destination.
+pub trait RoutePolicy: Debug + Send + Sync {
+    /// Returns the hops from the owner of `network` (its first node) to `dest`, both
+    /// included, through drones only. `None` if `dest` cannot be reached.
+    ///
+    /// `now` is the time of the clock of the router, to read time-dependent estimates
+    /// such as `EdgeStats::drop_probability` consistently with simulated clocks.
+    fn select_route(&self, network: &Network, dest: NodeId, now: Instant) -> Option<Vec<NodeId>>;
+
+    /// Returns whether the router may reuse a route selected by this policy until the
+    /// topology changes. Policies answering differently on each call return `false`.
+    fn cacheable(&self) -> bool {
+        true
+    }
+}
+
+/// The node a view belongs to, where its routes start.
+fn owner(network: &Network) -> Option<NodeId> {
+    network.nodes.first().map(Node::get_id)
+}
+
+/// Nodes `node` can forward to on the way to `dest`: drones, and `dest` itself.
+fn next_hops(network: &Network, node: NodeId, dest: NodeId) -> Vec<NodeId> {
+    let Some(node) = network.nodes.iter().find(|n| n.get_id() == node) else {
+        return Vec::new();
+    };
+    node.get_adjacents()
+        .iter()
+        .copied()
+        .filter(|adjacent| {
+            *adjacent == dest
+                || network
+                    .nodes
+                    .iter()
+                    .any(|n| n.get_id() == *adjacent && n.get_node_type() == NodeType::Drone)
+        })
+        .collect()
+}
+
+fn unwind(parents: &HashMap<NodeId, NodeId>, dest: NodeId) -> Vec<NodeId> {
+    let mut route = vec![dest];
+    let mut current = dest;
+    while let Some(&parent) = parents.get(&current) {
+        route.push(parent);
+        current = parent;
+    }
+    route.reverse();
+    route
+}
+
+/// Route with the fewest hops, found breadth-first; ties go to the lowest node ids.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShortestHop;
+
+impl RoutePolicy for ShortestHop {
+    fn select_route(&self, network: &Network, dest: NodeId, _now: Instant) -> Option<Vec<NodeId>> {
+        let source = owner(network)?;
+        let mut parents = HashMap::new();
+        let mut visited = HashSet::from([source]);
+        let mut queue = VecDeque::from([source]);
+        while let Some(node) = queue.pop_front() {
+            if node == dest {
+                return Some(unwind(&parents, dest));
+            }
+            let mut hops = next_hops(network, node, dest);
+            hops.sort_unstable();
+            for hop in hops {
+                if visited.insert(hop) {
+                    let _ = parents.insert(hop, node);
+                    queue.push_back(hop);
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Route most likely to deliver a fragment, from the drop probabilities estimated
+/// with the Nacks received (see `EdgeStats`). Drones without estimates count as
+/// reliable, so without measurements any shortest route may be picked.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MinDropProbability;
+
+impl RoutePolicy for MinDropProbability {
+    fn select_route(&self, network: &Network, dest: NodeId, now: Instant) -> Option<Vec<NodeId>> {
+        let source = owner(network)?;
+        // cost of a route: -ln of its delivery probability, plus a tie breaking hop cost
+        let cost_of = |drone: NodeId| {
+            let drop = network
+                .edge_stats()
+                .drop_probability(drone, now)
+                .unwrap_or_default();
+            -(1.0 - drop.min(0.999)).ln() + 1e-6
+        };
+        let mut costs = HashMap::<NodeId, f64>::from([(source, 0.0)]);
+        let mut parents = HashMap::new();
+        let mut done = HashSet::new();
+        loop {
+            let (node, cost) = costs
+                .iter()
+                .filter(|(node, _)| !done.contains(*node))
+                .min_by(|(a, x), (b, y)| x.total_cmp(y).then(a.cmp(b)))
+                .map(|(node, cost)| (*node, *cost))?;
+            if node == dest {
+                return Some(unwind(&parents, dest));
+            }
+            let _ = done.insert(node);
+            for hop in next_hops(network, node, dest) {
+                let next = cost + cost_of(hop);
+                if !done.contains(&hop) && costs.get(&hop).is_none_or(|c| next < *c) {
+                    let _ = costs.insert(hop, next);
+                    let _ = parents.insert(hop, node);
+                }
+            }
+        }
+    }
+}
+
+/// Spreads the messages over up to `paths` node-disjoint routes, taken in turn.
+#[derive(Debug, Default)]
+pub struct LoadBalancedRoundRobin {
+    paths: usize,
+    next: AtomicUsize,
+}
+
+impl LoadBalancedRoundRobin {
+    #[must_use]
+    pub fn new(paths: usize) -> Self {
+        Self {
+            paths: paths.max(1),
+            next: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl RoutePolicy for LoadBalancedRoundRobin {
+    fn select_route(&self, network: &Network, dest: NodeId, _now: Instant) -> Option<Vec<NodeId>> {
+        let source = owner(network)?;
+        let routes = network.k_shortest_paths(source, dest, self.paths, SearchBudget::default());
+        if routes.is_empty() {
+            return None;
+        }
+        let turn = self.next.fetch_add(1, Ordering::Relaxed);
+        Some(routes[turn % routes.len()].to_vec())
+    }
+
+    fn cacheable(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(test)]
+mod route_policy_tests {
+    use super::*;
+
+    /// 1 reaches server 9 through drone 2 directly, or through drones 3 and 4.
+    fn network() -> Network {
+        let mut network = Network::new(Node::new(1, NodeType::Client, vec![2, 3]));
+        network.add_node(Node::new(2, NodeType::Drone, vec![1, 9]));
+        network.add_node(Node::new(3, NodeType::Drone, vec![1, 4]));
+        network.add_node(Node::new(4, NodeType::Drone, vec![3, 9]));
+        network.add_node(Node::new(9, NodeType::Server, vec![2, 4]));
+        network
+    }
+
+    #[test]
+    /// Tests the routes picked by each built-in policy
+    fn test_route_policies() {
+        let mut network = network();
+        let now = Instant::now();
+        assert_eq!(
+            ShortestHop.select_route(&network, 9, now),
+            Some(vec![1, 2, 9])
+        );
+        assert_eq!(ShortestHop.select_route(&network, 7, now), None);
+
+        for _ in 0..4 {
+            network.edge_stats_mut().record_drop(&[1, 2, 9], 2, now);
+        }
+        network.edge_stats_mut().record_delivery(&[1, 3, 4, 9], now);
+        assert_eq!(
+            MinDropProbability.select_route(&network, 9, now),
+            Some(vec![1, 3, 4, 9])
+        );
+
+        let round_robin = LoadBalancedRoundRobin::new(2);
+        assert!(!round_robin.cacheable());
+        let routes = (0..3)
+            .map(|_| round_robin.select_route(&network, 9, now).unwrap())
+            .collect::<Vec<_>>();
+        assert_ne!(routes[0], routes[1]);
+        assert_eq!(routes[0], routes[2]);
+    }
+}
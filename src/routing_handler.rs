@@ -1,102 +1,405 @@
+#[cfg(feature = "compression")]
+use crate::compression::{CompressionConfig, CompressionStats, Compressor};
+use crate::blacklist::{AutoBlacklist, Blacklist};
+use crate::clock::Clock;
+use crate::config::{CommonConfig, MAX_FRAGMENT_SIZE, RetryPolicy};
+use crate::congestion::{CongestionState, CongestionWindow, Pacer, QosClass};
+use crate::fragment_trace::{FragmentFate, FragmentTrace, FragmentTraceEntry};
+use crate::keepalive::{HeartbeatConfig, HeartbeatMonitor};
+use crate::message_queue::{MessageQueue, QueuedMessage};
+use crate::metrics::Metrics;
+use crate::route_policy::RoutePolicy;
+use crate::node_state::NodeState;
+use crate::selfcheck::Diagnostic;
+use crate::session::{SessionHandle, SessionStatus};
+use crate::session_buffer::{FragmentState, OutgoingSession, SessionBuffer, StoredFragment};
+use crate::transform::{
+    PacketDirection, SharedMiddleware, SharedTap, SharedTransform, run_middlewares,
+};
 use crate::types::SerializedRequest;
 use crate::{
-    network::{Network, NetworkError, Node},
-    types::{Event, NodeEvent},
+    network::{
+        ChannelError, Network, NetworkError, Node, NodeMetadata, Route, RoutingError,
+        SearchBudget, TopologyDiff, TopologyError,
+    },
+    types::{
+        AggregateAck, Event, Heartbeat, NodeCommand, NodeEvent, NodeStats, ProtocolViolation,
+        ServerType, Severity, TerminationReason,
+    },
 };
-use crossbeam_channel::Sender;
-use std::collections::{HashMap, HashSet};
+use crossbeam_channel::{SendError, Sender, TrySendError};
+use crate::ring_log::RingLog;
+use std::borrow::Cow;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{fs, io};
 use rand::Rng;
 use wg_internal::{
     network::{NodeId, SourceRoutingHeader},
-    packet::{Ack, FloodRequest, FloodResponse, Fragment, Nack, NackType, NodeType, Packet},
+    packet::{
+        Ack, FloodRequest, FloodResponse, Fragment, Nack, NackType, NodeType, Packet, PacketType,
+    },
 };
 
+/// Outgoing session still waiting for acknowledgments, as listed by
+/// [`RoutingHandler::buffered_sessions`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferedSession {
+    pub session_id: u64,
+    pub destination: Option<NodeId>,
+    pub total_fragments: usize,
+    pub acked_fragments: usize,
+}
+
+/// Sessions of a broadcast still awaiting their outcome.
+#[derive(Debug, Clone, Default)]
+struct Broadcast {
+    pending: HashMap<u64, NodeId>,
+    delivered: Vec<NodeId>,
+    failed: Vec<NodeId>,
+}
+
+/// Activity of the route cache, as returned by [`RoutingHandler::route_cache_stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RouteCacheStats {
+    /// Routes served from the cache
+    pub hits: u64,
+    /// Routes searched in the network view
+    pub misses: u64,
+    /// Cached routes discarded after a topology change
+    pub invalidations: u64,
+    /// Destinations with a cached route
+    pub cached: usize,
+}
+
+/// Ack held back to travel together with the next message to the same peer.
 #[derive(Debug, Clone)]
-struct Buffer {
-    // represents packets which reached the destination
-    packets_received: HashMap<u64, Vec<(bool, Packet)>>,
-    packets_to_send: Vec<Packet>,
-    pending_ser_requests: HashSet<SerializedRequest>,
+struct PendingAck {
+    incoming: SourceRoutingHeader,
+    session_id: u64,
+    fragment_index: u64,
+    since: Instant,
 }
 
-impl Buffer {
-    fn new() -> Self {
-        Self {
-            packets_received: HashMap::new(),
-            packets_to_send: Vec::new(),
-            pending_ser_requests: HashSet::new(),
-        }
+/// Fragment indexes of a session received from a peer, acknowledged together with
+/// [`AggregateAck`]s once the aggregation delay expires.
+#[derive(Debug, Clone)]
+struct AggregatedAcks {
+    incoming: SourceRoutingHeader,
+    indexes: BTreeSet<u64>,
+    since: Instant,
+}
+
+/// What became of a packet handed to `RoutingHandler::push`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pushed {
+    Sent,
+    // waiting in the backlog of the neighbor, reported once flushed
+    Backlogged,
+    Dropped,
+}
+
+/// Cumulative fragment bytes exchanged with each peer and the optional send quotas.
+#[derive(Debug, Clone, Default)]
+struct ByteAccounting {
+    sent: HashMap<NodeId, u64>,
+    received: HashMap<NodeId, u64>,
+    quotas: HashMap<NodeId, u64>,
+}
+
+// bytes charged to a session throttle for every fragment sent
+const FRAGMENT_COST: f64 = 128.0;
+
+/// Token bucket limiting the rate at which the fragments of a session leave the node.
+#[derive(Debug, Clone)]
+struct SessionThrottle {
+    // `None` once the limit is lifted, the queue is then flushed by the next tick
+    bytes_per_sec: Option<u64>,
+    allowance: f64,
+    last_refill: Instant,
+    queue: VecDeque<Packet>,
+}
+
+impl SessionThrottle {
+    fn refill(&mut self, now: Instant) {
+        let Some(rate) = self.bytes_per_sec else {
+            return;
+        };
+        #[allow(clippy::cast_precision_loss)]
+        let rate = rate as f64;
+        let burst = rate.max(FRAGMENT_COST);
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.allowance = (self.allowance + elapsed * rate).min(burst);
+        self.last_refill = now;
     }
 
-    fn insert(&mut self, packet: Packet, session_id: u64) {
-        let id = session_id;
-        if let Some(v) = self.packets_received.get_mut(&id) {
-            for (_, p) in v.iter() {
-                if p.get_fragment_index() == packet.get_fragment_index() {
-                    return; // already exists
-                }
+    /// Takes the next fragment if the allowance covers it.
+    fn next_packet(&mut self) -> Option<Packet> {
+        if self.bytes_per_sec.is_some() {
+            if self.allowance < FRAGMENT_COST {
+                return None;
             }
-            v.push((false, packet));
-        } else {
-            let _ = self.packets_received.insert(id, vec![(false, packet)]);
+            self.allowance -= FRAGMENT_COST;
         }
+        self.queue.pop_front()
     }
+}
 
-    fn mark_as_received(&mut self, session_id: u64, fragment_index: u64) {
-        let id = session_id;
-        if let Some(f) = self.packets_received.get_mut(&id) {
-            #[allow(clippy::cast_possible_truncation)]
-            let index = fragment_index as usize;
-            let (_received, frag) = &f[index];
-            f[index] = (true, frag.clone());
+/// Returns whether `packet` belongs to the control plane (Ack, Nack and flood packets).
+#[must_use]
+pub fn is_control_packet(packet: &Packet) -> bool {
+    !matches!(packet.pack_type, PacketType::MsgFragment(_))
+}
 
-            if f.iter().all(|(r, _)| *r) {
-                // If all fragments are received, remove the session
-                self.packets_received.remove(&id);
-            }
-        }
-    }
+/// Key of the link between `a` and `b`, whichever end it is seen from.
+fn edge_key(a: NodeId, b: NodeId) -> (NodeId, NodeId) {
+    (a.min(b), a.max(b))
+}
 
-    fn get_fragment_by_id(
-        &mut self,
-        session_id: u64,
-        fragment_index: u64,
-    ) -> Option<Packet> {
-        let id = session_id;
-        if let Some(session) = self.packets_received.get(&id) {
-            #[allow(clippy::cast_possible_truncation)]
-            session
-                .get(fragment_index as usize)
-                .map(|(r, p)| if *r { None } else { Some(p.clone()) })?
-        } else {
-            None
-        }
+/// Returns whether `fragment` is a reserved control fragment ("fragment 0 of 0"), which
+/// carries a control payload on its own instead of a part of a message.
+#[must_use]
+pub fn is_reserved_control_fragment(fragment: &Fragment) -> bool {
+    fragment.fragment_index == 0 && fragment.total_n_fragments == 0
+}
+
+/// Builds a reserved control fragment carrying `payload`.
+/// # Errors
+/// `PayloadTooLarge` if the payload does not fit in one fragment.
+pub fn reserved_control_fragment(payload: &[u8]) -> Result<Fragment, RoutingError> {
+    if payload.len() > MAX_FRAGMENT_SIZE {
+        return Err(RoutingError::PayloadTooLarge { len: payload.len() });
     }
+    let mut data = [0; MAX_FRAGMENT_SIZE];
+    data[..payload.len()].copy_from_slice(payload);
+    Ok(Fragment {
+        fragment_index: 0,
+        total_n_fragments: 0,
+        #[allow(clippy::cast_possible_truncation)]
+        length: payload.len() as u8,
+        data,
+    })
+}
+
+/// How the route of the Ack answering a received fragment is built.
+/// Whatever the policy, a route that doesn't validate falls back to the controller shortcut.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AckPolicy {
+    /// Reverse the routing header of the received fragment
+    #[default]
+    ReverseHeader,
+    /// Compute a fresh route to the sender from the network view
+    FreshRoute,
+    /// Hand the Ack to the controller, which delivers it directly to the sender
+    ControllerShortcut,
+}
+
+/// How `FloodResponse`s belonging to an older flood than the latest one are treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloodMergePolicy {
+    /// Only responses to the latest flood update the network view
+    LatestOnly,
+    /// Responses at most `max_age` floods old are merged as well, unless their
+    /// path trace contains a node removed from the view after that flood started
+    MergeStale { max_age: u64 },
+}
 
-    fn add_pending_packet(&mut self, pkt: Packet) {
-        self.packets_to_send.push(pkt);
+impl Default for FloodMergePolicy {
+    fn default() -> Self {
+        Self::MergeStale { max_age: 8 }
     }
+}
+
+/// Which repeated `FloodRequest`s are answered with a `FloodResponse` instead of being
+/// forwarded again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FloodSuppression {
+    /// A flood is forwarded once, from whichever neighbor it arrives first
+    #[default]
+    Exact,
+    /// A flood is forwarded once per neighbor it arrives from
+    PerNeighbor,
+    /// Every request is forwarded, only one already through this node is answered
+    Off,
+}
 
-    fn get_packets_to_send(&mut self) -> Vec<Packet> {
-        self.packets_to_send.drain(..).collect()
+/// Bounds on how often a [`RoutingHandler`] floods, see
+/// [`RoutingHandler::set_flood_rate_limit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FloodRateLimit {
+    /// Minimum time between the start of two floods
+    pub min_interval: Duration,
+    /// A flood is in progress until no response to it arrived for this long
+    pub quiet_period: Duration,
+    /// Longest a flood is considered in progress, however its responses trickle in
+    pub settle_timeout: Duration,
+}
+
+impl Default for FloodRateLimit {
+    fn default() -> Self {
+        Self {
+            min_interval: Duration::from_millis(500),
+            quiet_period: Duration::from_millis(200),
+            settle_timeout: Duration::from_secs(2),
+        }
     }
 }
 
+type FloodKey = (u64, NodeId, Option<NodeId>);
+
 #[derive(Debug, Clone)]
 pub struct RoutingHandler {
     id: NodeId,
     network_view: Network,
     neighbors: HashMap<NodeId, Sender<Packet>>,
-    flood_seen: HashSet<(u64, NodeId)>,
-    session_counter: u64,
+    control_neighbors: HashMap<NodeId, Sender<Packet>>,
+    // flood requests seen as (flood id, initiator, previous hop if suppression is per
+    // neighbor), with when they were first seen
+    flood_seen: HashMap<FloodKey, Instant>,
+    flood_seen_order: RingLog<(FloodKey, Instant)>,
+    flood_seen_ttl: Option<Duration>,
+    flood_suppression: FloodSuppression,
+    floods_suppressed: u64,
+    // packets shown to `tap_packet`, sent and received
+    packets_sent: Cell<u64>,
+    packets_received: Cell<u64>,
+    fragments_sent: Cell<u64>,
+    fragments_received: Cell<u64>,
+    // fragments reported dropped by a drone
+    fragments_dropped: u64,
+    // fragments sent again after a Nack or a `ForceRetry`
+    retransmissions: u64,
+    // sessions allocated by destination, `None` for the ones without a destination
+    session_counters: HashMap<Option<NodeId>, u64>,
     session_id: u64,
+    // first session id handed out when sequential session ids are enabled
+    session_base: Option<u64>,
     flood_counter: u64,
     controller_send: Sender<Box<dyn Event>>,
-    buffer: Buffer,
+    // events lost since the controller disconnected, `None` while it is attached
+    dropped_events: Cell<Option<u64>>,
+    // fragments sent or held back, until acknowledged
+    buffer: SessionBuffer,
+    // packets waiting for a flood to find their destination
+    packets_to_send: Vec<Packet>,
+    pending_ser_requests: HashSet<SerializedRequest>,
     node_type: NodeType,
+    fragment_trace: FragmentTrace,
+    pinned_routes: HashMap<u64, SourceRoutingHeader>,
+    throttles: HashMap<u64, SessionThrottle>,
+    // fragments of a session allowed in flight, `None` to send them all at once
+    send_window: Option<usize>,
+    bytes: ByteAccounting,
+    ack_policy: AckPolicy,
+    flood_merge_policy: FloodMergePolicy,
+    // flood id of the latest own flood whose responses confirmed each node
+    node_confirmed_by: HashMap<NodeId, u64>,
+    // flood id of the latest own flood whose responses confirmed each link, by the
+    // ordered pair of its ends
+    edge_confirmed_by: HashMap<(NodeId, NodeId), u64>,
+    // floods a link may go unconfirmed before it's dropped from the view, `None` to keep it
+    edge_expiry: Option<u64>,
+    // flood counter at the time each node was removed from the view
+    node_removed_at: HashMap<NodeId, u64>,
+    fragment_size: usize,
+    // fragment sizes agreed with some peers, overriding `fragment_size`
+    peer_fragment_sizes: HashMap<NodeId, usize>,
+    retry_policy: RetryPolicy,
+    flood_interval: Option<Duration>,
+    last_flood: Instant,
+    // latest response to the latest own flood
+    last_flood_response: Instant,
+    flood_rate_limit: Option<FloodRateLimit>,
+    // a flood was asked for while the rate limit held it back
+    flood_deferred: bool,
+    floods_coalesced: u64,
+    // end of the grace period of a graceful shutdown, `None` while running normally
+    shutdown_deadline: Option<Instant>,
+    transforms: Vec<SharedTransform>,
+    // observer of every packet sent and received, e.g. a `PacketRecorder`
+    packet_tap: Option<SharedTap>,
+    // latest packets sent and received, empty when the journal is disabled
+    packet_journal: RefCell<RingLog<(Instant, PacketDirection, Packet)>>,
+    // chain every packet sent and received goes through, see `Processor::middlewares`
+    middlewares: Vec<SharedMiddleware>,
+    // how long Acks may wait for a message to piggyback on, `None` when disabled
+    ack_delay: Option<Duration>,
+    pending_acks: Vec<PendingAck>,
+    // how long Acks are batched per session, `None` to send one Ack per fragment
+    ack_aggregation: Option<Duration>,
+    aggregated_acks: HashMap<(NodeId, u64), AggregatedAcks>,
+    // node-disjoint alternative to the latest route computed towards each destination,
+    // `None` when backup routes are disabled
+    backup_routes: Option<HashMap<NodeId, SourceRoutingHeader>>,
+    // routes fragments are spread over, `None` to send each session along one route
+    multipath: Option<usize>,
+    // node-disjoint routes of the sessions spread over several of them
+    multipath_routes: HashMap<u64, Vec<SourceRoutingHeader>>,
+    // latest route computed towards each destination, dropped when the topology changes
+    route_cache: HashMap<NodeId, SourceRoutingHeader>,
+    // replaces the built-in path search when set
+    route_policy: Option<Arc<dyn RoutePolicy>>,
+    route_cache_stats: RouteCacheStats,
+    broadcasts: HashMap<u64, Broadcast>,
+    broadcast_counter: u64,
+    // handles of the sessions started by `send_message` and not settled yet
+    session_handles: HashMap<u64, SessionHandle>,
+    congestion: HashMap<NodeId, CongestionWindow>,
+    // RTT estimates and paced queues by destination
+    pacers: HashMap<NodeId, Pacer>,
+    pacing: bool,
+    // sessions not of the default `QosClass::Bulk`
+    session_classes: HashMap<u64, QosClass>,
+    congestion_report_interval: Option<Duration>,
+    last_congestion_report: Instant,
+    search_budget: SearchBudget,
+    event_filter: Severity,
+    // latest events emitted, empty when the history is disabled
+    event_history: RefCell<RingLog<(Instant, NodeEvent)>>,
+    // changes of the network view not reported yet, see `tracking_view`
+    view_changes: TopologyDiff,
+    // keep-alives sent as reserved control fragments
+    reserved_keep_alives: bool,
+    // latest Acks accepted as (session, fragment, peer), to ignore their duplicates
+    acks_seen: HashSet<(u64, u64, NodeId)>,
+    acks_seen_order: RingLog<(u64, u64, NodeId)>,
+    ack_anomalies: u64,
+    // servers reachable in the view at the latest partition check
+    reachable_servers: HashSet<NodeId>,
+    nacks_sent: u64,
+    // packets waiting for room in the bounded channel of a neighbor, by neighbor and
+    // whether they wait for its control channel
+    backlogs: HashMap<(NodeId, bool), VecDeque<Packet>>,
+    backlog_limit: usize,
+    // messages waiting for the session in flight to their destination
+    message_queue: MessageQueue,
+    // liveness of the watched peers, `None` when heartbeats are disabled
+    heartbeats: Option<HeartbeatMonitor>,
+    // heartbeats awaiting their Ack, by session
+    heartbeat_sessions: HashMap<u64, NodeId>,
+    // servers of the network view watched by the heartbeats, unwatched once they leave it
+    heartbeat_servers: HashSet<NodeId>,
+    // nodes routes must not go through
+    blacklist: Blacklist,
+    // source of the time of every timer of the handler
+    clock: Clock,
+    // compression of the messages sent and received, `None` when disabled
+    #[cfg(feature = "compression")]
+    compressor: Option<Compressor>,
 }
 
 impl RoutingHandler {
+    // file of the transfers directory holding the persisted buffer
+    const BUFFER_FILE: &'static str = "buffer.json";
+    // Acks remembered to detect duplicates
+    const ACK_HISTORY: usize = 4096;
+    /// Flood requests remembered by default to suppress their repetitions.
+    pub const DEFAULT_FLOOD_HISTORY: usize = 4096;
+    /// Packets queued by default for each neighbor whose channel is full.
+    pub const DEFAULT_BACKLOG_LIMIT: usize = 1024;
+
     #[must_use]
     pub fn new(
         id: NodeId,
@@ -108,526 +411,5720 @@ impl RoutingHandler {
             id,
             network_view: Network::new(Node::new(id, node_type, vec![])),
             neighbors,
-            session_counter: 0,
+            control_neighbors: HashMap::new(),
+            session_counters: HashMap::new(),
             session_id: 0,
+            session_base: None,
             flood_counter: 0,
-            flood_seen: HashSet::new(),
+            flood_seen: HashMap::new(),
+            flood_seen_order: RingLog::new(Self::DEFAULT_FLOOD_HISTORY),
+            flood_seen_ttl: None,
+            flood_suppression: FloodSuppression::default(),
+            floods_suppressed: 0,
+            packets_sent: Cell::new(0),
+            packets_received: Cell::new(0),
+            fragments_sent: Cell::new(0),
+            fragments_received: Cell::new(0),
+            fragments_dropped: 0,
+            retransmissions: 0,
             controller_send,
-            buffer: Buffer::new(),
+            dropped_events: Cell::new(None),
+            buffer: SessionBuffer::new(),
+            packets_to_send: Vec::new(),
+            pending_ser_requests: HashSet::new(),
             node_type,
+            fragment_trace: FragmentTrace::default(),
+            pinned_routes: HashMap::new(),
+            throttles: HashMap::new(),
+            send_window: None,
+            bytes: ByteAccounting::default(),
+            ack_policy: AckPolicy::default(),
+            flood_merge_policy: FloodMergePolicy::default(),
+            node_confirmed_by: HashMap::new(),
+            edge_confirmed_by: HashMap::new(),
+            edge_expiry: None,
+            node_removed_at: HashMap::new(),
+            fragment_size: MAX_FRAGMENT_SIZE,
+            peer_fragment_sizes: HashMap::new(),
+            retry_policy: RetryPolicy::default(),
+            flood_interval: None,
+            last_flood: Instant::now(),
+            last_flood_response: Instant::now(),
+            flood_rate_limit: None,
+            flood_deferred: false,
+            floods_coalesced: 0,
+            shutdown_deadline: None,
+            transforms: Vec::new(),
+            packet_tap: None,
+            packet_journal: RefCell::new(RingLog::new(0)),
+            middlewares: Vec::new(),
+            ack_delay: None,
+            pending_acks: Vec::new(),
+            ack_aggregation: None,
+            aggregated_acks: HashMap::new(),
+            backup_routes: None,
+            multipath: None,
+            multipath_routes: HashMap::new(),
+            route_cache: HashMap::new(),
+            route_policy: None,
+            route_cache_stats: RouteCacheStats::default(),
+            broadcasts: HashMap::new(),
+            broadcast_counter: 0,
+            session_handles: HashMap::new(),
+            congestion: HashMap::new(),
+            pacers: HashMap::new(),
+            session_classes: HashMap::new(),
+            pacing: false,
+            congestion_report_interval: None,
+            last_congestion_report: Instant::now(),
+            search_budget: SearchBudget::default(),
+            event_filter: Severity::default(),
+            event_history: RefCell::new(RingLog::new(0)),
+            view_changes: TopologyDiff::default(),
+            reserved_keep_alives: false,
+            acks_seen: HashSet::new(),
+            acks_seen_order: RingLog::new(Self::ACK_HISTORY),
+            ack_anomalies: 0,
+            reachable_servers: HashSet::new(),
+            nacks_sent: 0,
+            backlogs: HashMap::new(),
+            backlog_limit: Self::DEFAULT_BACKLOG_LIMIT,
+            message_queue: MessageQueue::default(),
+            heartbeats: None,
+            heartbeat_sessions: HashMap::new(),
+            heartbeat_servers: HashSet::new(),
+            blacklist: Blacklist::default(),
+            clock: Clock::default(),
+            #[cfg(feature = "compression")]
+            compressor: None,
         }
     }
 
-    fn update_session_id(&mut self) {
-        let mut rng = rand::rng();
-        self.session_counter += 1;
-        self.session_id = rng.random()
+    /// Creates a routing handler for neighbors reached through bounded channels
+    /// (`crossbeam_channel::bounded`). When the channel of a neighbor is full, up to
+    /// `backlog_limit` packets wait in a local backlog sent on [`Self::tick`], and the
+    /// controller is notified with `NodeEvent::Backpressure`.
+    #[must_use]
+    pub fn with_bounded_neighbors(
+        id: NodeId,
+        node_type: NodeType,
+        neighbors: HashMap<NodeId, Sender<Packet>>,
+        controller_send: Sender<Box<dyn Event>>,
+        backlog_limit: usize,
+    ) -> Self {
+        let mut handler = Self::new(id, node_type, neighbors, controller_send);
+        handler.set_backlog_limit(backlog_limit);
+        handler
     }
 
-    /// Sends a packet to a specific neighbor and notifies the controller about the packet sent.
-    /// # Errors
-    /// Returns an error if sending the packet to the neighbor fails or if sending the event to the controller fails.
-    fn send(&self, neighbor: &Sender<Packet>, packet: Packet) -> Result<(), NetworkError> {
-        neighbor.send(packet.clone())?;
-        self.controller_send
-            .send(Box::new(NodeEvent::PacketSent(packet)))
-            .map_err(|_e| NetworkError::ControllerDisconnected)?;
-        Ok(())
+    /// Creates a routing handler selecting its routes with `policy` instead of the
+    /// built-in search, see [`Self::set_route_policy`].
+    #[must_use]
+    pub fn with_route_policy(
+        id: NodeId,
+        node_type: NodeType,
+        neighbors: HashMap<NodeId, Sender<Packet>>,
+        controller_send: Sender<Box<dyn Event>>,
+        policy: Box<dyn RoutePolicy>,
+    ) -> Self {
+        let mut handler = Self::new(id, node_type, neighbors, controller_send);
+        handler.set_route_policy(Some(policy));
+        handler
     }
 
-    /// Starts a flood by incrementing the session and flood counters,
-    /// creating a flood request packet,
-    /// sending it to all neighbors,
-    /// and notifying the controller about the flood start.
-    /// # Errors
-    /// Returns an error if sending the packet to the controller fails or if sending to any neighbor fails.
-    pub fn start_flood(
-        &mut self,
-        pending_request: Option<SerializedRequest>,
-    ) -> Result<(), NetworkError> {
-        self.update_session_id();
-        self.flood_counter += 1;
-        let packet = Packet::new_flood_request(
-            SourceRoutingHeader::empty_route(),
-            self.session_id,
-            FloodRequest {
-                flood_id: self.flood_counter,
-                initiator_id: self.id,
-                path_trace: vec![(self.id, self.node_type)],
-            },
-        );
-        self.controller_send
-            .send(Box::new(NodeEvent::FloodStarted(
-                self.flood_counter,
-                self.id,
-            )))
-            .map_err(|_| NetworkError::ControllerDisconnected)?;
-        for (node_id, sender) in &self.neighbors.clone() {
-            if sender.send(packet.clone()).is_err() {
-                self.remove_neighbor(*node_id);
-            }
-        }
+    /// Selects the routes of the messages sent with `policy`, or with the built-in search
+    /// ranking paths by hops, latency and drop estimates within the search budget if
+    /// `None`. Cached routes are dropped.
+    pub fn set_route_policy(&mut self, policy: Option<Box<dyn RoutePolicy>>) {
+        self.route_policy = policy.map(Arc::from);
+        self.invalidate_routes();
+    }
 
-        if let Some(req) = pending_request {
-            self.buffer.pending_ser_requests.insert(req);
-        }
-        Ok(())
+    /// Sets how many packets may wait for room in the channel of each neighbor,
+    /// [`Self::DEFAULT_BACKLOG_LIMIT`] by default. Backlogs already longer are kept.
+    pub fn set_backlog_limit(&mut self, limit: usize) {
+        self.backlog_limit = limit;
     }
 
-    /// Tries to remove the neighbor from the neighbors map and network view
-    pub fn remove_neighbor(&mut self, node_id: NodeId) {
-        #[allow(clippy::let_unit_value)]
-        let _ = self.neighbors.remove(&node_id);
-        self.network_view.remove_node(node_id);
+    /// Returns how many packets wait for room in the channels of `neighbor`.
+    #[must_use]
+    pub fn backlog(&self, neighbor: NodeId) -> usize {
+        self.backlogs
+            .iter()
+            .filter(|((node, _), _)| *node == neighbor)
+            .map(|(_, backlog)| backlog.len())
+            .sum()
     }
 
-    /// Adds a new neighbor to the neighbors map and updates the network view
-    pub fn add_neighbor(&mut self, node_id: NodeId, sender: Sender<Packet>) {
-        let _ = self.neighbors.insert(node_id, sender);
-        let _ = self.network_view.update_node(self.id, vec![node_id]);
+    /// Creates a routing handler tuned by `config`.
+    #[must_use]
+    pub fn with_config(
+        id: NodeId,
+        node_type: NodeType,
+        neighbors: HashMap<NodeId, Sender<Packet>>,
+        controller_send: Sender<Box<dyn Event>>,
+        config: &CommonConfig,
+    ) -> Self {
+        let mut handler = Self::new(id, node_type, neighbors, controller_send);
+        handler.apply_config(config);
+        handler
     }
 
-    /// Handle `flood_response`
-    /// # Errors
-    /// Returns error if can't send the packet
-    pub fn handle_flood_response(
-        &mut self,
-        flood_response: &FloodResponse,
-    ) -> Result<(), NetworkError> {
-        if flood_response.flood_id == self.flood_counter {
-            self.update_network_view(&flood_response.path_trace);
-            let requests = self.buffer.pending_ser_requests.drain().collect::<Vec<_>>();
-            for req in requests {
-                self.send_message(&req.data, req.to, None)?;
-            }
-            for packet in self.buffer.get_packets_to_send() {
-                self.try_send(packet)?;
-            }
-        }
-        Ok(())
+    /// Applies the fragment size, retry policy and flood interval of `config`.
+    /// Fragments already buffered keep their size.
+    pub fn apply_config(&mut self, config: &CommonConfig) {
+        self.set_fragment_size(config.fragment_size);
+        self.retry_policy = config.retry;
+        self.flood_interval = config.flood_interval();
     }
 
-    fn update_network_view(&mut self, path_trace: &[(NodeId, NodeType)]) {
-        for (i, &(node_id, node_type)) in path_trace.iter().enumerate() {
-            let mut neighbors = Vec::new();
+    /// Sets the bytes of message data put in each fragment, clamped between 1 and
+    /// [`MAX_FRAGMENT_SIZE`], the default. Fragments already buffered keep their size.
+    pub fn set_fragment_size(&mut self, size: usize) {
+        self.fragment_size = size.clamp(1, MAX_FRAGMENT_SIZE);
+    }
 
-            // Add previous node as neighbor
-            if i > 0 {
-                neighbors.push(path_trace[i - 1].0);
-            }
+    #[must_use]
+    pub fn fragment_size(&self) -> usize {
+        self.fragment_size
+    }
 
-            // Add next node as neighbor
-            if i + 1 < path_trace.len() {
-                neighbors.push(path_trace[i + 1].0);
+    /// Uses `size` instead of [`Self::fragment_size`] for the messages sent to `peer`,
+    /// e.g. the MTU negotiated with it by the application; `None` reverts to the default.
+    /// Receivers need no setting, fragments of any length are reassembled.
+    pub fn set_peer_fragment_size(&mut self, peer: NodeId, size: Option<usize>) {
+        match size {
+            Some(size) => {
+                let _ = self
+                    .peer_fragment_sizes
+                    .insert(peer, size.clamp(1, MAX_FRAGMENT_SIZE));
             }
-
-            // Try to update existing node or add new one
-            if self
-                .network_view
-                .update_node(node_id, neighbors.clone())
-                .is_err()
-            {
-                let new_node = Node::new(node_id, node_type, neighbors.clone());
-                self.network_view.add_node(new_node);
+            None => {
+                let _ = self.peer_fragment_sizes.remove(&peer);
             }
         }
     }
 
-    /// Handles a flood request by checking if the flood has been seen before.
-    /// If it has not been seen, it generates a flood response and sends it to the neighbors.
-    /// If it has been seen, it forwards the flood request to the neighbors except for the previous hop.
-    /// # Errors
-    /// Returns an error if sending the packet fails or if the flood request is malformed.
-    pub fn handle_flood_request(
-        &mut self,
-        mut flood_request: FloodRequest,
-        session_id: u64,
-    ) -> Result<(), NetworkError> {
-        let prev_hop = flood_request
-            .path_trace
-            .last()
-            .map_or(flood_request.initiator_id, |x| x.0);
-
-        flood_request.path_trace.push((self.id, self.node_type));
-
-        let flood_session = (flood_request.flood_id, flood_request.initiator_id);
+    /// Returns the fragment size used for the messages sent to `peer`.
+    #[must_use]
+    pub fn peer_fragment_size(&self, peer: NodeId) -> usize {
+        self.peer_fragment_sizes
+            .get(&peer)
+            .copied()
+            .unwrap_or(self.fragment_size)
+    }
 
-        self.update_network_view(&flood_request.path_trace);
+    pub fn set_flood_merge_policy(&mut self, policy: FloodMergePolicy) {
+        self.flood_merge_policy = policy;
+    }
 
-        if !self.flood_seen.insert(flood_session) || self.neighbors.len() == 1 {
-            // generate flood response
-            let route = if let Some(path) = self.network_view.find_path(self.id, flood_request.initiator_id)
-            {
-                SourceRoutingHeader::new(path, 1)
-            } else {
-                let mut route: Vec<_> = flood_request
-                    .path_trace
-                    .clone()
-                    .iter()
-                    .map(|(id, _)| *id)
-                    .rev()
-                    .collect::<Vec<_>>();
+    /// Sets which repeated flood requests are answered instead of forwarded.
+    /// Requests seen under the previous scope are forgotten.
+    pub fn set_flood_suppression(&mut self, suppression: FloodSuppression) {
+        self.flood_suppression = suppression;
+        self.purge_flood_state();
+    }
 
-                if route.last() != Some(&flood_request.initiator_id) {
-                    route.push(flood_request.initiator_id);
-                }
+    /// Remembers the latest `capacity` flood requests seen, forgetting them after `ttl`
+    /// if given, to suppress their repetitions. Defaults to
+    /// [`Self::DEFAULT_FLOOD_HISTORY`] requests without expiry. Resizing keeps the latest
+    /// requests seen.
+    pub fn set_flood_history(&mut self, capacity: usize, ttl: Option<Duration>) {
+        let mut seen = self.flood_seen.drain().collect::<Vec<_>>();
+        seen.sort_by_key(|(_, since)| *since);
+        self.flood_seen_order = RingLog::new(capacity);
+        self.flood_seen_ttl = ttl;
+        for (key, since) in seen {
+            let _ = self.remember_flood(key, since);
+        }
+        self.purge_expired_floods();
+    }
 
-                SourceRoutingHeader::new(route, 1)
-            };
+    /// Returns the capacity and expiry of the flood requests remembered.
+    #[must_use]
+    pub fn flood_history(&self) -> (usize, Option<Duration>) {
+        (self.flood_seen_order.capacity(), self.flood_seen_ttl)
+    }
 
-            let flood_response = FloodResponse {
-                flood_id: flood_request.flood_id,
-                path_trace: flood_request.path_trace,
-            };
+    /// Returns how many flood requests are remembered.
+    #[must_use]
+    pub fn flood_state_len(&self) -> usize {
+        self.flood_seen.len()
+    }
 
-            let packet = Packet::new_flood_response(route, session_id, flood_response);
+    /// Forgets every flood request seen, so that their repetitions are forwarded again.
+    pub fn purge_flood_state(&mut self) {
+        self.flood_seen.clear();
+        self.flood_seen_order.clear();
+    }
 
-            self.try_send(packet)?;
+    // Records `key` seen at `now`, returning whether it was already remembered.
+    fn remember_flood(&mut self, key: FloodKey, now: Instant) -> bool {
+        let ttl = self.flood_seen_ttl;
+        if self
+            .flood_seen
+            .get(&key)
+            .is_some_and(|since| ttl.is_none_or(|ttl| now.duration_since(*since) < ttl))
+        {
+            return true;
+        }
+        let _ = self.flood_seen.insert(key, now);
+        // the ring may still hold an expired entry of the key, only its latest one counts
+        if let Some((evicted, since)) = self.flood_seen_order.push((key, now))
+            && self.flood_seen.get(&evicted) == Some(&since)
+        {
+            let _ = self.flood_seen.remove(&evicted);
+        }
+        false
+    }
 
-            return Ok(());
+    fn purge_expired_floods(&mut self) {
+        if let Some(ttl) = self.flood_seen_ttl {
+            let now = self.clock.now();
+            self.flood_seen
+                .retain(|_, since| now.duration_since(*since) < ttl);
         }
+    }
 
-        let srh = SourceRoutingHeader::new(vec![], 0);
+    /// Returns how many flood requests were answered because already seen.
+    #[must_use]
+    pub fn floods_suppressed(&self) -> u64 {
+        self.floods_suppressed
+    }
 
-        let new_flood_request = Packet::new_flood_request(srh, session_id, flood_request);
+    /// Limits how often the node floods, `None` to flood whenever asked (the default).
+    /// Floods asked for while the previous one is in progress or too recent are
+    /// coalesced: their pending requests wait for the responses of the current flood,
+    /// and a single new flood starts on the next [`Self::tick`] allowed to.
+    pub fn set_flood_rate_limit(&mut self, limit: Option<FloodRateLimit>) {
+        self.flood_rate_limit = limit;
+    }
 
-        for (neighbor_id, neighbor) in &self.neighbors {
-            if *neighbor_id != prev_hop {
-                neighbor.send(new_flood_request.clone())?;
+    /// Returns whether the latest flood still awaits responses under the rate limit,
+    /// always `false` without one.
+    #[must_use]
+    pub fn flood_in_progress(&self) -> bool {
+        self.flood_rate_limit.is_some_and(|limit| {
+            if self.flood_counter == 0 {
+                return false;
             }
-        }
-        Ok(())
+            let now = self.clock.now();
+            let latest = self.last_flood.max(self.last_flood_response);
+            now.duration_since(self.last_flood) < limit.settle_timeout
+                && now.duration_since(latest) < limit.quiet_period
+        })
     }
 
-    /// Handles a NACK packet by removing the neighbor if the NACK indicates an error in routing,
-    /// starting a flood to find a new route, and retrying to send the packet if it exists in the buffer.
-    /// # Errors
-    /// Returns an error if sending the packet fails or if the packet is not found in the buffer.
-    pub fn handle_nack(
-        &mut self,
-        nack: &Nack,
-        session_id: u64,
-        source_id: NodeId,
-    ) -> Result<(), NetworkError> {
-        match nack.nack_type {
-            NackType::ErrorInRouting(id) => {
-                self.remove_neighbor(id);
-                self.start_flood(None)?;
-            }
+    /// Returns how many floods the node started, i.e. the id of the latest one.
+    #[must_use]
+    pub fn floods_started(&self) -> u64 {
+        self.flood_counter
+    }
 
-            NackType::Dropped => {}
+    /// Returns when the latest flood started or a flood response last arrived, `None`
+    /// if the node never flooded.
+    #[must_use]
+    pub fn last_flood_activity(&self) -> Option<Instant> {
+        (self.flood_counter > 0).then(|| self.last_flood.max(self.last_flood_response))
+    }
 
-            NackType::DestinationIsDrone => self
-                .network_view
-                .change_node_type(source_id, NodeType::Drone),
+    /// Returns how many floods were folded into another one by the rate limit.
+    #[must_use]
+    pub fn floods_coalesced(&self) -> u64 {
+        self.floods_coalesced
+    }
 
-            NackType::UnexpectedRecipient(_) => todo!("Should fix network view accordingly"),
-        }
+    /// Returns whether the rate limit, if any, lets a flood start now.
+    fn may_flood(&self) -> bool {
+        self.flood_rate_limit.is_none_or(|limit| {
+            self.flood_counter == 0
+                || (!self.flood_in_progress()
+                    && self.clock.now().duration_since(self.last_flood) >= limit.min_interval)
+        })
+    }
 
-        self.retry_send(session_id, nack.fragment_index, source_id)?;
+    /// Returns how many Acks were rejected as unsolicited.
+    #[must_use]
+    pub fn ack_anomalies(&self) -> u64 {
+        self.ack_anomalies
+    }
 
-        Ok(())
+    /// Returns how many floods ago `node_id` was last confirmed by a flood response,
+    /// `None` if no response ever mentioned it.
+    #[must_use]
+    pub fn node_age(&self, node_id: NodeId) -> Option<u64> {
+        self.node_confirmed_by
+            .get(&node_id)
+            .map(|flood_id| self.flood_counter.saturating_sub(*flood_id))
     }
 
-    /// Send a packet to the first hop in its route
-    /// # Errors
-    /// Returns an error if send fails
-    fn send_packet_to_first_hop(&mut self, packet: Packet) -> Result<(), NetworkError> {
-        if packet.routing_header.hops.len() > 1 {
-            let first_hop = packet.routing_header.hops[1];
-            if let Some(sender) = self.neighbors.get(&first_hop) {
-                self.send(sender, packet.clone())?;
-                let session_id = packet.session_id;
-                self.buffer.insert(packet, session_id);
-            } else {
-                return Err(NetworkError::NodeIsNotANeighbor(first_hop));
+    /// Returns how many floods ago the link between `a` and `b` was last confirmed by a
+    /// flood response, `None` if no response ever went through it.
+    #[must_use]
+    pub fn edge_age(&self, a: NodeId, b: NodeId) -> Option<u64> {
+        self.edge_confirmed_by
+            .get(&edge_key(a, b))
+            .map(|flood_id| self.flood_counter.saturating_sub(*flood_id))
+    }
+
+    /// Drops from the view the links no flood response confirmed in the last `max_age`
+    /// floods, links to the current neighbors excepted, and returns them. Links never
+    /// confirmed by a response are kept.
+    pub fn age_out_edges(&mut self, max_age: u64) -> Vec<(NodeId, NodeId)> {
+        let mut expired = self
+            .edge_confirmed_by
+            .iter()
+            .filter(|(_, flood_id)| self.flood_counter.saturating_sub(**flood_id) > max_age)
+            .map(|(edge, _)| *edge)
+            .filter(|(a, b)| {
+                !(*a == self.id && self.neighbors.contains_key(b)
+                    || *b == self.id && self.neighbors.contains_key(a))
+            })
+            .collect::<Vec<_>>();
+        expired.sort_unstable();
+        self.tracking_view(|handler| {
+            for (a, b) in &expired {
+                let _ = handler.edge_confirmed_by.remove(&(*a, *b));
+                handler.remove_view_edge(*a, *b);
             }
+        });
+        if !expired.is_empty() {
+            self.invalidate_routes();
+            self.detect_partition();
         }
-        Ok(())
+        expired
     }
 
-    fn try_find_path(&mut self, destination: NodeId) -> Result<SourceRoutingHeader, NetworkError> {
-        if destination == self.id {
-            return Ok(SourceRoutingHeader::empty_route());
-        }
+    /// Ages out the links unconfirmed for more than `max_age` floods every time a flood
+    /// starts, see [`Self::age_out_edges`]; `None` (the default) keeps them.
+    pub fn set_edge_expiry(&mut self, max_age: Option<u64>) {
+        self.edge_expiry = max_age;
+    }
 
-        if let Some(path) = self.network_view.find_path(self.id, destination) {
-            return Ok(SourceRoutingHeader::new(path, 1).without_loops());
-        }
-        Err(NetworkError::PathNotFound(destination))
+    /// Makes every route computation also store a node-disjoint backup route, which
+    /// `try_send` switches to as soon as the primary route fails instead of searching
+    /// a new one.
+    pub fn set_backup_routes(&mut self, enabled: bool) {
+        self.backup_routes = enabled.then(HashMap::new);
+        // backup routes are computed along with the routes cached
+        self.invalidate_routes();
     }
 
-    /// Tries to send a packet to next hop until it succeeds or there are no more neighbors.
-    /// If sending fails, it removes the neighbor, finds a new route and tries again.
-    /// # Errors
-    /// Returns an error if the packet has no destination, if there are no neighbors, or if sending fails.
-    /// `SendError` if `send_packet_to_first_hop()` can't send the packet
-    /// `NoDestination` if the route is empty
-    /// `ControllerDisconnected` if `start_flood()` can't send event `FloodStarted` to controller
-    /// `NoNeighborAssigned` if there are no more neighbors
-    fn try_send(&mut self, mut packet: Packet) -> Result<(), NetworkError> {
-        // A packet must have a destination
-        let destination = packet
-            .routing_header
-            .destination()
-            .ok_or(NetworkError::NoDestination)?;
+    /// Spreads the fragments of each message round robin over up to `routes`
+    /// node-disjoint routes towards its destination, so that a lossy drone only slows
+    /// down part of them and retransmissions can take another route. `None` sends each
+    /// message along a single route (the default). Pinned routes are never spread.
+    pub fn set_multipath(&mut self, routes: Option<usize>) {
+        self.multipath = routes;
+    }
 
-        let mut packet_sent = false;
-        while !packet_sent && !self.neighbors.is_empty() {
-            match self.send_packet_to_first_hop(packet.clone()) {
+    /// Returns the routes the fragments of `session_id` are spread over, empty unless
+    /// it was sent over several.
+    #[must_use]
+    pub fn session_routes(&self, session_id: u64) -> Vec<&[NodeId]> {
+        self.multipath_routes
+            .get(&session_id)
+            .map(|routes| routes.iter().map(|shr| shr.hops.as_slice()).collect())
+            .unwrap_or_default()
+    }
+
+    /// Bounds every route search, so that huge topologies can't stall the processor loop.
+    /// Sends whose route search runs out of budget fail with `SearchBudgetExceeded`, while
+    /// flood responses then go back the way their request came.
+    pub fn set_search_budget(&mut self, budget: SearchBudget) {
+        self.search_budget = budget;
+        self.invalidate_routes();
+    }
+
+    /// Returns the backup route stored towards `destination`, if any.
+    #[must_use]
+    pub fn backup_route(&self, destination: NodeId) -> Option<&[NodeId]> {
+        self.backup_routes
+            .as_ref()?
+            .get(&destination)
+            .map(|shr| shr.hops.as_slice())
+    }
+
+    fn update_session_id(&mut self, destination: Option<NodeId>) {
+        let Some(base) = self.session_base else {
+            self.session_id = rand::rng().random();
+            return;
+        };
+        let counter = self.session_counters.entry(destination).or_default();
+        *counter += 1;
+        // each destination counts in its own range, so that ids stay unique
+        let range = destination.map_or(u64::from(NodeId::MAX) + 1, u64::from);
+        self.session_id = base + (range << 48) + *counter;
+    }
+
+    /// Makes session ids increase with every new session instead of being random,
+    /// which receivers using ordered delivery rely on. Each destination gets its own
+    /// sequence, so that a receiver sees no gap between the sessions sent to it. The
+    /// sequences start from a random base so that a restarted node doesn't reuse recent
+    /// session ids.
+    pub fn set_sequential_session_ids(&mut self, enabled: bool) {
+        self.session_base = enabled.then(|| rand::rng().random_range(0..u64::MAX / 2));
+    }
+
+    /// Makes session ids sequential from `base`, so that a simulation replays with the
+    /// same ids. Outside simulations prefer [`Self::set_sequential_session_ids`].
+    pub fn seed_session_ids(&mut self, base: u64) {
+        self.session_base = Some(base);
+    }
+
+    /// Makes the timers of the handler follow `clock`, e.g. the simulated clock of a
+    /// simulation. The flood and congestion report timers restart from its time.
+    pub fn set_clock(&mut self, clock: Clock) {
+        let now = clock.now();
+        self.last_flood = now;
+        self.last_flood_response = now;
+        self.last_congestion_report = now;
+        self.clock = clock;
+    }
+
+    #[must_use]
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+
+    /// Returns the current time of the clock of the handler.
+    #[must_use]
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Notifies the controller of `event`. Once the controller is disconnected the node
+    /// keeps routing in a degraded mode where events are dropped and counted, until
+    /// [`Self::reattach_controller`] is called. Events below the event filter are skipped.
+    /// Returns whether the event was delivered or skipped.
+    fn emit(&self, event: NodeEvent) -> bool {
+        if event.severity() < self.event_filter && !event.bypasses_filter() {
+            return true;
+        }
+        let mut history = self.event_history.borrow_mut();
+        if history.capacity() > 0 {
+            let _ = history.push((self.clock.now(), event.clone()));
+        }
+        let delivered = self.controller_send.send(Box::new(event)).is_ok();
+        if !delivered {
+            let dropped = self.dropped_events.get().unwrap_or_default();
+            self.dropped_events.set(Some(dropped + 1));
+        }
+        delivered
+    }
+
+    /// Sends the next events to `controller_send`, leaving the degraded mode entered when
+    /// the previous controller disconnected.
+    pub fn reattach_controller(&mut self, controller_send: Sender<Box<dyn Event>>) {
+        self.controller_send = controller_send;
+        self.dropped_events.set(None);
+    }
+
+    /// Keeps the latest `capacity` events emitted to the controller, 0 (the default)
+    /// to keep none. Resizing forgets the events kept so far.
+    pub fn set_event_history(&mut self, capacity: usize) {
+        self.event_history = RefCell::new(RingLog::new(capacity));
+    }
+
+    /// Returns the latest events emitted with the time they were emitted, oldest first.
+    #[must_use]
+    pub fn event_history(&self) -> Vec<(Instant, NodeEvent)> {
+        self.event_history.borrow().iter().cloned().collect()
+    }
+
+    /// Only emits the events at least as severe as `filter`, replies to commands excepted.
+    pub fn set_event_filter(&mut self, filter: Severity) {
+        self.event_filter = filter;
+    }
+
+    #[must_use]
+    pub fn event_filter(&self) -> Severity {
+        self.event_filter
+    }
+
+    /// Returns whether events reach the controller, i.e. whether no event was dropped
+    /// since the node started or the controller was last reattached.
+    #[must_use]
+    pub fn is_controller_attached(&self) -> bool {
+        self.dropped_events.get().is_none()
+    }
+
+    /// Returns how many events were dropped since the controller disconnected.
+    #[must_use]
+    pub fn dropped_events(&self) -> u64 {
+        self.dropped_events.get().unwrap_or_default()
+    }
+
+    /// Sends a packet to a specific neighbor and notifies the controller about the packet
+    /// sent, see [`Self::record_departure`]. A backlogged packet is recorded once flushed.
+    /// # Errors
+    /// Returns an error if sending the packet to the neighbor fails, see [`Self::push`].
+    fn send(&mut self, neighbor: NodeId, packet: &Packet) -> Result<(), NetworkError> {
+        match self.push(neighbor, is_control_packet(packet), packet.clone())? {
+            Pushed::Sent => self.record_departure(packet),
+            // a packet dropped by a middleware never left the node, but its fragment is
+            // timed as if lost on the way so that it is retransmitted
+            Pushed::Dropped => self.record_attempt(packet),
+            Pushed::Backlogged => {}
+        }
+        Ok(())
+    }
+
+    /// Notifies the controller of `packet` leaving the node and records when the
+    /// fragments it carries were sent.
+    fn record_departure(&mut self, packet: &Packet) {
+        self.emit(NodeEvent::PacketSent(packet.clone()));
+        if let PacketType::MsgFragment(fragment) = &packet.pack_type {
+            let index = fragment.fragment_index;
+            let _ = self
+                .fragment_trace
+                .record(packet.session_id, index, FragmentFate::Sent);
+            if let Some(destination) = packet.routing_header.destination() {
+                *self.bytes.sent.entry(destination).or_default() += fragment.data.len() as u64;
+            }
+        }
+        self.record_attempt(packet);
+    }
+
+    /// Records when the fragment `packet` carries was sent, starting the timer after
+    /// which it is retransmitted.
+    fn record_attempt(&mut self, packet: &Packet) {
+        if let PacketType::MsgFragment(fragment) = &packet.pack_type
+            && let Some(destination) = packet.routing_header.destination()
+        {
+            let pacer = self.pacers.entry(destination).or_default();
+            pacer.on_sent(packet.session_id, fragment.fragment_index, self.clock.now());
+        }
+        self.buffer.record_sent(packet, self.clock.now());
+    }
+
+    /// Hands `packet` to the channel reaching `neighbor`, see [`Self::neighbor_sender`].
+    /// If the channel is full, or packets already wait for it, the packet is queued in
+    /// the backlog of the channel and the controller notified when the backlog starts.
+    /// # Errors
+    /// `NodeIsNotANeighbor` if there is no channel to `neighbor`,
+    /// `Send` if the channel is disconnected,
+    /// `Full` if the backlog already holds the packets allowed by the backlog limit.
+    fn push(
+        &mut self,
+        neighbor: NodeId,
+        control: bool,
+        packet: Packet,
+    ) -> Result<Pushed, NetworkError> {
+        let Some(packet) = run_middlewares(&self.middlewares, packet, PacketDirection::Sent)
+        else {
+            return Ok(Pushed::Dropped);
+        };
+        let key = (
+            neighbor,
+            control && self.control_neighbors.contains_key(&neighbor),
+        );
+        self.flush_backlog(key)?;
+        let Some(sender) = self.neighbor_sender(neighbor, control) else {
+            return Err(RoutingError::NodeIsNotANeighbor { node: neighbor }.into());
+        };
+        if self.backlogs.get(&key).is_none_or(VecDeque::is_empty) {
+            match sender.try_send(packet.clone()) {
                 Ok(()) => {
-                    packet_sent = true;
+                    self.tap_packet(PacketDirection::Sent, &packet);
+                    return Ok(Pushed::Sent);
                 }
-                Err(NetworkError::SendError(_) | NetworkError::NodeIsNotANeighbor(_)) => {
-                    // If the first hop is not a neighbor, remove it and try again
-                    if let Some(first_hop) = packet.routing_header.hops.get(1) {
-                        self.remove_neighbor(*first_hop);
-                        // remove neighbor and start flood
-                        match self.try_find_path(destination) {
-                            Ok(shr) => packet.routing_header = shr,
-                            Err(NetworkError::PathNotFound(_)) => {
-                                self.start_flood(None)?;
-                                self.buffer.add_pending_packet(packet.clone());
-                            }
-                            Err(e) => return Err(e),
-                        }
-                    }
+                Err(TrySendError::Disconnected(packet)) => return Err(SendError(packet).into()),
+                Err(TrySendError::Full(_)) => {}
+            }
+        }
+        let backlog = self.backlogs.entry(key).or_default();
+        if backlog.len() >= self.backlog_limit {
+            return Err(ChannelError::Full { neighbor }.into());
+        }
+        backlog.push_back(packet);
+        if backlog.len() == 1 {
+            self.emit(NodeEvent::Backpressure(neighbor));
+        }
+        Ok(Pushed::Backlogged)
+    }
+
+    /// Sends the packets of the backlog `key` while its channel has room, recording the
+    /// departure of the routed ones (flood requests are broadcast without being reported).
+    /// # Errors
+    /// `Send` if the channel is disconnected.
+    fn flush_backlog(&mut self, key: (NodeId, bool)) -> Result<(), ChannelError> {
+        let Some(sender) = self.neighbor_sender(key.0, key.1).cloned() else {
+            let _ = self.backlogs.remove(&key);
+            return Ok(());
+        };
+        let Some(backlog) = self.backlogs.get_mut(&key) else {
+            return Ok(());
+        };
+        let mut sent = Vec::new();
+        let mut result = Ok(());
+        while let Some(packet) = backlog.pop_front() {
+            match sender.try_send(packet.clone()) {
+                Ok(()) => sent.push(packet),
+                Err(TrySendError::Full(_)) => {
+                    backlog.push_front(packet);
+                    break;
+                }
+                Err(TrySendError::Disconnected(packet)) => {
+                    result = Err(SendError(packet).into());
+                    break;
                 }
+            }
+        }
+        if backlog.is_empty() {
+            let _ = self.backlogs.remove(&key);
+        }
+        for packet in &sent {
+            self.tap_packet(PacketDirection::Sent, packet);
+            if !matches!(packet.pack_type, PacketType::FloodRequest(_)) {
+                self.record_departure(packet);
+            }
+        }
+        result
+    }
 
-                Err(e) => return Err(e),
+    /// Sends what the channels of the neighbors have room for from their backlogs,
+    /// removing the neighbors whose channel is disconnected.
+    fn flush_backlogs(&mut self) {
+        for key in self.backlogs.keys().copied().collect::<Vec<_>>() {
+            if self.flush_backlog(key).is_err() {
+                self.remove_neighbor(key.0);
             }
         }
+    }
 
-        if self.neighbors.is_empty() {
-            return Err(NetworkError::NoNeighborAssigned);
+    /// Starts a flood by incrementing the session and flood counters,
+    /// creating a flood request packet,
+    /// sending it to all neighbors,
+    /// and notifying the controller about the flood start.
+    /// Under a [`FloodRateLimit`] the flood may be deferred, `pending_request` then waits
+    /// for the responses of the current flood.
+    /// # Errors
+    /// Returns an error if sending to any neighbor fails.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(level = "debug", skip_all, fields(node = self.id))
+    )]
+    pub fn start_flood(
+        &mut self,
+        pending_request: Option<SerializedRequest>,
+    ) -> Result<(), NetworkError> {
+        if !self.may_flood() {
+            self.flood_deferred = true;
+            self.floods_coalesced += 1;
+            if let Some(req) = pending_request {
+                let _ = self.pending_ser_requests.insert(req);
+            }
+            return Ok(());
+        }
+        self.flood_deferred = false;
+        if let Some(max_age) = self.edge_expiry {
+            let _ = self.age_out_edges(max_age);
+        }
+        self.update_session_id(None);
+        self.flood_counter += 1;
+        self.last_flood = self.clock.now();
+        let packet = Packet::new_flood_request(
+            SourceRoutingHeader::empty_route(),
+            self.session_id,
+            FloodRequest {
+                flood_id: self.flood_counter,
+                initiator_id: self.id,
+                path_trace: vec![(self.id, self.node_type)],
+            },
+        );
+        #[cfg(feature = "telemetry")]
+        tracing::debug!(flood_id = self.flood_counter, "flood started");
+        self.emit(NodeEvent::FloodStarted(self.flood_counter, self.id));
+        for node_id in self.sorted_neighbors() {
+            match self.push(node_id, true, packet.clone()) {
+                // a congested neighbor is still alive, it misses this flood only
+                Ok(_) | Err(NetworkError::Channel(ChannelError::Full { .. })) => {}
+                Err(_) => self.remove_neighbor(node_id),
+            }
         }
 
+        if let Some(req) = pending_request {
+            let _ = self.pending_ser_requests.insert(req);
+        }
         Ok(())
     }
 
-    /// Sends a message by fragmenting it into 128-byte chunks and sending each chunk as a separate packet.
+    /// Tries to remove the neighbor from the neighbors map and network view
+    pub fn remove_neighbor(&mut self, node_id: NodeId) {
+        #[allow(clippy::let_unit_value)]
+        let _ = self.neighbors.remove(&node_id);
+        let _ = self.control_neighbors.remove(&node_id);
+        self.backlogs.retain(|(neighbor, _), _| *neighbor != node_id);
+        self.forget_node(node_id);
+    }
+
+    /// Removes `node_id` from the network view and drops the routes through it, keeping
+    /// the channel to it if it is a neighbor.
+    pub fn forget_node(&mut self, node_id: NodeId) {
+        self.tracking_view(|handler| handler.remove_view_node(node_id));
+        let _ = self.node_removed_at.insert(node_id, self.flood_counter);
+        let _ = self.node_confirmed_by.remove(&node_id);
+        self.edge_confirmed_by
+            .retain(|(a, b), _| *a != node_id && *b != node_id);
+        if let Some(backups) = &mut self.backup_routes {
+            backups.retain(|_, shr| !shr.hops.contains(&node_id));
+        }
+        self.invalidate_cached_routes(|_, shr| shr.hops.contains(&node_id));
+        self.detect_partition();
+    }
+
+    /// Compares the servers reachable in the network view with the ones reachable at the
+    /// previous check, notifying the controller with a `NodeEvent::PartitionDetected` of
+    /// the ones lost. Called by [`Self::tick`] and whenever a node is forgotten.
+    fn detect_partition(&mut self) {
+        let servers = self.network_view.get_servers().unwrap_or_default();
+        let reachable = self
+            .network_view
+            .reachable_nodes()
+            .into_iter()
+            .filter(|node_id| servers.contains(node_id))
+            .collect::<HashSet<_>>();
+        let mut unreachable = self
+            .reachable_servers
+            .difference(&reachable)
+            .copied()
+            .collect::<Vec<_>>();
+        self.reachable_servers = reachable;
+        if !unreachable.is_empty() {
+            unreachable.sort_unstable();
+            self.emit(NodeEvent::PartitionDetected {
+                notification_from: self.id,
+                unreachable,
+            });
+        }
+    }
+
+    /// Adds a new neighbor to the neighbors map and updates the network view
+    pub fn add_neighbor(&mut self, node_id: NodeId, sender: Sender<Packet>) {
+        let _ = self.neighbors.insert(node_id, sender);
+        let _ = self.network_view.update_node(self.id, vec![node_id]);
+        self.invalidate_routes();
+    }
+
+    /// Registers a channel dedicated to control traffic (Ack, Nack and flood packets)
+    /// towards an existing neighbor, so that it is not delayed behind data fragments.
+    /// Neighbors without one keep receiving control packets on their data channel.
+    pub fn add_control_neighbor(&mut self, node_id: NodeId, sender: Sender<Packet>) {
+        let _ = self.control_neighbors.insert(node_id, sender);
+    }
+
+    /// Returns the channel reaching `neighbor` for a control or data packet,
+    /// control packets prefer the dedicated control channel when there is one.
+    fn neighbor_sender(&self, neighbor: NodeId, control: bool) -> Option<&Sender<Packet>> {
+        control
+            .then(|| self.control_neighbors.get(&neighbor))
+            .flatten()
+            .or_else(|| self.neighbors.get(&neighbor))
+    }
+
+    /// Returns the neighbors in ascending order, so that floods fan out the same way on
+    /// every run.
+    fn sorted_neighbors(&self) -> Vec<NodeId> {
+        let mut neighbors = self.neighbors.keys().copied().collect::<Vec<_>>();
+        neighbors.sort_unstable();
+        neighbors
+    }
+
+    /// Handle `flood_response`
+    /// Responses to older floods are merged into the view according to the
+    /// [`FloodMergePolicy`], only the latest flood releases the pending requests.
+    /// Merged responses set the type of the nodes they mention and record the flood
+    /// confirming each node and link, see [`Self::node_age`] and [`Self::edge_age`].
     /// # Errors
-    /// Returns an error if the destination path cannot be found or if sending fails.
-    pub fn send_message(
+    /// Returns error if can't send the packet
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(
+            level = "trace",
+            skip_all,
+            fields(node = self.id, flood_id = flood_response.flood_id)
+        )
+    )]
+    pub fn handle_flood_response(
         &mut self,
-        message: &[u8],
-        dest: Option<NodeId>,
-        sid: Option<u64>,
+        flood_response: &FloodResponse,
     ) -> Result<(), NetworkError> {
-        // Split into 128-byte chunks
-        let chunks = message.chunks(128);
-        let total_n_fragments = chunks.len() as u64;
+        if flood_response.flood_id < self.flood_counter {
+            if self.is_mergeable_stale_response(flood_response) {
+                self.merge_flood_response(flood_response);
+            }
+            return Ok(());
+        }
 
-        // Decide session id
-        let session_id: u64;
-        if let Some(id) = sid {
-            session_id = id;
-        } else {
-            self.update_session_id();
-            session_id = self.session_id;
+        if flood_response.flood_id == self.flood_counter {
+            self.last_flood_response = self.clock.now();
+            self.merge_flood_response(flood_response);
+            self.annotate_path_quality(&flood_response.path_trace);
+            let requests = self.pending_ser_requests.drain().collect::<Vec<_>>();
+            for req in requests {
+                let _ = self.send_tracked(&req.data, req.to, req.session_id)?;
+            }
+            let mut packets = self.packets_to_send.drain(..).collect::<Vec<_>>();
+            packets.sort_by_key(|packet| self.send_priority(packet));
+            for packet in packets {
+                let packet = self.refresh_route(packet);
+                self.try_send(packet)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the route of `packet` with a fresh one if it goes through a node that
+    /// left the network view since, e.g. a drone reported crashed by an `ErrorInRouting`.
+    fn refresh_route(&mut self, mut packet: Packet) -> Packet {
+        let known = |hop: &NodeId| self.network_view.nodes.iter().any(|n| n.get_id() == *hop);
+        let stale = !packet.routing_header.hops.iter().all(known);
+        if let Some(destination) = packet.routing_header.destination().filter(|_| stale) {
+            if let Ok(shr) = self.try_find_path(destination) {
+                packet.routing_header = shr;
+            }
+        }
+        packet
+    }
+
+    fn is_mergeable_stale_response(&self, flood_response: &FloodResponse) -> bool {
+        let FloodMergePolicy::MergeStale { max_age } = self.flood_merge_policy else {
+            return false;
+        };
+        if self.flood_counter - flood_response.flood_id > max_age {
+            return false;
+        }
+        // the trace must describe a path starting from this node and must not
+        // resurrect nodes known to have disappeared after that flood started
+        flood_response.path_trace.first().map(|(id, _)| *id) == Some(self.id)
+            && flood_response.path_trace.iter().all(|(id, _)| {
+                self.node_removed_at
+                    .get(id)
+                    .is_none_or(|removed_at| *removed_at < flood_response.flood_id)
+            })
+    }
+
+    /// Records the hop count and response latency of every node of a response to the
+    /// latest flood, so that path selection can prefer the faster parts of the network.
+    fn annotate_path_quality(&mut self, path_trace: &[(NodeId, NodeType)]) {
+        let latency = self.clock.now().duration_since(self.last_flood);
+        for (hops, (node_id, _)) in path_trace.iter().enumerate().skip(1) {
+            self.network_view.annotate(*node_id, hops, latency);
         }
+    }
+
+    fn merge_flood_response(&mut self, flood_response: &FloodResponse) {
+        self.update_network_view(&flood_response.path_trace);
+        for (node_id, _) in &flood_response.path_trace {
+            let confirmed_by = self.node_confirmed_by.entry(*node_id).or_default();
+            *confirmed_by = (*confirmed_by).max(flood_response.flood_id);
+        }
+        for pair in flood_response.path_trace.windows(2) {
+            let edge = edge_key(pair[0].0, pair[1].0);
+            let confirmed_by = self.edge_confirmed_by.entry(edge).or_default();
+            *confirmed_by = (*confirmed_by).max(flood_response.flood_id);
+        }
+    }
+
+    /// Runs `change`, then notifies the controller of what it changed in the network
+    /// view with a `NodeEvent::TopologyChanged`, unless filtered out. The changes are
+    /// recorded as they are made, in `record_path_trace` and the `*_view_*` helpers.
+    fn tracking_view<T>(&mut self, change: impl FnOnce(&mut Self) -> T) -> T {
+        let result = change(self);
+        let mut diff = std::mem::take(&mut self.view_changes);
+        if self.event_filter > Severity::Info {
+            return result;
+        }
+        for nodes in [&mut diff.missing_nodes, &mut diff.extra_nodes] {
+            nodes.sort_unstable();
+            nodes.dedup();
+        }
+        for edges in [&mut diff.missing_edges, &mut diff.extra_edges] {
+            edges.sort_unstable();
+            edges.dedup();
+        }
+        diff.wrong_types.sort_unstable_by_key(|(node, _, _)| *node);
+        if !diff.is_empty() {
+            self.emit(NodeEvent::TopologyChanged(diff));
+        }
+        result
+    }
+
+    /// Returns whether either end of the link between `a` and `b` lists it.
+    fn view_has_link(&self, a: NodeId, b: NodeId) -> bool {
+        self.network_view.nodes.iter().any(|node| {
+            node.id == a && node.get_adjacents().contains(&b)
+                || node.id == b && node.get_adjacents().contains(&a)
+        })
+    }
+
+    fn remove_view_node(&mut self, node_id: NodeId) {
+        let mut lost = self
+            .network_view
+            .nodes
+            .iter()
+            .filter(|node| node.get_adjacents().contains(&node_id))
+            .map(|node| edge_key(node.id, node_id))
+            .collect::<Vec<_>>();
+        if let Some(node) = self.network_view.nodes.iter().find(|n| n.id == node_id) {
+            for adj in node.get_adjacents() {
+                lost.push(edge_key(node_id, *adj));
+            }
+            self.view_changes.missing_nodes.push(node_id);
+        }
+        self.view_changes.missing_edges.extend(lost);
+        self.network_view.remove_node(node_id);
+    }
+
+    fn remove_view_edge(&mut self, a: NodeId, b: NodeId) {
+        if self.network_view.remove_edge(a, b) {
+            self.view_changes.missing_edges.push(edge_key(a, b));
+        }
+    }
+
+    fn change_view_type(&mut self, node_id: NodeId, node_type: NodeType) {
+        let Some(node) = self.network_view.nodes.iter().find(|n| n.id == node_id) else {
+            return;
+        };
+        if node.get_node_type() != node_type {
+            let change = (node_id, node.get_node_type(), node_type);
+            self.view_changes.wrong_types.push(change);
+            self.network_view.change_node_type(node_id, node_type);
+        }
+    }
+
+    fn update_network_view(&mut self, path_trace: &[(NodeId, NodeType)]) {
+        // new links may shorten the cached routes
+        self.invalidate_routes();
+        self.tracking_view(|handler| handler.record_path_trace(path_trace));
+    }
+
+    fn record_path_trace(&mut self, path_trace: &[(NodeId, NodeType)]) {
+        for pair in path_trace.windows(2) {
+            let (a, b) = (pair[0].0, pair[1].0);
+            if !self.view_has_link(a, b) {
+                self.view_changes.extra_edges.push(edge_key(a, b));
+            }
+        }
+        for (i, &(node_id, node_type)) in path_trace.iter().enumerate() {
+            let mut neighbors = Vec::with_capacity(2);
+
+            // Add previous node as neighbor
+            if i > 0 {
+                neighbors.push(path_trace[i - 1].0);
+            }
+
+            // Add next node as neighbor
+            if i + 1 < path_trace.len() {
+                neighbors.push(path_trace[i + 1].0);
+            }
+
+            // Update existing node or add new one, its type as the node reported it
+            if self.network_view.nodes.iter().any(|n| n.id == node_id) {
+                self.change_view_type(node_id, node_type);
+                let _ = self.network_view.update_node(node_id, neighbors);
+            } else {
+                self.view_changes.extra_nodes.push(node_id);
+                let new_node = Node::new(node_id, node_type, neighbors);
+                self.network_view.add_node(new_node);
+            }
+        }
+    }
+
+    /// Handles a flood request by checking if the flood has been seen before.
+    /// If it has not been seen, it generates a flood response and sends it to the neighbors.
+    /// If it has been seen, it forwards the flood request to the neighbors except for the previous hop.
+    /// # Errors
+    /// Returns an error if sending the packet fails or if the flood request is malformed.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(
+            level = "trace",
+            skip_all,
+            fields(
+                node = self.id,
+                session_id = session_id,
+                flood_id = flood_request.flood_id,
+                initiator = flood_request.initiator_id
+            )
+        )
+    )]
+    pub fn handle_flood_request(
+        &mut self,
+        mut flood_request: FloodRequest,
+        session_id: u64,
+    ) -> Result<(), NetworkError> {
+        let prev_hop = flood_request
+            .path_trace
+            .last()
+            .map_or(flood_request.initiator_id, |x| x.0);
+
+        let looped = flood_request.initiator_id == self.id
+            || flood_request.path_trace.iter().any(|(id, _)| *id == self.id);
+        flood_request.path_trace.push((self.id, self.node_type));
+
+        let scope = match self.flood_suppression {
+            FloodSuppression::Exact => Some(None),
+            FloodSuppression::PerNeighbor => Some(Some(prev_hop)),
+            FloodSuppression::Off => None,
+        };
+        let seen = match scope {
+            Some(prev_hop) => self.remember_flood(
+                (flood_request.flood_id, flood_request.initiator_id, prev_hop),
+                self.clock.now(),
+            ),
+            None => looped,
+        };
+        if seen {
+            self.floods_suppressed += 1;
+        }
+
+        self.update_network_view(&flood_request.path_trace);
+
+        if seen || self.neighbors.len() == 1 {
+            // generate flood response, back the way the request came if the search runs
+            // out of budget
+            let initiator = flood_request.initiator_id;
+            let path = self.network_view.find_path_within(self.id, initiator, self.search_budget);
+            let route = if let Ok(Some(path)) = path {
+                SourceRoutingHeader::new(path.into_vec(), 1)
+            } else {
+                let mut route: Vec<_> = flood_request
+                    .path_trace
+                    .iter()
+                    .map(|(id, _)| *id)
+                    .rev()
+                    .collect::<Vec<_>>();
+
+                if route.last() != Some(&flood_request.initiator_id) {
+                    route.push(flood_request.initiator_id);
+                }
+
+                SourceRoutingHeader::new(route, 1)
+            };
+
+            let flood_response = FloodResponse {
+                flood_id: flood_request.flood_id,
+                path_trace: flood_request.path_trace,
+            };
+
+            let packet = Packet::new_flood_response(route, session_id, flood_response);
+
+            self.try_send(packet)?;
+
+            return Ok(());
+        }
+
+        let srh = SourceRoutingHeader::new(vec![], 0);
+
+        let new_flood_request = Packet::new_flood_request(srh, session_id, flood_request);
+
+        for neighbor_id in self.sorted_neighbors() {
+            if neighbor_id != prev_hop {
+                match self.push(neighbor_id, true, new_flood_request.clone()) {
+                    Ok(_) | Err(NetworkError::Channel(ChannelError::Full { .. })) => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a NACK packet by removing the neighbor if the NACK indicates an error in routing,
+    /// starting a flood to find a new route, and retrying to send the packet if it exists in the buffer.
+    /// Once a fragment exceeds the retries allowed by the [`RetryPolicy`] its whole session is dropped.
+    /// # Errors
+    /// Returns an error if sending the packet fails or if the packet is not found in the buffer.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                node = self.id,
+                session_id = session_id,
+                fragment_index = nack.fragment_index,
+                from = source_id,
+                nack_type = ?nack.nack_type
+            )
+        )
+    )]
+    pub fn handle_nack(
+        &mut self,
+        nack: &Nack,
+        session_id: u64,
+        source_id: NodeId,
+    ) -> Result<(), NetworkError> {
+        self.emit(NodeEvent::NackReceived {
+            notification_from: self.id,
+            from: source_id,
+            session_id,
+            nack_type: nack.nack_type,
+        });
+        let nacked = self
+            .buffer
+            .routed_through(session_id, nack.fragment_index, source_id);
+        match nack.nack_type {
+            NackType::ErrorInRouting(id) => {
+                // `id` is usually several hops away: only drop the channel if it is ours
+                let _ = self.pinned_routes.remove(&session_id);
+                if self.neighbors.contains_key(&id) {
+                    self.remove_neighbor(id);
+                } else {
+                    self.forget_node(id);
+                }
+                self.start_flood(None)?;
+            }
+
+            NackType::Dropped => {
+                self.fragments_dropped += 1;
+                if let Some(destination) = nacked {
+                    self.congestion.entry(destination).or_default().on_loss();
+                }
+                let fragment = nacked.and_then(|destination| {
+                    self.buffer
+                        .unacked(session_id, destination, nack.fragment_index)
+                });
+                if let Some(fragment) = fragment {
+                    self.network_view.edge_stats_mut().record_drop(
+                        &fragment.packet().routing_header.hops,
+                        source_id,
+                        self.clock.now(),
+                    );
+                }
+                // routes through the drone are weighed again with its new drop estimate
+                if self.blacklist.record_drop(source_id, self.clock.now()) {
+                    self.avoid_node(source_id);
+                } else {
+                    self.invalidate_cached_routes(|_, shr| shr.hops.contains(&source_id));
+                }
+            }
+
+            NackType::DestinationIsDrone => {
+                self.tracking_view(|handler| handler.change_view_type(source_id, NodeType::Drone));
+                self.invalidate_cached_routes(|destination, _| destination == source_id);
+            }
+
+            NackType::UnexpectedRecipient(id) => {
+                let _ = self.pinned_routes.remove(&session_id);
+                if let Some(destination) = nacked {
+                    self.forget_misdelivery(session_id, destination, nack.fragment_index, id);
+                }
+            }
+        }
+        let _ = self.fragment_trace.record(
+            session_id,
+            nack.fragment_index,
+            FragmentFate::Nacked(nack.nack_type),
+        );
+        let Some(destination) = nacked else {
+            return Ok(());
+        };
+
+        let fragment_index = nack.fragment_index;
+        let routed = match nack.nack_type {
+            NackType::ErrorInRouting(id) => {
+                self.switch_route(session_id, destination, fragment_index, id);
+                self.route_around(session_id, destination, fragment_index, id)
+            }
+            NackType::Dropped => {
+                self.switch_route(session_id, destination, fragment_index, source_id);
+                true
+            }
+            _ => true,
+        };
+
+        let retries = self
+            .buffer
+            .mark_failed(session_id, destination, fragment_index);
+        let exhausted = |max: &u32| retries.is_some_and(|retries| retries > *max);
+        if let Some(max) = self.retry_policy.max_retries.filter(exhausted) {
+            // the message can't be delivered anymore, give up the whole session
+            let reason = format!("fragment {} still lost after {max} retries", nack.fragment_index);
+            self.settle_session(session_id, SessionStatus::Failed(reason.clone()));
+            self.emit(NodeEvent::SendFailed {
+                notification_from: self.id,
+                session_id,
+                reason,
+            });
+            let _ = self.drop_session(session_id);
+            return Ok(());
+        }
+
+        if routed {
+            self.resend(session_id, destination, fragment_index)?;
+        } else if let Some(fragment) = self.buffer.unacked(session_id, destination, fragment_index)
+        {
+            // sent again once the flood started above finds a way around
+            self.packets_to_send.push(fragment.packet().clone());
+        }
+
+        Ok(())
+    }
+
+    /// Rewrites the route of fragment `fragment_index` of the session if it still goes
+    /// through `failed`, with a route searched in the view `failed` was removed from.
+    /// Returns `false` if no such route is known yet.
+    fn route_around(
+        &mut self,
+        session_id: u64,
+        destination: NodeId,
+        fragment_index: u64,
+        failed: NodeId,
+    ) -> bool {
+        let through_failed = self
+            .buffer
+            .fragment(session_id, destination, fragment_index)
+            .is_some_and(|fragment| fragment.packet().routing_header.hops.contains(&failed));
+        if !through_failed {
+            return true;
+        }
+        let Ok(shr) = self.try_find_path(destination) else {
+            return false;
+        };
+        self.buffer
+            .reroute(session_id, destination, fragment_index, shr);
+        let _ = self
+            .fragment_trace
+            .record(session_id, fragment_index, FragmentFate::Rerouted);
+        true
+    }
+
+    /// Send a packet to the first hop in its route
+    /// # Errors
+    /// Returns an error if send fails
+    fn send_packet_to_first_hop(&mut self, packet: &Packet) -> Result<(), NetworkError> {
+        if packet.routing_header.hops.len() > 1 {
+            let first_hop = packet.routing_header.hops[1];
+            if self.neighbors.contains_key(&first_hop) {
+                self.send(first_hop, packet)?;
+            } else {
+                return Err(RoutingError::NodeIsNotANeighbor { node: first_hop }.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the cached route towards `destination` while its first hop is still a
+    /// neighbor, else searches the network view and caches the route found.
+    fn try_find_path(&mut self, destination: NodeId) -> Result<SourceRoutingHeader, RoutingError> {
+        if destination == self.id {
+            return Ok(SourceRoutingHeader::empty_route());
+        }
+        let cacheable = self.route_policy.as_ref().is_none_or(|policy| policy.cacheable());
+        if let Some(shr) = self.route_cache.get(&destination).filter(|_| cacheable) {
+            if shr.hops.get(1).is_some_and(|hop| self.neighbors.contains_key(hop)) {
+                self.route_cache_stats.hits += 1;
+                return Ok(shr.clone());
+            }
+            let _ = self.route_cache.remove(&destination);
+            self.route_cache_stats.invalidations += 1;
+        }
+        self.route_cache_stats.misses += 1;
+        let shr = self.search_path(destination)?;
+        if cacheable {
+            let _ = self.route_cache.insert(destination, shr.clone());
+        }
+        Ok(shr)
+    }
+
+    fn search_path(&mut self, destination: NodeId) -> Result<SourceRoutingHeader, RoutingError> {
+        // blacklisted nodes are searched around, unless they are an end of the route
+        let now = self.clock.now();
+        let avoided = self
+            .blacklist
+            .entries(now)
+            .into_iter()
+            .map(|(node, _)| node)
+            .filter(|node| *node != self.id && *node != destination)
+            .collect::<Vec<_>>();
+        let pruned;
+        let view = if avoided.is_empty() {
+            &self.network_view
+        } else {
+            pruned = avoided.iter().fold(self.network_view.clone(), |mut view, node| {
+                view.remove_node(*node);
+                view
+            });
+            &pruned
+        };
+        let found = match &self.route_policy {
+            Some(policy) => policy
+                .select_route(view, destination, self.clock.now())
+                .map(Route::from_vec),
+            None => view.find_path_within(self.id, destination, self.search_budget)?,
+        };
+        if let Some(path) = found {
+            if let Some(backups) = &mut self.backup_routes {
+                let budget = self.search_budget;
+                match view.find_disjoint_path(self.id, destination, &path, budget) {
+                    Some(backup) => {
+                        let backup = SourceRoutingHeader::new(backup.into_vec(), 1).without_loops();
+                        let _ = backups.insert(destination, backup);
+                    }
+                    None => {
+                        let _ = backups.remove(&destination);
+                    }
+                }
+            }
+            return Ok(SourceRoutingHeader::new(path.into_vec(), 1).without_loops());
+        }
+        Err(RoutingError::PathNotFound { destination })
+    }
+
+    /// Keeps routes away from `node` for `duration`, e.g. a drone known to misbehave:
+    /// the cached and backup routes through it are dropped and new routes search
+    /// around it, unless it is their destination. Blacklisting a node again extends
+    /// its entry only.
+    pub fn blacklist_node(&mut self, node: NodeId, duration: Duration) {
+        if self.blacklist.insert(node, duration, self.clock.now()) {
+            self.avoid_node(node);
+        }
+    }
+
+    /// Lifts the blacklisting of `node`, returns whether it was blacklisted.
+    pub fn unblacklist_node(&mut self, node: NodeId) -> bool {
+        self.blacklist.remove(node)
+    }
+
+    /// Returns the blacklisted nodes with the time left, sorted by id.
+    #[must_use]
+    pub fn blacklist(&self) -> Vec<(NodeId, Duration)> {
+        self.blacklist.entries(self.clock.now())
+    }
+
+    /// Blacklists the drones answering more than `max_drops` fragments with a `Dropped`
+    /// Nack within `window`, for `duration`. Disabled by default.
+    pub fn set_auto_blacklist(&mut self, auto: Option<AutoBlacklist>) {
+        self.blacklist.set_auto(auto);
+    }
+
+    /// Drops the cached and backup routes going through `node`.
+    fn avoid_node(&mut self, node: NodeId) {
+        if let Some(backups) = &mut self.backup_routes {
+            backups.retain(|_, shr| !shr.hops.contains(&node));
+        }
+        self.invalidate_cached_routes(|_, shr| shr.hops.contains(&node));
+    }
+
+    /// Drops the cached routes `stale` holds for, given their destination.
+    fn invalidate_cached_routes(&mut self, stale: impl Fn(NodeId, &SourceRoutingHeader) -> bool) {
+        let cached = self.route_cache.len();
+        self.route_cache
+            .retain(|destination, shr| !stale(*destination, shr));
+        self.route_cache_stats.invalidations += (cached - self.route_cache.len()) as u64;
+    }
+
+    /// Drops what the route of the fragment `fragment_index` of the session got wrong,
+    /// once `recipient` reported receiving it unexpectedly. When `recipient` is on the
+    /// route it doesn't relay, and its link to the hop expected next is forgotten;
+    /// otherwise it received the fragment in place of a hop, and the route is dropped.
+    fn forget_misdelivery(
+        &mut self,
+        session_id: u64,
+        destination: NodeId,
+        fragment_index: u64,
+        recipient: NodeId,
+    ) {
+        let Some(route) = self
+            .buffer
+            .unacked(session_id, destination, fragment_index)
+            .map(|fragment| fragment.packet().routing_header.hops.clone())
+        else {
+            return;
+        };
+        let expected = route
+            .iter()
+            .position(|hop| *hop == recipient)
+            .and_then(|at| route.get(at + 1))
+            .copied();
+        let Some(expected) = expected else {
+            self.invalidate_cached_routes(|_, shr| shr.hops == route);
+            return;
+        };
+        let _ = self
+            .edge_confirmed_by
+            .remove(&edge_key(recipient, expected));
+        self.tracking_view(|handler| handler.remove_view_edge(recipient, expected));
+        self.invalidate_cached_routes(|_, shr| {
+            shr.hops
+                .windows(2)
+                .any(|pair| edge_key(pair[0], pair[1]) == edge_key(recipient, expected))
+        });
+    }
+
+    /// Drops every cached route, the next message to each destination searches the
+    /// network view again. The cache is also invalidated by the topology changes
+    /// learnt from floods, Nacks and neighbor updates.
+    pub fn invalidate_routes(&mut self) {
+        self.route_cache_stats.invalidations += self.route_cache.len() as u64;
+        self.route_cache.clear();
+    }
+
+    #[must_use]
+    pub fn route_cache_stats(&self) -> RouteCacheStats {
+        RouteCacheStats {
+            cached: self.route_cache.len(),
+            ..self.route_cache_stats
+        }
+    }
+
+    /// Takes the backup route towards `destination`, if its first hop is still a neighbor.
+    fn take_backup_route(&mut self, destination: NodeId) -> Option<SourceRoutingHeader> {
+        let shr = self.backup_routes.as_mut()?.remove(&destination)?;
+        let first_hop = shr.hops.get(1)?;
+        self.neighbors.contains_key(first_hop).then_some(shr)
+    }
+
+    /// Tries to send a packet to next hop until it succeeds or there are no more neighbors.
+    /// If sending fails, it removes the neighbor, finds a new route and tries again.
+    /// A full channel is not a failure, the packet waits in the backlog of the neighbor.
+    /// # Errors
+    /// Returns an error if the packet has no destination, if there are no neighbors, or if sending fails.
+    /// `SendError` if `send_packet_to_first_hop()` can't send the packet
+    /// `NoDestination` if the route is empty
+    /// `NoNeighborAssigned` if there are no more neighbors
+    /// `Full` if the backlog of the first hop is full too
+    fn try_send(&mut self, mut packet: Packet) -> Result<(), NetworkError> {
+        // A packet must have a destination
+        let destination = packet
+            .routing_header
+            .destination()
+            .ok_or(RoutingError::NoDestination)?;
+
+        let mut packet_sent = false;
+        while !packet_sent && !self.neighbors.is_empty() {
+            match self.send_packet_to_first_hop(&packet) {
+                Ok(()) => {
+                    packet_sent = true;
+                }
+                Err(
+                    NetworkError::Channel(ChannelError::Send(_))
+                    | NetworkError::Routing(RoutingError::NodeIsNotANeighbor { .. }),
+                ) => {
+                    // If the first hop is not a neighbor, remove it and try again
+                    if let Some(first_hop) = packet.routing_header.hops.get(1) {
+                        self.remove_neighbor(*first_hop);
+                        // switch to the backup route if any, else search a new one
+                        let route = match self.take_backup_route(destination) {
+                            Some(shr) => Ok(shr),
+                            None => self.try_find_path(destination),
+                        };
+                        match route {
+                            Ok(shr) => {
+                                // the pinned route failed, fall back to computed routes
+                                let _ = self.pinned_routes.remove(&packet.session_id);
+                                packet.routing_header = shr;
+                                if let PacketType::MsgFragment(fragment) = &packet.pack_type {
+                                    let _ = self.fragment_trace.record(
+                                        packet.session_id,
+                                        fragment.fragment_index,
+                                        FragmentFate::Rerouted,
+                                    );
+                                }
+                            }
+                            Err(RoutingError::PathNotFound { .. }) => {
+                                self.start_flood(None)?;
+                                self.packets_to_send.push(packet.clone());
+                            }
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                }
+
+                Err(e) => return Err(e),
+            }
+        }
+
+        if self.neighbors.is_empty() {
+            return Err(TopologyError::NoNeighborAssigned.into());
+        }
+
+        Ok(())
+    }
+
+    /// Sends a message by fragmenting it into chunks of [`Self::peer_fragment_size`] of the
+    /// destination and sending each chunk as a separate packet.
+    /// Returns a [`SessionHandle`] tracking the delivery of the message; if the destination
+    /// is unknown the handle stays pending until a flood finds it.
+    /// # Errors
+    /// `ShuttingDown` once [`Self::begin_shutdown`] was called,
+    /// or an error if the destination path cannot be found or if sending fails.
+    pub fn send_message(
+        &mut self,
+        message: &[u8],
+        dest: Option<NodeId>,
+        sid: Option<u64>,
+    ) -> Result<SessionHandle, NetworkError> {
+        if self.is_shutting_down() {
+            return Err(RoutingError::ShuttingDown.into());
+        }
+        self.send_tracked(message, dest, sid)
+    }
+
+    /// Sends a message as [`Self::send_message`] does, also while shutting down: the
+    /// requests that waited for a flood were accepted before.
+    fn send_tracked(
+        &mut self,
+        message: &[u8],
+        dest: Option<NodeId>,
+        sid: Option<u64>,
+    ) -> Result<SessionHandle, NetworkError> {
+        // Decide session id
+        let session_id: u64;
+        if let Some(id) = sid {
+            session_id = id;
+        } else {
+            self.update_session_id(dest);
+            session_id = self.session_id;
+        }
+
+        let handle = self
+            .session_handles
+            .entry(session_id)
+            .or_insert_with(|| SessionHandle::new(session_id))
+            .clone();
+        if let Some(destination) = dest
+            && self.message_queue.must_wait(destination, session_id)
+        {
+            let data = message.to_vec();
+            self.message_queue.push(destination, QueuedMessage { session_id, data });
+            return Ok(handle);
+        }
+        if let Err(e) = self.send_session(message, dest, session_id) {
+            self.settle_session(session_id, SessionStatus::Failed(e.to_string()));
+            let e = e.in_session(session_id);
+            return Err(match dest {
+                Some(destination) => e.towards(destination),
+                None => e,
+            });
+        }
+        Ok(handle)
+    }
+
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(
+                node = self.id,
+                session_id = session_id,
+                destination = ?dest,
+                bytes = message.len()
+            )
+        )
+    )]
+    fn send_session(
+        &mut self,
+        message: &[u8],
+        dest: Option<NodeId>,
+        session_id: u64,
+    ) -> Result<(), NetworkError> {
+        if let Some(destination) = dest {
+            // Try to send directly
+            match self.try_find_path(destination) {
+                Ok(shr) => return self.send_fragments(message, shr, session_id, destination),
+                Err(e @ RoutingError::SearchBudgetExceeded { .. }) => return Err(e.into()),
+                Err(_) => {}
+            }
+
+            // Path not found, try flooding passing the pending request
+            self.start_flood(Some(SerializedRequest {
+                to: Some(destination),
+                data: message.to_vec(),
+                session_id: Some(session_id),
+            }))?;
+            
+            
+
+            return Ok(());
+        }
+
+        // No explicit destination
+        if let Some(servers) = self.get_servers() {
+            for server in servers {
+                self.send_session(message, Some(server), session_id)?;
+            }
+            return Ok(());
+        }
+
+        // Fallback: flooding
+        self.start_flood(Some(SerializedRequest {
+            to: None,
+            data: message.to_vec(),
+            session_id: Some(session_id),
+        }))
+    }
+
+    /// Resolves the handle of `session_id`, if `send_message` gave one, and records a
+    /// failure in the broadcast it belongs to.
+    fn settle_session(&mut self, session_id: u64, status: SessionStatus) {
+        if matches!(status, SessionStatus::Failed(_)) {
+            self.settle_broadcast_session(session_id, false);
+        }
+        if let Some(handle) = self.session_handles.remove(&session_id) {
+            handle.settle(status);
+        }
+        // the message queued behind the session may go now, failures settle it in turn
+        let Some((destination, next)) = self.message_queue.finish(session_id) else {
+            return;
+        };
+        if self.is_shutting_down() {
+            // nothing new starts during a shutdown, the queued messages fail one by one
+            let reason = "node shut down".to_string();
+            self.settle_session(next.session_id, SessionStatus::Failed(reason));
+        } else {
+            let _ = self.send_tracked(&next.data, Some(destination), Some(next.session_id));
+        }
+    }
+
+    /// Delivers the messages of [`Self::send_message`] to each destination in order:
+    /// a message waits until the previous one to its destination is delivered or
+    /// given up, retransmissions included. Messages without a destination, broadcast or
+    /// sent along a route are never queued. Disabled by default; once disabled, the
+    /// messages already queued still wait for their turn.
+    pub fn set_ordered_sends(&mut self, ordered: bool) {
+        self.message_queue.set_ordered(ordered);
+    }
+
+    #[must_use]
+    pub fn ordered_sends(&self) -> bool {
+        self.message_queue.is_ordered()
+    }
+
+    /// Returns the messages waiting for their turn with ordered sends.
+    #[must_use]
+    pub fn message_queue(&self) -> &MessageQueue {
+        &self.message_queue
+    }
+
+    /// Returns how many messages to `destination` wait for their turn.
+    #[must_use]
+    pub fn queued_messages(&self, destination: NodeId) -> usize {
+        self.message_queue.depth(destination)
+    }
+
+    /// Cancels the message of `session_id` waiting for its turn, failing its handle.
+    /// Returns whether it was waiting: sessions in flight are given up with
+    /// [`Self::drop_session`] instead.
+    pub fn cancel_queued(&mut self, session_id: u64) -> bool {
+        if !self.message_queue.cancel(session_id) {
+            return false;
+        }
+        self.settle_session(session_id, SessionStatus::Failed("cancelled".to_string()));
+        true
+    }
+
+    /// Sends a message along `route`, pinning it for the new session: retransmissions
+    /// keep using the same route, which is only recomputed once it fails
+    /// (unreachable first hop or `ErrorInRouting`). Returns the session id.
+    /// # Errors
+    /// `NoDestination` if the route does not start at this node or has no other hop,
+    /// `NodeIsNotANeighbor` if its first hop is not a neighbor,
+    /// `ShuttingDown` once [`Self::begin_shutdown`] was called,
+    /// or any error returned while sending the fragments.
+    pub fn send_message_via(
+        &mut self,
+        route: Vec<NodeId>,
+        message: &[u8],
+    ) -> Result<u64, NetworkError> {
+        if self.is_shutting_down() {
+            return Err(RoutingError::ShuttingDown.into());
+        }
+        if route.len() < 2 || route[0] != self.id {
+            return Err(RoutingError::NoDestination.into());
+        }
+        if !self.neighbors.contains_key(&route[1]) {
+            return Err(RoutingError::NodeIsNotANeighbor { node: route[1] }.into());
+        }
+        let shr = SourceRoutingHeader::new(route, 1);
+        let destination = shr.destination().ok_or(RoutingError::NoDestination)?;
+
+        self.update_session_id(Some(destination));
+        let session_id = self.session_id;
+        let _ = self.pinned_routes.insert(session_id, shr.clone());
+        self.send_fragments(message, shr, session_id, destination)
+            .map_err(|e| e.in_session(session_id).towards(destination))?;
+        Ok(session_id)
+    }
+
+    /// Returns the route pinned for `session_id`, if it has neither failed nor been
+    /// fully acknowledged yet.
+    #[must_use]
+    pub fn pinned_route(&self, session_id: u64) -> Option<&[NodeId]> {
+        self.pinned_routes
+            .get(&session_id)
+            .map(|shr| shr.hops.as_slice())
+    }
+
+    /// Releases the route pinned for `session_id`, returns whether one was pinned.
+    pub fn unpin_route(&mut self, session_id: u64) -> bool {
+        self.pinned_routes.remove(&session_id).is_some()
+    }
+
+    /// Sends `message` to each of `destinations` in its own session, so that each is
+    /// acknowledged and retransmitted on its own; without transforms the message is
+    /// fragmented once for all of them. Destinations without a known route are sent the
+    /// message once a single flood finds them, like with [`Self::send_message`].
+    ///
+    /// The controller is told the session of each destination with a
+    /// `NodeEvent::BroadcastStarted`; each session then reports its delivery with a
+    /// `NodeEvent::MessageFullyAcked` or a `NodeEvent::SendFailed`, and the
+    /// `NodeEvent::BroadcastCompleted` sums them up once all are settled, the sessions of
+    /// the destinations the flood searches included.
+    /// Returns the id of the broadcast.
+    /// # Errors
+    /// `ShuttingDown` once [`Self::begin_shutdown`] was called,
+    /// or an error if the flood searching the unknown destinations can't start.
+    pub fn broadcast_message(
+        &mut self,
+        message: &[u8],
+        destinations: &[NodeId],
+    ) -> Result<u64, NetworkError> {
+        if self.is_shutting_down() {
+            return Err(RoutingError::ShuttingDown.into());
+        }
+        self.broadcast_counter += 1;
+        let broadcast_id = self.broadcast_counter;
+        // without transforms destinations sharing a fragment size share the fragments
+        let mut shared = HashMap::<usize, Vec<Fragment>>::new();
+        let mut broadcast = Broadcast::default();
+        let mut sessions = Vec::new();
+        let mut unreachable = Vec::new();
+        let mut seen = HashSet::new();
+        for &destination in destinations.iter().filter(|d| seen.insert(**d)) {
+            let shr = match self.try_find_path(destination) {
+                Ok(shr) => shr,
+                Err(RoutingError::PathNotFound { .. }) => {
+                    unreachable.push(destination);
+                    continue;
+                }
+                Err(_) => {
+                    broadcast.failed.push(destination);
+                    continue;
+                }
+            };
+            self.update_session_id(Some(destination));
+            let session_id = self.session_id;
+            let result = if self.transforms.is_empty() {
+                let size = self.peer_fragment_size(destination);
+                let fragments = shared
+                    .entry(size)
+                    .or_insert_with(|| Self::fragment(&self.compress_outgoing(message), size))
+                    .clone();
+                let result = self.try_send_prepared(fragments, shr, session_id, destination);
+                self.report_send_result(session_id, result)
+            } else {
+                self.send_fragments(message, shr, session_id, destination)
+            };
+            match result {
+                Ok(()) => {
+                    sessions.push((destination, session_id));
+                    let _ = broadcast.pending.insert(session_id, destination);
+                }
+                Err(_) => broadcast.failed.push(destination),
+            }
+        }
+
+        if !unreachable.is_empty() {
+            self.start_flood(None)?;
+            // their sessions settle the broadcast like the others once the flood ends
+            for &destination in &unreachable {
+                self.update_session_id(Some(destination));
+                let _ = broadcast.pending.insert(self.session_id, destination);
+                let _ = self.pending_ser_requests.insert(SerializedRequest {
+                    to: Some(destination),
+                    data: message.to_vec(),
+                    session_id: Some(self.session_id),
+                });
+            }
+        }
+        self.emit(NodeEvent::BroadcastStarted {
+            notification_from: self.id,
+            broadcast_id,
+            sessions,
+            pending: unreachable,
+            failed: broadcast.failed.clone(),
+        });
+        let _ = self.broadcasts.insert(broadcast_id, broadcast);
+        self.settle_broadcast(broadcast_id, None);
+        Ok(broadcast_id)
+    }
+
+    /// Records the outcome of `session` in the broadcast it belongs to, if any, and
+    /// reports the broadcast once every session is settled.
+    fn settle_broadcast_session(&mut self, session_id: u64, delivered: bool) {
+        let Some((broadcast_id, broadcast)) = self
+            .broadcasts
+            .iter_mut()
+            .find(|(_, broadcast)| broadcast.pending.contains_key(&session_id))
+        else {
+            return;
+        };
+        let broadcast_id = *broadcast_id;
+        if let Some(destination) = broadcast.pending.remove(&session_id) {
+            self.settle_broadcast(broadcast_id, Some((destination, delivered)));
+        }
+    }
+
+    fn settle_broadcast(&mut self, broadcast_id: u64, outcome: Option<(NodeId, bool)>) {
+        let Some(broadcast) = self.broadcasts.get_mut(&broadcast_id) else {
+            return;
+        };
+        match outcome {
+            Some((destination, true)) => broadcast.delivered.push(destination),
+            Some((destination, false)) => broadcast.failed.push(destination),
+            None => {}
+        }
+        if !broadcast.pending.is_empty() {
+            return;
+        }
+        if let Some(broadcast) = self.broadcasts.remove(&broadcast_id) {
+            self.emit(NodeEvent::BroadcastCompleted {
+                notification_from: self.id,
+                broadcast_id,
+                delivered: broadcast.delivered,
+                failed: broadcast.failed,
+            });
+        }
+    }
+
+    /// Fragments and sends `message`, reporting a `NodeEvent::SendFailed` if it fails.
+    fn send_fragments(
+        &mut self,
+        message: &[u8],
+        shr: SourceRoutingHeader,
+        session_id: u64,
+        destination: NodeId,
+    ) -> Result<(), NetworkError> {
+        let result = self.try_send_fragments(message, shr, session_id, destination);
+        self.report_send_result(session_id, result)
+    }
+
+    fn report_send_result(
+        &mut self,
+        session_id: u64,
+        result: Result<(), NetworkError>,
+    ) -> Result<(), NetworkError> {
+        if let Err(e) = &result {
+            self.emit(NodeEvent::SendFailed {
+                notification_from: self.id,
+                session_id,
+                reason: e.to_string(),
+            });
+            self.settle_session(session_id, SessionStatus::Failed(e.to_string()));
+            self.settle_broadcast_session(session_id, false);
+        }
+        result
+    }
+
+    fn try_send_fragments(
+        &mut self,
+        message: &[u8],
+        shr: SourceRoutingHeader,
+        session_id: u64,
+        destination: NodeId,
+    ) -> Result<(), NetworkError> {
+        let message = self.compress_outgoing(message);
+        let transformed;
+        let message: &[u8] = if self.transforms.is_empty() {
+            &message
+        } else {
+            let mut payload = message.to_vec();
+            for transform in &self.transforms {
+                if let Ok(mut transform) = transform.lock() {
+                    transform.outgoing(&mut payload, destination);
+                }
+            }
+            transformed = payload;
+            &transformed
+        };
+        let fragments = Self::fragment(message, self.peer_fragment_size(destination));
+        self.try_send_prepared(fragments, shr, session_id, destination)
+    }
+
+    /// Splits `message` into data fragments carrying `size` bytes each.
+    fn fragment(message: &[u8], size: usize) -> Vec<Fragment> {
+        let chunks = message.chunks(size);
+        let total_n_fragments = chunks.len() as u64;
+        chunks
+            .enumerate()
+            .map(|(i, chunk)| Self::data_fragment(i as u64, total_n_fragments, chunk))
+            .collect()
+    }
+
+    fn try_send_prepared(
+        &mut self,
+        fragments: Vec<Fragment>,
+        shr: SourceRoutingHeader,
+        session_id: u64,
+        destination: NodeId,
+    ) -> Result<(), NetworkError> {
+        self.flush_piggybacked_acks(destination, &shr)?;
+        let total_n_fragments = fragments.len() as u64;
+        self.check_quota(destination, total_n_fragments * MAX_FRAGMENT_SIZE as u64)?;
+        if let Some(handle) = self.session_handles.get(&session_id) {
+            handle.add_fragments(fragments.len());
+        }
+        let routes = self.spread_routes(session_id, destination, shr);
+        for (index, fragment) in fragments.into_iter().enumerate() {
+            let route = routes[index % routes.len()].clone();
+            let packet = Packet::new_fragment(route, session_id, fragment);
+            if self.send_window.is_some() {
+                self.buffer.hold(packet);
+                continue;
+            }
+            match self.throttles.get_mut(&session_id) {
+                Some(throttle) => throttle.queue.push_back(packet),
+                None => self.pace(packet)?,
+            }
+        }
+        self.advance_window(session_id, destination)?;
+        self.send_throttled(session_id)?;
+
+        self.emit(NodeEvent::MessageSent {
+            notification_from: self.id,
+            to: destination,
+            session_id,
+        });
+
+        Ok(())
+    }
+
+    /// Returns the routes to spread the fragments of `session_id` over: `shr` followed,
+    /// in multipath mode, by the node-disjoint routes towards `destination` starting at
+    /// a neighbor.
+    fn spread_routes(
+        &mut self,
+        session_id: u64,
+        destination: NodeId,
+        shr: SourceRoutingHeader,
+    ) -> Vec<SourceRoutingHeader> {
+        fn drones(hops: &[NodeId]) -> &[NodeId] {
+            hops.get(1..hops.len().saturating_sub(1)).unwrap_or(&[])
+        }
+        let Some(k) = self.multipath.filter(|k| *k > 1) else {
+            return vec![shr];
+        };
+        if self.pinned_routes.contains_key(&session_id) {
+            return vec![shr];
+        }
+        let mut routes = vec![shr];
+        // candidates stay inline until picked, only the routes followed become headers
+        for path in self
+            .network_view
+            .k_shortest_paths(self.id, destination, k, self.search_budget)
+        {
+            let reachable = path
+                .get(1)
+                .is_some_and(|hop| self.neighbors.contains_key(hop));
+            let disjoint = routes
+                .iter()
+                .all(|other| drones(&other.hops).iter().all(|drone| !path.contains(drone)));
+            if routes.len() < k && reachable && disjoint && !drones(&path).is_empty() {
+                routes.push(SourceRoutingHeader::new(path.into_vec(), 1).without_loops());
+            }
+        }
+        if routes.len() > 1 {
+            let _ = self.multipath_routes.insert(session_id, routes.clone());
+        }
+        routes
+    }
+
+    /// Moves a buffered fragment of a multipath session to another of its routes
+    /// avoiding `culprit`, so that its retransmission takes a different path.
+    fn switch_route(
+        &mut self,
+        session_id: u64,
+        destination: NodeId,
+        fragment_index: u64,
+        culprit: NodeId,
+    ) {
+        let Some(routes) = self.multipath_routes.get(&session_id) else {
+            return;
+        };
+        let Some(fragment) = self
+            .buffer
+            .fragment(session_id, destination, fragment_index)
+        else {
+            return;
+        };
+        let current = &fragment.packet().routing_header;
+        let next = routes.iter().find(|shr| {
+            shr.hops != current.hops
+                && shr.destination() == Some(destination)
+                && !shr.hops.contains(&culprit)
+        });
+        if let Some(next) = next.cloned() {
+            self.buffer
+                .reroute(session_id, destination, fragment_index, next);
+        }
+    }
+
+    /// Registers a transform applied to every payload sent and, through the `Processor`,
+    /// to every message received. Transforms run in registration order.
+    pub fn add_transform(&mut self, transform: SharedTransform) {
+        self.transforms.push(transform);
+    }
+
+    pub fn clear_transforms(&mut self) {
+        self.transforms.clear();
+    }
+
+    /// Returns the middleware chain of the node, applied to every packet sent to a
+    /// neighbor and, by the processors, to every packet received. See
+    /// [`PacketMiddleware`](crate::transform::PacketMiddleware).
+    pub fn middlewares(&mut self) -> &mut Vec<SharedMiddleware> {
+        &mut self.middlewares
+    }
+
+    /// Compresses the messages sent as set by `config`, and inflates the messages
+    /// received, before the transforms for the former and after them for the latter.
+    /// `None` (the default) disables it; both ends must agree, see [`crate::compression`].
+    #[cfg(feature = "compression")]
+    pub fn set_compression(&mut self, config: Option<CompressionConfig>) {
+        self.compressor = config.map(Compressor::new);
+    }
+
+    /// Returns the compression statistics, `None` while compression is disabled.
+    #[cfg(feature = "compression")]
+    #[must_use]
+    pub fn compression_stats(&self) -> Option<CompressionStats> {
+        self.compressor.as_ref().map(Compressor::stats)
+    }
+
+    /// Frames `message` for the wire, compressing it when compression is enabled.
+    #[cfg_attr(not(feature = "compression"), allow(clippy::unused_self))]
+    fn compress_outgoing<'a>(&self, message: &'a [u8]) -> Cow<'a, [u8]> {
+        #[cfg(feature = "compression")]
+        if let Some(compressor) = &self.compressor {
+            return Cow::Owned(compressor.compress(message));
+        }
+        Cow::Borrowed(message)
+    }
+
+    /// Shows every packet sent by the router and every packet received through
+    /// `Processor::handle_packet` to `tap`, replacing the previous one. `None` removes it.
+    pub fn set_packet_tap(&mut self, tap: Option<SharedTap>) {
+        self.packet_tap = tap;
+    }
+
+    /// Counts `packet` and shows it to the packet tap, if any.
+    pub(crate) fn tap_packet(&self, direction: PacketDirection, packet: &Packet) {
+        let (counter, fragments) = match direction {
+            PacketDirection::Sent => (&self.packets_sent, &self.fragments_sent),
+            PacketDirection::Received => (&self.packets_received, &self.fragments_received),
+        };
+        counter.set(counter.get() + 1);
+        if matches!(packet.pack_type, PacketType::MsgFragment(_)) {
+            fragments.set(fragments.get() + 1);
+        }
+        let mut journal = self.packet_journal.borrow_mut();
+        if journal.capacity() > 0 {
+            let _ = journal.push((self.clock.now(), direction, packet.clone()));
+        }
+        if let Some(Ok(mut tap)) = self.packet_tap.as_ref().map(|tap| tap.lock()) {
+            tap.on_packet(direction, packet);
+        }
+    }
+
+    /// Keeps the latest `capacity` packets sent and received, 0 (the default) to keep
+    /// none. Resizing forgets the packets kept so far.
+    pub fn set_packet_journal(&mut self, capacity: usize) {
+        self.packet_journal = RefCell::new(RingLog::new(capacity));
+    }
+
+    /// Returns the latest packets sent and received with the time they went through the
+    /// router, oldest first.
+    #[must_use]
+    pub fn packet_journal(&self) -> Vec<(Instant, PacketDirection, Packet)> {
+        self.packet_journal.borrow().iter().cloned().collect()
+    }
+
+    /// Applies the registered transforms, then decompression if enabled, to a message
+    /// received from `source`.
+    #[must_use]
+    pub fn transform_incoming(&self, mut payload: Vec<u8>, source: NodeId) -> Vec<u8> {
+        for transform in &self.transforms {
+            if let Ok(mut transform) = transform.lock() {
+                transform.incoming(&mut payload, source);
+            }
+        }
+        #[cfg(feature = "compression")]
+        if let Some(compressor) = &self.compressor {
+            payload = compressor.decompress(payload);
+        }
+        payload
+    }
+
+    /// Allocates a new session id towards `destination`, e.g. to configure the session
+    /// with [`Self::set_session_rate`] before passing it to [`Self::send_message`].
+    pub fn new_session_id(&mut self, destination: NodeId) -> u64 {
+        self.update_session_id(Some(destination));
+        self.session_id
+    }
+
+    /// Limits the rate at which the fragments of `session_id` are sent, so that
+    /// background transfers leave room to interactive traffic; `None` lifts the limit.
+    /// Fragments exceeding the rate are queued and released by [`Self::tick`].
+    /// The limit applies to the first transmission of each fragment, retransmissions
+    /// are never delayed, and is forgotten once all the fragments of the session left.
+    pub fn set_session_rate(&mut self, session_id: u64, bytes_per_sec: Option<u64>) {
+        match (self.throttles.get_mut(&session_id), bytes_per_sec) {
+            (Some(throttle), rate) => {
+                throttle.refill(self.clock.now());
+                throttle.bytes_per_sec = rate;
+            }
+            (None, Some(rate)) => {
+                #[allow(clippy::cast_precision_loss)]
+                let allowance = (rate as f64).max(FRAGMENT_COST);
+                let _ = self.throttles.insert(
+                    session_id,
+                    SessionThrottle {
+                        bytes_per_sec: Some(rate),
+                        allowance,
+                        last_refill: self.clock.now(),
+                        queue: VecDeque::new(),
+                    },
+                );
+            }
+            (None, None) => {}
+        }
+    }
+
+    /// Returns the number of fragments of `session_id` waiting for their turn.
+    #[must_use]
+    pub fn throttled_fragments(&self, session_id: u64) -> usize {
+        self.throttles
+            .get(&session_id)
+            .map_or(0, |throttle| throttle.queue.len())
+    }
+
+    /// Sends the fragments of every session `window` at a time: the next fragment
+    /// leaves when an Ack makes room among the fragments in flight (sent and not
+    /// acknowledged). `None`, the default, sends all the fragments of a message at once;
+    /// fragments already held are then released by [`Self::tick`].
+    pub fn set_send_window(&mut self, window: Option<usize>) {
+        self.send_window = window.map(|window| window.max(1));
+    }
+
+    /// Returns the number of fragments of `session_id` held back by the send window.
+    #[must_use]
+    pub fn held_fragments(&self, session_id: u64) -> usize {
+        self.buffer
+            .sessions_of(session_id)
+            .map(|session| self.buffer.pending(session_id, session.destination()))
+            .sum()
+    }
+
+    /// Sends the held fragments of `session_id` to `destination` the send window has
+    /// room for. Fragments waiting in the session throttle or the pacer count as in
+    /// flight.
+    fn advance_window(&mut self, session_id: u64, destination: NodeId) -> Result<(), NetworkError> {
+        loop {
+            let in_flight = self.buffer.outstanding(session_id, destination)
+                + self.throttled_fragments(session_id)
+                + self.paced_fragments(session_id);
+            if self.send_window.is_some_and(|window| in_flight >= window) {
+                return Ok(());
+            }
+            let Some(packet) = self.buffer.next_pending(session_id, destination) else {
+                return Ok(());
+            };
+            match self.throttles.get_mut(&session_id) {
+                Some(throttle) => throttle.queue.push_back(packet),
+                None if self.pacing => self.pace(packet)?,
+                None => {
+                    if let Err(e) = self.try_send(packet.clone()) {
+                        let fragment_index = packet.get_fragment_index();
+                        self.buffer.requeue(session_id, destination, fragment_index);
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Periodic work of the router, called by `Processor::tick`:
+    /// refreshes the network view every flood interval or once a deferred flood is
+    /// allowed to start, sends the packets backlogged for congested neighbors, the Acks that waited
+    /// too long for a message to piggyback on, the held fragments the send window has
+    /// room for, the throttled fragments allowed by their session rate and the paced
+    /// fragments whose turn came, reports the servers no longer reachable, forgets the
+    /// expired blacklist entries and, last, sends the heartbeats due.
+    /// # Errors
+    /// Returns any error returned while sending the fragments.
+    pub fn tick(&mut self) -> Result<(), NetworkError> {
+        if self
+            .flood_interval
+            .is_some_and(|interval| self.clock.now().duration_since(self.last_flood) >= interval)
+            || (self.flood_deferred && self.may_flood())
+        {
+            self.start_flood(None)?;
+        }
+        self.flush_backlogs();
+        self.flush_expired_acks()?;
+        self.flush_aggregated_acks()?;
+        self.purge_expired_floods();
+        let _ = self.blacklist.purge_expired(self.clock.now());
+        self.detect_partition();
+        let mut held = self.buffer.sessions_pending();
+        held.sort_by_key(|(session_id, _)| self.session_class(*session_id));
+        for (session_id, destination) in held {
+            self.advance_window(session_id, destination)?;
+        }
+        for session_id in self.throttles.keys().copied().collect::<Vec<_>>() {
+            self.send_throttled(session_id)?;
+        }
+        for destination in self.pacers.keys().copied().collect::<Vec<_>>() {
+            self.release_paced(destination)?;
+        }
+        self.report_congestion();
+        self.send_heartbeats()
+    }
+
+    /// Sends the queued fragments of `session_id` within its allowance, forgetting the
+    /// throttle once its queue is drained.
+    fn send_throttled(&mut self, session_id: u64) -> Result<(), NetworkError> {
+        let Some(throttle) = self.throttles.get_mut(&session_id) else {
+            return Ok(());
+        };
+        if throttle.queue.is_empty() {
+            return Ok(());
+        }
+        throttle.refill(self.clock.now());
+        while let Some(packet) = self
+            .throttles
+            .get_mut(&session_id)
+            .and_then(SessionThrottle::next_packet)
+        {
+            self.pace(packet)?;
+        }
+        if self
+            .throttles
+            .get(&session_id)
+            .is_some_and(|throttle| throttle.queue.is_empty())
+        {
+            let _ = self.throttles.remove(&session_id);
+        }
+        Ok(())
+    }
+
+    /// Spaces the first transmissions towards each destination by the smoothed round
+    /// trip time divided by the congestion window, and never closer than the smallest
+    /// recent gap between Acks, so that windows don't overflow the drone channels in
+    /// bursts. Until a round trip is measured fragments leave at once, retransmissions
+    /// are never delayed. Paced fragments are released as Acks
+    /// arrive and by [`Self::tick`], the spacing is therefore no finer than its period.
+    /// Disabling pacing releases the queued fragments on the next tick.
+    pub fn set_pacing(&mut self, enabled: bool) {
+        self.pacing = enabled;
+    }
+
+    /// Returns the number of fragments of `session_id` waiting for their turn in the pacer.
+    #[must_use]
+    pub fn paced_fragments(&self, session_id: u64) -> usize {
+        self.pacers
+            .values()
+            .map(|pacer| pacer.queued(session_id))
+            .sum()
+    }
+
+    /// Sends `packet` through the pacer of its destination when pacing is enabled.
+    fn pace(&mut self, packet: Packet) -> Result<(), NetworkError> {
+        let Some(destination) = packet
+            .routing_header
+            .destination()
+            .filter(|_| self.pacing)
+        else {
+            return self.try_send(packet);
+        };
+        let class = self.session_class(packet.session_id);
+        self.pacers
+            .entry(destination)
+            .or_default()
+            .enqueue(packet, class);
+        self.release_paced(destination)
+    }
+
+    /// Sends the paced fragments towards `destination` whose turn came, all of them
+    /// once pacing is disabled.
+    fn release_paced(&mut self, destination: NodeId) -> Result<(), NetworkError> {
+        let window = self
+            .congestion
+            .get(&destination)
+            .cloned()
+            .unwrap_or_default()
+            .window();
+        while let Some((class, packet)) = self
+            .pacers
+            .get_mut(&destination)
+            .and_then(|pacer| pacer.ready(self.clock.now(), window, self.pacing))
+        {
+            if let Err(e) = self.try_send(packet.clone()) {
+                if let Some(pacer) = self.pacers.get_mut(&destination) {
+                    pacer.requeue(class, packet);
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the class of service of `session_id`. Its fragments inherit the class:
+    /// queued first transmissions of interactive sessions overtake the bulk ones in the
+    /// pacer and the send window, and fragments waiting for a route leave after a flood
+    /// as interactive retransmissions first, then interactive, bulk retransmissions and
+    /// bulk fragments. Retransmissions are otherwise never queued. Set the class before
+    /// sending, with an id from [`Self::new_session_id`]; it is forgotten with the session.
+    pub fn set_session_class(&mut self, session_id: u64, class: QosClass) {
+        if class == QosClass::default() {
+            let _ = self.session_classes.remove(&session_id);
+        } else {
+            let _ = self.session_classes.insert(session_id, class);
+        }
+    }
+
+    #[must_use]
+    pub fn session_class(&self, session_id: u64) -> QosClass {
+        self.session_classes
+            .get(&session_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns the order in which a queued packet leaves: by class, retransmissions first.
+    fn send_priority(&self, packet: &Packet) -> (QosClass, bool) {
+        let fragment_index = packet.get_fragment_index();
+        let first_transmission = !packet
+            .routing_header
+            .destination()
+            .is_some_and(|destination| {
+                self.buffer
+                    .was_sent(packet.session_id, destination, fragment_index)
+            });
+        (self.session_class(packet.session_id), first_transmission)
+    }
+
+    /// Refuses sending `bytes` more to `destination` if that would exceed its quota,
+    /// notifying the controller with `NodeEvent::QuotaExceeded`.
+    fn check_quota(&self, destination: NodeId, bytes: u64) -> Result<(), RoutingError> {
+        let Some(quota) = self.bytes.quotas.get(&destination).copied() else {
+            return Ok(());
+        };
+        let used = self.bytes_sent(destination);
+        if used + bytes <= quota {
+            return Ok(());
+        }
+        self.emit(NodeEvent::QuotaExceeded {
+            notification_from: self.id,
+            destination,
+            quota,
+            used,
+        });
+        Err(RoutingError::QuotaExceeded { destination })
+    }
+
+    /// Limits the fragment bytes that can be sent to `destination`,
+    /// retransmissions included; `None` removes the limit.
+    pub fn set_quota(&mut self, destination: NodeId, quota: Option<u64>) {
+        match quota {
+            Some(quota) => {
+                let _ = self.bytes.quotas.insert(destination, quota);
+            }
+            None => {
+                let _ = self.bytes.quotas.remove(&destination);
+            }
+        }
+    }
+
+    /// Fragment bytes sent so far to `destination`, padding and retransmissions included.
+    #[must_use]
+    pub fn bytes_sent(&self, destination: NodeId) -> u64 {
+        self.bytes.sent.get(&destination).copied().unwrap_or_default()
+    }
+
+    /// Fragment bytes received so far from `source`.
+    #[must_use]
+    pub fn bytes_received(&self, source: NodeId) -> u64 {
+        self.bytes.received.get(&source).copied().unwrap_or_default()
+    }
+
+    /// Accounts `bytes` received from `source`, called by the `Processor` for every fragment.
+    pub fn record_received(&mut self, source: NodeId, bytes: u64) {
+        *self.bytes.received.entry(source).or_default() += bytes;
+        if let Some(monitor) = &mut self.heartbeats {
+            monitor.record_activity(source, self.clock.now());
+        }
+    }
+
+    /// Resets the byte counters, quotas are kept.
+    pub fn reset_byte_counters(&mut self) {
+        self.bytes.sent.clear();
+        self.bytes.received.clear();
+    }
+
+    /// Returns the routing counters of the node, assembler counters are left at zero.
+    #[must_use]
+    pub fn stats(&self) -> NodeStats {
+        NodeStats {
+            floods_started: self.flood_counter,
+            floods_suppressed: self.floods_suppressed,
+            bytes_sent: self.bytes.sent.values().sum(),
+            bytes_received: self.bytes.received.values().sum(),
+            packets_sent: self.packets_sent.get(),
+            packets_received: self.packets_received.get(),
+            retransmissions: self.retransmissions,
+            active_sessions: self.buffer.len(),
+            ack_anomalies: self.ack_anomalies,
+            nacks_sent: self.nacks_sent,
+            ..NodeStats::default()
+        }
+    }
+
+    /// Returns the traffic counters of the node, with the routing counters of
+    /// [`Self::stats`].
+    #[must_use]
+    pub fn metrics(&self) -> Metrics {
+        Metrics {
+            stats: self.stats(),
+            fragments_sent: self.fragments_sent.get(),
+            fragments_received: self.fragments_received.get(),
+            fragments_dropped: self.fragments_dropped,
+        }
+    }
+
+    /// Notifies the controller that the node stopped, with its closing statistics.
+    pub fn notify_terminated(&self, reason: TerminationReason, stats: NodeStats) {
+        self.emit(NodeEvent::Terminated {
+            notification_from: self.id,
+            reason,
+            stats,
+        });
+    }
+
+    /// Notifies the controller of the servers found so far and their type.
+    pub fn notify_servers_discovered(&self, servers: HashMap<NodeId, ServerType>) {
+        self.emit(NodeEvent::ServersDiscovered {
+            notification_from: self.id,
+            servers,
+        });
+    }
+
+    /// Notifies the controller that `received` fragments out of `total` of the message
+    /// `session_id` from `from` arrived.
+    pub fn notify_receive_progress(
+        &self,
+        from: NodeId,
+        session_id: u64,
+        received: u64,
+        total: u64,
+    ) {
+        self.emit(NodeEvent::ReceiveProgress {
+            notification_from: self.id,
+            from,
+            session_id,
+            received,
+            total,
+        });
+    }
+
+    /// Sends a keep-alive payload to `destination` as a single fragment.
+    /// Unlike [`Self::send_message`] it never starts a flood nor notifies the controller
+    /// about a sent message: if no route is known the keep-alive is just skipped,
+    /// the next flood will refresh the view anyway.
+    /// # Errors
+    /// `PayloadTooLarge` if the payload does not fit in one fragment,
+    /// `PathNotFound` if no route to `destination` is known,
+    /// or any error returned while sending the fragment.
+    pub fn send_keep_alive(
+        &mut self,
+        payload: &[u8],
+        destination: NodeId,
+    ) -> Result<(), NetworkError> {
+        if payload.len() > MAX_FRAGMENT_SIZE {
+            return Err(RoutingError::PayloadTooLarge { len: payload.len() }.into());
+        }
+        let fragment = if self.reserved_keep_alives {
+            reserved_control_fragment(payload)?
+        } else {
+            let payload = self.compress_outgoing(payload);
+            if payload.len() > MAX_FRAGMENT_SIZE {
+                return Err(RoutingError::PayloadTooLarge { len: payload.len() }.into());
+            }
+            Self::data_fragment(0, 1, &payload)
+        };
+        self.send_single_fragment(fragment, destination)
+    }
+
+    /// Sends `payload` to `destination` as a reserved control fragment, handed to
+    /// `Processor::handle_control_fragment` on the other side. Like keep-alives, it
+    /// never starts a flood.
+    /// # Errors
+    /// `PayloadTooLarge` if the payload does not fit in one fragment,
+    /// `PathNotFound` if no route to `destination` is known,
+    /// or any error returned while sending the fragment.
+    pub fn send_control_fragment(
+        &mut self,
+        payload: &[u8],
+        destination: NodeId,
+    ) -> Result<(), NetworkError> {
+        let fragment = reserved_control_fragment(payload)?;
+        self.send_single_fragment(fragment, destination)
+    }
+
+    fn send_single_fragment(
+        &mut self,
+        fragment: Fragment,
+        destination: NodeId,
+    ) -> Result<(), NetworkError> {
+        let shr = self
+            .try_find_path(destination)
+            .map_err(|e| NetworkError::from(e).towards(destination))?;
+        // control fragments skip the assembler, they would leave gaps in the sequence
+        let sequence = (!is_reserved_control_fragment(&fragment)).then_some(destination);
+        self.update_session_id(sequence);
+        let session_id = self.session_id;
+        let packet = Packet::new_fragment(shr, session_id, fragment);
+        self.try_send(packet)
+            .map_err(|e| e.in_session(session_id).towards(destination))
+    }
+
+    /// Probes the liveness of the servers of the network view and of the peers watched
+    /// with [`Self::watch_peer`] with a [`Heartbeat`] keep-alive (see
+    /// [`Self::send_keep_alive`]) after `interval` without hearing from them, answered
+    /// by its Ack; any fragment or Ack of a peer counts as an answer. A peer leaving
+    /// `max_missed` heartbeats in a row unanswered is reported with a
+    /// `NodeEvent::PeerUnresponsive` and a flood is started to find another route.
+    /// `None` disables heartbeats and forgets the watched peers.
+    pub fn set_heartbeats(&mut self, config: Option<HeartbeatConfig>) {
+        self.heartbeats = config.map(HeartbeatMonitor::new);
+        for (session_id, peer) in std::mem::take(&mut self.heartbeat_sessions) {
+            self.forget_heartbeat(session_id, peer);
+        }
+        self.heartbeat_servers.clear();
+    }
+
+    /// Returns the liveness of the watched peers, `None` when heartbeats are disabled.
+    #[must_use]
+    pub fn heartbeats(&self) -> Option<&HeartbeatMonitor> {
+        self.heartbeats.as_ref()
+    }
+
+    /// Watches `peer` besides the servers, e.g. a client chatting with this node.
+    /// Returns whether it was new, always `false` when heartbeats are disabled.
+    /// A peer watched explicitly stays watched when it leaves the network view.
+    pub fn watch_peer(&mut self, peer: NodeId) -> bool {
+        let Some(monitor) = &mut self.heartbeats else {
+            return false;
+        };
+        let _ = self.heartbeat_servers.remove(&peer);
+        monitor.watch(peer, self.clock.now())
+    }
+
+    /// Drops the heartbeat `session_id` sent to `peer` from the session buffer and the
+    /// pacer, once answered or superseded by the next heartbeat.
+    fn forget_heartbeat(&mut self, session_id: u64, peer: NodeId) {
+        let _ = self.buffer.remove(session_id, peer);
+        if let Some(pacer) = self.pacers.get_mut(&peer) {
+            pacer.forget_session(session_id);
+        }
+    }
+
+    /// Watches the servers of the network view, unwatching those that left it, then
+    /// sends the heartbeats due, reporting and flooding for the unresponsive peers.
+    /// Heartbeats without a route are skipped and count as missed.
+    fn send_heartbeats(&mut self) -> Result<(), NetworkError> {
+        let Some(monitor) = &mut self.heartbeats else {
+            return Ok(());
+        };
+        let now = self.clock.now();
+        let servers = self
+            .network_view
+            .get_servers()
+            .unwrap_or_default()
+            .into_iter()
+            .collect::<HashSet<_>>();
+        for server in self.heartbeat_servers.difference(&servers) {
+            let _ = monitor.unwatch(*server);
+        }
+        for server in &servers {
+            if monitor.watch(*server, now) {
+                let _ = self.heartbeat_servers.insert(*server);
+            }
+        }
+        self.heartbeat_servers
+            .retain(|server| servers.contains(server));
+        let first = monitor.sent();
+        let (due, unresponsive) = monitor.poll(now);
+        for (heartbeat, peer) in (first..).zip(due) {
+            let superseded = self
+                .heartbeat_sessions
+                .iter()
+                .filter(|(_, watched)| **watched == peer)
+                .map(|(session_id, _)| *session_id)
+                .collect::<Vec<_>>();
+            for session_id in superseded {
+                let _ = self.heartbeat_sessions.remove(&session_id);
+                self.forget_heartbeat(session_id, peer);
+            }
+            let Ok(payload) = serde_json::to_vec(&Heartbeat { heartbeat }) else {
+                continue;
+            };
+            if self.send_keep_alive(&payload, peer).is_ok() {
+                let _ = self.heartbeat_sessions.insert(self.session_id, peer);
+            }
+        }
+        if unresponsive.is_empty() {
+            return Ok(());
+        }
+        for peer in unresponsive {
+            self.emit(NodeEvent::PeerUnresponsive(peer));
+        }
+        self.start_flood(None)
+    }
+
+    /// Sends keep-alives as reserved control fragments ("fragment 0 of 0"), for the
+    /// controllers and peers relying on that convention of the protocol specification.
+    pub fn set_reserved_keep_alives(&mut self, enabled: bool) {
+        self.reserved_keep_alives = enabled;
+    }
+
+    /// Builds fragment `index` of `total` carrying `chunk`, its length field framing the
+    /// chunk so that the receiver trims the padding only.
+    fn data_fragment(index: u64, total: u64, chunk: &[u8]) -> Fragment {
+        let mut data = [0u8; MAX_FRAGMENT_SIZE];
+        data[..chunk.len()].copy_from_slice(chunk);
+        Fragment {
+            fragment_index: index,
+            total_n_fragments: total,
+            #[allow(clippy::cast_possible_truncation)]
+            length: chunk.len() as u8,
+            data,
+        }
+    }
+
+    /// Handles an Ack received from `from`. Late Acks of fragments `from` already
+    /// acknowledged, e.g. the second Ack of a retransmitted fragment, are ignored as
+    /// duplicates. Acks of fragments never sent to `from` are ignored and reported with a
+    /// `NodeEvent::ProtocolViolation`.
+    #[cfg_attr(
+        feature = "telemetry",
+        tracing::instrument(
+            level = "trace",
+            skip_all,
+            fields(
+                node = self.id,
+                session_id = session_id,
+                fragment_index = ack.fragment_index,
+                from = from
+            )
+        )
+    )]
+    pub fn handle_ack(&mut self, ack: &Ack, session_id: u64, from: NodeId) {
+        let fragment_index = ack.fragment_index;
+        if let Some(monitor) = &mut self.heartbeats {
+            monitor.record_activity(from, self.clock.now());
+            // heartbeats are never retransmitted, their Ack only closes the session
+            if self.heartbeat_sessions.get(&session_id) == Some(&from) {
+                let _ = self.heartbeat_sessions.remove(&session_id);
+                self.pacers
+                    .entry(from)
+                    .or_default()
+                    .on_ack(session_id, fragment_index, self.clock.now());
+                self.forget_heartbeat(session_id, from);
+                return;
+            }
+        }
+        let key = (session_id, fragment_index, from);
+        let awaited = self
+            .buffer
+            .unacked(session_id, from, fragment_index)
+            .is_some();
+        let already_acked = !awaited && self.buffer.was_sent(session_id, from, fragment_index);
+        if self.acks_seen.contains(&key) || already_acked {
+            return;
+        }
+        if !awaited {
+            self.ack_anomalies += 1;
+            self.emit(NodeEvent::ProtocolViolation {
+                notification_from: self.id,
+                peer: from,
+                session_id,
+                violation: ProtocolViolation::UnsolicitedAck { fragment_index },
+            });
+            return;
+        }
+        let _ = self.acks_seen.insert(key);
+        if let Some(evicted) = self.acks_seen_order.push(key) {
+            let _ = self.acks_seen.remove(&evicted);
+        }
+
+        if let Some(fragment) = self.buffer.fragment(session_id, from, fragment_index) {
+            self.network_view
+                .edge_stats_mut()
+                .record_delivery(&fragment.packet().routing_header.hops, self.clock.now());
+        }
+        self.congestion.entry(from).or_default().on_ack();
+        self.pacers
+            .entry(from)
+            .or_default()
+            .on_ack(session_id, fragment_index, self.clock.now());
+        self.emit(NodeEvent::AckReceived {
+            notification_from: self.id,
+            from,
+            session_id,
+            fragment_index,
+        });
+        if let Some(handle) = self.session_handles.get(&session_id) {
+            handle.on_ack();
+        }
+        let all_sent_acked = self.buffer.mark_acked(session_id, from, fragment_index);
+        // fragments held by the send window, a session rate or the pacer are still to be sent
+        if all_sent_acked
+            && self.buffer.pending(session_id, from) == 0
+            && self.throttled_fragments(session_id) == 0
+            && self.paced_fragments(session_id) == 0
+        {
+            self.emit(NodeEvent::MessageFullyAcked {
+                notification_from: self.id,
+                session_id,
+                destination: from,
+            });
+            let _ = self.session_classes.remove(&session_id);
+            let _ = self.pinned_routes.remove(&session_id);
+            let _ = self.multipath_routes.remove(&session_id);
+            self.settle_session(session_id, SessionStatus::Delivered);
+            self.settle_broadcast_session(session_id, true);
+        }
+        let _ = self
+            .fragment_trace
+            .record(session_id, ack.fragment_index, FragmentFate::Acked);
+        // held fragments that cannot leave now are sent again by the next tick
+        if self.advance_window(session_id, from).is_ok() && self.send_throttled(session_id).is_ok()
+        {
+            let _ = self.release_paced(from);
+        }
+    }
+
+    /// Returns the congestion window, the fragments in flight, the congestion phase and
+    /// the pacing estimates of every destination with fragments sent, by destination.
+    #[must_use]
+    pub fn congestion_state(&self) -> Vec<CongestionState> {
+        let mut in_flight = HashMap::<NodeId, usize>::new();
+        for (session_id, session) in self.buffer.sessions() {
+            let outstanding = self.buffer.outstanding(session_id, session.destination());
+            if outstanding > 0 {
+                *in_flight.entry(session.destination()).or_default() += outstanding;
+            }
+        }
+        let destinations = self
+            .congestion
+            .keys()
+            .chain(in_flight.keys())
+            .copied()
+            .collect::<HashSet<_>>();
+        let mut states = destinations
+            .into_iter()
+            .map(|destination| {
+                let in_flight = in_flight.get(&destination).copied().unwrap_or_default();
+                let mut state = self
+                    .congestion
+                    .get(&destination)
+                    .cloned()
+                    .unwrap_or_default()
+                    .state(destination, in_flight);
+                if let Some(pacer) = self.pacers.get(&destination) {
+                    state.srtt = pacer.srtt();
+                    state.pacing_interval = pacer.interval(state.window);
+                }
+                state
+            })
+            .collect::<Vec<_>>();
+        states.sort_by_key(|state| state.destination);
+        states
+    }
+
+    /// Makes `tick` notify the controller of the [`Self::congestion_state`] every
+    /// `interval` with a `NodeEvent::CongestionReport`, `None` to stop the reports.
+    pub fn set_congestion_reports(&mut self, interval: Option<Duration>) {
+        self.congestion_report_interval = interval;
+        self.last_congestion_report = self.clock.now();
+    }
+
+    fn report_congestion(&mut self) {
+        let now = self.clock.now();
+        if self
+            .congestion_report_interval
+            .is_none_or(|interval| now.duration_since(self.last_congestion_report) < interval)
+        {
+            return;
+        }
+        self.last_congestion_report = now;
+        self.emit(NodeEvent::CongestionReport {
+            notification_from: self.id,
+            states: self.congestion_state(),
+        });
+    }
+
+    /// Returns the outgoing sessions not acknowledged yet along with the state, retries
+    /// and send times of their fragments.
+    #[must_use]
+    pub fn session_buffer(&self) -> &SessionBuffer {
+        &self.buffer
+    }
+
+    /// Lists the outgoing sessions whose fragments are not all acknowledged yet.
+    #[must_use]
+    pub fn buffered_sessions(&self) -> Vec<BufferedSession> {
+        let mut sessions = self
+            .buffer
+            .sessions()
+            .map(|(session_id, session)| BufferedSession {
+                session_id,
+                destination: Some(session.destination()),
+                total_fragments: session.len(),
+                acked_fragments: session.count(FragmentState::Acked),
+            })
+            .collect::<Vec<_>>();
+        sessions.sort_by_key(|s| s.session_id);
+        sessions
+    }
+
+    /// Starts a graceful shutdown: new sends are refused with `ShuttingDown`, paced
+    /// fragments are released, and the sessions already accepted keep being
+    /// retransmitted until they are acknowledged or `grace` elapses, see
+    /// [`Self::poll_shutdown`]. Calling it again shortens or extends the grace period.
+    pub fn begin_shutdown(&mut self, grace: Duration) {
+        self.shutdown_deadline = Some(self.clock.now() + grace);
+        self.set_pacing(false);
+    }
+
+    #[must_use]
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutdown_deadline.is_some()
+    }
+
+    /// Lists the outgoing sessions not delivered yet: unacknowledged, held back by the
+    /// send window, a session rate or the pacer, waiting for a flood to find their
+    /// destination or for their turn with ordered sends.
+    #[must_use]
+    pub fn undelivered_sessions(&self) -> Vec<u64> {
+        let mut sessions = self
+            .buffer
+            .sessions()
+            .map(|(session_id, _)| session_id)
+            .chain(self.packets_to_send.iter().map(|p| p.session_id))
+            .chain(
+                self.pending_ser_requests
+                    .iter()
+                    .filter_map(|req| req.session_id),
+            )
+            .chain(self.message_queue.sessions())
+            .chain(
+                self.throttles
+                    .iter()
+                    .filter(|(_, throttle)| !throttle.queue.is_empty())
+                    .map(|(session_id, _)| *session_id),
+            )
+            .chain(self.pacers.values().flat_map(Pacer::sessions))
+            .collect::<Vec<_>>();
+        sessions.sort_unstable();
+        sessions.dedup();
+        sessions
+    }
+
+    /// Returns whether a graceful shutdown is over, i.e. every session is delivered or
+    /// the grace period elapsed. When it is, the handles of the undelivered sessions
+    /// fail and the controller is sent a `NodeEvent::ShutdownComplete` listing them.
+    /// Always `false` unless [`Self::begin_shutdown`] was called.
+    pub fn poll_shutdown(&mut self) -> bool {
+        let Some(deadline) = self.shutdown_deadline else {
+            return false;
+        };
+        let undelivered_sessions = self.undelivered_sessions();
+        if !undelivered_sessions.is_empty() && self.clock.now() < deadline {
+            return false;
+        }
+        for session_id in &undelivered_sessions {
+            let reason = "node shut down".to_string();
+            self.settle_session(*session_id, SessionStatus::Failed(reason));
+        }
+        self.emit(NodeEvent::ShutdownComplete {
+            notification_from: self.id,
+            undelivered_sessions,
+        });
+        true
+    }
+
+    /// Resends every unacknowledged fragment of `session_id` at once, returns how many
+    /// were resent or `None` if the session is not buffered.
+    /// # Errors
+    /// Returns any error returned while sending the fragments.
+    pub fn force_retry(&mut self, session_id: u64) -> Result<Option<usize>, NetworkError> {
+        let mut sessions = self.buffer.sessions_of(session_id).peekable();
+        if sessions.peek().is_none() {
+            return Ok(None);
+        }
+        let pending = sessions
+            .flat_map(OutgoingSession::fragments)
+            .filter(|(_, fragment)| fragment.is_unacked())
+            .map(|(_, fragment)| fragment.packet().clone())
+            .collect::<Vec<_>>();
+        let count = pending.len();
+        #[cfg(feature = "telemetry")]
+        tracing::debug!(session_id, fragments = count, "session retried");
+        for packet in pending {
+            self.try_send(packet)
+                .map_err(|e| e.in_session(session_id))?;
+            self.retransmissions += 1;
+        }
+        Ok(Some(count))
+    }
+
+    /// Gives up `session_id`: its unacknowledged fragments are marked as expired and
+    /// never retransmitted. Returns whether the session was buffered.
+    pub fn drop_session(&mut self, session_id: u64) -> bool {
+        self.give_up_session(session_id, "session dropped")
+    }
+
+    /// Cancels `session_id` mid-flight, e.g. a download the user navigated away from:
+    /// its fragments not sent yet are discarded, the unacknowledged ones are never
+    /// retransmitted, its handle fails and the controller is notified with a
+    /// `NodeEvent::SessionCancelled`. Messages still waiting for their turn with ordered
+    /// sends are cancelled as well. Returns whether the session was found.
+    pub fn cancel_session(&mut self, session_id: u64) -> bool {
+        let cancelled =
+            self.cancel_queued(session_id) || self.give_up_session(session_id, "cancelled");
+        if cancelled {
+            self.emit(NodeEvent::SessionCancelled {
+                notification_from: self.id,
+                session_id,
+            });
+        }
+        cancelled
+    }
+
+    /// Forgets `session_id` everywhere it may be held, failing its handle with `reason`.
+    fn give_up_session(&mut self, session_id: u64, reason: &str) -> bool {
+        let paced = self.paced_fragments(session_id);
+        for pacer in self.pacers.values_mut() {
+            pacer.forget_session(session_id);
+        }
+        let unsent = self.discard_unsent(session_id);
+        let _ = self.session_classes.remove(&session_id);
+        self.settle_session(session_id, SessionStatus::Failed(reason.to_string()));
+        let destinations = self
+            .buffer
+            .sessions_of(session_id)
+            .map(OutgoingSession::destination)
+            .collect::<Vec<_>>();
+        if destinations.is_empty() {
+            self.settle_broadcast_session(session_id, false);
+            return paced + unsent > 0;
+        }
+        for destination in destinations {
+            let Some(session) = self.buffer.remove(session_id, destination) else {
+                continue;
+            };
+            for (fragment_index, _) in session.fragments().filter(|(_, f)| f.is_unacked()) {
+                let _ =
+                    self.fragment_trace
+                        .record(session_id, fragment_index, FragmentFate::Expired);
+            }
+        }
+        let _ = self.throttles.remove(&session_id);
+        let _ = self.pinned_routes.remove(&session_id);
+        let _ = self.multipath_routes.remove(&session_id);
+        self.settle_broadcast_session(session_id, false);
+        true
+    }
+
+    /// Discards the message of `session_id` waiting for a flood and its packets waiting
+    /// for a route or for room in the channel of a neighbor, returns how many there were.
+    fn discard_unsent(&mut self, session_id: u64) -> usize {
+        let before = self.pending_ser_requests.len() + self.packets_to_send.len();
+        self.pending_ser_requests
+            .retain(|req| req.session_id != Some(session_id));
+        self.packets_to_send.retain(|p| p.session_id != session_id);
+        let mut discarded = before - self.pending_ser_requests.len() - self.packets_to_send.len();
+        for backlog in self.backlogs.values_mut() {
+            let before = backlog.len();
+            backlog.retain(|p| p.session_id != session_id);
+            discarded += before - backlog.len();
+        }
+        self.backlogs.retain(|_, backlog| !backlog.is_empty());
+        discarded
+    }
+
+    /// Persists the unacknowledged outgoing sessions into the transfers directory of `state`,
+    /// so that [`Self::load_buffer`] can resume them after a restart.
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub fn save_buffer(&self, state: &NodeState) -> io::Result<()> {
+        let data = serde_json::to_vec(&self.buffer.to_stored()).map_err(io::Error::other)?;
+        fs::write(state.transfers_dir().join(Self::BUFFER_FILE), data)
+    }
+
+    /// Restores the sessions saved by [`Self::save_buffer`] and retransmits their
+    /// unacknowledged fragments. Returns how many sessions were resumed.
+    /// The saved file is removed, so a crash before the next save doesn't resume them twice.
+    /// # Errors
+    /// Returns an error if the file is corrupted or cannot be read.
+    pub fn load_buffer(&mut self, state: &NodeState) -> io::Result<usize> {
+        let path = state.transfers_dir().join(Self::BUFFER_FILE);
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e),
+        };
+        let stored: Vec<StoredFragment> = serde_json::from_slice(&data)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::remove_file(&path)?;
+
+        let sessions = stored.iter().map(|f| f.session_id).collect::<HashSet<_>>();
+        let resumed = self.buffer.restore(stored);
+        for session_id in sessions {
+            // unreachable routes are handled like any retransmission failure
+            let _ = self.force_retry(session_id);
+        }
+        Ok(resumed)
+    }
+
+    /// Serves the session inspection commands (`ListSessions`, `ForceRetry`, `DropSession`),
+    /// replying to the controller with the matching event, and `SetEventFilter`.
+    /// Returns `false` for other commands.
+    /// # Errors
+    /// Returns an error if resending fails.
+    pub fn handle_session_command(&mut self, cmd: &NodeCommand) -> Result<bool, NetworkError> {
+        let event = match cmd {
+            NodeCommand::SetEventFilter(filter) => {
+                self.set_event_filter(*filter);
+                return Ok(true);
+            }
+            NodeCommand::ListSessions => NodeEvent::BufferedSessions {
+                notification_from: self.id,
+                sessions: self.buffered_sessions(),
+            },
+            NodeCommand::ForceRetry(session_id) => match self.force_retry(*session_id)? {
+                Some(fragments) => NodeEvent::SessionRetried {
+                    notification_from: self.id,
+                    session_id: *session_id,
+                    fragments,
+                },
+                None => NodeEvent::SessionNotFound {
+                    notification_from: self.id,
+                    session_id: *session_id,
+                },
+            },
+            NodeCommand::DropSession(session_id) => {
+                if self.drop_session(*session_id) {
+                    NodeEvent::SessionDropped {
+                        notification_from: self.id,
+                        session_id: *session_id,
+                    }
+                } else {
+                    NodeEvent::SessionNotFound {
+                        notification_from: self.id,
+                        session_id: *session_id,
+                    }
+                }
+            }
+            _ => return Ok(false),
+        };
+        self.emit(event);
+        Ok(true)
+    }
+
+    /// Returns the recorded lifecycle of the fragments of `session_id`, oldest first.
+    #[must_use]
+    pub fn trace(&self, session_id: u64) -> Vec<FragmentTraceEntry> {
+        self.fragment_trace.for_session(session_id)
+    }
+
+    /// Retries sending a specific packet identified by `session_id` and `fragment_index` from a specific node.
+    /// If the packet is found in the buffer, it is sent again.
+    /// # Errors
+    /// Returns an error if sending fails.
+    pub fn retry_send(
+        &mut self,
+        session_id: u64,
+        fragment_index: u64,
+        from: NodeId,
+    ) -> Result<(), NetworkError> {
+        match self.buffer.routed_through(session_id, fragment_index, from) {
+            Some(destination) => self.resend(session_id, destination, fragment_index),
+            None => Ok(()),
+        }
+    }
+
+    /// Sends the unacknowledged fragment `fragment_index` of the session again, along
+    /// its latest route.
+    fn resend(
+        &mut self,
+        session_id: u64,
+        destination: NodeId,
+        fragment_index: u64,
+    ) -> Result<(), NetworkError> {
+        if let Some(packet) = self
+            .buffer
+            .unacked(session_id, destination, fragment_index)
+            .map(|fragment| fragment.packet().clone())
+        {
+            #[cfg(feature = "telemetry")]
+            tracing::debug!(session_id, fragment_index, "fragment retransmitted");
+            self.try_send(packet)
+                .map_err(|e| e.in_session(session_id))?;
+            self.retransmissions += 1;
+        }
+        Ok(())
+    }
+
+    /// Sends an acknowledgment packet for a specific session and fragment index.
+    /// The acknowledgment is sent to the source routing header (shr) provided.
+    /// # Errors
+    /// Returns an error if sending fails.
+    pub fn send_ack(
+        &mut self,
+        shr: SourceRoutingHeader,
+        session_id: u64,
+        fragment_index: u64,
+    ) -> Result<(), NetworkError> {
+        let packet = Packet::new_ack(shr, session_id, fragment_index);
+        self.try_send(packet).map_err(|e| e.in_session(session_id))
+    }
+
+    /// Answers `original`, a packet received by this node and not processable, with a
+    /// Nack of `nack_type` travelling back to its source through the hops it came from,
+    /// as the protocol requires: e.g. `ErrorInRouting` when its next hop is not a
+    /// neighbor or `UnexpectedRecipient` when this node is not the hop expected.
+    /// `original` must be as received, its hop index on this node.
+    /// Acks, Nacks and flood responses are never nacked: as the protocol prescribes
+    /// they are handed to the controller instead with a `NodeEvent::ControllerShortcut`,
+    /// as are Nacks whose way back starts with a node that is not a neighbor.
+    /// # Errors
+    /// `NoDestination` if `original` is a flood request or came from no other node,
+    /// `ControllerDisconnected` if the controller is needed but unreachable,
+    /// or any error returned while sending the Nack.
+    pub fn send_nack(&mut self, original: &Packet, nack_type: NackType) -> Result<(), NetworkError> {
+        let fragment_index = match &original.pack_type {
+            PacketType::MsgFragment(fragment) => fragment.fragment_index,
+            PacketType::FloodRequest(_) => return Err(RoutingError::NoDestination.into()),
+            PacketType::Ack(_) | PacketType::Nack(_) | PacketType::FloodResponse(_) => {
+                return if self.emit(NodeEvent::ControllerShortcut(original.clone())) {
+                    Ok(())
+                } else {
+                    Err(ChannelError::ControllerDisconnected.into())
+                };
+            }
+        };
+        let header = &original.routing_header;
+        let previous = header
+            .hops
+            .get(..header.hop_index)
+            .filter(|previous| !previous.is_empty())
+            .ok_or(RoutingError::NoDestination)?;
+        let source = previous[0];
+        let hops = std::iter::once(self.id)
+            .chain(previous.iter().rev().copied())
+            .collect::<Vec<_>>();
+        let shr = SourceRoutingHeader::new(hops, 1);
+        let nack = Nack {
+            fragment_index,
+            nack_type,
+        };
+        self.nacks_sent += 1;
+        if self.is_valid_route(&shr, source) {
+            return self
+                .try_send(Packet::new_nack(shr, original.session_id, nack))
+                .map_err(|e| e.in_session(original.session_id).towards(source));
+        }
+        let packet = Packet::new_nack(shr, original.session_id, nack);
+        if self.emit(NodeEvent::ControllerShortcut(packet)) {
+            Ok(())
+        } else {
+            Err(ChannelError::ControllerDisconnected.into())
+        }
+    }
+
+    /// Sends a Nack of `nack_type` for a specific session and fragment index along the
+    /// source routing header `shr`, starting with this node. Unlike [`Self::send_nack`],
+    /// the route is given as is and never handed to the controller.
+    /// # Errors
+    /// Returns an error if sending fails.
+    pub fn send_nack_along(
+        &mut self,
+        shr: SourceRoutingHeader,
+        session_id: u64,
+        fragment_index: u64,
+        nack_type: NackType,
+    ) -> Result<(), NetworkError> {
+        let nack = Nack {
+            fragment_index,
+            nack_type,
+        };
+        self.nacks_sent += 1;
+        self.try_send(Packet::new_nack(shr, session_id, nack))
+            .map_err(|e| e.in_session(session_id))
+    }
+
+    /// Returns the Nack a fragment received along `shr` calls for on this node, `None`
+    /// if the fragment is for it: `UnexpectedRecipient` if this node is not the current
+    /// hop or the fragment must go further, since only drones forward, and
+    /// `DestinationIsDrone` if this node is a drone.
+    #[must_use]
+    pub fn misdelivery(&self, shr: &SourceRoutingHeader) -> Option<NackType> {
+        if shr.hops.get(shr.hop_index) != Some(&self.id) || shr.hop_index + 1 < shr.hops.len() {
+            Some(NackType::UnexpectedRecipient(self.id))
+        } else if self.node_type == NodeType::Drone {
+            Some(NackType::DestinationIsDrone)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_ack_policy(&mut self, policy: AckPolicy) {
+        self.ack_policy = policy;
+    }
+
+    #[must_use]
+    pub fn ack_policy(&self) -> AckPolicy {
+        self.ack_policy
+    }
+
+    /// Holds Acks back for at most `delay`, so that a message sent meanwhile to the same
+    /// peer carries them along its route instead of looking up a route for each Ack.
+    /// Acks still waiting when the delay expires are sent by [`Self::tick`] as usual.
+    /// `None` (the default) sends Acks immediately, releasing the ones held.
+    /// # Errors
+    /// Returns any error returned while sending the released Acks.
+    pub fn set_ack_piggybacking(&mut self, delay: Option<Duration>) -> Result<(), NetworkError> {
+        self.ack_delay = delay;
+        if delay.is_none() {
+            self.flush_expired_acks()?;
+        }
+        Ok(())
+    }
+
+    /// Batches the Acks of the fragments received for at most `delay` per session, then
+    /// acknowledges them at once with [`AggregateAck`]s, sparing the network an Ack per
+    /// fragment. `None` (the default) sends one Ack per fragment, releasing the batches.
+    /// # Errors
+    /// Returns any error returned while sending the released Acks.
+    pub fn set_ack_aggregation(&mut self, delay: Option<Duration>) -> Result<(), NetworkError> {
+        self.ack_aggregation = delay;
+        if delay.is_none() {
+            self.flush_aggregated_acks()?;
+        }
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn ack_aggregation(&self) -> Option<Duration> {
+        self.ack_aggregation
+    }
+
+    /// Acknowledges `fragment`, received with the `incoming` routing header: batched with
+    /// the other fragments of the session if Acks are aggregated, see [`Self::acknowledge`]
+    /// otherwise. Reserved control fragments, aggregate Acks included, are always
+    /// acknowledged on their own.
+    /// # Errors
+    /// Returns any error returned by [`Self::acknowledge`].
+    pub fn acknowledge_fragment(
+        &mut self,
+        incoming: &SourceRoutingHeader,
+        session_id: u64,
+        fragment: &Fragment,
+    ) -> Result<(), NetworkError> {
+        let Some(source) = incoming.hops.first().copied() else {
+            return Err(RoutingError::NoDestination.into());
+        };
+        if self.ack_aggregation.is_none() || is_reserved_control_fragment(fragment) {
+            return self.acknowledge(incoming, session_id, fragment.fragment_index);
+        }
+        let _ = self
+            .aggregated_acks
+            .entry((source, session_id))
+            .or_insert_with(|| AggregatedAcks {
+                incoming: incoming.clone(),
+                indexes: BTreeSet::new(),
+                since: self.clock.now(),
+            })
+            .indexes
+            .insert(fragment.fragment_index);
+        Ok(())
+    }
+
+    /// Sends the batches of Acks older than the aggregation delay, or all of them once
+    /// disabled. A batch goes back along the reversed header of its fragments; if that
+    /// route is not valid, its Acks are routed one by one as usual.
+    fn flush_aggregated_acks(&mut self) -> Result<(), NetworkError> {
+        let delay = self.ack_aggregation;
+        let now = self.clock.now();
+        let expired = self
+            .aggregated_acks
+            .iter()
+            .filter(|(_, batch)| delay.is_none_or(|d| now.duration_since(batch.since) >= d))
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        for key in expired {
+            let Some(batch) = self.aggregated_acks.remove(&key) else {
+                continue;
+            };
+            let (source, session_id) = key;
+            let mut reversed = batch.incoming.clone();
+            reversed.reverse();
+            reversed.hop_index = 1;
+            if !self.is_valid_route(&reversed, source) {
+                for fragment_index in batch.indexes {
+                    self.route_ack(&batch.incoming, session_id, fragment_index)?;
+                }
+                continue;
+            }
+            let acks = AggregateAck::for_indexes(session_id, &batch.indexes);
+            for payload in acks.iter().filter_map(|ack| serde_json::to_vec(ack).ok()) {
+                let fragment = reserved_control_fragment(&payload)?;
+                self.update_session_id(None);
+                let packet = Packet::new_fragment(reversed.clone(), self.session_id, fragment);
+                self.try_send(packet)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles an aggregate Ack received from `from`, passing each fragment it covers and
+    /// still awaits an Ack to [`Self::handle_ack`].
+    pub fn handle_aggregate_ack(&mut self, ack: &AggregateAck, from: NodeId) {
+        let AggregateAck::Ranges { session_id, .. } = ack;
+        let session_id = *session_id;
+        let acked = self
+            .buffer
+            .session(session_id, from)
+            .map(|session| {
+                session
+                    .fragments()
+                    .filter(|(index, fragment)| fragment.is_unacked() && ack.contains(*index))
+                    .map(|(index, _)| index)
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        for fragment_index in acked {
+            self.handle_ack(&Ack { fragment_index }, session_id, from);
+        }
+    }
+
+    /// Acknowledges a fragment received with the `incoming` routing header,
+    /// routing the Ack according to the [`AckPolicy`]. If the resulting route
+    /// is not valid the Ack is sent through the controller shortcut instead.
+    /// With piggybacking enabled the Ack is held until a message to the sender leaves.
+    /// # Errors
+    /// `NoDestination` if the incoming header is empty,
+    /// `ControllerDisconnected` if the shortcut is needed but the controller is unreachable,
+    /// or any error returned while sending the Ack.
+    pub fn acknowledge(
+        &mut self,
+        incoming: &SourceRoutingHeader,
+        session_id: u64,
+        fragment_index: u64,
+    ) -> Result<(), NetworkError> {
+        if incoming.hops.is_empty() {
+            return Err(RoutingError::NoDestination.into());
+        }
+        if self.ack_delay.is_some() && self.ack_policy != AckPolicy::ControllerShortcut {
+            self.pending_acks.push(PendingAck {
+                incoming: incoming.clone(),
+                session_id,
+                fragment_index,
+                since: self.clock.now(),
+            });
+            return Ok(());
+        }
+        self.route_ack(incoming, session_id, fragment_index)
+    }
+
+    /// Sends the Acks held for `destination` along `shr`, the route of a message
+    /// about to be sent to it.
+    fn flush_piggybacked_acks(
+        &mut self,
+        destination: NodeId,
+        shr: &SourceRoutingHeader,
+    ) -> Result<(), NetworkError> {
+        if self.pending_acks.is_empty() || !self.is_valid_route(shr, destination) {
+            return Ok(());
+        }
+        let (acks, others) = std::mem::take(&mut self.pending_acks)
+            .into_iter()
+            .partition::<Vec<_>, _>(|ack| ack.incoming.hops.first() == Some(&destination));
+        self.pending_acks = others;
+        for ack in acks {
+            let mut route = shr.clone();
+            route.hop_index = 1;
+            self.send_ack(route, ack.session_id, ack.fragment_index)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the held Acks older than the piggybacking delay, or all of them once disabled.
+    fn flush_expired_acks(&mut self) -> Result<(), NetworkError> {
+        let delay = self.ack_delay;
+        let now = self.clock.now();
+        let (expired, waiting) = std::mem::take(&mut self.pending_acks)
+            .into_iter()
+            .partition::<Vec<_>, _>(|ack| delay.is_none_or(|d| now.duration_since(ack.since) >= d));
+        self.pending_acks = waiting;
+        for ack in expired {
+            self.route_ack(&ack.incoming, ack.session_id, ack.fragment_index)?;
+        }
+        Ok(())
+    }
+
+    fn route_ack(
+        &mut self,
+        incoming: &SourceRoutingHeader,
+        session_id: u64,
+        fragment_index: u64,
+    ) -> Result<(), NetworkError> {
+        let source = *incoming.hops.first().ok_or(RoutingError::NoDestination)?;
+        let mut reversed = incoming.clone();
+        reversed.reverse();
+        reversed.hop_index = 1;
+
+        let route = match self.ack_policy {
+            AckPolicy::ReverseHeader => Some(reversed.clone()),
+            AckPolicy::FreshRoute => self.try_find_path(source).ok(),
+            AckPolicy::ControllerShortcut => None,
+        };
+        if let Some(shr) = route.filter(|shr| self.is_valid_route(shr, source)) {
+            return self.send_ack(shr, session_id, fragment_index);
+        }
+
+        let packet = Packet::new_ack(reversed, session_id, fragment_index);
+        if self.emit(NodeEvent::ControllerShortcut(packet)) {
+            Ok(())
+        } else {
+            Err(ChannelError::ControllerDisconnected.into())
+        }
+    }
+
+    /// Checks that `shr` starts at this node, goes through a known neighbor,
+    /// ends at `destination` and never visits a node twice.
+    fn is_valid_route(&self, shr: &SourceRoutingHeader, destination: NodeId) -> bool {
+        let hops = &shr.hops;
+        let mut seen = HashSet::new();
+        hops.len() >= 2
+            && hops[0] == self.id
+            && shr.destination() == Some(destination)
+            && self.neighbors.contains_key(&hops[1])
+            && hops.iter().all(|hop| seen.insert(*hop))
+    }
+
+    /// Returns the hop count and flood response latency observed for `node_id`.
+    #[must_use]
+    pub fn node_metadata(&self, node_id: NodeId) -> Option<NodeMetadata> {
+        self.network_view.node_metadata(node_id)
+    }
+
+    /// Returns the network view built from the flood responses received so far.
+    #[must_use]
+    pub fn network_view(&self) -> &Network {
+        &self.network_view
+    }
+
+    #[must_use]
+    pub fn get_servers(&self) -> Option<Vec<NodeId>> {
+        self.network_view.get_servers()
+    }
+
+    /// Checks the invariants of the send buffer, the network view and the neighbor channels.
+    #[must_use]
+    pub fn selfcheck(&self) -> Vec<Diagnostic> {
+        let mut diagnostics = self.network_view.selfcheck(self.id);
+
+        for (session_id, session) in self.buffer.sessions() {
+            for (key, fragment) in session.fragments() {
+                let fragment_index = fragment.packet().get_fragment_index();
+                if fragment_index != key {
+                    #[allow(clippy::cast_possible_truncation)]
+                    diagnostics.push(Diagnostic::MisplacedBufferedFragment {
+                        session_id,
+                        position: key as usize,
+                        fragment_index,
+                    });
+                }
+            }
+            if session.count(FragmentState::Acked) == session.len() {
+                diagnostics.push(Diagnostic::StaleBufferedSession(session_id));
+            }
+        }
+
+        let adjacents = self
+            .network_view
+            .nodes
+            .iter()
+            .find(|n| n.id == self.id)
+            .map(|n| n.get_adjacents().clone())
+            .unwrap_or_default();
+        for (neighbor, sender) in &self.neighbors {
+            if !adjacents.contains(neighbor) {
+                diagnostics.push(Diagnostic::NeighborNotInView(*neighbor));
+            }
+            if sender.is_full() {
+                diagnostics.push(Diagnostic::NeighborChannelFull(*neighbor));
+            }
+        }
+        for neighbor in self.control_neighbors.keys() {
+            if !self.neighbors.contains_key(neighbor) {
+                diagnostics.push(Diagnostic::OrphanControlChannel(*neighbor));
+            }
+        }
+        diagnostics
+    }
+
+    /// Notifies the controller about the inconsistencies found by a self-check.
+    pub fn report_selfcheck(&self, diagnostics: &[Diagnostic]) {
+        self.emit(NodeEvent::SelfCheckFailed {
+            notification_from: self.id,
+            diagnostics: diagnostics.to_vec(),
+        });
+    }
+}
+
+#[cfg(test)]
+mod routing_handler_tests {
+    use super::*;
+    use crate::congestion::CongestionPhase;
+    use crate::transform::PacketMiddleware;
+    use crate::types::CorrelationId;
+    use crossbeam_channel::{Receiver, bounded, unbounded};
+    use wg_internal::packet::PacketType;
+
+    #[test]
+    /// Tests adding a neighbor
+    fn test_add_neighbor() {
+        let (sender, _receiver) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), sender);
+
+        let (neighbor_sender, _neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+
+        assert!(handler.neighbors.contains_key(&2));
+        assert!(handler.network_view.nodes[0].get_adjacents().contains(&2));
+    }
+
+    #[test]
+    /// Tests removing a neighbor
+    fn test_remove_neighbor() {
+        let (sender, _receiver) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), sender);
+
+        let (neighbor_sender, _neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler.remove_neighbor(2);
+
+        assert!(!handler.neighbors.contains_key(&2));
+        assert!(!handler.network_view.nodes[0].get_adjacents().contains(&2));
+    }
+
+    #[test]
+    /// Tests starting a flood
+    fn test_start_flood() {
+        let (sender, receiver) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), sender);
+
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+
+        handler.start_flood(None).unwrap();
+
+        let packet = receiver.try_recv().unwrap();
+        let packet = packet.into_any();
+        if let Ok(cmd) = packet.downcast::<NodeEvent>() {
+            assert!(matches!(*cmd, NodeEvent::FloodStarted(_, _)));
+        }
+
+        let neighbor_packet = neighbor_receiver.try_recv().unwrap();
+        assert!(matches!(
+            neighbor_packet.pack_type,
+            PacketType::FloodRequest(_)
+        ));
+    }
+
+    #[test]
+    /// Tests that repeated flood requests are forwarded according to the suppression scope
+    fn test_flood_suppression() {
+        let (sender, _receiver) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Drone, HashMap::new(), sender);
+        let mut receivers = HashMap::new();
+        for neighbor in [2, 3, 4] {
+            let (neighbor_sender, neighbor_receiver) = unbounded();
+            handler.add_neighbor(neighbor, neighbor_sender);
+            let _ = receivers.insert(neighbor, neighbor_receiver);
+        }
+        let request = |prev_hop: NodeId| FloodRequest {
+            flood_id: 7,
+            initiator_id: 9,
+            path_trace: vec![(9, NodeType::Client), (prev_hop, NodeType::Drone)],
+        };
+        let forwarded = |handler: &mut RoutingHandler, request: FloodRequest| {
+            handler.handle_flood_request(request, 0).unwrap();
+            receivers
+                .values()
+                .flat_map(Receiver::try_iter)
+                .filter(|p| matches!(p.pack_type, PacketType::FloodRequest(_)))
+                .count()
+        };
+
+        assert_eq!(forwarded(&mut handler, request(2)), 2);
+        assert_eq!(forwarded(&mut handler, request(3)), 0);
+        assert_eq!(handler.floods_suppressed(), 1);
+
+        handler.set_flood_suppression(FloodSuppression::PerNeighbor);
+        assert_eq!(forwarded(&mut handler, request(2)), 2);
+        assert_eq!(forwarded(&mut handler, request(3)), 2);
+        assert_eq!(forwarded(&mut handler, request(3)), 0);
+
+        handler.set_flood_suppression(FloodSuppression::Off);
+        assert_eq!(forwarded(&mut handler, request(3)), 2);
+        assert_eq!(forwarded(&mut handler, request(3)), 2);
+        let mut looped = request(3);
+        looped.path_trace.insert(1, (2, NodeType::Drone));
+        looped.path_trace.insert(2, (1, NodeType::Drone));
+        assert_eq!(forwarded(&mut handler, looped), 0);
+        assert_eq!(handler.stats().floods_suppressed, 3);
+    }
+
+    #[test]
+    /// Tests that the route of a flood response is searched within the search budget
+    fn test_flood_response_budget() {
+        let (sender, _receiver) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Drone, HashMap::new(), sender);
+        let mut receivers = Vec::new();
+        for neighbor in [2, 3] {
+            let (neighbor_sender, neighbor_receiver) = unbounded();
+            handler.add_neighbor(neighbor, neighbor_sender);
+            receivers.push(neighbor_receiver);
+        }
+        let mut response_route = |handler: &mut RoutingHandler, flood_id| {
+            for path_trace in [vec![2], vec![4, 3]] {
+                let mut request = FloodRequest {
+                    flood_id,
+                    initiator_id: 9,
+                    path_trace: vec![(9, NodeType::Client)],
+                };
+                request
+                    .path_trace
+                    .extend(path_trace.into_iter().map(|id| (id, NodeType::Drone)));
+                handler.handle_flood_request(request, 0).unwrap();
+            }
+            receivers
+                .iter_mut()
+                .flat_map(|receiver| receiver.try_iter())
+                .find(|p| matches!(p.pack_type, PacketType::FloodResponse(_)))
+                .map(|p| p.routing_header.hops)
+        };
+
+        assert_eq!(response_route(&mut handler, 7), Some(vec![1, 2, 9]));
+        handler.set_search_budget(SearchBudget {
+            max_expansions: Some(0),
+            time_limit: None,
+        });
+        assert_eq!(response_route(&mut handler, 8), Some(vec![1, 3, 4, 9]));
+    }
+
+    #[test]
+    /// Tests that the flood requests remembered are bounded, expire and can be purged
+    fn test_flood_history() {
+        let (sender, _receiver) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Drone, HashMap::new(), sender);
+        let mut receivers = Vec::new();
+        for neighbor in [2, 3] {
+            let (neighbor_sender, neighbor_receiver) = unbounded();
+            handler.add_neighbor(neighbor, neighbor_sender);
+            receivers.push(neighbor_receiver);
+        }
+        let request = |flood_id: u64| FloodRequest {
+            flood_id,
+            initiator_id: 9,
+            path_trace: vec![(9, NodeType::Client), (2, NodeType::Drone)],
+        };
+        handler.set_flood_history(2, None);
+        for flood_id in 0..3 {
+            handler.handle_flood_request(request(flood_id), 0).unwrap();
+        }
+        assert_eq!(handler.flood_state_len(), 2);
+        // the oldest flood was forgotten, the latest ones are still suppressed
+        handler.handle_flood_request(request(2), 0).unwrap();
+        assert_eq!(handler.floods_suppressed(), 1);
+        handler.handle_flood_request(request(0), 0).unwrap();
+        assert_eq!(handler.floods_suppressed(), 1);
+
+        handler.purge_flood_state();
+        assert_eq!(handler.flood_state_len(), 0);
+        handler.handle_flood_request(request(2), 0).unwrap();
+        assert_eq!(handler.floods_suppressed(), 1);
+
+        handler.set_flood_history(8, Some(Duration::from_millis(20)));
+        assert_eq!(handler.flood_state_len(), 1);
+        std::thread::sleep(Duration::from_millis(30));
+        handler.tick().unwrap();
+        assert_eq!(handler.flood_state_len(), 0);
+        handler.handle_flood_request(request(2), 0).unwrap();
+        assert_eq!(handler.floods_suppressed(), 1);
+        assert_eq!(handler.flood_history(), (8, Some(Duration::from_millis(20))));
+    }
+
+    #[test]
+    /// Tests handling a `FloodResponse`
+    fn test_handle_flood_response() {
+        let (sender, _receiver) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), sender);
+        handler.flood_counter = 1;
+
+        let flood_response = FloodResponse {
+            flood_id: 1,
+            path_trace: vec![(2, NodeType::Drone), (3, NodeType::Client)],
+        };
+        let _ = handler.handle_flood_response(&flood_response);
+
+        assert!(handler.network_view.nodes.iter().any(|n| n.id == 2));
+        assert!(handler.network_view.nodes.iter().any(|n| n.id == 3));
+    }
+
+    #[test]
+    /// Tests that responses to older floods are merged unless they mention removed nodes
+    fn test_stale_flood_response_merge() {
+        let (mut handler, _) = create_test_routing_handler();
+        handler.flood_counter = 3;
+
+        let stale = FloodResponse {
+            flood_id: 2,
+            path_trace: vec![
+                (1, NodeType::Client),
+                (2, NodeType::Drone),
+                (5, NodeType::Server),
+            ],
+        };
+        handler.handle_flood_response(&stale).unwrap();
+        assert!(handler.network_view.nodes.iter().any(|n| n.id == 5));
+        assert_eq!(handler.node_age(5), Some(1));
+
+        handler.remove_neighbor(2);
+        handler.handle_flood_response(&stale).unwrap();
+        assert!(!handler.network_view.nodes.iter().any(|n| n.id == 2));
+
+        handler.set_flood_merge_policy(FloodMergePolicy::LatestOnly);
+        let stale = FloodResponse {
+            flood_id: 2,
+            path_trace: vec![(1, NodeType::Client), (6, NodeType::Server)],
+        };
+        handler.handle_flood_response(&stale).unwrap();
+        assert!(!handler.network_view.nodes.iter().any(|n| n.id == 6));
+    }
+
+    #[test]
+    /// Tests that flood responses confirm links and node types, and that old links age out
+    fn test_edge_aging() {
+        let (mut handler, _) = create_test_routing_handler();
+        let respond = |handler: &mut RoutingHandler, flood_id, path_trace| {
+            let response = FloodResponse {
+                flood_id,
+                path_trace,
+            };
+            handler.handle_flood_response(&response).unwrap();
+        };
+        let (client, drone, server) = (NodeType::Client, NodeType::Drone, NodeType::Server);
+        handler.flood_counter = 1;
+        respond(&mut handler, 1, vec![(1, client), (2, drone), (3, drone), (4, client)]);
+        handler.flood_counter = 3;
+        respond(&mut handler, 3, vec![(1, client), (2, drone), (3, drone), (6, server)]);
+        // stale, still merged
+        respond(&mut handler, 2, vec![(1, client), (2, drone), (4, server)]);
+
+        let node = |handler: &RoutingHandler, id| {
+            handler
+                .network_view
+                .nodes
+                .iter()
+                .find(|n| n.id == id)
+                .cloned()
+                .unwrap()
+        };
+        assert_eq!(node(&handler, 4).get_node_type(), NodeType::Server);
+        assert_eq!(handler.edge_age(4, 3), Some(2));
+        assert_eq!(handler.edge_age(2, 4), Some(1));
+        assert_eq!(handler.edge_age(1, 2), Some(0));
+        assert_eq!(handler.edge_age(1, 4), None);
+
+        assert_eq!(handler.age_out_edges(1), vec![(3, 4)]);
+        assert!(!node(&handler, 3).get_adjacents().contains(&4));
+        assert!(!node(&handler, 4).get_adjacents().contains(&3));
+        assert!(node(&handler, 4).get_adjacents().contains(&2));
+
+        // the link to a neighbor stays
+        handler.flood_counter = 10;
+        assert_eq!(handler.age_out_edges(1), vec![(2, 3), (2, 4), (3, 6)]);
+        assert_eq!(handler.edge_age(1, 2), Some(7));
+    }
+
+    #[test]
+    /// Tests that the errors of the sends carry their session and destination
+    fn test_send_error_context() {
+        let (mut handler, _controller_recv) = create_test_routing_handler();
+        let error = handler.send_control_fragment(b"hi", 9).unwrap_err();
+        assert_eq!(error.code(), "routing.path_not_found");
+        assert_eq!(error.destination(), Some(9));
+
+        // the only neighbor is disconnected, nothing is left to reroute through
+        let error = handler
+            .send_ack(SourceRoutingHeader::new(vec![1, 2], 1), 7, 0)
+            .unwrap_err();
+        assert!(matches!(
+            error.root(),
+            NetworkError::Topology(TopologyError::NoNeighborAssigned)
+        ));
+        assert_eq!(error.session_id(), Some(7));
+    }
+
+    #[test]
+    /// Tests that a pinned route is used as given and released once it fails or delivers
+    fn test_send_message_via() {
+        let (mut handler, _controller_recv) = create_test_routing_handler();
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(3, neighbor_sender);
+
+        assert!(matches!(
+            handler.send_message_via(vec![1, 4, 5], b"hi"),
+            Err(NetworkError::Routing(RoutingError::NodeIsNotANeighbor { node: 4 }))
+        ));
+
+        let session_id = handler.send_message_via(vec![1, 3, 7, 5], b"hi").unwrap();
+        let packet = neighbor_receiver.try_recv().unwrap();
+        assert_eq!(packet.routing_header.hops, vec![1, 3, 7, 5]);
+        assert_eq!(handler.pinned_route(session_id), Some([1, 3, 7, 5].as_slice()));
+
+        let nack = Nack {
+            fragment_index: 0,
+            nack_type: NackType::ErrorInRouting(7),
+        };
+        let _ = handler.handle_nack(&nack, session_id, 3);
+        assert_eq!(handler.pinned_route(session_id), None);
+
+        let session_id = handler.send_message_via(vec![1, 3, 5], b"hi").unwrap();
+        assert!(handler.pinned_route(session_id).is_some());
+        handler.handle_ack(&Ack { fragment_index: 0 }, session_id, 5);
+        assert_eq!(handler.pinned_route(session_id), None);
+    }
+
+    #[test]
+    /// Tests that a drone crashing mid-route is routed around, waiting for a flood if needed
+    fn test_mid_route_crash() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (sender_2, receiver_2) = unbounded();
+        let (sender_6, receiver_6) = unbounded();
+        handler.add_neighbor(2, sender_2);
+        handler.add_neighbor(6, sender_6);
+        let drones = [(2, [1, 3]), (3, [2, 5]), (6, [1, 7]), (7, [6, 8]), (8, [7, 5])];
+        for (id, adjacents) in drones {
+            let drone = Node::new(id, NodeType::Drone, adjacents.to_vec());
+            handler.network_view.add_node(drone);
+        }
+        let server = Node::new(5, NodeType::Server, vec![3, 8]);
+        handler.network_view.add_node(server);
+        let session_id = handler.new_session_id(5);
+        handler.send_message(b"hi", Some(5), Some(session_id)).unwrap();
+        assert_eq!(receiver_2.try_recv().unwrap().routing_header.hops, vec![1, 2, 3, 5]);
+
+        // drone 2 can't reach 3 anymore
+        let nack = |crashed| Nack {
+            fragment_index: 0,
+            nack_type: NackType::ErrorInRouting(crashed),
+        };
+        handler.handle_nack(&nack(3), session_id, 2).unwrap();
+        assert!(handler.neighbors.contains_key(&2));
+        assert!(!handler.network_view().nodes.iter().any(|n| n.get_id() == 3));
+        let fragment = |receiver: &Receiver<Packet>| {
+            receiver
+                .try_iter()
+                .find(|p| matches!(p.pack_type, PacketType::MsgFragment(_)))
+        };
+        assert_eq!(fragment(&receiver_2), None);
+        let resent = fragment(&receiver_6).unwrap();
+        assert_eq!(resent.routing_header.hops, vec![1, 6, 7, 8, 5]);
+
+        // no way around 8 is known: the fragment waits for the flood responses
+        handler.handle_nack(&nack(8), session_id, 7).unwrap();
+        assert_eq!(fragment(&receiver_6), None);
+        let flood_response = FloodResponse {
+            flood_id: handler.flood_counter,
+            path_trace: vec![
+                (1, NodeType::Client),
+                (2, NodeType::Drone),
+                (4, NodeType::Drone),
+                (5, NodeType::Server),
+            ],
+        };
+        handler.handle_flood_response(&flood_response).unwrap();
+        let resent = fragment(&receiver_2).unwrap();
+        assert_eq!(resent.routing_header.hops, vec![1, 2, 4, 5]);
+        assert_eq!(handler.session_buffer().outstanding(session_id, 5), 1);
+    }
+
+    #[test]
+    /// Tests that a failed first hop switches to the precomputed backup route
+    fn test_backup_route() {
+        let (mut handler, _controller_recv) = create_test_routing_handler();
+        let (sender_2, receiver_2) = unbounded();
+        let (sender_3, receiver_3) = unbounded();
+        handler.add_neighbor(2, sender_2);
+        handler.add_neighbor(3, sender_3);
+        handler.network_view.add_node(Node::new(2, NodeType::Drone, vec![1, 4]));
+        handler.network_view.add_node(Node::new(3, NodeType::Drone, vec![1, 4]));
+        handler.network_view.add_node(Node::new(4, NodeType::Server, vec![2, 3]));
+        handler.set_backup_routes(true);
+
+        handler.send_message(b"hi", Some(4), None).unwrap();
+        assert_eq!(receiver_2.try_recv().unwrap().routing_header.hops, vec![1, 2, 4]);
+        assert_eq!(handler.backup_route(4), Some([1, 3, 4].as_slice()));
+
+        drop(receiver_2);
+        handler.send_message(b"hi", Some(4), None).unwrap();
+        assert_eq!(receiver_3.try_recv().unwrap().routing_header.hops, vec![1, 3, 4]);
+        assert_eq!(handler.backup_route(4), None);
+    }
+
+    #[test]
+    /// Tests that multipath sends spread fragments over disjoint routes and retry on another one
+    fn test_multipath() {
+        let (mut handler, _controller_recv) = create_test_routing_handler();
+        let (sender_2, receiver_2) = unbounded();
+        let (sender_3, receiver_3) = unbounded();
+        handler.add_neighbor(2, sender_2);
+        handler.add_neighbor(3, sender_3);
+        handler.network_view.add_node(Node::new(2, NodeType::Drone, vec![1, 4]));
+        handler.network_view.add_node(Node::new(3, NodeType::Drone, vec![1, 4]));
+        handler.network_view.add_node(Node::new(4, NodeType::Server, vec![2, 3]));
+        handler.set_multipath(Some(2));
+
+        let session_id = handler.send_message(&[1; 300], Some(4), None).unwrap().session_id();
+        let indexes = |receiver: &Receiver<Packet>| {
+            receiver
+                .try_iter()
+                .map(|packet| packet.get_fragment_index())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(indexes(&receiver_2), vec![0, 2]);
+        assert_eq!(indexes(&receiver_3), vec![1]);
+        assert_eq!(
+            handler.session_routes(session_id),
+            vec![[1, 2, 4].as_slice(), [1, 3, 4].as_slice()]
+        );
+
+        // the fragment dropped by 2 is retransmitted through 3
+        let nack = Nack {
+            fragment_index: 0,
+            nack_type: NackType::Dropped,
+        };
+        handler.handle_nack(&nack, session_id, 2).unwrap();
+        assert_eq!(indexes(&receiver_3), vec![0]);
+        assert!(receiver_2.try_recv().is_err());
+
+        for fragment_index in 0..3 {
+            handler.handle_ack(&Ack { fragment_index }, session_id, 4);
+        }
+        assert!(handler.session_routes(session_id).is_empty());
+    }
+
+    #[test]
+    /// Tests that routes are served from the cache until the topology changes
+    fn test_route_cache() {
+        let (mut handler, _controller_recv) = create_test_routing_handler();
+        let (sender_2, receiver_2) = unbounded();
+        let (sender_3, receiver_3) = unbounded();
+        handler.add_neighbor(2, sender_2);
+        handler.add_neighbor(3, sender_3);
+        handler.network_view.add_node(Node::new(2, NodeType::Drone, vec![1, 4]));
+        handler.network_view.add_node(Node::new(3, NodeType::Drone, vec![1, 4]));
+        handler.network_view.add_node(Node::new(4, NodeType::Server, vec![2, 3]));
+
+        handler.send_message(b"hi", Some(4), None).unwrap();
+        handler.send_message(b"hi", Some(4), None).unwrap();
+        handler.send_message(b"hi", Some(3), None).unwrap();
+        let stats = handler.route_cache_stats();
+        assert_eq!((stats.hits, stats.misses, stats.cached), (1, 2, 2));
+        assert_eq!(receiver_2.try_iter().count(), 2);
+
+        // only the routes through the removed neighbor are dropped
+        handler.remove_neighbor(2);
+        let stats = handler.route_cache_stats();
+        assert_eq!((stats.invalidations, stats.cached), (1, 1));
+        handler.send_message(b"hi", Some(4), None).unwrap();
+        let hops = receiver_3
+            .try_iter()
+            .map(|packet| packet.routing_header.hops)
+            .collect::<Vec<_>>();
+        assert_eq!(hops, vec![vec![1, 3], vec![1, 3, 4]]);
+
+        handler.invalidate_routes();
+        let stats = handler.route_cache_stats();
+        assert_eq!((stats.hits, stats.misses), (1, 3));
+        assert_eq!((stats.invalidations, stats.cached), (3, 0));
+    }
+
+    #[test]
+    /// Tests that Acks and drops move the congestion window of the destination
+    fn test_congestion_state() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, _neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        handler.set_congestion_reports(Some(Duration::ZERO));
+
+        let session_id = handler.new_session_id(2);
+        handler.send_message(&[1; 400], Some(2), Some(session_id)).unwrap();
+        handler.handle_ack(&Ack { fragment_index: 0 }, session_id, 2);
+        handler.handle_ack(&Ack { fragment_index: 0 }, session_id, 2);
+        let state = handler.congestion_state()[0];
+        assert_eq!((state.destination, state.window, state.in_flight), (2, 5, 3));
+
+        let nack = Nack {
+            fragment_index: 1,
+            nack_type: NackType::Dropped,
+        };
+        handler.handle_nack(&nack, session_id, 2).unwrap();
+        let _ = controller_recv.try_iter().count();
+        handler.tick().unwrap();
+        let reports = controller_recv
+            .try_iter()
+            .filter_map(|event| match event.into_any().downcast::<NodeEvent>() {
+                Ok(event) => match *event {
+                    NodeEvent::CongestionReport { states, .. } => Some(states),
+                    _ => None,
+                },
+                Err(_) => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(reports.len(), 1);
+        assert_eq!(reports[0][0].window, 2);
+        assert_eq!(reports[0][0].phase, CongestionPhase::Recovery);
+    }
+
+    #[test]
+    /// Tests that pacing spaces a window over the measured round trip time
+    fn test_pacing() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        handler.set_pacing(true);
+
+        let session_id = handler.new_session_id(2);
+        handler.send_message(&[1; 100], Some(2), Some(session_id)).unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+        handler.handle_ack(&Ack { fragment_index: 0 }, session_id, 2);
+        let state = handler.congestion_state()[0];
+        assert!(state.srtt.is_some_and(|srtt| srtt >= Duration::from_millis(40)));
+        assert!(state.pacing_interval >= Duration::from_millis(8));
+        assert_eq!(neighbor_receiver.try_iter().count(), 1);
+
+        let session_id = handler.new_session_id(2);
+        handler.send_message(&[1; 500], Some(2), Some(session_id)).unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 1);
+        assert_eq!(handler.paced_fragments(session_id), 3);
+        handler.tick().unwrap();
+        assert_eq!(handler.paced_fragments(session_id), 3);
+
+        std::thread::sleep(state.pacing_interval);
+        handler.tick().unwrap();
+        assert_eq!(handler.paced_fragments(session_id), 2);
+
+        handler.set_pacing(false);
+        handler.tick().unwrap();
+        assert_eq!(handler.paced_fragments(session_id), 0);
+        assert_eq!(neighbor_receiver.try_iter().count(), 3);
+    }
+
+    #[test]
+    /// Tests that interactive fragments overtake queued bulk ones, retransmissions first
+    fn test_session_class() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        handler.set_pacing(true);
+        let session_id = handler.new_session_id(2);
+        handler.send_message(&[1; 100], Some(2), Some(session_id)).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        handler.handle_ack(&Ack { fragment_index: 0 }, session_id, 2);
+        let _ = neighbor_receiver.try_iter().count();
+
+        let bulk = handler.new_session_id(2);
+        handler.send_message(&[1; 500], Some(2), Some(bulk)).unwrap();
+        let chat = handler.new_session_id(2);
+        handler.set_session_class(chat, QosClass::Interactive);
+        assert_eq!(handler.session_class(chat), QosClass::Interactive);
+        handler.send_message(&[2; 200], Some(2), Some(chat)).unwrap();
+        handler.set_pacing(false);
+        handler.tick().unwrap();
+        let order = neighbor_receiver
+            .try_iter()
+            .map(|packet| packet.session_id)
+            .collect::<Vec<_>>();
+        assert_eq!(order, vec![bulk, chat, chat, bulk, bulk, bulk]);
+
+        // fragments already sent are retransmissions, served before first transmissions
+        let sent = handler.buffer.unacked(chat, 2, 1).unwrap().packet().clone();
+        assert_eq!(handler.send_priority(&sent), (QosClass::Interactive, false));
+        let mut fresh = sent.clone();
+        fresh.session_id = handler.new_session_id(2);
+        assert_eq!(handler.send_priority(&fresh), (QosClass::Bulk, true));
+        assert!(handler.drop_session(chat));
+        assert_eq!(handler.session_class(chat), QosClass::Bulk);
+    }
+
+    #[test]
+    /// Tests that a broadcast reaches each destination in its own session and sums up their delivery
+    fn test_broadcast_message() {
+        let (mut handler, controller_recv) = create_test_routing_handler();
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler.network_view.add_node(Node::new(2, NodeType::Drone, vec![1]));
+        handler.network_view.add_node(Node::new(3, NodeType::Client, vec![2]));
+        handler.network_view.add_node(Node::new(4, NodeType::Client, vec![2]));
+        handler.network_view.add_node(Node::new(5, NodeType::Client, vec![2]));
+        handler.set_quota(5, Some(0));
+        let events = || {
+            controller_recv
+                .try_iter()
+                .filter_map(|event| event.into_any().downcast::<NodeEvent>().ok())
+                .filter(|event| {
+                    matches!(
+                        **event,
+                        NodeEvent::BroadcastStarted { .. } | NodeEvent::BroadcastCompleted { .. }
+                    )
+                })
+                .map(|event| *event)
+                .collect::<Vec<_>>()
+        };
+
+        let broadcast_id = handler
+            .broadcast_message(b"news", &[3, 4, 3, 5, 9])
+            .unwrap();
+        let fragments = neighbor_receiver
+            .try_iter()
+            .filter(|packet| matches!(packet.pack_type, PacketType::MsgFragment(_)))
+            .collect::<Vec<_>>();
+        assert_eq!(fragments.len(), 2);
+        assert_eq!(fragments[0].pack_type, fragments[1].pack_type);
+        let (to_3, to_4) = (fragments[0].session_id, fragments[1].session_id);
+        assert_ne!(to_3, to_4);
+        assert_eq!(
+            events(),
+            vec![NodeEvent::BroadcastStarted {
+                notification_from: 1,
+                broadcast_id,
+                sessions: vec![(3, to_3), (4, to_4)],
+                pending: vec![9],
+                failed: vec![5],
+            }]
+        );
+
+        handler.handle_ack(&Ack { fragment_index: 0 }, to_4, 4);
+        handler.handle_ack(&Ack { fragment_index: 0 }, to_3, 3);
+        assert!(events().is_empty());
+
+        // 9 waits for the flood, giving its session up completes the broadcast
+        let to_9 = handler.undelivered_sessions();
+        assert_eq!(to_9.len(), 1);
+        assert!(handler.drop_session(to_9[0]));
+        assert_eq!(
+            events(),
+            vec![NodeEvent::BroadcastCompleted {
+                notification_from: 1,
+                broadcast_id,
+                delivered: vec![4, 3],
+                failed: vec![5, 9],
+            }]
+        );
+    }
+
+    #[test]
+    /// Tests that Nacks go back along the hops the packet came through
+    fn test_send_nack() {
+        let (mut handler, controller_recv) = create_test_routing_handler();
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        let fragment = Fragment {
+            fragment_index: 3,
+            total_n_fragments: 5,
+            length: 0,
+            data: [0; 128],
+        };
+        let received = Packet::new_fragment(
+            SourceRoutingHeader::new(vec![5, 2, 1, 7], 2),
+            9,
+            fragment.clone(),
+        );
+        handler
+            .send_nack(&received, NackType::ErrorInRouting(7))
+            .unwrap();
+        let nack = neighbor_receiver.try_recv().unwrap();
+        assert_eq!(nack.routing_header.hops, vec![1, 2, 5]);
+        assert_eq!(nack.routing_header.hop_index, 1);
+        assert_eq!(nack.session_id, 9);
+        assert_eq!(
+            nack.pack_type,
+            PacketType::Nack(Nack {
+                fragment_index: 3,
+                nack_type: NackType::ErrorInRouting(7)
+            })
+        );
+
+        // this node is not the hop expected
+        let misrouted =
+            Packet::new_fragment(SourceRoutingHeader::new(vec![5, 2, 8], 2), 9, fragment);
+        handler
+            .send_nack(&misrouted, NackType::UnexpectedRecipient(1))
+            .unwrap();
+        assert_eq!(
+            neighbor_receiver.try_recv().unwrap().routing_header.hops,
+            vec![1, 2, 5]
+        );
+
+        let ack = Packet::new_ack(SourceRoutingHeader::new(vec![5, 2, 1, 7], 2), 9, 3);
+        handler.send_nack(&ack, NackType::Dropped).unwrap();
+        assert!(neighbor_receiver.try_recv().is_err());
+        assert!(controller_recv.try_iter().any(|event| matches!(
+            event.into_any().downcast::<NodeEvent>().map(|event| *event),
+            Ok(NodeEvent::ControllerShortcut(packet)) if packet == ack
+        )));
+        assert_eq!(handler.stats().nacks_sent, 2);
+    }
+
+    #[test]
+    /// Tests the Nacks called for by misdelivered fragments and sending them along a route
+    fn test_misdelivery() {
+        let (mut handler, _controller_recv) = create_test_routing_handler();
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        assert_eq!(handler.misdelivery(&SourceRoutingHeader::new(vec![5, 2, 1], 2)), None);
+        let unexpected = Some(NackType::UnexpectedRecipient(1));
+        assert_eq!(handler.misdelivery(&SourceRoutingHeader::new(vec![5, 2, 8], 2)), unexpected);
+        assert_eq!(handler.misdelivery(&SourceRoutingHeader::new(vec![2, 1, 5], 1)), unexpected);
+        let (sender, _receiver) = unbounded();
+        let drone = RoutingHandler::new(1, NodeType::Drone, HashMap::new(), sender);
+        assert_eq!(
+            drone.misdelivery(&SourceRoutingHeader::new(vec![5, 2, 1], 2)),
+            Some(NackType::DestinationIsDrone)
+        );
+
+        handler
+            .send_nack_along(SourceRoutingHeader::new(vec![1, 2, 5], 1), 9, 3, NackType::Dropped)
+            .unwrap();
+        let nack = neighbor_receiver.try_recv().unwrap();
+        assert_eq!(nack.routing_header.hops, vec![1, 2, 5]);
+        assert_eq!(
+            nack.pack_type,
+            PacketType::Nack(Nack {
+                fragment_index: 3,
+                nack_type: NackType::Dropped
+            })
+        );
+        assert_eq!(handler.stats().nacks_sent, 1);
+    }
+
+    #[test]
+    /// Tests that an `UnexpectedRecipient` Nack forgets the link it reveals without flooding
+    fn test_unexpected_recipient_nack() {
+        let (mut handler, _controller_recv) = create_test_routing_handler();
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Drone, vec![1, 3]));
+        handler
+            .network_view
+            .add_node(Node::new(3, NodeType::Drone, vec![2, 4]));
+        handler
+            .network_view
+            .add_node(Node::new(4, NodeType::Server, vec![3]));
+        let session = handler.send_message(b"hi", Some(4), None).unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 1);
+        assert_eq!(handler.route_cache_stats().cached, 1);
+
+        // 3 is no drone after all and can't relay to 4
+        let nack = Nack {
+            fragment_index: 0,
+            nack_type: NackType::UnexpectedRecipient(3),
+        };
+        handler.handle_nack(&nack, session.session_id(), 3).unwrap();
+        assert_eq!(handler.route_cache_stats().cached, 0);
+        assert!(handler.network_view.find_path(1, 4).is_none());
+        assert!(
+            neighbor_receiver
+                .try_iter()
+                .all(|packet| !matches!(packet.pack_type, PacketType::FloodRequest(_)))
+        );
+    }
+
+    #[test]
+    /// Tests that routing goes on without a controller and that events resume once reattached
+    fn test_controller_disconnection() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        drop(controller_recv);
+
+        handler.send_message(b"hi", Some(2), None).unwrap();
+        handler.start_flood(None).unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 2);
+        assert!(!handler.is_controller_attached());
+        assert_eq!(handler.dropped_events(), 3);
+
+        let (controller_send, controller_recv) = unbounded();
+        handler.reattach_controller(controller_send);
+        handler.send_message(b"hi", Some(2), None).unwrap();
+        assert!(handler.is_controller_attached());
+        assert_eq!(handler.dropped_events(), 0);
+        assert_eq!(controller_recv.try_iter().count(), 2);
+    }
+
+    #[test]
+    /// Tests that events below the filter are skipped, replies to commands excepted
+    fn test_event_filter() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, _neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        assert!(handler
+            .handle_session_command(&NodeCommand::SetEventFilter(Severity::Warn))
+            .unwrap());
+        assert_eq!(handler.event_filter(), Severity::Warn);
+
+        handler.send_message(b"hi", Some(2), None).unwrap();
+        handler.start_flood(None).unwrap();
+        assert_eq!(controller_recv.try_iter().count(), 0);
+        assert!(handler.is_controller_attached());
+
+        handler.set_quota(2, Some(1));
+        let _ = handler.send_message(b"hi", Some(2), None);
+        assert!(handler.handle_session_command(&NodeCommand::ListSessions).unwrap());
+        let severities = controller_recv
+            .try_iter()
+            .filter_map(|event| event.into_any().downcast::<NodeEvent>().ok())
+            .map(|event| event.severity())
+            .collect::<Vec<_>>();
+        // QuotaExceeded, SendFailed, BufferedSessions
+        assert_eq!(severities, vec![Severity::Warn, Severity::Warn, Severity::Info]);
+    }
+
+    #[test]
+    /// Tests that the events of a message and the Acks sent back share its correlation id
+    fn test_correlation_id() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+
+        handler.send_message(b"hi", Some(2), None).unwrap();
+        let fragment = neighbor_receiver.try_recv().unwrap();
+        let expected = CorrelationId {
+            origin: 1,
+            session_id: fragment.session_id,
+        };
+        let ids = controller_recv
+            .try_iter()
+            .filter_map(|event| event.into_any().downcast::<NodeEvent>().ok())
+            .map(|event| event.correlation_id())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, vec![Some(expected); 2]);
+
+        let mut back = fragment.routing_header.clone();
+        back.reverse();
+        let ack = Packet::new_ack(back, fragment.session_id, 0);
+        assert_eq!(NodeEvent::PacketSent(ack).correlation_id(), Some(expected));
+        assert_eq!(NodeEvent::NodeRemoved(2).correlation_id(), None);
+        assert_eq!(expected.to_string(), format!("1:{}", fragment.session_id));
+    }
+
+    #[test]
+    /// Tests that the event history keeps the latest events emitted only
+    fn test_event_history() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        handler.start_flood(None).unwrap();
+        assert!(handler.event_history().is_empty());
+
+        handler.set_event_history(2);
+        for _ in 0..3 {
+            handler.start_flood(None).unwrap();
+        }
+        let floods = handler
+            .event_history()
+            .into_iter()
+            .map(|(_, event)| event)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            floods,
+            vec![NodeEvent::FloodStarted(3, 1), NodeEvent::FloodStarted(4, 1)]
+        );
+    }
+
+    #[test]
+    /// Tests that the packet journal keeps the latest packets sent only
+    fn test_packet_journal() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_send, _neighbor_recv) = unbounded();
+        handler.add_neighbor(2, neighbor_send);
+        handler.start_flood(None).unwrap();
+        assert!(handler.packet_journal().is_empty());
+
+        handler.set_packet_journal(2);
+        for _ in 0..3 {
+            handler.start_flood(None).unwrap();
+        }
+        let floods = handler
+            .packet_journal()
+            .into_iter()
+            .map(|(_, direction, packet)| match packet.pack_type {
+                PacketType::FloodRequest(request) => (direction, request.flood_id),
+                other => panic!("unexpected {other:?}"),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            floods,
+            vec![(PacketDirection::Sent, 3), (PacketDirection::Sent, 4)]
+        );
+    }
+
+    /// Swallows every packet sent.
+    #[derive(Debug)]
+    struct DropOutgoing;
+
+    impl PacketMiddleware for DropOutgoing {
+        fn outgoing(&mut self, _packet: Packet) -> Option<Packet> {
+            None
+        }
+    }
+
+    #[test]
+    /// Tests that a fragment dropped by a middleware is not reported as sent but is still
+    /// timed for its retransmission
+    fn test_dropped_by_middleware() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_send, neighbor_recv) = unbounded();
+        handler.add_neighbor(2, neighbor_send);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        handler
+            .middlewares()
+            .push(Arc::new(std::sync::Mutex::new(DropOutgoing)));
+
+        let session = handler.send_message(b"hello", Some(2), None).unwrap();
+        assert!(neighbor_recv.is_empty());
+        assert_eq!(handler.bytes_sent(2), 0);
+        assert!(
+            !controller_recv
+                .try_iter()
+                .filter_map(|event| event.into_any().downcast::<NodeEvent>().ok())
+                .any(|event| matches!(*event, NodeEvent::PacketSent(_)))
+        );
+        assert!(handler.buffer.was_sent(session.session_id(), 2, 0));
+    }
+
+    #[test]
+    /// Tests that keep-alives can be sent as reserved control fragments
+    fn test_reserved_keep_alive() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        handler.set_reserved_keep_alives(true);
+
+        handler.send_keep_alive(b"ping", 2).unwrap();
+        let PacketType::MsgFragment(fragment) = neighbor_receiver.try_recv().unwrap().pack_type
+        else {
+            panic!("expected a fragment");
+        };
+        assert!(is_reserved_control_fragment(&fragment));
+        assert_eq!(&fragment.data[..usize::from(fragment.length)], b"ping");
+        assert!(matches!(
+            reserved_control_fragment(&[0; 129]),
+            Err(RoutingError::PayloadTooLarge { len: 129 })
+        ));
+
+        let mut assembler = crate::FragmentAssembler::default();
+        assert_eq!(assembler.add_fragment(fragment, 7, 2), None);
+        assert!(assembler.selfcheck().is_empty());
+    }
+
+    #[test]
+    /// Tests that control packets prefer the dedicated control channel
+    fn test_control_plane_channel() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (data_send, data_recv) = unbounded();
+        let (control_send, control_recv) = unbounded();
+        handler.add_neighbor(2, data_send);
+        handler.add_control_neighbor(2, control_send);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+
+        handler
+            .send_ack(SourceRoutingHeader::new(vec![1, 2], 1), 7, 0)
+            .unwrap();
+        handler.send_message(b"data", Some(2), None).unwrap();
+
+        assert!(matches!(control_recv.try_recv().unwrap().pack_type, PacketType::Ack(_)));
+        assert!(control_recv.try_recv().is_err());
+        assert!(matches!(
+            data_recv.try_recv().unwrap().pack_type,
+            PacketType::MsgFragment(_)
+        ));
+    }
+
+    #[test]
+    /// Tests that messages exceeding the destination quota are refused and reported
+    fn test_quota_exceeded() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, _neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        handler.set_quota(2, Some(256));
+
+        handler.send_message(&[1; 200], Some(2), None).unwrap();
+        assert_eq!(handler.bytes_sent(2), 256);
+        let refused = handler.send_message(b"more", Some(2), None).unwrap_err();
+        assert!(matches!(
+            refused.root(),
+            NetworkError::Routing(RoutingError::QuotaExceeded { destination: 2 })
+        ));
+        assert_eq!(refused.session_id(), Some(handler.session_id));
+        let exceeded = controller_recv.try_iter().any(|e| {
+            e.into_any()
+                .downcast::<NodeEvent>()
+                .is_ok_and(|e| matches!(*e, NodeEvent::QuotaExceeded { used: 256, .. }))
+        });
+        assert!(exceeded);
+    }
+
+    #[test]
+    /// Tests that a full neighbor channel backlogs packets instead of dropping the neighbor
+    fn test_backpressure() {
+        let (controller_send, controller_recv) = unbounded();
+        let (neighbor_sender, neighbor_receiver) = bounded(1);
+        let mut handler = RoutingHandler::with_bounded_neighbors(
+            1,
+            NodeType::Client,
+            HashMap::new(),
+            controller_send,
+            2,
+        );
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+
+        // 3 fragments: one in the channel, two in the backlog
+        let session = handler.send_message(&[1; 300], Some(2), None).unwrap();
+        assert_eq!(handler.backlog(2), 2);
+        let refused = handler.send_message(b"more", Some(2), None).unwrap_err();
+        assert!(matches!(
+            refused.root(),
+            NetworkError::Channel(ChannelError::Full { neighbor: 2 })
+        ));
+        assert!(handler.neighbors.contains_key(&2));
+        let events = || {
+            controller_recv
+                .try_iter()
+                .filter_map(|e| e.into_any().downcast::<NodeEvent>().ok())
+                .collect::<Vec<_>>()
+        };
+        let sent = |events: &[Box<NodeEvent>]| {
+            events
+                .iter()
+                .filter(|e| matches!(***e, NodeEvent::PacketSent(_)))
+                .count()
+        };
+        let session_id = session.session_id();
+        let queued = events();
+        let backpressure = queued
+            .iter()
+            .filter(|e| matches!(***e, NodeEvent::Backpressure(2)))
+            .count();
+        assert_eq!(backpressure, 1);
+        // backlogged packets are reported once they leave
+        assert_eq!(sent(&queued), 1);
+        assert_eq!(handler.session_buffer().outstanding(session_id, 2), 1);
+
+        // room made by the neighbor is filled on the next tick, in order
+        let first = neighbor_receiver.try_recv().unwrap();
+        handler.tick().unwrap();
+        assert_eq!(handler.backlog(2), 1);
+        assert_eq!(sent(&events()), 1);
+        assert_eq!(handler.session_buffer().outstanding(session_id, 2), 2);
+        let second = neighbor_receiver.try_recv().unwrap();
+        let index = |packet: &Packet| match &packet.pack_type {
+            PacketType::MsgFragment(fragment) => fragment.fragment_index,
+            _ => panic!("expected a fragment"),
+        };
+        assert_eq!((index(&first), index(&second)), (0, 1));
+
+        // a cancelled session leaves nothing behind in the backlog
+        assert!(handler.cancel_session(session.session_id()));
+        assert_eq!(handler.backlog(2), 0);
+        handler.tick().unwrap();
+        assert!(neighbor_receiver.try_recv().is_err());
+    }
+
+    #[test]
+    /// Tests the Ack routes produced by each `AckPolicy`
+    fn test_ack_policy() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Server, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(3, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(3, NodeType::Drone, vec![1, 9]));
+        handler
+            .network_view
+            .add_node(Node::new(9, NodeType::Client, vec![3]));
+
+        // the fragment came through 4, which is not a neighbor anymore
+        let incoming = SourceRoutingHeader::new(vec![9, 4, 1], 2);
+        handler.acknowledge(&incoming, 5, 0).unwrap();
+        assert!(neighbor_receiver.try_recv().is_err());
+        let shortcut = controller_recv.try_iter().any(|e| {
+            e.into_any()
+                .downcast::<NodeEvent>()
+                .is_ok_and(|e| matches!(*e, NodeEvent::ControllerShortcut(_)))
+        });
+        assert!(shortcut);
+
+        handler.set_ack_policy(AckPolicy::FreshRoute);
+        handler.acknowledge(&incoming, 5, 0).unwrap();
+        let ack = neighbor_receiver.try_recv().unwrap();
+        assert!(matches!(ack.pack_type, PacketType::Ack(_)));
+        assert_eq!(ack.routing_header.hops, vec![1, 3, 9]);
+    }
+
+    #[test]
+    /// Tests that inconsistent neighbor channels are reported by the self-check
+    fn test_selfcheck() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, _neighbor_receiver) = crossbeam_channel::bounded(1);
+        handler.add_neighbor(2, neighbor_sender.clone());
+        assert!(handler.selfcheck().is_empty());
+
+        neighbor_sender.send(Packet::new_ack(SourceRoutingHeader::empty_route(), 0, 0)).unwrap();
+        let (control_sender, _control_receiver) = unbounded();
+        handler.add_control_neighbor(5, control_sender);
+        assert_eq!(
+            handler.selfcheck(),
+            vec![
+                Diagnostic::NeighborChannelFull(2),
+                Diagnostic::OrphanControlChannel(5)
+            ]
+        );
+    }
+
+    #[test]
+    /// Tests that a throttled session sends its fragments no faster than its rate
+    fn test_session_rate() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        let clock = Clock::simulated();
+        handler.set_clock(clock.clone());
+
+        let session_id = handler.new_session_id(2);
+        handler.set_session_rate(session_id, Some(128 * 20));
+        handler.send_message(&[1; 128 * 25], Some(2), Some(session_id)).unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 20);
+        assert_eq!(handler.throttled_fragments(session_id), 5);
+
+        handler.tick().unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 0);
+        // 125 ms at 20 fragments per second cover 2.5 fragments
+        clock.advance(Duration::from_millis(125));
+        handler.tick().unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 2);
+
+        handler.set_session_rate(session_id, None);
+        handler.tick().unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 3);
+        assert_eq!(handler.throttled_fragments(session_id), 0);
+    }
+
+    #[test]
+    /// Tests that a windowed session keeps at most the window in flight and advances on Acks
+    fn test_send_window() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        handler.set_send_window(Some(2));
+
+        let session_id = handler.new_session_id(2);
+        handler
+            .send_message(&[1; 128 * 5], Some(2), Some(session_id))
+            .unwrap();
+        let indexes = |receiver: &Receiver<Packet>| {
+            receiver
+                .try_iter()
+                .map(|packet| packet.get_fragment_index())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(indexes(&neighbor_receiver), vec![0, 1]);
+        assert_eq!(handler.held_fragments(session_id), 3);
+
+        handler.handle_ack(&Ack { fragment_index: 1 }, session_id, 2);
+        assert_eq!(indexes(&neighbor_receiver), vec![2]);
+        handler.handle_ack(&Ack { fragment_index: 1 }, session_id, 2);
+        assert!(indexes(&neighbor_receiver).is_empty());
+
+        handler.set_send_window(None);
+        handler.tick().unwrap();
+        assert_eq!(indexes(&neighbor_receiver), vec![3, 4]);
+        assert_eq!(handler.held_fragments(session_id), 0);
+        assert_eq!(handler.buffered_sessions()[0].total_fragments, 5);
+    }
+
+    #[test]
+    /// Tests that the fragment size negotiated with a peer overrides the default one
+    fn test_fragment_size() {
+        let (mut handler, _controller_recv) = create_test_routing_handler();
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler.network_view.add_node(Node::new(2, NodeType::Drone, vec![1]));
+        handler.network_view.add_node(Node::new(3, NodeType::Server, vec![2]));
+        handler.network_view.add_node(Node::new(4, NodeType::Server, vec![2]));
+        handler.set_fragment_size(500);
+        assert_eq!(handler.fragment_size(), MAX_FRAGMENT_SIZE);
+        handler.set_fragment_size(100);
+        handler.set_peer_fragment_size(4, Some(40));
+        assert_eq!(handler.peer_fragment_size(3), 100);
+
+        handler.send_message(&[1; 120], Some(3), None).unwrap();
+        handler.send_message(&[1; 120], Some(4), None).unwrap();
+        let lengths = neighbor_receiver
+            .try_iter()
+            .filter_map(|packet| match packet.pack_type {
+                PacketType::MsgFragment(fragment) => Some(fragment.length),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(lengths, vec![100, 20, 40, 40, 40]);
+
+        handler.set_peer_fragment_size(4, None);
+        assert_eq!(handler.peer_fragment_size(4), 100);
+    }
+
+    #[test]
+    /// Tests that a session is given up once a fragment exhausts its retries
+    fn test_retry_policy() {
+        let (controller_send, _controller_recv) = unbounded();
+        let config = CommonConfig::default()
+            .with_toml("fragment_size = 64\n[retry]\nmax_retries = 1")
+            .unwrap();
+        let mut handler =
+            RoutingHandler::with_config(1, NodeType::Client, HashMap::new(), controller_send, &config);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+
+        let session_id = handler.new_session_id(2);
+        handler.send_message(&[1; 100], Some(2), Some(session_id)).unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 2);
+
+        let nack = Nack {
+            fragment_index: 1,
+            nack_type: NackType::Dropped,
+        };
+        handler.handle_nack(&nack, session_id, 2).unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 1);
+        handler.handle_nack(&nack, session_id, 2).unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 0);
+        assert_eq!(
+            handler.trace(session_id).last().map(|e| e.fate),
+            Some(FragmentFate::Expired)
+        );
+        assert!(handler.selfcheck().is_empty());
+    }
+
+    #[test]
+    /// Tests listing, force-retrying and dropping buffered sessions through commands
+    fn test_session_commands() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+
+        let session_id = handler.new_session_id(2);
+        handler.send_message(&[1; 300], Some(2), Some(session_id)).unwrap();
+        handler.handle_ack(&Ack { fragment_index: 0 }, session_id, 2);
+        let _ = neighbor_receiver.try_iter().count();
+        let _ = controller_recv.try_iter().count();
+
+        let reply = |handler: &mut RoutingHandler, cmd: NodeCommand| {
+            assert!(handler.handle_session_command(&cmd).unwrap());
+            *controller_recv
+                .try_iter()
+                .last()
+                .unwrap()
+                .into_any()
+                .downcast::<NodeEvent>()
+                .unwrap()
+        };
+        let sessions = vec![BufferedSession {
+            session_id,
+            destination: Some(2),
+            total_fragments: 3,
+            acked_fragments: 1,
+        }];
+        assert_eq!(
+            reply(&mut handler, NodeCommand::ListSessions),
+            NodeEvent::BufferedSessions { notification_from: 1, sessions }
+        );
+        assert!(matches!(
+            reply(&mut handler, NodeCommand::ForceRetry(session_id)),
+            NodeEvent::SessionRetried { fragments: 2, .. }
+        ));
+        assert_eq!(neighbor_receiver.try_iter().count(), 2);
+        assert!(matches!(
+            reply(&mut handler, NodeCommand::DropSession(session_id)),
+            NodeEvent::SessionDropped { .. }
+        ));
+        assert!(handler.buffered_sessions().is_empty());
+        assert!(matches!(
+            reply(&mut handler, NodeCommand::ForceRetry(session_id)),
+            NodeEvent::SessionNotFound { .. }
+        ));
+        assert!(!handler.handle_session_command(&NodeCommand::Shutdown).unwrap());
+    }
+
+    #[test]
+    /// Tests that held Acks leave with the next message to their peer or after the delay
+    fn test_ack_piggybacking() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        handler
+            .set_ack_piggybacking(Some(std::time::Duration::from_mins(1)))
+            .unwrap();
+
+        let incoming = SourceRoutingHeader::new(vec![2, 1], 1);
+        handler.acknowledge(&incoming, 40, 0).unwrap();
+        handler.acknowledge(&incoming, 40, 1).unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 0);
+
+        handler.send_message(b"response", Some(2), None).unwrap();
+        let kinds = neighbor_receiver
+            .try_iter()
+            .map(|p| matches!(p.pack_type, PacketType::Ack(_)))
+            .collect::<Vec<_>>();
+        assert_eq!(kinds, vec![true, true, false]);
+
+        handler.acknowledge(&incoming, 40, 2).unwrap();
+        handler.tick().unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 0);
+        handler.set_ack_piggybacking(None).unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 1);
+    }
+
+    #[test]
+    /// Tests that aggregated Acks leave as one control fragment and are understood back
+    fn test_ack_aggregation() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        handler
+            .set_ack_aggregation(Some(std::time::Duration::from_mins(1)))
+            .unwrap();
+
+        let incoming = SourceRoutingHeader::new(vec![2, 1], 1);
+        for index in [0, 1, 2, 5] {
+            let fragment = Fragment::new(index, 6, [0; 128]);
+            handler
+                .acknowledge_fragment(&incoming, 40, &fragment)
+                .unwrap();
+        }
+        handler.tick().unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 0);
+        handler.set_ack_aggregation(None).unwrap();
+        let sent = neighbor_receiver.try_iter().collect::<Vec<_>>();
+        assert_eq!(sent.len(), 1);
+        let PacketType::MsgFragment(fragment) = &sent[0].pack_type else {
+            panic!("expected a control fragment");
+        };
+        assert!(is_reserved_control_fragment(fragment));
+        let ack: AggregateAck =
+            serde_json::from_slice(&fragment.data[..usize::from(fragment.length)]).unwrap();
+        assert_eq!(
+            ack,
+            AggregateAck::Ranges {
+                session_id: 40,
+                ranges: vec![(0, 2), (5, 5)]
+            }
+        );
+
+        let sparse = (0..200).map(|i| i * 2).collect::<BTreeSet<u64>>();
+        let acks = AggregateAck::for_indexes(40, &sparse);
+        assert!(acks.len() > 1);
+        assert!(
+            acks.iter()
+                .all(|ack| serde_json::to_vec(ack).unwrap().len() <= MAX_FRAGMENT_SIZE)
+        );
+        assert!((0..200).all(|i| acks.iter().any(|ack| ack.contains(i * 2))));
+
+        let session_id = handler.new_session_id(2);
+        handler.send_message(&[5; 400], Some(2), Some(session_id)).unwrap();
+        assert_eq!(handler.session_buffer().outstanding(session_id, 2), 4);
+        let ack = AggregateAck::Ranges {
+            session_id,
+            ranges: vec![(0, 2)],
+        };
+        handler.handle_aggregate_ack(&ack, 2);
+        assert_eq!(handler.session_buffer().outstanding(session_id, 2), 1);
+        // acknowledged fragments are skipped as duplicates
+        handler.handle_aggregate_ack(&ack, 2);
+        assert_eq!(handler.ack_anomalies(), 0);
+    }
+
+    #[test]
+    /// Tests that the servers behind a removed drone are reported unreachable once
+    fn test_partition_detection() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, _neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender.clone());
+        handler.add_neighbor(3, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Drone, vec![1, 4]));
+        handler
+            .network_view
+            .add_node(Node::new(3, NodeType::Drone, vec![1, 5]));
+        handler
+            .network_view
+            .add_node(Node::new(4, NodeType::Server, vec![2]));
+        handler
+            .network_view
+            .add_node(Node::new(5, NodeType::Server, vec![3]));
+        handler.tick().unwrap();
+        let partitions = |recv: &Receiver<Box<dyn Event>>| {
+            recv.try_iter()
+                .filter_map(|e| e.into_any().downcast::<NodeEvent>().ok())
+                .filter_map(|e| match *e {
+                    NodeEvent::PartitionDetected { unreachable, .. } => Some(unreachable),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        assert!(partitions(&controller_recv).is_empty());
+
+        handler.remove_neighbor(2);
+        handler.tick().unwrap();
+        assert_eq!(partitions(&controller_recv), vec![vec![4]]);
+        assert!(handler.network_view().is_reachable(5));
+    }
+
+    #[test]
+    /// Tests that every change of the network view is streamed to the controller
+    fn test_topology_changed() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, _neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        let changes = |recv: &Receiver<Box<dyn Event>>| {
+            recv.try_iter()
+                .filter_map(|e| e.into_any().downcast::<NodeEvent>().ok())
+                .filter_map(|e| match *e {
+                    NodeEvent::TopologyChanged(diff) => Some(diff),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        handler.start_flood(None).unwrap();
+        let flood_response = FloodResponse {
+            flood_id: handler.flood_counter,
+            path_trace: vec![(1, NodeType::Client), (2, NodeType::Drone), (3, NodeType::Client)],
+        };
+        handler.handle_flood_response(&flood_response).unwrap();
+        let [learned] = changes(&controller_recv).try_into().unwrap();
+        assert_eq!(learned.extra_nodes, vec![2, 3]);
+        assert_eq!(learned.extra_edges, vec![(2, 3)]);
+        // the same response teaches nothing new
+        handler.handle_flood_response(&flood_response).unwrap();
+        assert!(changes(&controller_recv).is_empty());
+
+        let nack = Nack {
+            fragment_index: 0,
+            nack_type: NackType::DestinationIsDrone,
+        };
+        handler.handle_nack(&nack, 7, 3).unwrap();
+        let [retyped] = changes(&controller_recv).try_into().unwrap();
+        assert_eq!(retyped.wrong_types, vec![(3, NodeType::Client, NodeType::Drone)]);
+
+        handler.remove_neighbor(2);
+        let [lost] = changes(&controller_recv).try_into().unwrap();
+        assert_eq!(lost.missing_nodes, vec![2]);
+        assert_eq!(lost.missing_edges, vec![(1, 2), (2, 3)]);
+    }
+
+    #[test]
+    /// Tests that servers are probed with heartbeats and reported once they stop answering
+    fn test_heartbeats() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Drone, vec![1, 3]));
+        handler
+            .network_view
+            .add_node(Node::new(3, NodeType::Server, vec![2]));
+        handler.set_heartbeats(Some(HeartbeatConfig {
+            interval: Duration::ZERO,
+            max_missed: 2,
+        }));
+        let heartbeat = |recv: &Receiver<Packet>| {
+            let [packet] = recv.try_iter().collect::<Vec<_>>().try_into().unwrap();
+            let PacketType::MsgFragment(fragment) = packet.pack_type else {
+                panic!("expected a heartbeat fragment");
+            };
+            assert_eq!(fragment.total_n_fragments, 1);
+            packet.session_id
+        };
+        let unresponsive = |recv: &Receiver<Box<dyn Event>>| {
+            recv.try_iter()
+                .filter_map(|e| e.into_any().downcast::<NodeEvent>().ok())
+                .filter_map(|e| match *e {
+                    NodeEvent::PeerUnresponsive(peer) => Some(peer),
+                    NodeEvent::ProtocolViolation { .. } => panic!("heartbeat Ack rejected"),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
 
-        if let Some(destination) = dest {
-            // Try to send directly
-            if let Ok(shr) = self.try_find_path(destination) {
-                for (i, chunk) in chunks.enumerate() {
-                    let fragment =
-                        Fragment::new(i as u64, total_n_fragments, Self::pad_chunk(chunk));
-                    let packet = Packet::new_fragment(shr.clone(), session_id, fragment);
-                    self.try_send(packet)?;
-                }
+        handler.tick().unwrap();
+        let session_id = heartbeat(&neighbor_receiver);
+        handler.handle_ack(&Ack { fragment_index: 0 }, session_id, 3);
+        assert_eq!(handler.heartbeats().unwrap().peers(), vec![3]);
+        assert!(handler.session_buffer().is_empty());
+        assert!(handler.undelivered_sessions().is_empty());
 
-                self.controller_send
-                    .send(Box::new(NodeEvent::MessageSent {
-                        notification_from: self.id,
-                        to: destination,
-                    }))
-                    .map_err(|_e| NetworkError::ControllerDisconnected)?;
+        // the answered heartbeat, then a missed one
+        for _ in 0..2 {
+            handler.tick().unwrap();
+            let _ = heartbeat(&neighbor_receiver);
+        }
+        assert!(unresponsive(&controller_recv).is_empty());
+        assert_eq!(handler.heartbeats().unwrap().missed(3), 1);
+        // a heartbeat superseded by the next one leaves the buffer
+        assert_eq!(handler.session_buffer().len(), 1);
+        let flood_id = handler.flood_counter;
+        handler.tick().unwrap();
+        assert_eq!(unresponsive(&controller_recv), vec![3]);
+        assert_eq!(handler.flood_counter, flood_id + 1);
+        let _ = neighbor_receiver.try_iter().count();
 
-                return Ok(());
-            }
+        // reported once until heard from again
+        handler.tick().unwrap();
+        assert!(unresponsive(&controller_recv).is_empty());
+        handler.record_received(3, 10);
+        assert_eq!(handler.heartbeats().unwrap().missed(3), 0);
+        assert!(handler.watch_peer(4));
 
-            // Path not found, try flooding passing the pending request
-            self.start_flood(Some(SerializedRequest {
-                to: Some(destination),
-                data: message.to_vec(),
-            }))?;
-            
-            
+        // servers leaving the view are unwatched, explicitly watched peers are not
+        handler.forget_node(3);
+        handler.tick().unwrap();
+        assert_eq!(handler.heartbeats().unwrap().peers(), vec![4]);
+        handler.set_heartbeats(None);
+        assert!(!handler.watch_peer(4));
+    }
 
-            return Ok(());
-        }
+    #[test]
+    /// Tests that unacknowledged sessions survive a restart and are retransmitted
+    fn test_buffer_persistence() {
+        let root = tempfile::tempdir().unwrap();
+        let state = NodeState::init(root.path(), 1).unwrap();
+        let (neighbor_sender, neighbor_receiver) = unbounded();
 
-        // No explicit destination
-        if let Some(servers) = self.get_servers() {
-            for server in servers {
-                self.send_message(message, Some(server), Some(session_id))?;
-            }
-            return Ok(());
-        }
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        handler.add_neighbor(2, neighbor_sender.clone());
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        let session_id = handler.new_session_id(2);
+        handler.send_message(&[5; 200], Some(2), Some(session_id)).unwrap();
+        handler.handle_ack(&Ack { fragment_index: 0 }, session_id, 2);
+        handler.save_buffer(&state).unwrap();
+        let _ = neighbor_receiver.try_iter().count();
 
-        // Fallback: flooding
-        self.start_flood(Some(SerializedRequest {
-            to: None,
-            data: message.to_vec(),
-        }))
-    }
+        let (controller_send, _controller_recv) = unbounded();
+        let mut restarted =
+            RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        restarted.add_neighbor(2, neighbor_sender);
+        assert_eq!(restarted.load_buffer(&state).unwrap(), 1);
+        assert_eq!(restarted.buffered_sessions(), handler.buffered_sessions());
 
-    fn pad_chunk(chunk: &[u8]) -> [u8; 128] {
-        let mut arr = [0u8; 128];
-        arr[..chunk.len()].copy_from_slice(chunk);
-        arr
+        let resent = neighbor_receiver.try_iter().collect::<Vec<_>>();
+        assert_eq!(resent.len(), 1);
+        assert_eq!(resent[0].session_id, session_id);
+        assert_eq!(resent[0].get_fragment_index(), 1);
+        assert_eq!(restarted.load_buffer(&state).unwrap(), 0);
     }
 
-    pub fn handle_ack(&mut self, ack: &Ack, session_id: u64, from: NodeId) {
-        self.buffer
-            .mark_as_received(session_id, ack.fragment_index);
-    }
+    #[test]
+    /// Tests sending a message
+    fn test_send_message() {
+        let (sender, _receiver) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), sender);
 
-    /// Retries sending a specific packet identified by `session_id` and `fragment_index` from a specific node.
-    /// If the packet is found in the buffer, it is sent again.
-    /// # Errors
-    /// Returns an error if sending fails.
-    pub fn retry_send(
-        &mut self,
-        session_id: u64,
-        fragment_index: u64,
-        from: NodeId,
-    ) -> Result<(), NetworkError> {
-        if let Some(packet) = self
-            .buffer
-            .get_fragment_by_id(session_id, fragment_index)
-        {
-            self.try_send(packet)?;
-        }
-        Ok(())
-    }
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
 
-    /// Sends an acknowledgment packet for a specific session and fragment index.
-    /// The acknowledgment is sent to the source routing header (shr) provided.
-    /// # Errors
-    /// Returns an error if sending fails.
-    pub fn send_ack(
-        &mut self,
-        shr: SourceRoutingHeader,
-        session_id: u64,
-        fragment_index: u64,
-    ) -> Result<(), NetworkError> {
-        let packet = Packet::new_ack(shr, session_id, fragment_index);
-        self.try_send(packet)?;
-        Ok(())
-    }
+        let message = b"Hello world".to_vec(); // 128 bytes total
+        handler.send_message(&message, Some(2), None).unwrap();
 
-    #[must_use]
-    pub fn get_servers(&self) -> Option<Vec<NodeId>> {
-        self.network_view.get_servers()
+        let packet = neighbor_receiver.try_recv().unwrap();
+        assert!(matches!(packet.pack_type, PacketType::MsgFragment(_)));
     }
-}
-
-#[cfg(test)]
-mod routing_handler_tests {
-    use super::*;
-    use crossbeam_channel::{Receiver, unbounded};
-    use wg_internal::packet::PacketType;
 
     #[test]
-    /// Tests adding a neighbor
-    fn test_add_neighbor() {
+    /// Tests that a sent and acked fragment shows up in the session trace
+    fn test_fragment_trace() {
         let (sender, _receiver) = unbounded();
         let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), sender);
 
         let (neighbor_sender, _neighbor_receiver) = unbounded();
         handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
 
-        assert!(handler.neighbors.contains_key(&2));
-        assert!(handler.network_view.nodes[0].get_adjacents().contains(&2));
+        handler.send_message(b"Hello", Some(2), Some(42)).unwrap();
+        handler.handle_ack(&Ack { fragment_index: 0 }, 42, 2);
+
+        let fates = handler.trace(42).iter().map(|e| e.fate).collect::<Vec<_>>();
+        assert_eq!(fates, vec![FragmentFate::Sent, FragmentFate::Acked]);
     }
 
     #[test]
-    /// Tests removing a neighbor
-    fn test_remove_neighbor() {
+    /// Tests handling an `Ack`
+    fn test_handle_ack() {
         let (sender, _receiver) = unbounded();
         let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), sender);
 
         let (neighbor_sender, _neighbor_receiver) = unbounded();
         handler.add_neighbor(2, neighbor_sender);
-        handler.remove_neighbor(2);
 
-        assert!(!handler.neighbors.contains_key(&2));
-        assert!(!handler.network_view.nodes[0].get_adjacents().contains(&2));
+        let message = b"Hello, world!".to_vec();
+        handler.send_message(&message, Some(2), None).unwrap();
+
+        let ack = Ack { fragment_index: 0 };
+        handler.handle_ack(&ack, 1, 2);
     }
 
     #[test]
-    /// Tests starting a flood
-    fn test_start_flood() {
-        let (sender, receiver) = unbounded();
-        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), sender);
+    /// Tests that Acks, Nacks, completed and failed messages are reported to the controller
+    fn test_delivery_events() {
+        let (controller_send, controller_recv) = unbounded();
+        let config = CommonConfig::default()
+            .with_toml("[retry]\nmax_retries = 0")
+            .unwrap();
+        let mut handler =
+            RoutingHandler::with_config(1, NodeType::Client, HashMap::new(), controller_send, &config);
+        let (neighbor_sender, _neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        let events = || {
+            controller_recv
+                .try_iter()
+                .filter_map(|event| event.into_any().downcast::<NodeEvent>().ok())
+                .filter(|event| !matches!(**event, NodeEvent::PacketSent(_)))
+                .map(|event| *event)
+                .collect::<Vec<_>>()
+        };
+
+        let delivered = handler.new_session_id(2);
+        handler.send_message(&[1; 200], Some(2), Some(delivered)).unwrap();
+        let failed = handler.new_session_id(2);
+        handler.send_message(&[1; 10], Some(2), Some(failed)).unwrap();
+        let _ = events();
+
+        handler.handle_ack(&Ack { fragment_index: 0 }, delivered, 2);
+        assert_eq!(
+            events(),
+            vec![NodeEvent::AckReceived {
+                notification_from: 1,
+                from: 2,
+                session_id: delivered,
+                fragment_index: 0
+            }]
+        );
+        handler.handle_ack(&Ack { fragment_index: 1 }, delivered, 2);
+        assert_eq!(
+            events()[1],
+            NodeEvent::MessageFullyAcked {
+                notification_from: 1,
+                session_id: delivered,
+                destination: 2
+            }
+        );
+
+        let nack = Nack {
+            fragment_index: 0,
+            nack_type: NackType::Dropped,
+        };
+        handler.handle_nack(&nack, failed, 2).unwrap();
+        let events = events();
+        assert_eq!(
+            events[0],
+            NodeEvent::NackReceived {
+                notification_from: 1,
+                from: 2,
+                session_id: failed,
+                nack_type: NackType::Dropped
+            }
+        );
+        assert!(matches!(
+            events.last(),
+            Some(NodeEvent::SendFailed { session_id, .. }) if *session_id == failed
+        ));
+    }
+
+    #[test]
+    /// Tests that the handle of a message follows its Acks and settles with the session
+    fn test_session_handles() {
+        let (controller_send, _controller_recv) = unbounded();
+        let config = CommonConfig::default()
+            .with_toml("[retry]\nmax_retries = 0")
+            .unwrap();
+        let mut handler =
+            RoutingHandler::with_config(1, NodeType::Client, HashMap::new(), controller_send, &config);
+        let (neighbor_sender, _neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Drone, vec![1]));
+        handler
+            .network_view
+            .add_node(Node::new(3, NodeType::Server, vec![2]));
+
+        let delivered = handler.send_message(&[1; 200], Some(3), None).unwrap();
+        assert_eq!(delivered.progress(), (0, 2));
+        handler.handle_ack(&Ack { fragment_index: 1 }, delivered.session_id(), 3);
+        assert_eq!(delivered.progress(), (1, 2));
+        assert_eq!(delivered.status(), SessionStatus::Pending);
+        handler.handle_ack(&Ack { fragment_index: 0 }, delivered.session_id(), 3);
+        assert_eq!(delivered.wait(), SessionStatus::Delivered);
 
+        let failed = handler.send_message(&[1; 10], Some(3), None).unwrap();
+        let nack = Nack {
+            fragment_index: 0,
+            nack_type: NackType::Dropped,
+        };
+        handler.handle_nack(&nack, failed.session_id(), 3).unwrap();
+        assert!(matches!(failed.status(), SessionStatus::Failed(reason) if reason.contains("retries")));
+
+        // unknown destinations keep their session through the flood
+        let searched = handler.send_message(b"hi", Some(4), None).unwrap();
+        assert_eq!(searched.progress(), (0, 0));
+        let flood_response = FloodResponse {
+            flood_id: handler.flood_counter,
+            path_trace: vec![(1, NodeType::Client), (2, NodeType::Drone), (4, NodeType::Server)],
+        };
+        handler.handle_flood_response(&flood_response).unwrap();
+        assert_eq!(searched.progress(), (0, 1));
+        assert!(handler.drop_session(searched.session_id()));
+        assert_eq!(
+            searched.status(),
+            SessionStatus::Failed("session dropped".to_string())
+        );
+        assert!(handler.session_handles.is_empty());
+    }
+
+    #[test]
+    /// Tests that ordered sends start a message once the previous one is delivered
+    fn test_ordered_sends() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
         let (neighbor_sender, neighbor_receiver) = unbounded();
         handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Drone, vec![1]));
+        handler
+            .network_view
+            .add_node(Node::new(3, NodeType::Server, vec![2]));
+        handler.set_ordered_sends(true);
 
-        handler.start_flood(None).unwrap();
+        let first = handler.send_message(b"first", Some(3), None).unwrap();
+        let second = handler.send_message(b"second", Some(3), None).unwrap();
+        let third = handler.send_message(b"third", Some(3), None).unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 1);
+        assert_eq!(handler.queued_messages(3), 2);
+        assert_eq!(
+            handler.message_queue().queued_sessions(3),
+            vec![second.session_id(), third.session_id()]
+        );
 
-        let packet = receiver.try_recv().unwrap();
-        let packet = packet.into_any();
-        if let Ok(cmd) = packet.downcast::<NodeEvent>() {
-            assert!(matches!(*cmd, NodeEvent::FloodStarted(_, _)));
+        assert!(handler.cancel_queued(third.session_id()));
+        assert!(!handler.cancel_queued(third.session_id()));
+        assert_eq!(third.status(), SessionStatus::Failed("cancelled".to_string()));
+
+        handler.handle_ack(&Ack { fragment_index: 0 }, first.session_id(), 3);
+        assert_eq!(first.status(), SessionStatus::Delivered);
+        let sent = neighbor_receiver.try_recv().unwrap();
+        assert_eq!(sent.session_id, second.session_id());
+        assert_eq!(handler.queued_messages(3), 0);
+        assert_eq!(handler.message_queue().in_flight(3), Some(second.session_id()));
+    }
+
+    #[test]
+    /// Tests that routes avoid blacklisted nodes, set by hand or after repeated drops
+    fn test_blacklist_node() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, _neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender.clone());
+        handler.add_neighbor(3, neighbor_sender);
+        for node in [
+            Node::new(2, NodeType::Drone, vec![1, 9]),
+            Node::new(3, NodeType::Drone, vec![1, 4]),
+            Node::new(4, NodeType::Drone, vec![3, 9]),
+            Node::new(9, NodeType::Server, vec![2, 4]),
+        ] {
+            handler.network_view.add_node(node);
         }
+        assert_eq!(handler.try_find_path(9).unwrap().hops, vec![1, 2, 9]);
 
-        let neighbor_packet = neighbor_receiver.try_recv().unwrap();
-        assert!(matches!(
-            neighbor_packet.pack_type,
-            PacketType::FloodRequest(_)
-        ));
+        handler.blacklist_node(2, Duration::from_mins(1));
+        assert_eq!(handler.try_find_path(9).unwrap().hops, vec![1, 3, 4, 9]);
+        assert_eq!(handler.blacklist()[0].0, 2);
+        // a blacklisted destination is still reached
+        handler.blacklist_node(9, Duration::from_mins(1));
+        assert_eq!(handler.try_find_path(9).unwrap().hops, vec![1, 3, 4, 9]);
+        assert!(handler.unblacklist_node(2));
+        assert!(handler.unblacklist_node(9));
+        handler.blacklist_node(4, Duration::ZERO);
+        handler.tick().unwrap();
+        assert!(handler.blacklist().is_empty());
+
+        handler.set_auto_blacklist(Some(AutoBlacklist {
+            max_drops: 1,
+            window: Duration::from_mins(1),
+            duration: Duration::from_mins(1),
+        }));
+        let dropped = Nack {
+            fragment_index: 0,
+            nack_type: NackType::Dropped,
+        };
+        handler.handle_nack(&dropped, 7, 3).unwrap();
+        assert!(handler.blacklist().is_empty());
+        handler.handle_nack(&dropped, 7, 3).unwrap();
+        assert_eq!(
+            handler.blacklist().iter().map(|(node, _)| *node).collect::<Vec<_>>(),
+            vec![3]
+        );
+        assert_eq!(handler.try_find_path(9).unwrap().hops, vec![1, 2, 9]);
     }
 
     #[test]
-    /// Tests handling a `FloodResponse`
-    fn test_handle_flood_response() {
-        let (sender, _receiver) = unbounded();
-        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), sender);
-        handler.flood_counter = 1;
+    /// Tests that a cancelled session is forgotten, its retries stopped and its successor sent
+    fn test_cancel_session() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Drone, vec![1]));
+        handler
+            .network_view
+            .add_node(Node::new(3, NodeType::Server, vec![2]));
+        handler.set_ordered_sends(true);
+
+        let download = handler.send_message(&[7; 1000], Some(3), None).unwrap();
+        let next = handler.send_message(b"next", Some(3), None).unwrap();
+        handler.handle_ack(&Ack { fragment_index: 0 }, download.session_id(), 3);
+        let _ = neighbor_receiver.try_iter().count();
+
+        assert!(handler.cancel_session(download.session_id()));
+        assert!(!handler.cancel_session(download.session_id()));
+        assert_eq!(download.status(), SessionStatus::Failed("cancelled".to_string()));
+        assert!(handler.buffered_sessions().iter().all(|s| s.session_id != download.session_id()));
+        assert_eq!(handler.force_retry(download.session_id()).unwrap(), None);
+        let sent = neighbor_receiver.try_iter().collect::<Vec<_>>();
+        assert!(sent.iter().all(|packet| packet.session_id == next.session_id()));
+        assert_eq!(sent.len(), 1);
 
+        let cancelled = controller_recv
+            .try_iter()
+            .filter_map(|e| e.into_any().downcast::<NodeEvent>().ok())
+            .filter(|e| matches!(**e, NodeEvent::SessionCancelled { .. }))
+            .count();
+        assert_eq!(cancelled, 1);
+    }
+
+    #[test]
+    /// Tests that floods asked for during a flood are folded into it and deferred
+    fn test_flood_rate_limit() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        let patient = Duration::from_mins(1);
+        handler.set_flood_rate_limit(Some(FloodRateLimit {
+            min_interval: patient,
+            quiet_period: patient,
+            settle_timeout: patient,
+        }));
+        handler.start_flood(None).unwrap();
+        assert!(handler.flood_in_progress());
+        assert_eq!(neighbor_receiver.try_iter().count(), 1);
+
+        // the request waits for the responses of the running flood
+        let searched = handler.send_message(b"hi", Some(4), None).unwrap();
+        handler.start_flood(None).unwrap();
+        assert_eq!(handler.flood_counter, 1);
+        assert_eq!(handler.floods_coalesced(), 2);
+        assert_eq!(neighbor_receiver.try_iter().count(), 0);
         let flood_response = FloodResponse {
             flood_id: 1,
-            path_trace: vec![(2, NodeType::Drone), (3, NodeType::Client)],
+            path_trace: vec![(1, NodeType::Client), (2, NodeType::Drone), (4, NodeType::Server)],
         };
-        let _ = handler.handle_flood_response(&flood_response);
+        handler.handle_flood_response(&flood_response).unwrap();
+        assert_eq!(searched.progress(), (0, 1));
+        assert!(matches!(
+            neighbor_receiver.try_recv().unwrap().pack_type,
+            PacketType::MsgFragment(_)
+        ));
 
-        assert!(handler.network_view.nodes.iter().any(|n| n.id == 2));
-        assert!(handler.network_view.nodes.iter().any(|n| n.id == 3));
+        // a single flood starts once the limit allows it
+        handler.tick().unwrap();
+        assert_eq!(handler.flood_counter, 1);
+        handler.set_flood_rate_limit(Some(FloodRateLimit {
+            min_interval: Duration::ZERO,
+            quiet_period: Duration::ZERO,
+            settle_timeout: Duration::ZERO,
+        }));
+        assert!(!handler.flood_in_progress());
+        handler.tick().unwrap();
+        handler.tick().unwrap();
+        assert_eq!(handler.flood_counter, 2);
     }
 
     #[test]
-    /// Tests sending a message
-    fn test_send_message() {
-        let (sender, _receiver) = unbounded();
-        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), sender);
+    /// Tests that a graceful shutdown refuses new sends and waits for the accepted ones
+    fn test_graceful_shutdown() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, _neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Drone, vec![1]));
+        handler
+            .network_view
+            .add_node(Node::new(3, NodeType::Server, vec![2]));
+        let acked = handler.send_message(b"one", Some(3), None).unwrap();
+        let lost = handler.send_message(b"two", Some(3), None).unwrap();
+        assert!(!handler.poll_shutdown());
+
+        handler.begin_shutdown(Duration::from_mins(1));
+        assert!(handler.is_shutting_down());
+        assert!(matches!(
+            handler.send_message(b"three", Some(3), None),
+            Err(NetworkError::Routing(RoutingError::ShuttingDown))
+        ));
+        let mut accepted = vec![acked.session_id(), lost.session_id()];
+        accepted.sort_unstable();
+        assert_eq!(handler.undelivered_sessions(), accepted);
+        handler.handle_ack(&Ack { fragment_index: 0 }, acked.session_id(), 3);
+        assert!(!handler.poll_shutdown());
 
+        // the grace period runs out
+        handler.begin_shutdown(Duration::ZERO);
+        assert!(handler.poll_shutdown());
+        assert_eq!(acked.status(), SessionStatus::Delivered);
+        assert_eq!(
+            lost.status(),
+            SessionStatus::Failed("node shut down".to_string())
+        );
+        let undelivered = controller_recv
+            .try_iter()
+            .filter_map(|event| event.into_any().downcast::<NodeEvent>().ok())
+            .find_map(|event| match *event {
+                NodeEvent::ShutdownComplete {
+                    undelivered_sessions,
+                    ..
+                } => Some(undelivered_sessions),
+                _ => None,
+            });
+        assert_eq!(undelivered, Some(vec![lost.session_id()]));
+    }
+
+    #[test]
+    /// Tests that a shutdown waits for the fragments a session rate still holds back
+    fn test_shutdown_throttled() {
+        let (mut handler, _controller_recv) = create_test_routing_handler();
         let (neighbor_sender, neighbor_receiver) = unbounded();
         handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        let session_id = handler.new_session_id(2);
+        handler.set_session_rate(session_id, Some(1));
+        handler
+            .send_message(&[1; 300], Some(2), Some(session_id))
+            .unwrap();
+        assert_eq!(neighbor_receiver.try_iter().count(), 1);
+        handler.handle_ack(&Ack { fragment_index: 0 }, session_id, 2);
 
-        let message = b"Hello world".to_vec(); // 128 bytes total
-        handler.send_message(&message, Some(2), None).unwrap();
-
-        let packet = neighbor_receiver.try_recv().unwrap();
-        assert!(matches!(packet.pack_type, PacketType::MsgFragment(_)));
+        handler.begin_shutdown(Duration::from_mins(1));
+        assert_eq!(handler.undelivered_sessions(), vec![session_id]);
+        assert!(!handler.poll_shutdown());
     }
 
     #[test]
-    /// Tests handling an `Ack`
-    fn test_handle_ack() {
-        let (sender, _receiver) = unbounded();
-        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), sender);
+    /// Tests that a shutdown waits for the messages queued by ordered sends and fails them
+    fn test_shutdown_ordered_sends() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Drone, vec![1]));
+        handler
+            .network_view
+            .add_node(Node::new(3, NodeType::Server, vec![2]));
+        handler.set_ordered_sends(true);
+        let first = handler.send_message(b"one", Some(3), None).unwrap();
+        let queued = handler.send_message(b"two", Some(3), None).unwrap();
+        let _ = neighbor_receiver.try_iter().count();
+
+        handler.begin_shutdown(Duration::from_mins(1));
+        let mut accepted = vec![first.session_id(), queued.session_id()];
+        accepted.sort_unstable();
+        assert_eq!(handler.undelivered_sessions(), accepted);
+        handler.handle_ack(&Ack { fragment_index: 0 }, first.session_id(), 3);
+        assert_eq!(first.status(), SessionStatus::Delivered);
+        assert_eq!(
+            queued.status(),
+            SessionStatus::Failed("node shut down".to_string())
+        );
+        assert_eq!(neighbor_receiver.try_iter().count(), 0);
+        assert!(handler.poll_shutdown());
+    }
 
+    #[test]
+    /// Tests that replayed and unsolicited Acks are rejected and reported
+    fn test_ack_verification() {
+        let (controller_send, controller_recv) = unbounded();
+        let mut handler = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
         let (neighbor_sender, _neighbor_receiver) = unbounded();
         handler.add_neighbor(2, neighbor_sender);
+        handler
+            .network_view
+            .add_node(Node::new(2, NodeType::Server, vec![1]));
+        let session_id = handler.new_session_id(2);
+        handler.send_message(&[1; 300], Some(2), Some(session_id)).unwrap();
+        let _ = controller_recv.try_iter().count();
 
-        let message = b"Hello, world!".to_vec();
-        handler.send_message(&message, Some(2), None).unwrap();
+        handler.handle_ack(&Ack { fragment_index: 0 }, session_id, 2);
+        assert_eq!(handler.ack_anomalies(), 0);
+        // the late Ack of a retransmission is a duplicate
+        handler.handle_ack(&Ack { fragment_index: 0 }, session_id, 2);
+        assert_eq!(handler.ack_anomalies(), 0);
+        handler.handle_ack(&Ack { fragment_index: 1 }, session_id, 3);
+        handler.handle_ack(&Ack { fragment_index: 9 }, session_id, 2);
+        assert_eq!(handler.ack_anomalies(), 2);
+        assert_eq!(handler.stats().ack_anomalies, 2);
 
-        let ack = Ack { fragment_index: 0 };
-        handler.handle_ack(&ack, 1, 2);
+        let violations = controller_recv
+            .try_iter()
+            .filter_map(|event| match *event.into_any().downcast::<NodeEvent>().ok()? {
+                NodeEvent::ProtocolViolation {
+                    peer, violation, ..
+                } => Some((peer, violation)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            violations,
+            vec![
+                (3, ProtocolViolation::UnsolicitedAck { fragment_index: 1 }),
+                (2, ProtocolViolation::UnsolicitedAck { fragment_index: 9 }),
+            ]
+        );
+        // the fragments still awaited are unaffected
+        handler.handle_ack(&Ack { fragment_index: 1 }, session_id, 2);
+        handler.handle_ack(&Ack { fragment_index: 2 }, session_id, 2);
+        assert!(handler.buffered_sessions().is_empty());
+        handler.handle_ack(&Ack { fragment_index: 2 }, session_id, 2);
+        assert_eq!(handler.ack_anomalies(), 2);
     }
 
     fn create_test_routing_handler() -> (RoutingHandler, Receiver<Box<dyn Event>>) {
@@ -658,7 +6155,7 @@ mod routing_handler_tests {
         let _ = handler.handle_flood_response(&flood_response);
 
         let path_to_server = handler.network_view.find_path(1,  2);
-        assert_eq!(path_to_server, Some(vec![1, 3, 4, 2]));
+        assert_eq!(path_to_server.as_deref(), Some([1, 3, 4, 2].as_slice()));
     }
 
     #[test]
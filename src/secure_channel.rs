@@ -0,0 +1,559 @@
+//! End-to-end encryption of message payloads, so that drones relaying the fragments
+//! cannot read them.
+//!
+//! Peers exchange X25519 public keys over the drone network, along with an ephemeral
+//! key generated for each exchange, derive one ChaCha20-Poly1305 key per direction with
+//! HKDF-SHA256, and seal every payload with a counter nonce. Since both ends contribute
+//! a fresh ephemeral key, every exchange yields new keys and counters never repeat under
+//! the same key. The keys aren't authenticated: compare [`SecureChannel::peer_key`] out
+//! of band to rule out a drone rewriting the exchange.
+
+use crate::RoutingHandler;
+use crate::network::{ChannelError, NetworkError, RoutingError};
+use crate::types::SecureMessage;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use wg_internal::network::NodeId;
+use x25519_dalek::{PublicKey, StaticSecret};
+
+const KDF_INFO: &[u8] = b"common secure channel v1";
+
+/// Counters received recently, refusing the ones replayed by the network.
+#[derive(Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    // bit n is set if `highest - n` was received
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn accept(&mut self, counter: u64) -> bool {
+        let Some(highest) = self.highest.filter(|highest| counter <= *highest) else {
+            let shift = self.highest.map_or(64, |highest| counter - highest);
+            self.seen = if shift < 64 { self.seen << shift } else { 0 } | 1;
+            self.highest = Some(counter);
+            return true;
+        };
+        let age = highest - counter;
+        if age >= 64 || self.seen & (1 << age) != 0 {
+            return false;
+        }
+        self.seen |= 1 << age;
+        true
+    }
+}
+
+/// Keys shared with one peer.
+struct Peer {
+    key: PublicKey,
+    send: ChaCha20Poly1305,
+    recv: ChaCha20Poly1305,
+    next_counter: u64,
+    replay: ReplayWindow,
+}
+
+impl Peer {
+    /// Derives the keys shared with the owner of `key` for the exchange of the ephemeral
+    /// keys `own` and `ephemeral`, `None` if either key is a low order point.
+    fn derive(
+        secret: &StaticSecret,
+        own: &StaticSecret,
+        key: PublicKey,
+        ephemeral: PublicKey,
+    ) -> Option<Self> {
+        let shared = secret.diffie_hellman(&key);
+        let fresh = own.diffie_hellman(&ephemeral);
+        if !shared.was_contributory() || !fresh.was_contributory() {
+            return None;
+        }
+        // both ends order the ephemeral keys the same way, the lower one sending with the
+        // first half
+        let public = PublicKey::from(own);
+        let (low, high) = if public.as_bytes() < ephemeral.as_bytes() {
+            (&public, &ephemeral)
+        } else {
+            (&ephemeral, &public)
+        };
+        let salt = [low.as_bytes().as_slice(), high.as_bytes()].concat();
+        let ikm = [shared.as_bytes().as_slice(), fresh.as_bytes()].concat();
+        let mut okm = [0; 64];
+        Hkdf::<Sha256>::new(Some(&salt), &ikm)
+            .expand(KDF_INFO, &mut okm)
+            .ok()?;
+        let first = ChaCha20Poly1305::new(Key::from_slice(&okm[..32]));
+        let second = ChaCha20Poly1305::new(Key::from_slice(&okm[32..]));
+        let (send, recv) = if *low == public {
+            (first, second)
+        } else {
+            (second, first)
+        };
+        Some(Self {
+            key,
+            send,
+            recv,
+            next_counter: 0,
+            replay: ReplayWindow::default(),
+        })
+    }
+}
+
+fn nonce(counter: u64) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[4..].copy_from_slice(&counter.to_le_bytes());
+    Nonce::from(nonce)
+}
+
+/// Encrypted channels towards the peers of a node, layered over
+/// [`RoutingHandler::send_message`] and `Processor::handle_msg`.
+///
+/// Messages sent to a peer before its key is known are queued, and sent once the
+/// key exchange started by the first of them completes.
+pub struct SecureChannel {
+    secret: StaticSecret,
+    public: PublicKey,
+    peers: HashMap<NodeId, Peer>,
+    // peers sent our key that didn't answer yet, with the ephemeral key sent
+    awaiting: HashMap<NodeId, StaticSecret>,
+    // ephemeral keys received from each peer, refused if the network replays them
+    exchanged: HashMap<NodeId, HashSet<[u8; 32]>>,
+    queued: HashMap<NodeId, Vec<Vec<u8>>>,
+}
+
+impl Default for SecureChannel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SecureChannel {
+    /// Creates a channel with a fresh key pair.
+    #[must_use]
+    pub fn new() -> Self {
+        let secret = fresh_secret();
+        Self {
+            public: PublicKey::from(&secret),
+            secret,
+            peers: HashMap::new(),
+            awaiting: HashMap::new(),
+            exchanged: HashMap::new(),
+            queued: HashMap::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public.to_bytes()
+    }
+
+    /// Returns the public key announced by `peer`, once exchanged.
+    #[must_use]
+    pub fn peer_key(&self, peer: NodeId) -> Option<[u8; 32]> {
+        self.peers.get(&peer).map(|p| p.key.to_bytes())
+    }
+
+    #[must_use]
+    pub fn is_established(&self, peer: NodeId) -> bool {
+        self.peers.contains_key(&peer)
+    }
+
+    /// Forgets the keys of `peer` and sends it a new key exchange, so that both ends
+    /// renegotiate them. Messages sent meanwhile are queued until the peer answers, while
+    /// the ones it sealed with the old keys can no longer be read.
+    /// # Errors
+    /// Returns an error if the key exchange cannot be sent.
+    pub fn forget(
+        &mut self,
+        router: &mut RoutingHandler,
+        peer: NodeId,
+    ) -> Result<(), NetworkError> {
+        let _ = self.peers.remove(&peer);
+        let own = fresh_secret();
+        self.send_key(router, &own, peer, None)?;
+        let _ = self.awaiting.insert(peer, own);
+        Ok(())
+    }
+
+    /// Encrypts `message` and sends it to `to`, or queues it and starts the key exchange
+    /// if `to` has no keys yet.
+    /// # Errors
+    /// Returns an error if the message or the key exchange cannot be sent.
+    pub fn send_message(
+        &mut self,
+        router: &mut RoutingHandler,
+        message: &[u8],
+        to: NodeId,
+    ) -> Result<(), NetworkError> {
+        if self.peers.contains_key(&to) {
+            return self.seal(router, message, to);
+        }
+        self.queued.entry(to).or_default().push(message.to_vec());
+        if !self.awaiting.contains_key(&to) {
+            let own = fresh_secret();
+            self.send_key(router, &own, to, None)?;
+            let _ = self.awaiting.insert(to, own);
+        }
+        Ok(())
+    }
+
+    /// Handles a message received from `from`: returns the plaintext of sealed messages,
+    /// `None` for key exchanges, which are answered and release the messages queued.
+    ///
+    /// A key exchange we didn't start means the peer restarted or renegotiates: its keys
+    /// are replaced and answered with a fresh ephemeral key. Answers are never answered:
+    /// one that doesn't answer the exchange we are waiting for, e.g. forged by a drone or
+    /// answering an exchange superseded by [`Self::forget`], is ignored.
+    /// # Errors
+    /// `Undecryptable` for messages that aren't part of a channel, fail authentication,
+    /// are replayed (key exchanges included) or come from a peer without keys, as drones
+    /// may forge them.
+    /// Otherwise any error returned while answering a key exchange or sending the
+    /// messages queued.
+    pub fn handle_msg(
+        &mut self,
+        router: &mut RoutingHandler,
+        msg: &[u8],
+        from: NodeId,
+    ) -> Result<Option<Vec<u8>>, NetworkError> {
+        match serde_json::from_slice(msg) {
+            Ok(SecureMessage::KeyExchange {
+                public_key,
+                ephemeral,
+                answers,
+            }) => {
+                if self
+                    .exchanged
+                    .get(&from)
+                    .is_some_and(|seen| seen.contains(&ephemeral))
+                {
+                    return Err(RoutingError::Undecryptable { from }.into());
+                }
+                let pending = self.awaiting.get(&from).cloned();
+                let awaited = pending.as_ref().map(|own| PublicKey::from(own).to_bytes());
+                if answers.is_some() && answers != awaited {
+                    return Ok(None);
+                }
+                let own = pending.clone().unwrap_or_else(fresh_secret);
+                let key = PublicKey::from(public_key);
+                let peer = Peer::derive(&self.secret, &own, key, PublicKey::from(ephemeral))
+                    .ok_or(RoutingError::Undecryptable { from })?;
+                let _ = self.exchanged.entry(from).or_default().insert(ephemeral);
+                let _ = self.awaiting.remove(&from);
+                let _ = self.peers.insert(from, peer);
+                if pending.is_none() {
+                    self.send_key(router, &own, from, Some(ephemeral))?;
+                }
+                for message in self.queued.remove(&from).unwrap_or_default() {
+                    self.seal(router, &message, from)?;
+                }
+                Ok(None)
+            }
+            Ok(SecureMessage::Sealed {
+                counter,
+                ciphertext,
+            }) => {
+                let peer = self
+                    .peers
+                    .get_mut(&from)
+                    .ok_or(RoutingError::Undecryptable { from })?;
+                let plaintext = peer
+                    .recv
+                    .decrypt(&nonce(counter), ciphertext.as_slice())
+                    .map_err(|_| RoutingError::Undecryptable { from })?;
+                if !peer.replay.accept(counter) {
+                    return Err(RoutingError::Undecryptable { from }.into());
+                }
+                Ok(Some(plaintext))
+            }
+            Err(_) => Err(RoutingError::Undecryptable { from }.into()),
+        }
+    }
+
+    /// Sends our keys to `to`, starting an exchange or answering the one of the
+    /// ephemeral key `answers`.
+    fn send_key(
+        &self,
+        router: &mut RoutingHandler,
+        own: &StaticSecret,
+        to: NodeId,
+        answers: Option<[u8; 32]>,
+    ) -> Result<(), NetworkError> {
+        let exchange = SecureMessage::KeyExchange {
+            public_key: self.public_key(),
+            ephemeral: PublicKey::from(own).to_bytes(),
+            answers,
+        };
+        send(router, &exchange, to)
+    }
+
+    fn seal(
+        &mut self,
+        router: &mut RoutingHandler,
+        message: &[u8],
+        to: NodeId,
+    ) -> Result<(), NetworkError> {
+        let peer = self.peers.get_mut(&to).ok_or(RoutingError::NoDestination)?;
+        let counter = peer.next_counter;
+        let ciphertext = peer
+            .send
+            .encrypt(&nonce(counter), message)
+            .map_err(|e| ChannelError::Encoding(e.to_string()))?;
+        peer.next_counter += 1;
+        send(
+            router,
+            &SecureMessage::Sealed {
+                counter,
+                ciphertext,
+            },
+            to,
+        )
+    }
+}
+
+fn fresh_secret() -> StaticSecret {
+    StaticSecret::from(rand::random::<[u8; 32]>())
+}
+
+fn send(
+    router: &mut RoutingHandler,
+    message: &SecureMessage,
+    to: NodeId,
+) -> Result<(), NetworkError> {
+    let data = serde_json::to_vec(message).map_err(|e| ChannelError::Encoding(e.to_string()))?;
+    router.send_message(&data, Some(to), None)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod secure_channel_tests {
+    use super::*;
+    use crate::FragmentAssembler;
+    use crossbeam_channel::{Receiver, unbounded};
+    use wg_internal::packet::{FloodResponse, NodeType, Packet, PacketType};
+
+    /// Returns a client router knowing `peer` as a neighbor, and the channel towards it.
+    fn router(id: NodeId, peer: NodeId) -> (RoutingHandler, Receiver<Packet>) {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut router = RoutingHandler::new(id, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_sender, neighbor_receiver) = unbounded();
+        router.add_neighbor(peer, neighbor_sender);
+        router.start_flood(None).unwrap();
+        let _ = neighbor_receiver.try_iter().count();
+        router
+            .handle_flood_response(&FloodResponse {
+                flood_id: 1,
+                path_trace: vec![(id, NodeType::Client), (peer, NodeType::Client)],
+            })
+            .unwrap();
+        (router, neighbor_receiver)
+    }
+
+    /// Reassembles the messages sent on `link` by `from`.
+    fn messages(link: &Receiver<Packet>, from: NodeId) -> Vec<Vec<u8>> {
+        let mut assembler = FragmentAssembler::default();
+        link.try_iter()
+            .filter_map(|packet| match packet.pack_type {
+                PacketType::MsgFragment(fragment) => {
+                    assembler.add_fragment(fragment, packet.session_id, from)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    /// Tests that messages queued during the key exchange arrive sealed and readable only by the peer
+    fn test_secure_channel() {
+        let (mut alice_router, to_bob) = router(1, 2);
+        let (mut bob_router, to_alice) = router(2, 1);
+        let mut alice = SecureChannel::new();
+        let mut bob = SecureChannel::new();
+
+        alice.send_message(&mut alice_router, b"first", 2).unwrap();
+        alice.send_message(&mut alice_router, b"second", 2).unwrap();
+        let exchange = messages(&to_bob, 1);
+        assert_eq!(exchange.len(), 1);
+        assert_eq!(
+            bob.handle_msg(&mut bob_router, &exchange[0], 1).unwrap(),
+            None
+        );
+        assert_eq!(bob.peer_key(1), Some(alice.public_key()));
+
+        let answer = messages(&to_alice, 2);
+        assert_eq!(answer.len(), 1);
+        assert_eq!(
+            alice.handle_msg(&mut alice_router, &answer[0], 2).unwrap(),
+            None
+        );
+        assert!(alice.is_established(2));
+        let sealed = messages(&to_bob, 1);
+        assert_eq!(sealed.len(), 2);
+        assert!(sealed.iter().all(|m| !m.windows(5).any(|w| w == b"first")));
+        let received = sealed
+            .iter()
+            .map(|m| bob.handle_msg(&mut bob_router, m, 1).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            received,
+            vec![Some(b"first".to_vec()), Some(b"second".to_vec())]
+        );
+
+        // replayed, tampered and plaintext messages are refused
+        assert!(matches!(
+            bob.handle_msg(&mut bob_router, &sealed[0], 1),
+            Err(NetworkError::Routing(RoutingError::Undecryptable {
+                from: 1
+            }))
+        ));
+        let mut tampered = serde_json::from_slice::<SecureMessage>(&sealed[1]).unwrap();
+        if let SecureMessage::Sealed {
+            counter,
+            ciphertext,
+        } = &mut tampered
+        {
+            *counter = 7;
+            ciphertext[0] ^= 1;
+        }
+        let tampered = serde_json::to_vec(&tampered).unwrap();
+        assert!(bob.handle_msg(&mut bob_router, &tampered, 1).is_err());
+        assert!(bob.handle_msg(&mut bob_router, b"hello", 1).is_err());
+
+        bob.send_message(&mut bob_router, b"reply", 1).unwrap();
+        let reply = messages(&to_alice, 2);
+        assert_eq!(
+            alice.handle_msg(&mut alice_router, &reply[0], 2).unwrap(),
+            Some(b"reply".to_vec())
+        );
+
+        // a replayed key exchange neither resets the keys nor the counters
+        assert!(matches!(
+            bob.handle_msg(&mut bob_router, &exchange[0], 1),
+            Err(NetworkError::Routing(RoutingError::Undecryptable {
+                from: 1
+            }))
+        ));
+        assert!(messages(&to_alice, 2).is_empty());
+        alice.send_message(&mut alice_router, b"third", 2).unwrap();
+        let third = messages(&to_bob, 1);
+        assert_eq!(
+            bob.handle_msg(&mut bob_router, &third[0], 1).unwrap(),
+            Some(b"third".to_vec())
+        );
+    }
+
+    #[test]
+    /// Tests that forgetting a peer makes both ends renegotiate fresh keys
+    fn test_secure_channel_forget() {
+        let (mut alice_router, to_bob) = router(1, 2);
+        let (mut bob_router, to_alice) = router(2, 1);
+        let mut alice = SecureChannel::new();
+        let mut bob = SecureChannel::new();
+        alice.send_message(&mut alice_router, b"first", 2).unwrap();
+        let exchange = messages(&to_bob, 1);
+        let _ = bob.handle_msg(&mut bob_router, &exchange[0], 1).unwrap();
+        let answer = messages(&to_alice, 2);
+        let _ = alice.handle_msg(&mut alice_router, &answer[0], 2).unwrap();
+        let first = messages(&to_bob, 1);
+        assert_eq!(
+            bob.handle_msg(&mut bob_router, &first[0], 1).unwrap(),
+            Some(b"first".to_vec())
+        );
+
+        alice.forget(&mut alice_router, 2).unwrap();
+        assert!(!alice.is_established(2));
+        alice.send_message(&mut alice_router, b"second", 2).unwrap();
+        let renegotiation = messages(&to_bob, 1);
+        assert_eq!(renegotiation.len(), 1);
+        assert_eq!(
+            bob.handle_msg(&mut bob_router, &renegotiation[0], 1)
+                .unwrap(),
+            None
+        );
+        let answer = messages(&to_alice, 2);
+        assert_eq!(answer.len(), 1);
+        let _ = alice.handle_msg(&mut alice_router, &answer[0], 2).unwrap();
+        assert!(alice.is_established(2));
+        let second = messages(&to_bob, 1);
+        assert_eq!(
+            bob.handle_msg(&mut bob_router, &second[0], 1).unwrap(),
+            Some(b"second".to_vec())
+        );
+        // the first message was sealed with the old keys
+        assert!(bob.handle_msg(&mut bob_router, &first[0], 1).is_err());
+    }
+
+    #[test]
+    /// Tests that the answer to a forged key exchange is not answered in turn
+    fn test_forged_key_exchange() {
+        let (mut alice_router, to_bob) = router(1, 2);
+        let (mut bob_router, to_alice) = router(2, 1);
+        let mut alice = SecureChannel::new();
+        let mut bob = SecureChannel::new();
+
+        // a drone forges an exchange from alice carrying her public key
+        let forged = SecureMessage::KeyExchange {
+            public_key: alice.public_key(),
+            ephemeral: PublicKey::from(&fresh_secret()).to_bytes(),
+            answers: None,
+        };
+        let forged = serde_json::to_vec(&forged).unwrap();
+        assert_eq!(bob.handle_msg(&mut bob_router, &forged, 1).unwrap(), None);
+        let answer = messages(&to_alice, 2);
+        assert_eq!(answer.len(), 1);
+        assert_eq!(
+            alice.handle_msg(&mut alice_router, &answer[0], 2).unwrap(),
+            None
+        );
+        assert!(!alice.is_established(2));
+        assert!(messages(&to_bob, 1).is_empty());
+    }
+
+    #[test]
+    /// Tests that forgetting a peer while an exchange is pending settles on the latest one
+    fn test_forget_while_pending() {
+        let (mut alice_router, to_bob) = router(1, 2);
+        let (mut bob_router, to_alice) = router(2, 1);
+        let mut alice = SecureChannel::new();
+        let mut bob = SecureChannel::new();
+
+        alice.send_message(&mut alice_router, b"first", 2).unwrap();
+        alice.forget(&mut alice_router, 2).unwrap();
+        let exchanges = messages(&to_bob, 1);
+        assert_eq!(exchanges.len(), 2);
+        for exchange in &exchanges {
+            assert_eq!(bob.handle_msg(&mut bob_router, exchange, 1).unwrap(), None);
+        }
+        let answers = messages(&to_alice, 2);
+        assert_eq!(answers.len(), 2);
+        for answer in &answers {
+            assert_eq!(
+                alice.handle_msg(&mut alice_router, answer, 2).unwrap(),
+                None
+            );
+        }
+        assert!(alice.is_established(2));
+        // the queued message is sealed with the keys of the latest exchange, nothing else
+        let sealed = messages(&to_bob, 1);
+        assert_eq!(sealed.len(), 1);
+        assert_eq!(
+            bob.handle_msg(&mut bob_router, &sealed[0], 1).unwrap(),
+            Some(b"first".to_vec())
+        );
+        assert!(messages(&to_alice, 2).is_empty());
+    }
+
+    #[test]
+    /// Tests that counters are accepted once, out of order within the window
+    fn test_replay_window() {
+        let mut window = ReplayWindow::default();
+        assert!(window.accept(3));
+        assert!(window.accept(1));
+        assert!(!window.accept(1));
+        assert!(window.accept(100));
+        assert!(!window.accept(3));
+        assert!(window.accept(99));
+        assert!(!window.accept(100));
+    }
+}
@@ -0,0 +1,57 @@
+use crate::Processor;
+use crate::packet_processor::{NodeCore, SyncNode};
+use wg_internal::network::NodeId;
+
+/// Inconsistency found by [`selfcheck`] in the state of a node.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// The node doesn't appear in its own network view
+    MissingSelf,
+    /// The node appears more than once in the network view
+    DuplicateNode(NodeId),
+    /// A node of the view lists itself as adjacent
+    SelfLoop(NodeId),
+    /// The first node lists the second as adjacent but not the other way around
+    AsymmetricLink(NodeId, NodeId),
+    /// A neighbor with a channel is not adjacent to the node in its network view
+    NeighborNotInView(NodeId),
+    /// A control channel is registered towards a node that is not a neighbor
+    OrphanControlChannel(NodeId),
+    /// The bounded channel towards a neighbor is full
+    NeighborChannelFull(NodeId),
+    /// A sent fragment is buffered at a position not matching its index
+    MisplacedBufferedFragment {
+        session_id: u64,
+        position: usize,
+        fragment_index: u64,
+    },
+    /// A sent session is still buffered although all its fragments were acknowledged
+    StaleBufferedSession(u64),
+    /// The assembler holds a fragment whose index is past the announced total
+    FragmentOutOfRange {
+        session_id: u64,
+        sender: NodeId,
+        fragment_index: u64,
+        total: u64,
+    },
+}
+
+/// Verifies the invariants of the buffers, the assembler, the network view and
+/// the neighbor channels of `node`.
+///
+/// Meant to be run at startup and periodically (see [`Processor::run`]):
+/// the diagnostics found are also notified to the controller with a
+/// `NodeEvent::SelfCheckFailed`.
+pub fn selfcheck<P: Processor + ?Sized>(node: &mut P) -> Vec<Diagnostic> {
+    check_node(&mut SyncNode(node))
+}
+
+pub(crate) fn check_node<N: NodeCore + ?Sized>(node: &mut N) -> Vec<Diagnostic> {
+    let mut diagnostics = node.assembler().selfcheck();
+    let router = node.routing_handler();
+    diagnostics.extend(router.selfcheck());
+    if !diagnostics.is_empty() {
+        router.report_selfcheck(&diagnostics);
+    }
+    diagnostics
+}
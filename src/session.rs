@@ -0,0 +1,149 @@
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Outcome of an outgoing session, as tracked by a [`SessionHandle`].
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum SessionStatus {
+    /// Some fragments are not acknowledged yet, or the destination is still searched
+    #[default]
+    Pending,
+    /// Every fragment was acknowledged
+    Delivered,
+    /// The message was given up on, with the reason
+    Failed(String),
+}
+
+#[derive(Debug, Default)]
+struct SessionState {
+    acked: usize,
+    total: usize,
+    status: SessionStatus,
+}
+
+/// Tracks the delivery of a message sent with `RoutingHandler::send_message`.
+///
+/// The routing handler updates it as Acks arrive: it resolves to
+/// [`SessionStatus::Delivered`] once every fragment is acknowledged, or to
+/// [`SessionStatus::Failed`] when sending fails, the retries run out or the session
+/// is dropped. Handles are cheap to clone and can be moved to other threads.
+#[derive(Debug, Clone)]
+pub struct SessionHandle {
+    session_id: u64,
+    state: Arc<(Mutex<SessionState>, Condvar)>,
+}
+
+impl SessionHandle {
+    pub(crate) fn new(session_id: u64) -> Self {
+        Self {
+            session_id,
+            state: Arc::default(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, SessionState> {
+        self.state.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[must_use]
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    /// Returns the fragments acknowledged and the fragments sent so far.
+    #[must_use]
+    pub fn progress(&self) -> (usize, usize) {
+        let state = self.state();
+        (state.acked, state.total)
+    }
+
+    #[must_use]
+    pub fn status(&self) -> SessionStatus {
+        self.state().status.clone()
+    }
+
+    /// Returns whether the session is delivered or failed.
+    #[must_use]
+    pub fn is_settled(&self) -> bool {
+        self.state().status != SessionStatus::Pending
+    }
+
+    /// Blocks until the session is settled. Must not be called from the thread running
+    /// the node, which would never process the Acks.
+    #[must_use]
+    pub fn wait(&self) -> SessionStatus {
+        let (_, settled) = &*self.state;
+        let state = settled
+            .wait_while(self.state(), |state| state.status == SessionStatus::Pending)
+            .unwrap_or_else(PoisonError::into_inner);
+        state.status.clone()
+    }
+
+    /// Blocks until the session is settled or `timeout` elapses, then returns its
+    /// status. Must not be called from the thread running the node.
+    #[must_use]
+    pub fn wait_timeout(&self, timeout: Duration) -> SessionStatus {
+        let deadline = Instant::now() + timeout;
+        let (_, settled) = &*self.state;
+        let mut state = self.state();
+        while state.status == SessionStatus::Pending {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if left.is_zero() {
+                break;
+            }
+            state = settled
+                .wait_timeout(state, left)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+        }
+        state.status.clone()
+    }
+
+    pub(crate) fn add_fragments(&self, fragments: usize) {
+        self.state().total += fragments;
+    }
+
+    pub(crate) fn on_ack(&self) {
+        let mut state = self.state();
+        state.acked = (state.acked + 1).min(state.total);
+    }
+
+    /// Resolves the session with `status`, unless it is already settled.
+    pub(crate) fn settle(&self, status: SessionStatus) {
+        let mut state = self.state();
+        if state.status == SessionStatus::Pending {
+            state.status = status;
+            self.state.1.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod session_tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    /// Tests that a handle reports progress and wakes up waiters on other threads once settled
+    fn test_session_handle() {
+        let handle = SessionHandle::new(7);
+        handle.add_fragments(2);
+        handle.on_ack();
+        assert_eq!(handle.progress(), (1, 2));
+        assert_eq!(
+            handle.wait_timeout(Duration::from_millis(10)),
+            SessionStatus::Pending
+        );
+
+        let waiter = handle.clone();
+        let waiting = thread::spawn(move || waiter.wait());
+        handle.on_ack();
+        handle.settle(SessionStatus::Delivered);
+        assert_eq!(waiting.join().unwrap(), SessionStatus::Delivered);
+        assert_eq!(handle.progress(), (2, 2));
+
+        // the first outcome sticks
+        handle.settle(SessionStatus::Failed("late".to_string()));
+        assert!(handle.is_settled());
+        assert_eq!(handle.status(), SessionStatus::Delivered);
+    }
+}
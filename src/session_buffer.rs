@@ -0,0 +1,534 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+use wg_internal::network::{NodeId, SourceRoutingHeader};
+use wg_internal::packet::{Fragment, Packet, PacketType};
+
+/// Delivery state of a fragment buffered by a [`SessionBuffer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FragmentState {
+    /// Held back, e.g. by the send window, and not sent yet
+    Pending,
+    /// Sent and awaiting its Ack
+    Sent,
+    Acked,
+    /// Nacked, awaiting its retransmission
+    Failed,
+}
+
+/// Fragment of an outgoing session along with its delivery state.
+#[derive(Debug, Clone)]
+pub struct BufferedFragment {
+    packet: Packet,
+    state: FragmentState,
+    retries: u32,
+    first_sent: Option<Instant>,
+    last_sent: Option<Instant>,
+}
+
+impl BufferedFragment {
+    fn new(packet: Packet, state: FragmentState) -> Self {
+        Self {
+            packet,
+            state,
+            retries: 0,
+            first_sent: None,
+            last_sent: None,
+        }
+    }
+
+    /// Returns the packet as last sent, along the route of its latest transmission.
+    #[must_use]
+    pub fn packet(&self) -> &Packet {
+        &self.packet
+    }
+
+    #[must_use]
+    pub fn state(&self) -> FragmentState {
+        self.state
+    }
+
+    /// Returns how many times the fragment was Nacked.
+    #[must_use]
+    pub fn retries(&self) -> u32 {
+        self.retries
+    }
+
+    #[must_use]
+    pub fn first_sent(&self) -> Option<Instant> {
+        self.first_sent
+    }
+
+    #[must_use]
+    pub fn last_sent(&self) -> Option<Instant> {
+        self.last_sent
+    }
+
+    /// Returns whether the fragment was sent and is not acknowledged yet.
+    #[must_use]
+    pub fn is_unacked(&self) -> bool {
+        matches!(self.state, FragmentState::Sent | FragmentState::Failed)
+    }
+}
+
+/// Fragments of a message sent to one destination, by fragment index.
+#[derive(Debug, Clone)]
+pub struct OutgoingSession {
+    destination: NodeId,
+    fragments: HashMap<u64, BufferedFragment>,
+    // indexes of the pending fragments, in sending order
+    pending: VecDeque<u64>,
+}
+
+impl OutgoingSession {
+    fn new(destination: NodeId) -> Self {
+        Self {
+            destination,
+            fragments: HashMap::new(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn destination(&self) -> NodeId {
+        self.destination
+    }
+
+    #[must_use]
+    pub fn fragment(&self, fragment_index: u64) -> Option<&BufferedFragment> {
+        self.fragments.get(&fragment_index)
+    }
+
+    /// Iterates over the fragments by index, in no particular order.
+    pub fn fragments(&self) -> impl Iterator<Item = (u64, &BufferedFragment)> {
+        self.fragments
+            .iter()
+            .map(|(fragment_index, fragment)| (*fragment_index, fragment))
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.fragments.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.fragments.is_empty()
+    }
+
+    /// Returns how many fragments are in `state`.
+    #[must_use]
+    pub fn count(&self, state: FragmentState) -> usize {
+        self.fragments
+            .values()
+            .filter(|fragment| fragment.state == state)
+            .count()
+    }
+}
+
+/// Buffered fragment as persisted in the node state directory.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub(crate) struct StoredFragment {
+    pub(crate) session_id: u64,
+    hops: Vec<NodeId>,
+    fragment_index: u64,
+    total_n_fragments: u64,
+    length: u8,
+    data: Vec<u8>,
+    acked: bool,
+}
+
+/// Outgoing sessions of a `RoutingHandler` not acknowledged yet, by session id and
+/// destination.
+///
+/// Every session keeps its fragments by index, each in a [`FragmentState`] going from
+/// `Pending` to `Sent`, then `Acked`, or `Failed` when Nacked until it is sent again.
+/// Sessions leave the buffer once all their fragments are acknowledged or they are
+/// given up.
+#[derive(Debug, Clone, Default)]
+pub struct SessionBuffer {
+    sessions: HashMap<(u64, NodeId), OutgoingSession>,
+}
+
+impl SessionBuffer {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn session(&self, session_id: u64, destination: NodeId) -> Option<&OutgoingSession> {
+        self.sessions.get(&(session_id, destination))
+    }
+
+    /// Iterates over the buffered sessions, in no particular order.
+    pub fn sessions(&self) -> impl Iterator<Item = (u64, &OutgoingSession)> {
+        self.sessions
+            .iter()
+            .map(|((session_id, _), session)| (*session_id, session))
+    }
+
+    /// Iterates over the sessions `session_id` buffered, one per destination, in no
+    /// particular order.
+    pub fn sessions_of(&self, session_id: u64) -> impl Iterator<Item = &OutgoingSession> {
+        self.sessions()
+            .filter(move |(id, _)| *id == session_id)
+            .map(|(_, session)| session)
+    }
+
+    #[must_use]
+    pub fn fragment(
+        &self,
+        session_id: u64,
+        destination: NodeId,
+        fragment_index: u64,
+    ) -> Option<&BufferedFragment> {
+        self.session(session_id, destination)?
+            .fragment(fragment_index)
+    }
+
+    /// Returns the fragment if it was sent and is not acknowledged yet.
+    #[must_use]
+    pub fn unacked(
+        &self,
+        session_id: u64,
+        destination: NodeId,
+        fragment_index: u64,
+    ) -> Option<&BufferedFragment> {
+        self.fragment(session_id, destination, fragment_index)
+            .filter(|fragment| fragment.is_unacked())
+    }
+
+    /// Returns whether the fragment was already sent once.
+    #[must_use]
+    pub fn was_sent(&self, session_id: u64, destination: NodeId, fragment_index: u64) -> bool {
+        self.fragment(session_id, destination, fragment_index)
+            .is_some_and(|fragment| fragment.state != FragmentState::Pending)
+    }
+
+    /// Returns the destination of the fragment `fragment_index` of `session_id` whose
+    /// route goes through `node`. Nacks carry no destination: the fragment they refer
+    /// to is the one routed through the node that sent them.
+    #[must_use]
+    pub fn routed_through(
+        &self,
+        session_id: u64,
+        fragment_index: u64,
+        node: NodeId,
+    ) -> Option<NodeId> {
+        self.sessions_of(session_id)
+            .filter(|session| {
+                session
+                    .fragment(fragment_index)
+                    .is_some_and(|f| f.packet.routing_header.hops.contains(&node))
+            })
+            .map(OutgoingSession::destination)
+            .min()
+    }
+
+    /// Returns the number of fragments of the session in flight, sent and not
+    /// acknowledged.
+    #[must_use]
+    pub fn outstanding(&self, session_id: u64, destination: NodeId) -> usize {
+        self.session(session_id, destination).map_or(0, |session| {
+            session
+                .fragments
+                .values()
+                .filter(|f| f.is_unacked())
+                .count()
+        })
+    }
+
+    /// Returns the number of pending fragments of the session.
+    #[must_use]
+    pub fn pending(&self, session_id: u64, destination: NodeId) -> usize {
+        self.session(session_id, destination)
+            .map_or(0, |session| session.pending.len())
+    }
+
+    /// Lists the sessions with pending fragments, by session id and destination.
+    #[must_use]
+    pub fn sessions_pending(&self) -> Vec<(u64, NodeId)> {
+        self.sessions
+            .iter()
+            .filter(|(_, session)| !session.pending.is_empty())
+            .map(|(key, _)| *key)
+            .collect()
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.sessions.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    /// Buffers `packet`, a fragment not sent yet, after the pending ones of its session.
+    /// Packets without a destination are ignored.
+    pub(crate) fn hold(&mut self, packet: Packet) {
+        let Some(destination) = packet.routing_header.destination() else {
+            return;
+        };
+        let fragment_index = packet.get_fragment_index();
+        let session = self
+            .sessions
+            .entry((packet.session_id, destination))
+            .or_insert_with(|| OutgoingSession::new(destination));
+        let fragment = BufferedFragment::new(packet, FragmentState::Pending);
+        if session.fragments.insert(fragment_index, fragment).is_none() {
+            session.pending.push_back(fragment_index);
+        }
+    }
+
+    /// Returns the next pending fragment of the session, which stays `Pending` until
+    /// [`Self::record_sent`].
+    pub(crate) fn next_pending(&mut self, session_id: u64, destination: NodeId) -> Option<Packet> {
+        let session = self.sessions.get_mut(&(session_id, destination))?;
+        while let Some(fragment_index) = session.pending.pop_front() {
+            if let Some(fragment) = session.fragments.get(&fragment_index) {
+                return Some(fragment.packet.clone());
+            }
+        }
+        None
+    }
+
+    /// Puts a fragment returned by [`Self::next_pending`] back in front of the queue,
+    /// e.g. when it could not be sent.
+    pub(crate) fn requeue(&mut self, session_id: u64, destination: NodeId, fragment_index: u64) {
+        if let Some(session) = self.sessions.get_mut(&(session_id, destination)) {
+            session.pending.push_front(fragment_index);
+        }
+    }
+
+    /// Records that the fragment `packet` left along its route at `now`. Other packets
+    /// are ignored.
+    pub(crate) fn record_sent(&mut self, packet: &Packet, now: Instant) {
+        let PacketType::MsgFragment(fragment) = &packet.pack_type else {
+            return;
+        };
+        let Some(destination) = packet.routing_header.destination() else {
+            return;
+        };
+        let session = self
+            .sessions
+            .entry((packet.session_id, destination))
+            .or_insert_with(|| OutgoingSession::new(destination));
+        let buffered = session
+            .fragments
+            .entry(fragment.fragment_index)
+            .or_insert_with(|| BufferedFragment::new(packet.clone(), FragmentState::Pending));
+        if buffered.state == FragmentState::Acked {
+            return;
+        }
+        buffered.packet.routing_header = packet.routing_header.clone();
+        buffered.state = FragmentState::Sent;
+        buffered.first_sent.get_or_insert(now);
+        buffered.last_sent = Some(now);
+    }
+
+    /// Marks a sent fragment as acknowledged. Returns whether every fragment of the
+    /// session is now acknowledged, the session then leaving the buffer.
+    pub(crate) fn mark_acked(
+        &mut self,
+        session_id: u64,
+        destination: NodeId,
+        fragment_index: u64,
+    ) -> bool {
+        let key = (session_id, destination);
+        let Some(session) = self.sessions.get_mut(&key) else {
+            return false;
+        };
+        match session.fragments.get_mut(&fragment_index) {
+            Some(fragment) if fragment.is_unacked() => fragment.state = FragmentState::Acked,
+            _ => return false,
+        }
+        let acked = session.count(FragmentState::Acked) == session.len();
+        if acked {
+            let _ = self.sessions.remove(&key);
+        }
+        acked
+    }
+
+    /// Marks a sent fragment as Nacked, returns how many times it was so far or `None` if
+    /// it is not awaiting an Ack.
+    pub(crate) fn mark_failed(
+        &mut self,
+        session_id: u64,
+        destination: NodeId,
+        fragment_index: u64,
+    ) -> Option<u32> {
+        let fragment = self
+            .sessions
+            .get_mut(&(session_id, destination))?
+            .fragments
+            .get_mut(&fragment_index)
+            .filter(|fragment| fragment.is_unacked())?;
+        fragment.state = FragmentState::Failed;
+        fragment.retries += 1;
+        Some(fragment.retries)
+    }
+
+    /// Records that the buffered fragment is to be retransmitted along `shr`.
+    pub(crate) fn reroute(
+        &mut self,
+        session_id: u64,
+        destination: NodeId,
+        fragment_index: u64,
+        shr: SourceRoutingHeader,
+    ) {
+        let fragment = self
+            .sessions
+            .get_mut(&(session_id, destination))
+            .and_then(|session| session.fragments.get_mut(&fragment_index));
+        if let Some(fragment) = fragment {
+            fragment.packet.routing_header = shr;
+        }
+    }
+
+    pub(crate) fn remove(
+        &mut self,
+        session_id: u64,
+        destination: NodeId,
+    ) -> Option<OutgoingSession> {
+        self.sessions.remove(&(session_id, destination))
+    }
+
+    /// Returns the buffered fragments in their persisted form, by session and index.
+    /// Pending fragments are stored as sent and not acknowledged.
+    pub(crate) fn to_stored(&self) -> Vec<StoredFragment> {
+        let mut stored = self
+            .sessions
+            .iter()
+            .flat_map(|((session_id, _), session)| {
+                session.fragments.values().map(move |f| (*session_id, f))
+            })
+            .filter_map(|(session_id, buffered)| {
+                let PacketType::MsgFragment(fragment) = &buffered.packet.pack_type else {
+                    return None;
+                };
+                Some(StoredFragment {
+                    session_id,
+                    hops: buffered.packet.routing_header.hops.clone(),
+                    fragment_index: fragment.fragment_index,
+                    total_n_fragments: fragment.total_n_fragments,
+                    length: fragment.length,
+                    data: fragment.data.to_vec(),
+                    acked: buffered.state == FragmentState::Acked,
+                })
+            })
+            .collect::<Vec<_>>();
+        stored.sort_by_key(|f| (f.session_id, f.hops.last().copied(), f.fragment_index));
+        stored
+    }
+
+    /// Puts persisted fragments back in the buffer as sent, returns how many sessions
+    /// were restored.
+    pub(crate) fn restore(&mut self, stored: Vec<StoredFragment>) -> usize {
+        let mut restored = 0;
+        for f in stored {
+            let Some(destination) = f.hops.last().copied() else {
+                continue;
+            };
+            let mut data = [0; 128];
+            let len = f.data.len().min(data.len());
+            data[..len].copy_from_slice(&f.data[..len]);
+            let packet = Packet::new_fragment(
+                SourceRoutingHeader::new(f.hops, 1),
+                f.session_id,
+                Fragment {
+                    fragment_index: f.fragment_index,
+                    total_n_fragments: f.total_n_fragments,
+                    length: f.length,
+                    data,
+                },
+            );
+            let state = if f.acked {
+                FragmentState::Acked
+            } else {
+                FragmentState::Sent
+            };
+            let key = (f.session_id, destination);
+            let session = self.sessions.entry(key).or_insert_with(|| {
+                restored += 1;
+                OutgoingSession::new(destination)
+            });
+            let _ = session
+                .fragments
+                .insert(f.fragment_index, BufferedFragment::new(packet, state));
+        }
+        restored
+    }
+}
+
+#[cfg(test)]
+mod session_buffer_tests {
+    use super::*;
+
+    fn fragment(session_id: u64, fragment_index: u64) -> Packet {
+        Packet::new_fragment(
+            SourceRoutingHeader::new(vec![1, 2, 3], 1),
+            session_id,
+            Fragment {
+                fragment_index,
+                total_n_fragments: 3,
+                length: 2,
+                data: [1; 128],
+            },
+        )
+    }
+
+    #[test]
+    /// Tests the states of the fragments of a session, in and out of order
+    fn test_session_buffer() {
+        let mut buffer = SessionBuffer::new();
+        buffer.hold(fragment(7, 1));
+        buffer.hold(fragment(7, 2));
+        // an Ack arriving before the fragment is sent is ignored
+        assert!(!buffer.mark_acked(7, 3, 1));
+        buffer.record_sent(&fragment(7, 0), Instant::now());
+        assert_eq!(buffer.session(7, 3).unwrap().destination(), 3);
+        assert_eq!((buffer.pending(7, 3), buffer.outstanding(7, 3)), (2, 1));
+
+        let next = buffer.next_pending(7, 3).unwrap();
+        assert_eq!(next.get_fragment_index(), 1);
+        assert!(!buffer.was_sent(7, 3, 1));
+        buffer.record_sent(&next, Instant::now());
+        let next = buffer.next_pending(7, 3).unwrap();
+        buffer.requeue(7, 3, next.get_fragment_index());
+        let next = buffer.next_pending(7, 3).unwrap();
+        assert_eq!(next.get_fragment_index(), 2);
+        buffer.record_sent(&next, Instant::now());
+        assert!(buffer.next_pending(7, 3).is_none());
+
+        // fragment indexes past the ones sent don't panic
+        assert_eq!(buffer.mark_failed(7, 3, 9), None);
+        assert_eq!(buffer.mark_failed(7, 3, 2), Some(1));
+        assert_eq!(
+            buffer.fragment(7, 3, 2).unwrap().state(),
+            FragmentState::Failed
+        );
+        buffer.record_sent(&fragment(7, 2), Instant::now());
+        assert_eq!(buffer.mark_failed(7, 3, 2), Some(2));
+
+        assert!(!buffer.mark_acked(7, 3, 2));
+        assert!(!buffer.mark_acked(7, 3, 0));
+        assert!(buffer.unacked(7, 3, 0).is_none());
+        assert_eq!(buffer.session(7, 3).unwrap().count(FragmentState::Acked), 2);
+        assert!(buffer.mark_acked(7, 3, 1));
+        assert!(buffer.is_empty());
+
+        // the same session id sent to another destination is another session
+        let mut elsewhere = fragment(7, 0);
+        elsewhere.routing_header = SourceRoutingHeader::new(vec![1, 4], 1);
+        buffer.record_sent(&fragment(7, 0), Instant::now());
+        buffer.record_sent(&elsewhere, Instant::now());
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer.routed_through(7, 0, 2), Some(3));
+        assert_eq!(buffer.routed_through(7, 0, 4), Some(4));
+        assert!(buffer.mark_acked(7, 4, 0));
+        assert_eq!(buffer.outstanding(7, 3), 1);
+    }
+}
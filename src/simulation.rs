@@ -0,0 +1,626 @@
+//! In-process network of routing handlers and simulated drones, stepped in rounds.
+//!
+//! Every round each packet in flight moves one hop and lasts [`ROUND_DURATION`] of the
+//! simulated clock shared by the nodes. Drones follow the protocol specification,
+//! dropping fragments with the given loss rate and answering with a `Nack`; the loss
+//! draws are seeded, and so are the session ids of the endpoints, so a simulation always
+//! replays the same way.
+//!
+//! [`MockNetwork`] is available to downstream crates through `testing`, to write the
+//! integration tests of their clients and servers without wiring channels by hand.
+
+use crate::clock::Clock;
+use crate::discovery::ServiceDiscovery;
+use crate::negotiation::VersionNegotiator;
+use crate::network::{NetworkError, Topology, TopologyError};
+use crate::packet_processor::{NodeCore, deliver_ready, dispatch_packet};
+use crate::transform::{PacketDirection, run_middlewares};
+use crate::{FragmentAssembler, Processor, RoutingHandler};
+use crossbeam_channel::{Receiver, Sender, unbounded};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::time::{Duration, Instant};
+use wg_internal::config::Config;
+use wg_internal::network::{NodeId, SourceRoutingHeader};
+use wg_internal::packet::{FloodResponse, Nack, NackType, NodeType, Packet, PacketType};
+
+/// Rounds after which a run is given up, in case messages can never be delivered.
+pub const MAX_ROUNDS: u64 = 100_000;
+
+/// Simulated time elapsing in every round.
+pub const ROUND_DURATION: Duration = Duration::from_millis(1);
+
+/// Client or server of the mock network, driven by the standard packet handling.
+pub(crate) struct Endpoint {
+    pub(crate) router: RoutingHandler,
+    assembler: FragmentAssembler,
+    // messages delivered to the endpoint as (source, message)
+    pub(crate) received: Vec<(NodeId, Vec<u8>)>,
+}
+
+impl NodeCore for Endpoint {
+    fn assembler(&mut self) -> &mut FragmentAssembler {
+        &mut self.assembler
+    }
+    fn routing_handler(&mut self) -> &mut RoutingHandler {
+        &mut self.router
+    }
+    fn version_negotiator(&mut self) -> Option<&mut VersionNegotiator> {
+        None
+    }
+    fn service_discovery(&mut self) -> Option<&mut ServiceDiscovery> {
+        None
+    }
+    fn handle_msg(&mut self, msg: Vec<u8>, from: NodeId, _session_id: u64) {
+        self.received.push((from, msg));
+    }
+    fn handle_control_fragment(&mut self, _payload: Vec<u8>, _from: NodeId, _session_id: u64) {}
+    fn deliver_ready_messages(&mut self) {
+        deliver_ready(self);
+    }
+}
+
+struct Drone {
+    // ordered, so that floods fan out the same way on every run
+    neighbors: BTreeMap<NodeId, Sender<Packet>>,
+    floods_seen: HashSet<(u64, NodeId)>,
+}
+
+/// Clients and servers of a topology, each a [`RoutingHandler`] fed by the standard
+/// packet handling, connected through simulated drones over in-memory channels.
+///
+/// Nothing runs on its own: [`Self::step`] and [`Self::run_until`] move the packets in
+/// flight one hop per round, so tests are deterministic for a given seed. Every node
+/// follows the simulated clock of the network, which moves [`ROUND_DURATION`] per
+/// round and on [`Self::advance`]; timers only fire on [`Self::tick`] and
+/// [`Self::advance`]. Clients and servers are built-in endpoints recording the messages
+/// they receive, unless a downstream [`Processor`] is hosted in their place with
+/// [`Self::host`]. Drones drop fragments with their drop rate and can be crashed, in
+/// which case their neighbors forget them as after a `RemoveSender` command.
+pub struct MockNetwork {
+    inboxes: BTreeMap<NodeId, Receiver<Packet>>,
+    senders: HashMap<NodeId, Sender<Packet>>,
+    links: Topology,
+    pub(crate) endpoints: BTreeMap<NodeId, Endpoint>,
+    processors: BTreeMap<NodeId, Box<dyn Processor>>,
+    drones: BTreeMap<NodeId, Drone>,
+    loss_rate: f32,
+    // drop rates overriding `loss_rate` by drone
+    drop_rates: HashMap<NodeId, f32>,
+    rng: StdRng,
+    // fragments sent by each endpoint, retransmissions included
+    pub(crate) fragments_from: HashMap<NodeId, u64>,
+    // rounds stepped since the network was created
+    pub(crate) clock: u64,
+    time: Clock,
+    // every packet delivered as (round, recipient, packet), `None` when not recorded
+    pub(crate) trace: Option<Vec<(u64, NodeId, Packet)>>,
+}
+
+impl MockNetwork {
+    /// Builds the network of `topology`, every drone dropping fragments with
+    /// `loss_rate` instead of its configured PDR.
+    #[must_use]
+    pub fn new(topology: &Config, loss_rate: f32) -> Self {
+        let mut senders = HashMap::new();
+        let mut inboxes = BTreeMap::new();
+        let ids = topology
+            .drone
+            .iter()
+            .map(|d| d.id)
+            .chain(topology.client.iter().map(|c| c.id))
+            .chain(topology.server.iter().map(|s| s.id));
+        for id in ids {
+            let (send, recv) = unbounded();
+            let _ = senders.insert(id, send);
+            let _ = inboxes.insert(id, recv);
+        }
+        let links = Topology::from_config(topology);
+        let time = Clock::simulated();
+        let neighbors = |id: NodeId| {
+            links
+                .neighbors(id)
+                .into_iter()
+                .filter_map(|adj| Some((adj, senders.get(&adj)?.clone())))
+                .collect::<HashMap<_, _>>()
+        };
+
+        let drones = topology
+            .drone
+            .iter()
+            .map(|d| {
+                let drone = Drone {
+                    neighbors: neighbors(d.id).into_iter().collect(),
+                    floods_seen: HashSet::new(),
+                };
+                (d.id, drone)
+            })
+            .collect();
+        let endpoint = |id, node_type| {
+            // nobody listens to the events of the endpoints
+            let (controller_send, _) = unbounded();
+            let mut router = RoutingHandler::new(id, node_type, neighbors(id), controller_send);
+            router.seed_session_ids(u64::from(id) << 32);
+            router.set_clock(time.clone());
+            let mut assembler = FragmentAssembler::default();
+            assembler.set_clock(time.clone());
+            Endpoint {
+                router,
+                assembler,
+                received: Vec::new(),
+            }
+        };
+        let endpoints = topology
+            .client
+            .iter()
+            .map(|c| (c.id, endpoint(c.id, NodeType::Client)))
+            .chain(
+                topology
+                    .server
+                    .iter()
+                    .map(|s| (s.id, endpoint(s.id, NodeType::Server))),
+            )
+            .collect();
+
+        Self {
+            inboxes,
+            senders,
+            links,
+            endpoints,
+            processors: BTreeMap::new(),
+            drones,
+            loss_rate,
+            drop_rates: HashMap::new(),
+            rng: StdRng::seed_from_u64(0),
+            fragments_from: HashMap::new(),
+            clock: 0,
+            time,
+            trace: None,
+        }
+    }
+
+    /// Builds the network of `topology`, every drone dropping fragments with its
+    /// configured PDR.
+    #[must_use]
+    pub fn from_config(topology: &Config) -> Self {
+        let mut network = Self::new(topology, 0.0);
+        for drone in &topology.drone {
+            network.set_drop_rate(drone.id, drone.pdr);
+        }
+        network
+    }
+
+    /// Seeds the loss draws of the drones, so that runs can be repeated with other
+    /// outcomes.
+    #[must_use]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = StdRng::seed_from_u64(seed);
+        self
+    }
+
+    /// Makes `drone` drop fragments with `rate` from now on.
+    pub fn set_drop_rate(&mut self, drone: NodeId, rate: f32) {
+        let _ = self.drop_rates.insert(drone, rate);
+    }
+
+    /// Crashes `drone`: the packets it holds are lost and its neighbors stop sending to
+    /// it. Returns whether it was running.
+    pub fn crash(&mut self, drone: NodeId) -> bool {
+        if self.drones.remove(&drone).is_none() {
+            return false;
+        }
+        let _ = self.inboxes.remove(&drone);
+        for other in self.drones.values_mut() {
+            let _ = other.neighbors.remove(&drone);
+        }
+        for endpoint in self.endpoints.values_mut() {
+            endpoint.router.remove_neighbor(drone);
+        }
+        for processor in self.processors.values_mut() {
+            processor.routing_handler().remove_neighbor(drone);
+        }
+        true
+    }
+
+    /// Returns the channels towards the neighbors of `node`, to build the router of a
+    /// processor hosted with [`Self::host`].
+    #[must_use]
+    pub fn neighbors(&self, node: NodeId) -> HashMap<NodeId, Sender<Packet>> {
+        self.links
+            .neighbors(node)
+            .into_iter()
+            .filter(|adj| self.drones.contains_key(adj))
+            .filter_map(|adj| Some((adj, self.senders.get(&adj)?.clone())))
+            .collect()
+    }
+
+    /// Runs `processor` at the client or server `node` in place of its endpoint, its
+    /// router being built on [`Self::neighbors`]. The processor receives the packets of
+    /// the node through `Processor::handle_packet`, after its middlewares, has the
+    /// commands of its controller channel served every round and ticks with the network;
+    /// its packet channel is left unused. It is switched to the simulated clock.
+    /// Returns whether `node` is a client or server of the network.
+    pub fn host(&mut self, node: NodeId, mut processor: Box<dyn Processor>) -> bool {
+        if !self.endpoints.contains_key(&node) && !self.processors.contains_key(&node) {
+            return false;
+        }
+        let _ = self.endpoints.remove(&node);
+        processor.routing_handler().set_clock(self.time.clone());
+        processor.assembler().set_clock(self.time.clone());
+        let _ = self.processors.insert(node, processor);
+        true
+    }
+
+    /// Returns the router of the endpoint `node`, see [`Self::router_mut`] for the
+    /// hosted processors.
+    #[must_use]
+    pub fn router(&self, node: NodeId) -> Option<&RoutingHandler> {
+        self.endpoints.get(&node).map(|endpoint| &endpoint.router)
+    }
+
+    #[must_use]
+    pub fn router_mut(&mut self, node: NodeId) -> Option<&mut RoutingHandler> {
+        if let Some(processor) = self.processors.get_mut(&node) {
+            return Some(processor.routing_handler());
+        }
+        self.endpoints
+            .get_mut(&node)
+            .map(|endpoint| &mut endpoint.router)
+    }
+
+    /// Returns the processor hosted at `node`, see [`Self::host`].
+    #[must_use]
+    pub fn processor_mut(&mut self, node: NodeId) -> Option<&mut dyn Processor> {
+        match self.processors.get_mut(&node) {
+            Some(processor) => Some(processor.as_mut()),
+            None => None,
+        }
+    }
+
+    /// Returns the messages delivered to `node` so far as (source, message), in
+    /// delivery order.
+    #[must_use]
+    pub fn received(&self, node: NodeId) -> &[(NodeId, Vec<u8>)] {
+        self.endpoints
+            .get(&node)
+            .map_or(&[], |endpoint| endpoint.received.as_slice())
+    }
+
+    /// Returns the rounds stepped since the network was built.
+    #[must_use]
+    pub fn clock(&self) -> u64 {
+        self.clock
+    }
+
+    /// Returns the current time of the simulated clock.
+    #[must_use]
+    pub fn now(&self) -> Instant {
+        self.time.now()
+    }
+
+    /// Floods from every client and server, then runs until the floods settle.
+    /// # Errors
+    /// Returns any error returned while starting the floods.
+    pub fn flood(&mut self) -> Result<(), NetworkError> {
+        for endpoint in self.endpoints.values_mut() {
+            endpoint.router.start_flood(None)?;
+        }
+        for processor in self.processors.values_mut() {
+            processor.routing_handler().start_flood(None)?;
+        }
+        let _ = self.run_until(|_| false);
+        Ok(())
+    }
+
+    /// Sends `payload` from `from` to `to` and runs until it is delivered or nothing
+    /// is in flight anymore. Returns the rounds stepped.
+    /// # Errors
+    /// `TopologyError` if `from` is not a client or server of the network,
+    /// or any error returned by `RoutingHandler::send_message`.
+    pub fn send(&mut self, from: NodeId, to: NodeId, payload: &[u8]) -> Result<u64, NetworkError> {
+        let delivered = self.received(to).len();
+        let router = self.router_mut(from).ok_or(TopologyError::Invalid)?;
+        let _ = router.send_message(payload, Some(to), None)?;
+        Ok(self.run_until(|network| network.received(to).len() > delivered))
+    }
+
+    /// Runs the periodic work of every node once, see `RoutingHandler::tick` and
+    /// `Processor::tick`.
+    /// # Errors
+    /// Returns the first error returned by the router of an endpoint.
+    pub fn tick(&mut self) -> Result<(), NetworkError> {
+        for endpoint in self.endpoints.values_mut() {
+            endpoint.router.tick()?;
+        }
+        for processor in self.processors.values_mut() {
+            processor.tick();
+        }
+        Ok(())
+    }
+
+    /// Moves the simulated clock `by` forward, then runs the periodic work of every
+    /// node, so that the timers due meanwhile fire.
+    /// # Errors
+    /// Returns the first error returned by the router of an endpoint.
+    pub fn advance(&mut self, by: Duration) -> Result<(), NetworkError> {
+        self.time.advance(by);
+        self.tick()
+    }
+
+    /// Moves every packet in flight one hop, returns whether any packet moved.
+    /// The commands sent to the hosted processors are served first.
+    pub fn step(&mut self) -> bool {
+        for processor in self.processors.values_mut() {
+            let commands = processor.controller_recv().try_iter().collect::<Vec<_>>();
+            for cmd in commands {
+                if !processor.answer_query(cmd.as_ref()) {
+                    let _ = processor.handle_command(cmd);
+                }
+            }
+        }
+        // only the packets queued before the round move, so each moves one hop
+        let queued = self
+            .inboxes
+            .iter()
+            .map(|(id, inbox)| (*id, inbox.try_iter().collect::<Vec<_>>()))
+            .filter(|(_, packets)| !packets.is_empty())
+            .collect::<Vec<_>>();
+        if queued.is_empty() {
+            return false;
+        }
+        self.clock += 1;
+        self.time.advance(ROUND_DURATION);
+        for (id, packets) in queued {
+            self.defer_delivery(id, true);
+            for packet in packets {
+                self.deliver(id, packet);
+            }
+            self.defer_delivery(id, false);
+        }
+        true
+    }
+
+    /// Steps the network until `done` holds, no packet is in flight or [`MAX_ROUNDS`]
+    /// elapsed, and returns the rounds stepped.
+    pub fn run_until(&mut self, done: impl Fn(&Self) -> bool) -> u64 {
+        let mut rounds = 0;
+        while rounds < MAX_ROUNDS && !done(self) && self.step() {
+            rounds += 1;
+        }
+        rounds
+    }
+
+    /// Defers the delivery of the messages completed by the node `id` during a round,
+    /// or hands them over round-robin once `defer` is lifted.
+    fn defer_delivery(&mut self, id: NodeId, defer: bool) {
+        if let Some(endpoint) = self.endpoints.get_mut(&id) {
+            endpoint.assembler.defer_delivery(defer);
+            if !defer {
+                deliver_ready(endpoint);
+            }
+        } else if let Some(processor) = self.processors.get_mut(&id) {
+            processor.assembler().defer_delivery(defer);
+            if !defer {
+                processor.deliver_ready_messages();
+            }
+        }
+    }
+
+    fn deliver(&mut self, id: NodeId, packet: Packet) {
+        if let Some(trace) = &mut self.trace {
+            trace.push((self.clock, id, packet.clone()));
+        }
+        if let PacketType::MsgFragment(_) = packet.pack_type {
+            if packet.routing_header.hop_index == 1 {
+                if let Some(source) = packet.routing_header.hops.first() {
+                    *self.fragments_from.entry(*source).or_default() += 1;
+                }
+            }
+        }
+        if let Some(endpoint) = self.endpoints.get_mut(&id) {
+            let chain = endpoint.router.middlewares();
+            if let Some(packet) = run_middlewares(chain, packet, PacketDirection::Received) {
+                let _ = dispatch_packet(endpoint, packet);
+            }
+        } else if let Some(processor) = self.processors.get_mut(&id) {
+            let chain = processor.middlewares();
+            if let Some(packet) = run_middlewares(chain, packet, PacketDirection::Received) {
+                let _ = processor.handle_packet(packet);
+            }
+        } else if self.drones.contains_key(&id) {
+            self.forward(id, packet);
+        }
+    }
+
+    /// Handles `packet` as a drone following the protocol specification would.
+    fn forward(&mut self, id: NodeId, mut packet: Packet) {
+        let Some(drone) = self.drones.get_mut(&id) else {
+            return;
+        };
+        if let PacketType::FloodRequest(request) = &mut packet.pack_type {
+            let prev_hop = request
+                .path_trace
+                .last()
+                .map_or(request.initiator_id, |(prev, _)| *prev);
+            request.path_trace.push((id, NodeType::Drone));
+            let others = drone
+                .neighbors
+                .iter()
+                .filter(|(neighbor, _)| **neighbor != prev_hop)
+                .collect::<Vec<_>>();
+            if drone
+                .floods_seen
+                .insert((request.flood_id, request.initiator_id))
+                && !others.is_empty()
+            {
+                for (_, sender) in others {
+                    let _ = sender.send(packet.clone());
+                }
+                return;
+            }
+            let mut route = request
+                .path_trace
+                .iter()
+                .map(|(node, _)| *node)
+                .rev()
+                .collect::<Vec<_>>();
+            if route.last() != Some(&request.initiator_id) {
+                route.push(request.initiator_id);
+            }
+            let response = FloodResponse {
+                flood_id: request.flood_id,
+                path_trace: request.path_trace.clone(),
+            };
+            packet = Packet::new_flood_response(
+                SourceRoutingHeader::new(route, 0),
+                packet.session_id,
+                response,
+            );
+        }
+
+        let header = &packet.routing_header;
+        let nack = |nack_type| {
+            let PacketType::MsgFragment(fragment) = &packet.pack_type else {
+                return None;
+            };
+            let mut route = header.hops[..=header.hop_index].to_vec();
+            route.reverse();
+            Some(Packet::new_nack(
+                SourceRoutingHeader::new(route, 0),
+                packet.session_id,
+                Nack {
+                    fragment_index: fragment.fragment_index,
+                    nack_type,
+                },
+            ))
+        };
+        let next = header.hops.get(header.hop_index + 1).copied();
+        let reply = match next {
+            None => nack(NackType::DestinationIsDrone),
+            Some(next) if !drone.neighbors.contains_key(&next) => {
+                nack(NackType::ErrorInRouting(next))
+            }
+            // only fragments are ever dropped
+            Some(_)
+                if self.rng.random::<f32>()
+                    < self.drop_rates.get(&id).copied().unwrap_or(self.loss_rate) =>
+            {
+                nack(NackType::Dropped)
+            }
+            Some(_) => None,
+        };
+        let mut packet = reply.unwrap_or(packet);
+        packet.routing_header.hop_index += 1;
+        let next = packet
+            .routing_header
+            .hops
+            .get(packet.routing_header.hop_index);
+        if let Some(sender) = next.and_then(|next| drone.neighbors.get(next)) {
+            let _ = sender.send(packet);
+        }
+    }
+}
+
+#[cfg(test)]
+mod simulation_tests {
+    use super::*;
+    use crate::testing::LoopbackNode;
+    use crate::types::{ServerType, WebRequest, WebResponse};
+    use wg_internal::config::{Client, Drone, Server};
+
+    // client 1 reaches server 4 through drone 2 or drone 3
+    fn diamond() -> Config {
+        let drone = |id| Drone {
+            id,
+            connected_node_ids: vec![1, 4],
+            pdr: 0.0,
+        };
+        Config {
+            drone: vec![drone(2), drone(3)],
+            client: vec![Client {
+                id: 1,
+                connected_drone_ids: vec![2, 3],
+            }],
+            server: vec![Server {
+                id: 4,
+                connected_drone_ids: vec![2, 3],
+            }],
+        }
+    }
+
+    #[test]
+    /// Tests that messages get through lossy and crashed drones, the same way on every run
+    fn test_mock_network() {
+        let run = || {
+            let mut network = MockNetwork::from_config(&diamond()).with_seed(7);
+            network.set_drop_rate(2, 0.5);
+            network.set_drop_rate(3, 0.5);
+            network.flood().unwrap();
+            let _ = network.send(1, 4, &[1; 600]).unwrap();
+            assert_eq!(network.received(4), &[(1, vec![1; 600])]);
+            network.clock()
+        };
+        assert_eq!(run(), run());
+
+        let mut network = MockNetwork::new(&diamond(), 0.0);
+        network.flood().unwrap();
+        assert!(network.crash(2));
+        assert!(!network.crash(2));
+        let _ = network.send(4, 1, b"hi").unwrap();
+        assert_eq!(network.received(1), &[(4, b"hi".to_vec())]);
+        assert!(network.router(1).is_some());
+        assert!(matches!(
+            network.send(2, 1, b"hi"),
+            Err(NetworkError::Topology(TopologyError::Invalid))
+        ));
+    }
+
+    #[test]
+    /// Tests that a hosted processor serves the requests of an endpoint on simulated time
+    fn test_hosted_processor() {
+        let mut network = MockNetwork::new(&diamond(), 0.0);
+        let (controller_send, _controller_recv) = unbounded();
+        let (_commands, controller_recv) = unbounded();
+        let (_packet_send, packet_recv) = unbounded();
+        let server = LoopbackNode::new(
+            4,
+            controller_send,
+            controller_recv,
+            packet_recv,
+            network.neighbors(4),
+        );
+        assert_eq!(network.neighbors(4).len(), 2);
+        assert!(network.host(4, Box::new(server)));
+        assert!(!network.host(
+            2,
+            Box::new(LoopbackNode::new(
+                2,
+                unbounded().0,
+                unbounded().1,
+                unbounded().1,
+                HashMap::new(),
+            ))
+        ));
+        network.flood().unwrap();
+
+        let start = network.now();
+        let query = serde_json::to_vec(&WebRequest::ServerTypeQuery).unwrap();
+        let rounds = network.send(1, 4, &query).unwrap();
+        let _ = network.run_until(|network| !network.received(1).is_empty());
+        let (from, response) = &network.received(1)[0];
+        assert_eq!(*from, 4);
+        assert!(matches!(
+            serde_json::from_slice(response).unwrap(),
+            WebResponse::ServerType {
+                server_type: ServerType::TextServer
+            }
+        ));
+        assert!(network.now() >= start + ROUND_DURATION * u32::try_from(rounds).unwrap());
+        assert_eq!(network.router_mut(4).unwrap().now(), network.now());
+
+        network.advance(Duration::from_secs(5)).unwrap();
+        assert_eq!(network.router(1).unwrap().now(), network.now());
+        assert!(network.now() >= start + Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,181 @@
+//! Persistence of the files kept by a node, behind a [`StorageBackend`].
+//!
+//! [`FsStorage`] keeps them in a directory, as the crate always did, while
+//! [`MemoryStorage`] keeps them in memory so that servers and their tests can run
+//! without a disk.
+
+use std::collections::BTreeMap;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Flat key-value store of files, keyed by file name.
+pub trait StorageBackend: Debug {
+    /// Stores `data` under `key`, replacing any previous value.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key is invalid or the data cannot be written.
+    fn put(&mut self, key: &str, data: &[u8]) -> io::Result<()>;
+
+    /// Returns the data stored under `key`, if any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key is invalid or the data cannot be read.
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Removes the data stored under `key`, returns whether there was any.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the key is invalid or the data cannot be removed.
+    fn delete(&mut self, key: &str) -> io::Result<bool>;
+
+    /// Lists the keys stored, in ascending order.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the keys cannot be listed.
+    fn list(&self) -> io::Result<Vec<String>>;
+}
+
+/// Rejects the keys that aren't plain file names.
+fn check_key(key: &str) -> io::Result<()> {
+    if key.is_empty() || key == "." || key == ".." || key.contains(['/', '\\']) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("invalid storage key {key:?}"),
+        ));
+    }
+    Ok(())
+}
+
+/// Files kept in a directory, one per key.
+#[derive(Debug, Clone)]
+pub struct FsStorage {
+    dir: PathBuf,
+}
+
+impl FsStorage {
+    /// Opens the storage in `dir`, creating it on first use.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir })
+    }
+
+    #[must_use]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl StorageBackend for FsStorage {
+    fn put(&mut self, key: &str, data: &[u8]) -> io::Result<()> {
+        check_key(key)?;
+        fs::write(self.dir.join(key), data)
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        check_key(key)?;
+        match fs::read(self.dir.join(key)) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn delete(&mut self, key: &str) -> io::Result<bool> {
+        check_key(key)?;
+        match fs::remove_file(self.dir.join(key)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            if entry.file_type()?.is_file() {
+                if let Some(name) = entry.file_name().to_str() {
+                    keys.push(name.to_string());
+                }
+            }
+        }
+        keys.sort_unstable();
+        Ok(keys)
+    }
+}
+
+/// Files kept in memory, lost when dropped.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryStorage {
+    files: BTreeMap<String, Vec<u8>>,
+}
+
+impl MemoryStorage {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl StorageBackend for MemoryStorage {
+    fn put(&mut self, key: &str, data: &[u8]) -> io::Result<()> {
+        check_key(key)?;
+        let _ = self.files.insert(key.to_string(), data.to_vec());
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        check_key(key)?;
+        Ok(self.files.get(key).cloned())
+    }
+
+    fn delete(&mut self, key: &str) -> io::Result<bool> {
+        check_key(key)?;
+        Ok(self.files.remove(key).is_some())
+    }
+
+    fn list(&self) -> io::Result<Vec<String>> {
+        Ok(self.files.keys().cloned().collect())
+    }
+}
+
+#[cfg(test)]
+mod storage_tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    fn exercise(storage: &mut impl StorageBackend) {
+        storage.put("b.json", b"{}").unwrap();
+        storage.put("a.png", &[1, 2, 3]).unwrap();
+        storage.put("a.png", &[4]).unwrap();
+        assert_eq!(storage.get("a.png").unwrap(), Some(vec![4]));
+        assert_eq!(storage.get("missing").unwrap(), None);
+        assert_eq!(storage.list().unwrap(), vec!["a.png", "b.json"]);
+        assert!(storage.delete("a.png").unwrap());
+        assert!(!storage.delete("a.png").unwrap());
+        assert_eq!(storage.list().unwrap(), vec!["b.json"]);
+        assert!(storage.put("../escape", b"").is_err());
+        assert!(storage.get("dir/file").is_err());
+    }
+
+    #[test]
+    /// Tests that both backends store, list and delete files alike
+    fn test_storage_backends() {
+        let dir = tempdir().unwrap();
+        let mut fs_storage = FsStorage::open(dir.path().join("files")).unwrap();
+        exercise(&mut fs_storage);
+        assert_eq!(fs::read(dir.path().join("files/b.json")).unwrap(), b"{}");
+        exercise(&mut MemoryStorage::new());
+    }
+}
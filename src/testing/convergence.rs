@@ -0,0 +1,166 @@
+use crate::network::{Network, Topology, TopologyDiff};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::thread;
+use std::time::{Duration, Instant};
+use wg_internal::config::Config;
+use wg_internal::network::NodeId;
+
+/// How often the views are polled while waiting for convergence.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Views that didn't converge before the timeout, with their last differences.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConvergenceError {
+    pub timeout: Duration,
+    // `None` when no snapshot of the node could be taken
+    pub pending: BTreeMap<NodeId, Option<TopologyDiff>>,
+}
+
+impl Display for ConvergenceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} node(s) did not converge within {:?}",
+            self.pending.len(),
+            self.timeout
+        )?;
+        for (id, diff) in &self.pending {
+            match diff {
+                Some(diff) => write!(f, "node {id}:\n{diff}")?,
+                None => writeln!(f, "node {id}: no snapshot available")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConvergenceError {}
+
+/// Polls the network view of every node in `nodes` through `snapshot` until all of them
+/// match `expected` or `timeout` expires.
+///
+/// `snapshot` returns the current view of a node, or `None` if it cannot be taken yet.
+///
+/// # Errors
+///
+/// Returns the nodes still differing from `expected` when the timeout expires.
+pub fn wait_for_convergence<F>(
+    expected: &Topology,
+    nodes: &[NodeId],
+    timeout: Duration,
+    mut snapshot: F,
+) -> Result<(), ConvergenceError>
+where
+    F: FnMut(NodeId) -> Option<Network>,
+{
+    let start = Instant::now();
+    loop {
+        let mut pending = BTreeMap::new();
+        for id in nodes {
+            let diff = snapshot(*id).map(|view| expected.diff(&Topology::from_network(&view)));
+            if diff.as_ref().is_none_or(|d| !d.is_empty()) {
+                let _ = pending.insert(*id, diff);
+            }
+        }
+        if pending.is_empty() {
+            return Ok(());
+        }
+        if start.elapsed() >= timeout {
+            return Err(ConvergenceError { timeout, pending });
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Like [`wait_for_convergence`], with the expected topology taken from `config`.
+///
+/// # Panics
+///
+/// Panics with a per-node diff if the views don't converge within `timeout`.
+pub fn assert_converges<F>(config: &Config, nodes: &[NodeId], timeout: Duration, snapshot: F)
+where
+    F: FnMut(NodeId) -> Option<Network>,
+{
+    let expected = Topology::from_config(config);
+    if let Err(e) = wait_for_convergence(&expected, nodes, timeout, snapshot) {
+        panic!("{e}");
+    }
+}
+
+#[cfg(test)]
+mod convergence_tests {
+    use super::*;
+    use wg_internal::config::{Client, Drone, Server};
+    use wg_internal::packet::NodeType;
+
+    fn config() -> Config {
+        Config {
+            drone: vec![
+                Drone {
+                    id: 1,
+                    connected_node_ids: vec![2, 10],
+                    pdr: 0.0,
+                },
+                Drone {
+                    id: 2,
+                    connected_node_ids: vec![1, 20],
+                    pdr: 0.0,
+                },
+            ],
+            client: vec![Client {
+                id: 10,
+                connected_drone_ids: vec![1],
+            }],
+            server: vec![Server {
+                id: 20,
+                connected_drone_ids: vec![2],
+            }],
+        }
+    }
+
+    fn view(nodes: &[(NodeId, NodeType, &[NodeId])]) -> Network {
+        let mut network = Network::default();
+        for (id, kind, adjacents) in nodes {
+            network.add_node_controller_view(*id, *kind, adjacents);
+        }
+        network
+    }
+
+    #[test]
+    /// Tests that a complete view matches the configured topology
+    fn test_converged() {
+        let full = view(&[
+            (10, NodeType::Client, &[1]),
+            (1, NodeType::Drone, &[10, 2]),
+            (2, NodeType::Drone, &[1, 20]),
+            (20, NodeType::Server, &[2]),
+        ]);
+        assert_converges(&config(), &[10, 20], Duration::from_millis(50), |_| {
+            Some(full.clone())
+        });
+    }
+
+    #[test]
+    /// Tests that a partial view times out with a diff of what is missing
+    fn test_not_converged() {
+        let partial = view(&[(10, NodeType::Client, &[1]), (1, NodeType::Server, &[10])]);
+        let err = wait_for_convergence(
+            &Topology::from_config(&config()),
+            &[10, 20],
+            Duration::from_millis(30),
+            |id| (id == 10).then(|| partial.clone()),
+        )
+        .unwrap_err();
+
+        assert_eq!(err.pending.get(&20), Some(&None));
+        let diff = err.pending[&10].clone().unwrap();
+        assert_eq!(diff.missing_nodes, vec![2, 20]);
+        assert_eq!(
+            diff.wrong_types,
+            vec![(1, NodeType::Drone, NodeType::Server)]
+        );
+        assert_eq!(diff.missing_edges, vec![(1, 2), (2, 20)]);
+        assert!(err.to_string().contains("missing links: [(1, 2), (2, 20)]"));
+    }
+}
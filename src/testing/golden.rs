@@ -0,0 +1,301 @@
+use crate::simulation::MockNetwork;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::path::Path;
+use std::{fs, io};
+use wg_internal::config::Config;
+use wg_internal::network::NodeId;
+use wg_internal::packet::{Packet, PacketType};
+
+/// Environment variable which, when set, makes [`assert_matches_golden`] rewrite the
+/// golden traces instead of comparing against them.
+pub const UPDATE_GOLDEN_ENV: &str = "UPDATE_GOLDEN";
+
+/// A packet delivered during a simulation.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Round of the simulation the packet was delivered in
+    pub round: u64,
+    pub to: NodeId,
+    pub session_id: u64,
+    pub hops: Vec<NodeId>,
+    pub hop_index: usize,
+    /// Type and content of the packet, e.g. `Fragment 0/3 (128 bytes)`
+    pub packet: String,
+}
+
+impl TraceEntry {
+    fn new(round: u64, to: NodeId, packet: &Packet) -> Self {
+        let description = match &packet.pack_type {
+            PacketType::MsgFragment(f) => format!(
+                "Fragment {}/{} ({} bytes)",
+                f.fragment_index, f.total_n_fragments, f.length
+            ),
+            PacketType::Ack(ack) => format!("Ack {}", ack.fragment_index),
+            PacketType::Nack(nack) => format!("Nack {} {:?}", nack.fragment_index, nack.nack_type),
+            PacketType::FloodRequest(request) => format!(
+                "FloodRequest {} from {} {:?}",
+                request.flood_id, request.initiator_id, request.path_trace
+            ),
+            PacketType::FloodResponse(response) => {
+                format!(
+                    "FloodResponse {} {:?}",
+                    response.flood_id, response.path_trace
+                )
+            }
+        };
+        Self {
+            round,
+            to,
+            session_id: packet.session_id,
+            hops: packet.routing_header.hops.clone(),
+            hop_index: packet.routing_header.hop_index,
+            packet: description,
+        }
+    }
+
+    /// Returns the names of the fields differing between `self` and `other`.
+    fn differing_fields(&self, other: &Self) -> Vec<&'static str> {
+        [
+            ("round", self.round != other.round),
+            ("to", self.to != other.to),
+            ("session_id", self.session_id != other.session_id),
+            ("hops", self.hops != other.hops),
+            ("hop_index", self.hop_index != other.hop_index),
+            ("packet", self.packet != other.packet),
+        ]
+        .into_iter()
+        .filter(|(_, differs)| *differs)
+        .map(|(field, _)| field)
+        .collect()
+    }
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "round {} to {}: {} (session {}, hops {:?} at {})",
+            self.round, self.to, self.packet, self.session_id, self.hops, self.hop_index
+        )
+    }
+}
+
+/// Ordered trace of every packet delivered during a deterministic simulation.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct GoldenTrace {
+    pub entries: Vec<TraceEntry>,
+}
+
+impl GoldenTrace {
+    /// Simulates `topology`: every client and server floods, then each `(from, to, payload)`
+    /// of `messages` is sent in turn, every drone losing fragments with `loss_rate`.
+    /// The simulation is seeded and runs on the simulated clock of [`MockNetwork`], and
+    /// floods fan out by ascending neighbor id, so the same arguments always give the
+    /// same trace as long as the protocol behaves the same.
+    #[must_use]
+    pub fn record(
+        topology: &Config,
+        messages: &[(NodeId, NodeId, Vec<u8>)],
+        loss_rate: f32,
+    ) -> Self {
+        let mut network = MockNetwork::new(topology, loss_rate);
+        network.trace = Some(Vec::new());
+        for endpoint in network.endpoints.values_mut() {
+            let _ = endpoint.router.start_flood(None);
+        }
+        let _ = network.run_until(|_| false);
+        for (from, to, payload) in messages {
+            if let Some(endpoint) = network.endpoints.get_mut(from) {
+                let _ = endpoint.router.send_message(payload, Some(*to), None);
+            }
+            let _ = network.run_until(|_| false);
+        }
+        let entries = network
+            .trace
+            .unwrap_or_default()
+            .iter()
+            .map(|(round, to, packet)| TraceEntry::new(*round, *to, packet))
+            .collect();
+        Self { entries }
+    }
+
+    /// Returns how `actual` departs from `self`, `None` if the traces are identical.
+    #[must_use]
+    pub fn diff(&self, actual: &GoldenTrace) -> Option<TraceDiff> {
+        let index = (0..self.entries.len().max(actual.entries.len()))
+            .find(|i| self.entries.get(*i) != actual.entries.get(*i))?;
+        let expected = self.entries.get(index).cloned();
+        let found = actual.entries.get(index).cloned();
+        let fields = match (&expected, &found) {
+            (Some(expected), Some(found)) => expected.differing_fields(found),
+            _ => Vec::new(),
+        };
+        Some(TraceDiff {
+            index,
+            expected,
+            actual: found,
+            fields,
+            expected_len: self.entries.len(),
+            actual_len: actual.entries.len(),
+        })
+    }
+
+    /// # Errors
+    /// Returns an error if the file cannot be read or doesn't hold a trace.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let data = fs::read(path)?;
+        serde_json::from_slice(&data).map_err(io::Error::other)
+    }
+
+    /// # Errors
+    /// Returns an error if the file cannot be written.
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let data = serde_json::to_vec_pretty(self).map_err(io::Error::other)?;
+        fs::write(path, data)
+    }
+}
+
+/// First divergence between a golden and an actual [`GoldenTrace`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceDiff {
+    /// Index of the first entry differing
+    pub index: usize,
+    /// Golden entry at `index`, `None` if the actual trace is longer
+    pub expected: Option<TraceEntry>,
+    /// Actual entry at `index`, `None` if the actual trace is shorter
+    pub actual: Option<TraceEntry>,
+    /// Fields differing when both entries exist
+    pub fields: Vec<&'static str>,
+    pub expected_len: usize,
+    pub actual_len: usize,
+}
+
+impl Display for TraceDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "traces diverge at entry {}", self.index)?;
+        match &self.expected {
+            Some(entry) => writeln!(f, "  expected: {entry}")?,
+            None => writeln!(f, "  expected: end of trace")?,
+        }
+        match &self.actual {
+            Some(entry) => writeln!(f, "  actual:   {entry}")?,
+            None => writeln!(f, "  actual:   end of trace")?,
+        }
+        if !self.fields.is_empty() {
+            writeln!(f, "  differing fields: {}", self.fields.join(", "))?;
+        }
+        write!(
+            f,
+            "  {} entries expected, {} recorded",
+            self.expected_len, self.actual_len
+        )
+    }
+}
+
+/// Compares `actual` with the golden trace stored at `path`, writing it there instead
+/// when the file doesn't exist yet or [`UPDATE_GOLDEN_ENV`] is set.
+///
+/// # Panics
+///
+/// Panics with the [`TraceDiff`] if the traces differ, or if the golden file cannot be
+/// read or written.
+pub fn assert_matches_golden(path: impl AsRef<Path>, actual: &GoldenTrace) {
+    let path = path.as_ref();
+    if std::env::var_os(UPDATE_GOLDEN_ENV).is_some() || !path.exists() {
+        actual
+            .save(path)
+            .unwrap_or_else(|e| panic!("cannot write {}: {e}", path.display()));
+        return;
+    }
+    let golden =
+        GoldenTrace::load(path).unwrap_or_else(|e| panic!("cannot read {}: {e}", path.display()));
+    if let Some(diff) = golden.diff(actual) {
+        panic!("{} doesn't match:\n{diff}", path.display());
+    }
+}
+
+#[cfg(test)]
+mod golden_tests {
+    use super::*;
+    use wg_internal::config::{Client, Drone, Server};
+
+    fn line() -> Config {
+        Config {
+            drone: vec![
+                Drone {
+                    id: 2,
+                    connected_node_ids: vec![1, 3],
+                    pdr: 0.0,
+                },
+                Drone {
+                    id: 3,
+                    connected_node_ids: vec![2, 4],
+                    pdr: 0.0,
+                },
+            ],
+            client: vec![Client {
+                id: 1,
+                connected_drone_ids: vec![2],
+            }],
+            server: vec![Server {
+                id: 4,
+                connected_drone_ids: vec![3],
+            }],
+        }
+    }
+
+    // client 1 reaches server 4 through drone 2 or drone 3, routes being tied
+    fn diamond() -> Config {
+        let drone = |id| Drone {
+            id,
+            connected_node_ids: vec![1, 4],
+            pdr: 0.0,
+        };
+        Config {
+            drone: vec![drone(2), drone(3)],
+            client: vec![Client {
+                id: 1,
+                connected_drone_ids: vec![2, 3],
+            }],
+            server: vec![Server {
+                id: 4,
+                connected_drone_ids: vec![2, 3],
+            }],
+        }
+    }
+
+    #[test]
+    /// Tests that tied routes are picked the same way on every run
+    fn test_golden_trace_ties() {
+        let messages = vec![(1, 4, vec![7; 300]), (4, 1, vec![8; 10])];
+        let golden = GoldenTrace::record(&diamond(), &messages, 0.0);
+        for _ in 0..5 {
+            assert_eq!(GoldenTrace::record(&diamond(), &messages, 0.0), golden);
+        }
+    }
+
+    #[test]
+    /// Tests that a seeded simulation replays identically and that changes are pinpointed
+    fn test_golden_trace() {
+        let messages = vec![(1, 4, vec![7; 300]), (4, 1, vec![8; 10])];
+        let golden = GoldenTrace::record(&line(), &messages, 0.2);
+        assert_eq!(GoldenTrace::record(&line(), &messages, 0.2), golden);
+        assert!(golden.entries.iter().any(|e| e.packet.starts_with("Nack")));
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("line.json");
+        assert_matches_golden(&path, &golden);
+        assert_eq!(GoldenTrace::load(&path).unwrap(), golden);
+        assert_matches_golden(&path, &golden);
+
+        let mut changed = golden.clone();
+        changed.entries[3].hop_index += 1;
+        let _ = changed.entries.pop();
+        let diff = golden.diff(&changed).unwrap();
+        assert_eq!(diff.index, 3);
+        assert_eq!(diff.fields, vec!["hop_index"]);
+        assert_eq!(diff.actual_len, golden.entries.len() - 1);
+        assert!(diff.to_string().contains("differing fields: hop_index"));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::catalog::{catalog_digest, files_page};
+use crate::file_transfer::chunk_response;
+use crate::types::{
+    Command, Event, MediaFile, MediaReference, NodeCommand, ServerType, TextFile, WebRequest,
+    WebResponse,
+};
+use crate::{FragmentAssembler, Processor, RoutingHandler};
+use crossbeam_channel::{Receiver, Sender};
+use std::collections::{BTreeMap, HashMap};
+use std::str::FromStr;
+use uuid::Uuid;
+use wg_internal::network::NodeId;
+use wg_internal::packet::{NodeType, Packet};
+
+/// Web server answering every request at once with canned responses, so that
+/// frontends and clients can be developed and demoed without drones or real servers.
+///
+/// It serves one text file referencing one tiny media, both hosted by the node itself,
+/// announces itself as a [`ServerType::TextServer`] unless told otherwise, and keeps the
+/// files uploaded to it. Requests that aren't web requests are ignored.
+pub struct LoopbackNode {
+    controller_recv: Receiver<Box<dyn Command>>,
+    packet_recv: Receiver<Packet>,
+    assembler: FragmentAssembler,
+    router: RoutingHandler,
+    server_type: ServerType,
+    text_files: BTreeMap<Uuid, TextFile>,
+    media_files: HashMap<Uuid, MediaFile>,
+}
+
+impl LoopbackNode {
+    #[must_use]
+    pub fn new(
+        id: NodeId,
+        controller_send: Sender<Box<dyn Event>>,
+        controller_recv: Receiver<Box<dyn Command>>,
+        packet_recv: Receiver<Packet>,
+        packet_send: HashMap<NodeId, Sender<Packet>>,
+    ) -> Self {
+        let media = MediaFile::from_u8("loopback.txt".to_string(), b"loopback media");
+        let text = TextFile::new(
+            "Loopback".to_string(),
+            "Served by a loopback node".to_string(),
+            vec![MediaReference {
+                location: id,
+                id: media.id,
+            }],
+        );
+        Self {
+            controller_recv,
+            packet_recv,
+            assembler: FragmentAssembler::default(),
+            router: RoutingHandler::new(id, NodeType::Server, packet_send, controller_send),
+            server_type: ServerType::TextServer,
+            text_files: BTreeMap::from([(text.id, text)]),
+            media_files: HashMap::from([(media.id, media)]),
+        }
+    }
+
+    /// Sets the type announced in answer to `server_type?`.
+    #[must_use]
+    pub fn with_server_type(mut self, server_type: ServerType) -> Self {
+        self.server_type = server_type;
+        self
+    }
+
+    /// Returns the text files served, in ascending id order.
+    pub fn text_files(&self) -> impl Iterator<Item = &TextFile> {
+        self.text_files.values()
+    }
+
+    #[must_use]
+    pub fn media_file(&self, media_id: Uuid) -> Option<&MediaFile> {
+        self.media_files.get(&media_id)
+    }
+
+    /// Returns the canned answer to `request`.
+    fn respond(&mut self, request: WebRequest) -> WebResponse {
+        match request {
+            WebRequest::ServerTypeQuery => WebResponse::ServerType {
+                server_type: self.server_type.clone(),
+            },
+            WebRequest::TextFilesListQuery => WebResponse::TextFilesList {
+                files: self.text_files.keys().map(Uuid::to_string).collect(),
+            },
+            WebRequest::FileQuery { file_id } => match Uuid::from_str(&file_id) {
+                Ok(id) => match self.text_files.get(&id) {
+                    Some(file) => WebResponse::TextFile {
+                        file_data: serde_json::to_vec(file).unwrap_or_default(),
+                    },
+                    None => WebResponse::ErrorFileNotFound(id),
+                },
+                Err(_) => WebResponse::BadUuid(file_id),
+            },
+            WebRequest::MediaQuery { media_id } => match Uuid::from_str(&media_id) {
+                Ok(id) => match self.media_files.get(&id) {
+                    Some(media) => WebResponse::MediaFile {
+                        media_data: media.get_content().concat(),
+                    },
+                    None => WebResponse::ErrorFileNotFound(id),
+                },
+                Err(_) => WebResponse::BadUuid(media_id),
+            },
+            WebRequest::FileChunkRequest { file_id, offset } => {
+                let Ok(id) = Uuid::from_str(&file_id) else {
+                    return WebResponse::BadUuid(file_id);
+                };
+                let data = match (self.text_files.get(&id), self.media_files.get(&id)) {
+                    (Some(file), _) => serde_json::to_vec(file).unwrap_or_default(),
+                    (None, Some(media)) => media.get_content().concat(),
+                    (None, None) => return WebResponse::ErrorFileNotFound(id),
+                };
+                chunk_response(id, &data, offset).unwrap_or(WebResponse::ErrorFileNotFound(id))
+            }
+            WebRequest::FilesListQuery { page, page_size } => {
+                let (entries, total) = files_page(self.text_files.values(), page, page_size);
+                WebResponse::FilesListResponse { entries, total }
+            }
+            WebRequest::CatalogDigestQuery => WebResponse::CatalogDigest {
+                files: catalog_digest(self.text_files.values()),
+            },
+            WebRequest::UploadTextFile { file } => {
+                let file_id = file.id;
+                let _ = self.text_files.insert(file_id, file);
+                WebResponse::UploadAccepted { file_id }
+            }
+            WebRequest::UploadMediaFile { file } => {
+                let file_id = file.id;
+                let _ = self.media_files.insert(file_id, file);
+                WebResponse::UploadAccepted { file_id }
+            }
+        }
+    }
+}
+
+impl Processor for LoopbackNode {
+    fn controller_recv(&self) -> &Receiver<Box<dyn Command>> {
+        &self.controller_recv
+    }
+
+    fn packet_recv(&self) -> &Receiver<Packet> {
+        &self.packet_recv
+    }
+
+    fn assembler(&mut self) -> &mut FragmentAssembler {
+        &mut self.assembler
+    }
+
+    fn routing_handler(&mut self) -> &mut RoutingHandler {
+        &mut self.router
+    }
+
+    fn handle_msg(&mut self, msg: Vec<u8>, from: NodeId, _session_id: u64) {
+        let Ok(request) = serde_json::from_slice::<WebRequest>(&msg) else {
+            return;
+        };
+        let response = self.respond(request);
+        if let Ok(response) = serde_json::to_vec(&response) {
+            let _ = self.router.send_message(&response, Some(from), None);
+        }
+    }
+
+    fn handle_command(&mut self, cmd: Box<dyn Command>) -> bool {
+        let Ok(cmd) = cmd.into_any().downcast::<NodeCommand>() else {
+            return false;
+        };
+        match *cmd {
+            NodeCommand::Shutdown => return true,
+            NodeCommand::AddSender(id, sender) => self.router.add_neighbor(id, sender),
+            NodeCommand::AddControlSender(id, sender) => {
+                self.router.add_control_neighbor(id, sender);
+            }
+            NodeCommand::RemoveSender(id) => self.router.remove_neighbor(id),
+            cmd => {
+                let _ = self.router.handle_session_command(&cmd);
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod loopback_tests {
+    use super::*;
+    use crate::types::File;
+    use crossbeam_channel::unbounded;
+    use wg_internal::network::SourceRoutingHeader;
+    use wg_internal::packet::{FloodRequest, Fragment, PacketType};
+
+    /// Sends `request` from the client 2 in session `session_id` and returns the response of the loopback node 1.
+    fn ask(
+        node: &mut LoopbackNode,
+        client: &Receiver<Packet>,
+        session_id: u64,
+        request: &WebRequest,
+    ) -> WebResponse {
+        let msg = serde_json::to_vec(request).unwrap();
+        let mut fragments = FragmentAssembler::default();
+        for (i, chunk) in msg.chunks(128).enumerate() {
+            let mut data = [0; 128];
+            data[..chunk.len()].copy_from_slice(chunk);
+            let fragment = Fragment {
+                fragment_index: i as u64,
+                total_n_fragments: msg.len().div_ceil(128) as u64,
+                #[allow(clippy::cast_possible_truncation)]
+                length: chunk.len() as u8,
+                data,
+            };
+            let header = SourceRoutingHeader::new(vec![2, 1], 1);
+            node.handle_packet(Packet::new_fragment(header, session_id, fragment))
+                .unwrap();
+        }
+        for packet in client.try_iter() {
+            if let PacketType::MsgFragment(fragment) = packet.pack_type {
+                if let Some(response) = fragments.add_fragment(fragment, packet.session_id, 1) {
+                    return serde_json::from_slice(&response).unwrap();
+                }
+            }
+        }
+        panic!("no response");
+    }
+
+    #[test]
+    /// Tests that the canned file can be browsed end to end
+    fn test_loopback() {
+        let (controller_send, _controller_recv) = unbounded();
+        let (_cmd_send, cmd_recv) = unbounded();
+        let (_packet_send, packet_recv) = unbounded();
+        let (client_send, client_recv) = unbounded();
+        let mut node = LoopbackNode::new(
+            1,
+            controller_send,
+            cmd_recv,
+            packet_recv,
+            HashMap::from([(2, client_send)]),
+        );
+        // the flood of the client teaches the node the way back
+        let flood = FloodRequest {
+            flood_id: 1,
+            initiator_id: 2,
+            path_trace: vec![(2, NodeType::Client)],
+        };
+        node.handle_packet(Packet::new_flood_request(
+            SourceRoutingHeader::empty_route(),
+            1,
+            flood,
+        ))
+        .unwrap();
+
+        assert!(matches!(
+            ask(&mut node, &client_recv, 11, &WebRequest::ServerTypeQuery),
+            WebResponse::ServerType {
+                server_type: ServerType::TextServer
+            }
+        ));
+        let WebResponse::TextFilesList { files } =
+            ask(&mut node, &client_recv, 12, &WebRequest::TextFilesListQuery)
+        else {
+            panic!("expected a files list");
+        };
+        assert_eq!(files.len(), 1);
+        let query = WebRequest::FilesListQuery {
+            page: 0,
+            page_size: 10,
+        };
+        let WebResponse::FilesListResponse { entries, total } =
+            ask(&mut node, &client_recv, 16, &query)
+        else {
+            panic!("expected a files page");
+        };
+        assert_eq!(total, 1);
+        assert_eq!(entries[0].file_id.to_string(), files[0]);
+
+        let query = WebRequest::FileQuery {
+            file_id: files[0].clone(),
+        };
+        let WebResponse::TextFile { file_data } = ask(&mut node, &client_recv, 13, &query) else {
+            panic!("expected a text file");
+        };
+        let mut file = File::with_placeholders(serde_json::from_slice(&file_data).unwrap());
+        let media_ref = file.placeholders()[0].clone();
+        assert_eq!(media_ref.get_location(), 1);
+
+        let query = WebRequest::MediaQuery {
+            media_id: media_ref.id.to_string(),
+        };
+        let WebResponse::MediaFile { media_data } = ask(&mut node, &client_recv, 14, &query) else {
+            panic!("expected a media file");
+        };
+        assert_eq!(media_data, b"loopback media");
+        let media = node.media_file(media_ref.id).unwrap().clone();
+        assert!(file.add_media(media));
+        assert!(file.is_complete());
+
+        let query = WebRequest::FileQuery {
+            file_id: "nope".to_string(),
+        };
+        assert!(matches!(
+            ask(&mut node, &client_recv, 15, &query),
+            WebResponse::BadUuid(_)
+        ));
+    }
+}
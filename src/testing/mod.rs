@@ -0,0 +1,13 @@
+//! Helpers for the integration tests of the nodes built on this crate.
+//!
+//! Available to downstream crates with the `testing` feature.
+
+mod convergence;
+mod golden;
+mod loopback;
+
+pub use crate::network::{Topology, TopologyDiff};
+pub use convergence::{ConvergenceError, assert_converges, wait_for_convergence};
+pub use golden::{GoldenTrace, TraceDiff, TraceEntry, UPDATE_GOLDEN_ENV, assert_matches_golden};
+pub use crate::simulation::{MAX_ROUNDS, MockNetwork};
+pub use loopback::LoopbackNode;
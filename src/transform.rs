@@ -0,0 +1,238 @@
+use rand::rngs::StdRng;
+use rand::seq::IndexedRandom;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use wg_internal::network::NodeId;
+use wg_internal::packet::Packet;
+
+/// Hook rewriting message payloads: outgoing ones before they are fragmented by the
+/// `RoutingHandler`, incoming ones once reassembled and before `Processor::handle_msg`.
+pub trait PayloadTransform: Send + Debug {
+    fn outgoing(&mut self, _payload: &mut Vec<u8>, _destination: NodeId) {}
+    fn incoming(&mut self, _payload: &mut Vec<u8>, _source: NodeId) {}
+}
+
+/// Transform registered on a `RoutingHandler`, shared so that tests can keep a
+/// handle on it (e.g. to read the statistics of an [`ErrorInjector`]).
+pub type SharedTransform = Arc<Mutex<dyn PayloadTransform>>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+    Sent,
+    Received,
+}
+
+/// Hook observing the packets of a `RoutingHandler`: every packet it sends to a
+/// neighbor and every packet received through `Processor::handle_packet`, before it is
+/// handled.
+pub trait PacketTap: Send + Debug {
+    fn on_packet(&mut self, direction: PacketDirection, packet: &Packet);
+}
+
+/// Tap set with `RoutingHandler::set_packet_tap`, shared so that tests can keep a
+/// handle on it.
+pub type SharedTap = Arc<Mutex<dyn PacketTap>>;
+
+/// Hook observing, rewriting or dropping whole packets, for cross-cutting features
+/// (logging, filtering, simulated latency, encryption...) shared by several nodes.
+/// Middlewares run in the order of `Processor::middlewares`: on the packets received,
+/// before `Processor::handle_packet`, and on the packets the `RoutingHandler` sends to
+/// a neighbor. Returning `None` drops the packet.
+pub trait PacketMiddleware: Send + Debug {
+    fn incoming(&mut self, packet: Packet) -> Option<Packet> {
+        Some(packet)
+    }
+    fn outgoing(&mut self, packet: Packet) -> Option<Packet> {
+        Some(packet)
+    }
+}
+
+/// Middleware in a chain, shared so that tests can keep a handle on it.
+pub type SharedMiddleware = Arc<Mutex<dyn PacketMiddleware>>;
+
+/// Passes `packet` through `chain` in order, `None` if a middleware dropped it.
+pub(crate) fn run_middlewares(
+    chain: &[SharedMiddleware],
+    packet: Packet,
+    direction: PacketDirection,
+) -> Option<Packet> {
+    chain.iter().try_fold(packet, |packet, middleware| {
+        let Ok(mut middleware) = middleware.lock() else {
+            return Some(packet);
+        };
+        match direction {
+            PacketDirection::Sent => middleware.outgoing(packet),
+            PacketDirection::Received => middleware.incoming(packet),
+        }
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    /// Flips the bits of a random byte
+    Corrupt,
+    /// Cuts the payload at a random position
+    Truncate,
+    /// Repeats a random slice of the payload right after itself
+    Duplicate,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Outgoing,
+    Incoming,
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct InjectionStats {
+    pub payloads_seen: u64,
+    pub corrupted: u64,
+    pub truncated: u64,
+    pub duplicated: u64,
+}
+
+/// Built-in transform damaging payloads at a seeded rate, to check that checksums,
+/// retransmissions and validation catch in-flight corruption.
+/// The same seed always damages the same payloads in the same way.
+#[derive(Debug)]
+pub struct ErrorInjector {
+    rng: StdRng,
+    rate: f64,
+    faults: Vec<Fault>,
+    direction: Direction,
+    stats: InjectionStats,
+}
+
+impl ErrorInjector {
+    /// Creates an injector damaging each incoming payload with probability `rate`,
+    /// with a fault picked among all the kinds.
+    #[must_use]
+    pub fn new(seed: u64, rate: f64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            rate: rate.clamp(0.0, 1.0),
+            faults: vec![Fault::Corrupt, Fault::Truncate, Fault::Duplicate],
+            direction: Direction::Incoming,
+            stats: InjectionStats::default(),
+        }
+    }
+
+    /// Restricts the faults injected, no fault is injected if `faults` is empty.
+    #[must_use]
+    pub fn with_faults(mut self, faults: &[Fault]) -> Self {
+        self.faults = faults.to_vec();
+        self
+    }
+
+    #[must_use]
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = direction;
+        self
+    }
+
+    /// Wraps the injector to be registered with `RoutingHandler::add_transform`.
+    #[must_use]
+    pub fn shared(self) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(self))
+    }
+
+    #[must_use]
+    pub fn stats(&self) -> InjectionStats {
+        self.stats
+    }
+
+    fn inject(&mut self, payload: &mut Vec<u8>) {
+        self.stats.payloads_seen += 1;
+        if payload.is_empty() || !self.rng.random_bool(self.rate) {
+            return;
+        }
+        let Some(fault) = self.faults.choose(&mut self.rng).copied() else {
+            return;
+        };
+        match fault {
+            Fault::Corrupt => {
+                let pos = self.rng.random_range(0..payload.len());
+                payload[pos] ^= self.rng.random_range(1..=u8::MAX);
+                self.stats.corrupted += 1;
+            }
+            Fault::Truncate => {
+                payload.truncate(self.rng.random_range(0..payload.len()));
+                self.stats.truncated += 1;
+            }
+            Fault::Duplicate => {
+                let start = self.rng.random_range(0..payload.len());
+                let end = self.rng.random_range(start + 1..=payload.len());
+                let slice = payload[start..end].to_vec();
+                let _ = payload.splice(end..end, slice);
+                self.stats.duplicated += 1;
+            }
+        }
+    }
+}
+
+impl PayloadTransform for ErrorInjector {
+    fn outgoing(&mut self, payload: &mut Vec<u8>, _destination: NodeId) {
+        if matches!(self.direction, Direction::Outgoing | Direction::Both) {
+            self.inject(payload);
+        }
+    }
+
+    fn incoming(&mut self, payload: &mut Vec<u8>, _source: NodeId) {
+        if matches!(self.direction, Direction::Incoming | Direction::Both) {
+            self.inject(payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod transform_tests {
+    use super::*;
+
+    fn damage(seed: u64) -> (Vec<Vec<u8>>, InjectionStats) {
+        let mut injector = ErrorInjector::new(seed, 0.5);
+        let payloads = (0..64)
+            .map(|_| {
+                let mut payload = b"a payload travelling the network".to_vec();
+                injector.incoming(&mut payload, 1);
+                injector.outgoing(&mut payload, 1);
+                payload
+            })
+            .collect();
+        (payloads, injector.stats())
+    }
+
+    #[test]
+    /// Tests that the same seed damages the same payloads in the same way
+    fn test_seeded() {
+        let (payloads, stats) = damage(7);
+        assert_eq!(damage(7), (payloads.clone(), stats));
+        assert_eq!(stats.payloads_seen, 64);
+
+        let damaged = payloads
+            .iter()
+            .filter(|p| p.as_slice() != b"a payload travelling the network")
+            .count() as u64;
+        assert_eq!(
+            damaged,
+            stats.corrupted + stats.truncated + stats.duplicated
+        );
+        assert!(damaged > 0 && damaged < 64);
+    }
+
+    #[test]
+    /// Tests that only the configured faults and directions are injected
+    fn test_restricted() {
+        let mut injector = ErrorInjector::new(1, 1.0)
+            .with_faults(&[Fault::Duplicate])
+            .with_direction(Direction::Outgoing);
+        let mut payload = vec![1, 2, 3];
+        injector.incoming(&mut payload, 2);
+        assert_eq!(payload, vec![1, 2, 3]);
+        injector.outgoing(&mut payload, 2);
+        assert!(payload.len() > 3 && payload.starts_with(&[1]));
+        assert_eq!(injector.stats().duplicated, 1);
+    }
+}
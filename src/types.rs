@@ -1,17 +1,34 @@
+use crate::catalog::{FileDigest, FileMetadata, sha256_hex};
+use crate::codec::{Codec, CodecFlags, WireCodec};
+use crate::config::MAX_FRAGMENT_SIZE;
+use crate::congestion::CongestionState;
+use crate::metrics::Metrics;
+use crate::network::{Network, TopologyDiff};
+use crate::routing_handler::BufferedSession;
+use crate::selfcheck::Diagnostic;
 use anyhow::anyhow;
 use crossbeam_channel::Sender;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use std::any::Any;
 use std::fmt::Display;
-use std::{collections::HashMap, str::FromStr};
+use std::time::Duration;
+use std::{
+    collections::{BTreeSet, HashMap},
+    str::FromStr,
+};
 use uuid::Uuid;
-use wg_internal::{network::NodeId, packet::Packet};
+use wg_internal::{
+    network::NodeId,
+    packet::{NackType, Packet, PacketType},
+};
 pub type Bytes = Vec<u8>;
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct SerializedRequest {
     pub to: Option<NodeId>,
     pub data: Vec<u8>,
+    /// Session the message is sent in once the destination is found, a new one if `None`
+    pub session_id: Option<u64>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
@@ -95,15 +112,30 @@ pub struct MediaFile {
     pub id: Uuid,
     pub title: String,
     pub content: Vec<Bytes>,
+    /// Hex encoded SHA-256 of the original bytes, empty if unknown
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub hash: String,
 }
 
+/// Magic numbers of the media formats recognized by [`MediaFile::extension`].
+const MEDIA_SIGNATURES: [(&[u8], &str); 6] = [
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (b"\xff\xd8\xff", "jpg"),
+    (b"GIF8", "gif"),
+    (b"BM", "bmp"),
+    (b"%PDF", "pdf"),
+    (b"ID3", "mp3"),
+];
+
 impl MediaFile {
     #[must_use]
     pub fn new(title: String, content: Vec<Bytes>) -> Self {
+        let hash = sha256_hex(&content.concat());
         Self {
             id: Uuid::new_v4(),
             title,
             content,
+            hash,
         }
     }
 
@@ -115,6 +147,56 @@ impl MediaFile {
         Self::new(filename, content)
     }
 
+    /// Chunks `data` and records its hash, see [`Self::to_bytes`].
+    #[must_use]
+    pub fn from_bytes(title: String, data: &[u8]) -> Self {
+        Self::from_u8(title, data)
+    }
+
+    /// Reassembles the original bytes from the chunks.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`MediaIntegrityError`] if the bytes don't match the recorded hash.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, MediaIntegrityError> {
+        let data = self.content.concat();
+        if self.hash.is_empty() {
+            return Ok(data);
+        }
+        let actual = sha256_hex(&data);
+        if actual != self.hash {
+            return Err(MediaIntegrityError {
+                media_id: self.id,
+                expected: self.hash.clone(),
+                actual,
+            });
+        }
+        Ok(data)
+    }
+
+    /// Returns whether the chunks match the recorded hash, true if none was recorded.
+    #[must_use]
+    pub fn verify(&self) -> bool {
+        self.to_bytes().is_ok()
+    }
+
+    /// Returns the extension of the title or, failing that, the one of the format
+    /// recognized from the first bytes.
+    #[must_use]
+    pub fn extension(&self) -> Option<&str> {
+        let from_title = std::path::Path::new(&self.title)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .filter(|ext| ext.chars().all(|c| c.is_ascii_alphanumeric()));
+        from_title.or_else(|| {
+            let head = self.content.first()?;
+            MEDIA_SIGNATURES
+                .iter()
+                .find(|(magic, _)| head.starts_with(magic))
+                .map(|(_, ext)| *ext)
+        })
+    }
+
     #[must_use]
     pub fn get_title(&self) -> &str {
         &self.title
@@ -131,6 +213,26 @@ impl MediaFile {
     }
 }
 
+/// A media file whose chunks don't hash to the value recorded with it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaIntegrityError {
+    pub media_id: Uuid,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Display for MediaIntegrityError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Media {} is corrupted: expected hash {}, got {}",
+            self.media_id, self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for MediaIntegrityError {}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq, Eq)]
 pub struct File {
     pub id: Uuid,
@@ -147,6 +249,60 @@ impl File {
             media_files,
         }
     }
+
+    /// Creates a file whose media are all placeholders, so that the text can be shown
+    /// while the media are fetched and added with [`Self::add_media`].
+    #[must_use]
+    pub fn with_placeholders(text_file: TextFile) -> Self {
+        Self::new(text_file, Vec::new())
+    }
+
+    /// Returns the state of the media `media_id`, `None` if the text doesn't reference it.
+    #[must_use]
+    pub fn media_state(&self, media_id: Uuid) -> Option<MediaState> {
+        if !self.text_file.media_refs.iter().any(|r| r.id == media_id) {
+            return None;
+        }
+        if self.media_files.iter().any(|m| m.id == media_id) {
+            Some(MediaState::Ready)
+        } else {
+            Some(MediaState::Placeholder)
+        }
+    }
+
+    /// Returns the references of the media not received yet, in text order.
+    #[must_use]
+    pub fn placeholders(&self) -> Vec<MediaReference> {
+        self.text_file
+            .media_refs
+            .iter()
+            .filter(|r| self.media_state(r.id) == Some(MediaState::Placeholder))
+            .cloned()
+            .collect()
+    }
+
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.placeholders().is_empty()
+    }
+
+    /// Fills the placeholder of `media`, returns whether it was one; media not
+    /// referenced by the text or already received are ignored.
+    pub fn add_media(&mut self, media: MediaFile) -> bool {
+        if self.media_state(media.id) != Some(MediaState::Placeholder) {
+            return false;
+        }
+        self.media_files.push(media);
+        true
+    }
+}
+
+/// State of a media referenced by the text of a [`File`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MediaState {
+    /// The media is still being fetched, the UI shows a placeholder in its place
+    Placeholder,
+    Ready,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -163,13 +319,34 @@ pub enum WebRequest {
 
     #[serde(rename = "media?")]
     MediaQuery { media_id: String },
+
+    // Digest of every text file, to only fetch the ones changed since cached
+    #[serde(rename = "catalog_digest?")]
+    CatalogDigestQuery,
+
+    // Uploads published by content-producing clients, answered with `upload_accepted!`
+    #[serde(rename = "upload_text")]
+    UploadTextFile { file: TextFile },
+
+    #[serde(rename = "upload_media")]
+    UploadMediaFile { file: MediaFile },
+
+    // Chunk of a text or media file starting at byte `offset`, see `FileTransfer`
+    #[serde(rename = "file_chunk?")]
+    FileChunkRequest { file_id: String, offset: u64 },
+
+    // Page `page` (from 0) of the listing with metadata, see `catalog::files_page`
+    #[serde(rename = "files_page?")]
+    FilesListQuery { page: u32, page_size: u32 },
 }
 
 impl WebRequest {
     #[must_use]
     pub fn get_file_id(&self) -> Option<String> {
         match self {
-            Self::FileQuery { file_id } => Some(file_id.clone()),
+            Self::FileQuery { file_id } | Self::FileChunkRequest { file_id, .. } => {
+                Some(file_id.clone())
+            }
             Self::MediaQuery { media_id } => Some(media_id.clone()),
             _ => None,
         }
@@ -191,11 +368,121 @@ pub enum WebResponse {
     #[serde(rename = "media!")]
     MediaFile { media_data: Vec<u8> },
 
-    #[serde(rename = "error_requested_not_found!")]
+    #[serde(rename = "error_requested_not_found!", with = "file_id_field")]
     ErrorFileNotFound(Uuid),
 
-    #[serde(rename = "error_uuid_parsing!")]
+    #[serde(rename = "error_uuid_parsing!", with = "uuid_field")]
     BadUuid(String),
+
+    #[serde(rename = "upload_accepted!")]
+    UploadAccepted { file_id: Uuid },
+
+    #[serde(rename = "catalog_digest!")]
+    CatalogDigest { files: Vec<FileDigest> },
+
+    #[serde(rename = "file_chunk!")]
+    FileChunkResponse {
+        file_id: Uuid,
+        chunk_index: u64,
+        total_chunks: u64,
+        data: Vec<u8>,
+    },
+
+    // `total` counts the files of every page
+    #[serde(rename = "files_page!")]
+    FilesListResponse {
+        entries: Vec<FileMetadata>,
+        total: u64,
+    },
+}
+
+// Internally tagged enums can't carry bare strings, the payload of the error
+// responses travels as a named field instead.
+mod file_id_field {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use uuid::Uuid;
+
+    #[derive(Serialize, Deserialize)]
+    struct Field<T> {
+        file_id: T,
+    }
+
+    pub fn serialize<S: Serializer>(file_id: &Uuid, s: S) -> Result<S::Ok, S::Error> {
+        Field { file_id }.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Uuid, D::Error> {
+        Field::<Uuid>::deserialize(d).map(|f| f.file_id)
+    }
+}
+
+mod uuid_field {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    #[derive(Serialize, Deserialize)]
+    struct Field<T> {
+        uuid: T,
+    }
+
+    pub fn serialize<S: Serializer>(uuid: &str, s: S) -> Result<S::Ok, S::Error> {
+        Field { uuid }.serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+        Field::<String>::deserialize(d).map(|f| f.uuid)
+    }
+}
+
+/// Codec the internally tagged envelopes travel with: `codec` if it can decode them,
+/// JSON otherwise.
+fn envelope_codec(codec: &impl WireCodec) -> Codec {
+    if codec.self_describing() {
+        codec.codec()
+    } else {
+        Codec::Json
+    }
+}
+
+/// Serializes a request (`WebRequest`, `ChatRequest`) with `codec`, or as JSON if
+/// `codec` is not self-describing.
+/// # Errors
+/// Returns an error if `request` cannot be serialized.
+pub fn encode_request<R: Serialize>(
+    codec: &impl WireCodec,
+    request: &R,
+) -> anyhow::Result<Vec<u8>> {
+    envelope_codec(codec).encode(request)
+}
+
+/// Deserializes a request encoded by [`encode_request`] with the same codec.
+/// # Errors
+/// Returns an error if `bytes` are not a valid encoding of `R`.
+pub fn decode_request<R: DeserializeOwned>(
+    codec: &impl WireCodec,
+    bytes: &[u8],
+) -> anyhow::Result<R> {
+    envelope_codec(codec).decode(bytes)
+}
+
+/// Serializes a response (`WebResponse`, `ChatResponse`) with `codec`, or as JSON if
+/// `codec` is not self-describing.
+/// # Errors
+/// Returns an error if `response` cannot be serialized.
+pub fn encode_response<R: Serialize>(
+    codec: &impl WireCodec,
+    response: &R,
+) -> anyhow::Result<Vec<u8>> {
+    envelope_codec(codec).encode(response)
+}
+
+/// Deserializes a response encoded by [`encode_response`] with the same codec.
+/// # Errors
+/// Returns an error if `bytes` are not a valid encoding of `R`.
+pub fn decode_response<R: DeserializeOwned>(
+    codec: &impl WireCodec,
+    bytes: &[u8],
+) -> anyhow::Result<R> {
+    envelope_codec(codec).decode(bytes)
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug)]
@@ -205,16 +492,52 @@ pub enum ChatRequest {
     ServerTypeQuery,
 
     #[serde(rename = "registration_to_chat")]
-    RegistrationToChat { client_id: NodeId },
+    RegistrationToChat {
+        client_id: NodeId,
+        // codecs the client is able to decode, absent for clients unaware of negotiation
+        #[serde(default)]
+        codecs: CodecFlags,
+    },
 
     #[serde(rename = "client_list?")]
     ClientListQuery,
 
     #[serde(rename = "message_for?")]
     MessageFor { client_id: NodeId, message: String },
+
+    // Sent periodically by idle registered clients so the server keeps them listed
+    #[serde(rename = "keep_alive?")]
+    KeepAlive { client_id: NodeId },
+
+    // Group chats, see `RoomRegistry`; the creator of a room joins it
+    #[serde(rename = "create_room")]
+    CreateRoom { name: String },
+
+    #[serde(rename = "join_room")]
+    JoinRoom { room_id: RoomId },
+
+    #[serde(rename = "leave_room")]
+    LeaveRoom { room_id: RoomId },
+
+    #[serde(rename = "room_list?")]
+    RoomListQuery,
+
+    #[serde(rename = "message_to_room?")]
+    MessageToRoom { room_id: RoomId, message: String },
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Identifier of a chat room, assigned by the chat server.
+pub type RoomId = u64;
+
+/// A chat room, as listed in a `ChatResponse::RoomList`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RoomInfo {
+    pub room_id: RoomId,
+    pub name: String,
+    pub members: Vec<NodeId>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde(tag = "response_type")]
 pub enum ChatResponse {
     #[serde(rename = "server_type!")]
@@ -232,6 +555,38 @@ pub enum ChatResponse {
     // Custom response for successful registration
     #[serde(rename = "registration_success")]
     RegistrationSuccess,
+
+    // Successful registration of a client which advertised its codecs
+    #[serde(rename = "registration_accepted")]
+    RegistrationAccepted { codec: Codec },
+
+    #[serde(rename = "keep_alive!")]
+    KeepAliveAck,
+
+    #[serde(rename = "room_created!")]
+    RoomCreated { room_id: RoomId, name: String },
+
+    // Sent to the client joining and to the members already in the room
+    #[serde(rename = "room_joined!")]
+    RoomJoined { room_id: RoomId, client_id: NodeId },
+
+    // Sent to the client leaving and to the members still in the room
+    #[serde(rename = "room_left!")]
+    RoomLeft { room_id: RoomId, client_id: NodeId },
+
+    #[serde(rename = "room_list!")]
+    RoomList { rooms: Vec<RoomInfo> },
+
+    #[serde(rename = "message_from_room!")]
+    MessageFromRoom {
+        room_id: RoomId,
+        client_id: NodeId,
+        message: String,
+    },
+
+    // The room doesn't exist, or the client is not a member of it
+    #[serde(rename = "error_wrong_room_id!")]
+    ErrorWrongRoomId { room_id: RoomId },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -248,6 +603,116 @@ impl Message {
     }
 }
 
+/// Messages of an end-to-end encrypted channel, see `SecureChannel` (feature `crypto`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "secure_type")]
+pub enum SecureMessage {
+    // X25519 public key of the sender and a fresh ephemeral one for this exchange.
+    // An exchange started by the sender has no `answers`, and is answered with the keys
+    // of the receiver; the answer carries the ephemeral key it answers and is never
+    // answered in turn
+    #[serde(rename = "key_exchange")]
+    KeyExchange {
+        public_key: [u8; 32],
+        ephemeral: [u8; 32],
+        #[serde(default)]
+        answers: Option<[u8; 32]>,
+    },
+
+    // ChaCha20-Poly1305 ciphertext, `counter` being the nonce of the sender
+    #[serde(rename = "sealed")]
+    Sealed { counter: u64, ciphertext: Vec<u8> },
+}
+
+/// Handshake exchanged on first contact to agree on a protocol version, see
+/// `VersionNegotiator`. Sent as a reserved control fragment, so it must fit in one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "version_type")]
+pub enum ProtocolVersion {
+    // versions the sender speaks, answered with `Agreed` or `Unsupported`
+    #[serde(rename = "version?")]
+    Hello { supported: Vec<u32> },
+
+    // highest version both sides speak
+    #[serde(rename = "version!")]
+    Agreed { version: u32 },
+
+    // no version in common, with the versions the sender speaks
+    #[serde(rename = "error_unsupported_version!")]
+    Unsupported { supported: Vec<u32> },
+}
+
+/// Acknowledgement of several fragments of a session at once, sent instead of one Ack
+/// per fragment by the routers aggregating Acks (see `RoutingHandler::set_ack_aggregation`).
+/// Sent as a reserved control fragment; every router understands it.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "ack_type")]
+pub enum AggregateAck {
+    // inclusive ranges of the fragment indexes received, "acked up to N" being `[(0, N)]`
+    #[serde(rename = "ranges")]
+    Ranges {
+        session_id: u64,
+        ranges: Vec<(u64, u64)>,
+    },
+}
+
+impl AggregateAck {
+    /// Builds the aggregate Acks of the fragments `indexes` of `session_id`, split so that
+    /// each fits in one fragment.
+    #[must_use]
+    pub fn for_indexes(session_id: u64, indexes: &BTreeSet<u64>) -> Vec<Self> {
+        let mut ranges: Vec<(u64, u64)> = Vec::new();
+        for &index in indexes {
+            match ranges.last_mut() {
+                Some((_, end)) if end.checked_add(1) == Some(index) => *end = index,
+                _ => ranges.push((index, index)),
+            }
+        }
+        let mut acks = Vec::new();
+        let mut current = Vec::new();
+        for range in ranges {
+            current.push(range);
+            let fits = serde_json::to_vec(&Self::Ranges {
+                session_id,
+                ranges: current.clone(),
+            })
+            .is_ok_and(|payload| payload.len() <= MAX_FRAGMENT_SIZE);
+            if !fits && current.len() > 1 {
+                let _ = current.pop();
+                acks.push(Self::Ranges {
+                    session_id,
+                    ranges: std::mem::replace(&mut current, vec![range]),
+                });
+            }
+        }
+        if !current.is_empty() {
+            acks.push(Self::Ranges {
+                session_id,
+                ranges: current,
+            });
+        }
+        acks
+    }
+
+    /// Returns whether `fragment_index` is acknowledged.
+    #[must_use]
+    pub fn contains(&self, fragment_index: u64) -> bool {
+        match self {
+            Self::Ranges { ranges, .. } => ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&fragment_index)),
+        }
+    }
+}
+
+/// Liveness probe of the routers sending heartbeats (see `RoutingHandler::set_heartbeats`),
+/// answered by the Ack of its fragment. Sent as a keep-alive, reserved control fragment
+/// or not; every `Processor` swallows it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Heartbeat {
+    pub heartbeat: u64,
+}
+
 pub trait Command: Send {
     fn as_any(&self) -> &dyn Any;
     fn into_any(self: Box<Self>) -> Box<dyn Any>;
@@ -369,6 +834,12 @@ pub enum WebEvent {
         notification_from: NodeId,
         file: MediaFile,
     },
+    // a media of a file delivered with placeholders was received
+    MediaArrived {
+        notification_from: NodeId,
+        file_id: Uuid,
+        media_id: Uuid,
+    },
     FileNotFound {
         notification_from: NodeId,
         uuid: Uuid,
@@ -422,25 +893,373 @@ pub enum NodeEvent {
     PacketSent(Packet),
     FloodStarted(u64, NodeId),
     NodeRemoved(NodeId),
+    // the bounded channel to a neighbor is full, packets wait in a local backlog
+    Backpressure(NodeId),
     MessageReceived {
         notification_from: NodeId,
         from: NodeId,
+        session_id: u64,
     }, // from, to
+    // fragments received so far of a message still being assembled
+    ReceiveProgress {
+        notification_from: NodeId,
+        from: NodeId,
+        session_id: u64,
+        received: u64,
+        total: u64,
+    },
     MessageSent {
         notification_from: NodeId,
         to: NodeId,
+        session_id: u64,
     }, // from, to
     ServerTypeQueried {
         notification_from: NodeId,
         from: NodeId,
     }, // server_id, requester_id
+    // servers known to a ServiceDiscovery after a discovery round, with their type
+    ServersDiscovered {
+        notification_from: NodeId,
+        servers: HashMap<NodeId, ServerType>,
+    },
+    // servers that were reachable in the network view and no longer are, e.g. after a
+    // drone crashed
+    PartitionDetected {
+        notification_from: NodeId,
+        unreachable: Vec<NodeId>,
+    },
+    // what a flood or a failure changed in the network view: the `missing_*` fields
+    // list what it lost, the `extra_*` ones what it learned
+    TopologyChanged(TopologyDiff),
+    // watched peer that missed too many heartbeats in a row, a flood was started
+    PeerUnresponsive(NodeId),
+    // packet that couldn't be routed and must be delivered by the controller
+    ControllerShortcut(Packet),
+    QuotaExceeded {
+        notification_from: NodeId,
+        destination: NodeId,
+        quota: u64,
+        used: u64,
+    },
+    SelfCheckFailed {
+        notification_from: NodeId,
+        diagnostics: Vec<Diagnostic>,
+    },
+    BufferedSessions {
+        notification_from: NodeId,
+        sessions: Vec<BufferedSession>,
+    },
+    SessionRetried {
+        notification_from: NodeId,
+        session_id: u64,
+        fragments: usize,
+    },
+    SessionDropped {
+        notification_from: NodeId,
+        session_id: u64,
+    },
+    // session given up with RoutingHandler::cancel_session
+    SessionCancelled {
+        notification_from: NodeId,
+        session_id: u64,
+    },
+    // reply to ForceRetry/DropSession for a session not in the buffer
+    SessionNotFound {
+        notification_from: NodeId,
+        session_id: u64,
+    },
+    // periodic report enabled by RoutingHandler::set_congestion_reports
+    CongestionReport {
+        notification_from: NodeId,
+        states: Vec<CongestionState>,
+    },
+    AckReceived {
+        notification_from: NodeId,
+        from: NodeId,
+        session_id: u64,
+        fragment_index: u64,
+    },
+    NackReceived {
+        notification_from: NodeId,
+        from: NodeId,
+        session_id: u64,
+        nack_type: NackType,
+    },
+    // every fragment of a message sent by the node was acknowledged
+    MessageFullyAcked {
+        notification_from: NodeId,
+        session_id: u64,
+        destination: NodeId,
+    },
+    // a message sent by the node was given up
+    SendFailed {
+        notification_from: NodeId,
+        session_id: u64,
+        reason: String,
+    },
+    // session carrying a broadcast to each destination reached, destinations waiting
+    // for the flood to find them and destinations the message couldn't be sent to
+    BroadcastStarted {
+        notification_from: NodeId,
+        broadcast_id: u64,
+        sessions: Vec<(NodeId, u64)>,
+        pending: Vec<NodeId>,
+        failed: Vec<NodeId>,
+    },
+    // every session of a broadcast was acknowledged or given up
+    BroadcastCompleted {
+        notification_from: NodeId,
+        broadcast_id: u64,
+        delivered: Vec<NodeId>,
+        failed: Vec<NodeId>,
+    },
+    // packet of a peer breaking the protocol, ignored
+    ProtocolViolation {
+        notification_from: NodeId,
+        peer: NodeId,
+        session_id: u64,
+        violation: ProtocolViolation,
+    },
+    // end of a graceful shutdown, with the sessions still undelivered
+    ShutdownComplete {
+        notification_from: NodeId,
+        undelivered_sessions: Vec<u64>,
+    },
+    // last event sent by a node whose run loop exited
+    Terminated {
+        notification_from: NodeId,
+        reason: TerminationReason,
+        stats: NodeStats,
+    },
+}
+
+/// Misbehavior of a peer, reported by a `NodeEvent::ProtocolViolation`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProtocolViolation {
+    /// Ack of a fragment never sent to the peer, or no longer awaiting an Ack
+    UnsolicitedAck { fragment_index: u64 },
+}
+
+/// Identifies a message across the events emitted along its lifecycle, by every node
+/// it went through: the node that sent it and the session it was sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CorrelationId {
+    pub origin: NodeId,
+    pub session_id: u64,
+}
+
+impl Display for CorrelationId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.origin, self.session_id)
+    }
+}
+
+/// Importance of a [`NodeEvent`], from the most verbose to the most severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Severity {
+    #[default]
+    Trace,
+    Info,
+    Warn,
+    Error,
+}
+
+impl NodeEvent {
+    #[must_use]
+    pub fn severity(&self) -> Severity {
+        match self {
+            Self::PacketSent(_)
+            | Self::FloodStarted(..)
+            | Self::CongestionReport { .. }
+            | Self::ReceiveProgress { .. }
+            | Self::AckReceived { .. } => Severity::Trace,
+            Self::MessageReceived { .. }
+            | Self::MessageSent { .. }
+            | Self::NackReceived { .. }
+            | Self::MessageFullyAcked { .. }
+            | Self::BroadcastStarted { .. }
+            | Self::BroadcastCompleted { .. }
+            | Self::ServerTypeQueried { .. }
+            | Self::ServersDiscovered { .. }
+            | Self::TopologyChanged(_)
+            | Self::BufferedSessions { .. }
+            | Self::SessionRetried { .. }
+            | Self::SessionDropped { .. }
+            | Self::SessionCancelled { .. }
+            | Self::SessionNotFound { .. } => Severity::Info,
+            Self::ShutdownComplete {
+                undelivered_sessions,
+                ..
+            } => {
+                if undelivered_sessions.is_empty() {
+                    Severity::Info
+                } else {
+                    Severity::Warn
+                }
+            }
+            Self::NodeRemoved(_)
+            | Self::Backpressure(_)
+            | Self::QuotaExceeded { .. }
+            | Self::SelfCheckFailed { .. }
+            | Self::ProtocolViolation { .. }
+            | Self::PartitionDetected { .. }
+            | Self::PeerUnresponsive(_)
+            | Self::SendFailed { .. } => Severity::Warn,
+            Self::Terminated { reason, .. } => match reason {
+                TerminationReason::Shutdown => Severity::Info,
+                TerminationReason::Error(_) | TerminationReason::Panic(_) => Severity::Error,
+            },
+            Self::ControllerShortcut(_) => Severity::Error,
+        }
+    }
+
+    /// Returns the message the event belongs to, if any. Acks, Nacks and flood responses
+    /// travel back to the origin, so their origin is the last hop of their route.
+    #[must_use]
+    pub fn correlation_id(&self) -> Option<CorrelationId> {
+        let (origin, session_id) = match self {
+            Self::PacketSent(packet) | Self::ControllerShortcut(packet) => {
+                let origin = match &packet.pack_type {
+                    PacketType::MsgFragment(_) => packet.routing_header.hops.first(),
+                    PacketType::FloodRequest(request) => Some(&request.initiator_id),
+                    PacketType::Ack(_) | PacketType::Nack(_) | PacketType::FloodResponse(_) => {
+                        packet.routing_header.hops.last()
+                    }
+                };
+                (*origin?, packet.session_id)
+            }
+            Self::MessageSent {
+                notification_from,
+                session_id,
+                ..
+            }
+            | Self::SessionRetried {
+                notification_from,
+                session_id,
+                ..
+            }
+            | Self::SessionDropped {
+                notification_from,
+                session_id,
+            }
+            | Self::SessionCancelled {
+                notification_from,
+                session_id,
+            }
+            | Self::SessionNotFound {
+                notification_from,
+                session_id,
+            }
+            | Self::ProtocolViolation {
+                notification_from,
+                session_id,
+                ..
+            }
+            | Self::AckReceived {
+                notification_from,
+                session_id,
+                ..
+            }
+            | Self::NackReceived {
+                notification_from,
+                session_id,
+                ..
+            }
+            | Self::MessageFullyAcked {
+                notification_from,
+                session_id,
+                ..
+            }
+            | Self::SendFailed {
+                notification_from,
+                session_id,
+                ..
+            } => (*notification_from, *session_id),
+            Self::MessageReceived {
+                from, session_id, ..
+            }
+            | Self::ReceiveProgress {
+                from, session_id, ..
+            } => (*from, *session_id),
+            _ => return None,
+        };
+        Some(CorrelationId { origin, session_id })
+    }
+
+    /// Returns whether the event must reach the controller whatever its event filter:
+    /// replies to commands and packets the controller has to deliver.
+    #[must_use]
+    pub fn bypasses_filter(&self) -> bool {
+        matches!(
+            self,
+            Self::ControllerShortcut(_)
+                | Self::BufferedSessions { .. }
+                | Self::SessionRetried { .. }
+                | Self::SessionDropped { .. }
+                | Self::SessionNotFound { .. }
+        )
+    }
+}
+
+/// Why the run loop of a node exited.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminationReason {
+    /// The controller asked the node to shut down
+    Shutdown,
+    /// Handling a packet failed with the given error
+    Error(String),
+    /// The node panicked with the given message
+    Panic(String),
+}
+
+/// Counters describing the activity of a node since it started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct NodeStats {
+    pub floods_started: u64,
+    // flood requests answered because already seen
+    pub floods_suppressed: u64,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub packets_sent: u64,
+    pub packets_received: u64,
+    // fragments sent again after a Nack or a ForceRetry
+    pub retransmissions: u64,
+    // sessions sent whose fragments are not all acknowledged yet
+    pub active_sessions: usize,
+    pub messages_delivered: u64,
+    pub duplicate_messages: u64,
+    // Acks rejected as unsolicited
+    pub ack_anomalies: u64,
+    // Nacks answering packets this node couldn't process
+    pub nacks_sent: u64,
 }
 
 #[derive(Debug, Clone)]
 pub enum NodeCommand {
     AddSender(NodeId, Sender<Packet>),
+    // optional channel towards a neighbor reserved to Ack/Nack/Flood packets
+    AddControlSender(NodeId, Sender<Packet>),
     RemoveSender(NodeId),
     Shutdown,
+    // refuse new sends and keep retrying the accepted ones for at most this long,
+    // then emit NodeEvent::ShutdownComplete and stop
+    GracefulShutdown(Duration),
+    // replied with NodeEvent::BufferedSessions
+    ListSessions,
+    // resend the unacknowledged fragments of a session, replied with NodeEvent::SessionRetried
+    ForceRetry(u64),
+    // give up a session, replied with NodeEvent::SessionDropped
+    DropSession(u64),
+    // only emit the events at least this severe
+    SetEventFilter(Severity),
+    // answered by the run loop with a snapshot of the network view
+    QueryTopology(Sender<Network>),
+    // answered by the run loop with the current NodeStats
+    QueryStats(Sender<NodeStats>),
+    // answered by the run loop with the current Metrics
+    QueryMetrics(Sender<Metrics>),
+    // answered by the run loop with the blacklisted nodes and the time left, by id
+    QueryBlacklist(Sender<Vec<(NodeId, Duration)>>),
 }
 
 impl NodeCommand {
@@ -491,3 +1310,85 @@ impl Display for NodeType {
         }
     }
 }
+
+#[cfg(test)]
+mod types_tests {
+    use super::*;
+    use crate::codec::{BincodeCodec, JsonCodec, MessagePackCodec};
+
+    #[test]
+    /// Tests that placeholders are filled only by the media the text references
+    fn test_file_placeholders() {
+        let image = MediaFile::new("image".to_string(), vec![vec![1]]);
+        let sound = MediaFile::new("sound".to_string(), vec![vec![2]]);
+        let mut refs = vec![MediaReference::new(4), MediaReference::new(5)];
+        refs[0].id = image.id;
+        refs[1].id = sound.id;
+        let text = TextFile::new("title".to_string(), "text".to_string(), refs.clone());
+        let mut file = File::with_placeholders(text);
+        assert_eq!(file.placeholders(), refs);
+
+        let unrelated = MediaFile::new("other".to_string(), vec![]);
+        assert!(!file.add_media(unrelated.clone()));
+        assert_eq!(file.media_state(unrelated.id), None);
+        assert!(file.add_media(sound.clone()));
+        assert!(!file.add_media(sound.clone()));
+        assert_eq!(file.media_state(sound.id), Some(MediaState::Ready));
+        assert_eq!(file.placeholders(), vec![refs[0].clone()]);
+        assert!(!file.is_complete());
+
+        assert!(file.add_media(image));
+        assert!(file.is_complete());
+    }
+
+    #[test]
+    /// Tests that media bytes round-trip through their chunks and corruption is detected
+    fn test_media_integrity() {
+        let data = [b"\x89PNG\r\n\x1a\n".as_slice(), &[9; 3000]].concat();
+        let mut media = MediaFile::from_bytes("photo".to_string(), &data);
+        assert_eq!(media.content.len(), 3);
+        assert_eq!(media.to_bytes(), Ok(data.clone()));
+        assert_eq!(media.extension(), Some("png"));
+
+        let json = serde_json::to_vec(&media).unwrap();
+        let received: MediaFile = serde_json::from_slice(&json).unwrap();
+        assert!(received.verify());
+
+        media.content[1][0] ^= 1;
+        let error = media.to_bytes().unwrap_err();
+        assert_eq!(error.media_id, media.id);
+        assert_eq!(error.expected, media.hash);
+
+        // files without a hash, e.g. from older peers, are taken as they are
+        media.hash.clear();
+        assert!(media.verify());
+        media.title = "song.ogg".to_string();
+        assert_eq!(media.extension(), Some("ogg"));
+    }
+
+    #[test]
+    /// Tests that envelopes round-trip with every codec and `MessagePack` shrinks file data
+    fn test_envelope_codecs() {
+        let response = WebResponse::TextFile {
+            file_data: (0..=255).collect(),
+        };
+        let json = encode_response(&JsonCodec, &response).unwrap();
+        let packed = encode_response(&MessagePackCodec, &response).unwrap();
+        assert!(packed.len() * 2 < json.len());
+        // bincode can't decode internally tagged enums, the envelope falls back to JSON
+        assert_eq!(encode_response(&BincodeCodec, &response).unwrap(), json);
+        for codec in Codec::PREFERENCE {
+            let bytes =
+                encode_response(&codec, &WebResponse::ErrorFileNotFound(Uuid::nil())).unwrap();
+            assert!(matches!(
+                decode_response(&codec, &bytes).unwrap(),
+                WebResponse::ErrorFileNotFound(id) if id.is_nil()
+            ));
+            let bytes = encode_request(&codec, &ChatRequest::JoinRoom { room_id: 4 }).unwrap();
+            assert!(matches!(
+                decode_request(&codec, &bytes).unwrap(),
+                ChatRequest::JoinRoom { room_id: 4 }
+            ));
+        }
+    }
+}
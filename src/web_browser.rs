@@ -0,0 +1,256 @@
+use crate::RoutingHandler;
+use crate::file_transfer::FileTransfer;
+use crate::network::{ChannelError, NetworkError};
+use crate::types::{File, MediaFile, TextFile, WebEvent, WebRequest, WebResponse};
+use std::collections::HashMap;
+use uuid::Uuid;
+use wg_internal::network::NodeId;
+
+/// Client-side facade fetching the files of web servers for a UI.
+///
+/// [`Self::request_file`] asks a server for a text file. Once it arrives, the file is
+/// queued as a `WebEvent::File` whose media are placeholders, so that the text can be
+/// shown at once, while every media it references is downloaded in chunks from the
+/// node hosting it. Each media completed fills its placeholder and queues a
+/// `WebEvent::MediaArrived`, see [`Self::take_events`]. Hand the messages received
+/// to [`Self::handle_response`].
+#[derive(Debug, Clone, Default)]
+pub struct WebBrowser {
+    // files received, media included as they arrive
+    files: HashMap<Uuid, File>,
+    // media downloads by media id, with the node hosting the media
+    transfers: HashMap<Uuid, (NodeId, FileTransfer)>,
+    events: Vec<WebEvent>,
+}
+
+impl WebBrowser {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks `server` for the text file `file_id`.
+    /// # Errors
+    /// Returns an error if the request cannot be sent.
+    pub fn request_file(
+        &mut self,
+        router: &mut RoutingHandler,
+        server: NodeId,
+        file_id: Uuid,
+    ) -> Result<(), NetworkError> {
+        send(
+            router,
+            server,
+            &WebRequest::FileQuery {
+                file_id: file_id.to_string(),
+            },
+        )
+    }
+
+    /// Handles a message received from `from`: records text files and media chunks,
+    /// requesting the media and chunks still missing, and queues the events of the
+    /// UI. Returns whether `msg` was such a response, other messages are ignored.
+    /// # Errors
+    /// Returns an error if a media request cannot be sent.
+    pub fn handle_response(
+        &mut self,
+        router: &mut RoutingHandler,
+        from: NodeId,
+        msg: &[u8],
+    ) -> Result<bool, NetworkError> {
+        let Ok(response) = serde_json::from_slice::<WebResponse>(msg) else {
+            return Ok(false);
+        };
+        match &response {
+            WebResponse::TextFile { file_data } => {
+                let Ok(text) = serde_json::from_slice::<TextFile>(file_data) else {
+                    return Ok(false);
+                };
+                self.show(router, from, text)?;
+            }
+            WebResponse::FileChunkResponse { file_id, .. } => {
+                let Some((location, transfer)) = self.transfers.get_mut(file_id) else {
+                    return Ok(false);
+                };
+                let location = *location;
+                if !transfer.handle_response(&response) {
+                    return Ok(true);
+                }
+                match transfer.assemble() {
+                    Some(data) => self.arrived(from, *file_id, &data),
+                    // the size is known from the first chunk, request all the others
+                    None if transfer.progress().0 == 1 => {
+                        for request in transfer.requests(usize::MAX) {
+                            send(router, location, &request)?;
+                        }
+                    }
+                    None => {}
+                }
+            }
+            WebResponse::ErrorFileNotFound(uuid) => {
+                let _ = self.transfers.remove(uuid);
+                self.events.push(WebEvent::FileNotFound {
+                    notification_from: from,
+                    uuid: *uuid,
+                });
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Requests again the chunks still missing of every media download, e.g. after
+    /// losses. Returns the requests sent.
+    /// # Errors
+    /// Returns an error if a request cannot be sent.
+    pub fn retry(&self, router: &mut RoutingHandler) -> Result<usize, NetworkError> {
+        let mut sent = 0;
+        for (location, transfer) in self.transfers.values() {
+            for request in transfer.requests(usize::MAX) {
+                send(router, *location, &request)?;
+                sent += 1;
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Returns the file `file_id` as received so far.
+    #[must_use]
+    pub fn file(&self, file_id: Uuid) -> Option<&File> {
+        self.files.get(&file_id)
+    }
+
+    /// Returns the media still downloading, in ascending id order.
+    #[must_use]
+    pub fn pending_media(&self) -> Vec<Uuid> {
+        let mut pending = self.transfers.keys().copied().collect::<Vec<_>>();
+        pending.sort_unstable();
+        pending
+    }
+
+    /// Returns the events queued since the previous call, oldest first.
+    pub fn take_events(&mut self) -> Vec<WebEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// Shows `text` with placeholders and starts downloading its media.
+    fn show(
+        &mut self,
+        router: &mut RoutingHandler,
+        from: NodeId,
+        text: TextFile,
+    ) -> Result<(), NetworkError> {
+        let file = File::with_placeholders(text);
+        for media_ref in file.placeholders() {
+            if self.transfers.contains_key(&media_ref.id) {
+                continue;
+            }
+            let transfer = FileTransfer::new(media_ref.id);
+            for request in transfer.requests(1) {
+                send(router, media_ref.location, &request)?;
+            }
+            let _ = self
+                .transfers
+                .insert(media_ref.id, (media_ref.location, transfer));
+        }
+        self.events.push(WebEvent::File {
+            notification_from: from,
+            file: file.clone(),
+        });
+        let _ = self.files.insert(file.id, file);
+        Ok(())
+    }
+
+    /// Fills the placeholders of the media `media_id` downloaded from `from`.
+    fn arrived(&mut self, from: NodeId, media_id: Uuid, data: &[u8]) {
+        let _ = self.transfers.remove(&media_id);
+        let mut media = MediaFile::from_bytes(media_id.to_string(), data);
+        media.id = media_id;
+        let mut filled = self
+            .files
+            .values_mut()
+            .filter_map(|file| file.add_media(media.clone()).then_some(file.id))
+            .collect::<Vec<_>>();
+        filled.sort_unstable();
+        for file_id in filled {
+            self.events.push(WebEvent::MediaArrived {
+                notification_from: from,
+                file_id,
+                media_id,
+            });
+        }
+    }
+}
+
+fn send(router: &mut RoutingHandler, to: NodeId, request: &WebRequest) -> Result<(), NetworkError> {
+    let data = serde_json::to_vec(request).map_err(|e| ChannelError::Encoding(e.to_string()))?;
+    router.send_message(&data, Some(to), None)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod web_browser_tests {
+    use super::*;
+    use crate::file_transfer::chunk_response;
+    use crate::types::MediaReference;
+    use crossbeam_channel::unbounded;
+    use wg_internal::packet::{FloodResponse, NodeType};
+
+    #[test]
+    /// Tests that the text is shown with placeholders and each media completed is announced
+    fn test_web_browser() {
+        let (controller_send, _controller_recv) = unbounded();
+        let mut router = RoutingHandler::new(1, NodeType::Client, HashMap::new(), controller_send);
+        let (neighbor_send, _neighbor_recv) = unbounded();
+        router.add_neighbor(2, neighbor_send);
+        router.start_flood(None).unwrap();
+        router
+            .handle_flood_response(&FloodResponse {
+                flood_id: 1,
+                path_trace: vec![(1, NodeType::Client), (2, NodeType::Server)],
+            })
+            .unwrap();
+
+        let picture = vec![7; 2500];
+        let picture_ref = MediaReference::new(2);
+        let text = TextFile::new(
+            "title".to_string(),
+            "text".to_string(),
+            vec![picture_ref.clone()],
+        );
+        let mut browser = WebBrowser::new();
+        browser.request_file(&mut router, 2, text.id).unwrap();
+        let response = WebResponse::TextFile {
+            file_data: serde_json::to_vec(&text).unwrap(),
+        };
+        assert!(
+            browser
+                .handle_response(&mut router, 2, &serde_json::to_vec(&response).unwrap())
+                .unwrap()
+        );
+        assert!(matches!(
+            browser.take_events().as_slice(),
+            [WebEvent::File { file, .. }] if file.placeholders() == vec![picture_ref.clone()]
+        ));
+        assert_eq!(browser.pending_media(), vec![picture_ref.id]);
+
+        for offset in [0, 1024, 1024, 2048] {
+            let chunk = chunk_response(picture_ref.id, &picture, offset).unwrap();
+            let msg = serde_json::to_vec(&chunk).unwrap();
+            assert!(browser.handle_response(&mut router, 2, &msg).unwrap());
+        }
+        assert_eq!(
+            browser.take_events(),
+            vec![WebEvent::MediaArrived {
+                notification_from: 2,
+                file_id: text.id,
+                media_id: picture_ref.id,
+            }]
+        );
+        let file = browser.file(text.id).unwrap();
+        assert!(file.is_complete());
+        assert_eq!(file.media_files[0].to_bytes().unwrap(), picture);
+        assert!(browser.pending_media().is_empty());
+        assert!(!browser.handle_response(&mut router, 2, b"hello").unwrap());
+    }
+}